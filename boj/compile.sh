@@ -1 +1,11 @@
-cargo run -- boj/1000.pna -o out.cpp
\ No newline at end of file
+#!/usr/bin/env bash
+# Judges boj/<N>.pna on the cases in boj/<N>/ (1.in / 1.out, ...) and, when
+# they all pass, writes the file to submit to boj/<N>.cpp.
+#
+#   boj/compile.sh 1000
+set -eu
+cd "$(dirname "$0")/.."
+n=${1:?usage: boj/compile.sh <problem>}
+cargo build -q
+target/debug/pna-cc judge "boj/$n.pna" "boj/$n" "${@:2}"
+target/debug/pna-cc bundle "boj/$n.pna" --shorten -o "boj/$n.cpp"
@@ -0,0 +1,87 @@
+#!/usr/bin/env bash
+# Runs every examples/*.pna and boj/*.pna that has a matching .out file through
+# each backend and diffs stdout+stderr against it. A matching .in file is fed
+# to stdin. A `# backends: ...` line in the source limits it to those
+# backends (for programs using features some backends lack, such as `cpp`
+# blocks or computed keys under wat), and a `# flags: ...` line passes flags
+# such as `--strict` to the compiler (to `pna-cc exec` for vm). Exit codes are
+# not checked, so failing programs are pinned by their error messages.
+#
+# With the cpp backend, each examples/judge/NAME.pna is also run through
+# `pna-cc judge` on the cases in examples/judge/NAME/ (its `# flags: ...` go
+# to `judge`) and the report, without the time and memory columns, is diffed
+# against examples/judge/NAME.out.
+#
+#   scripts/golden.sh            # all backends
+#   scripts/golden.sh c vm       # only the named ones (cpp, bundle, c, js, vm, wat)
+#
+# The wat backend needs wasmtime and is left out of the default list when it
+# is not installed.
+set -u
+cd "$(dirname "$0")/.."
+cargo build -q || exit 1
+PNA=target/debug/pna-cc
+CXX=${CXX:-g++}
+CC=${CC:-cc}
+WASMTIME=${WASMTIME:-wasmtime}
+backends=("$@")
+if [ ${#backends[@]} -eq 0 ]; then
+  backends=(cpp bundle c js vm)
+  command -v "$WASMTIME" >/dev/null && backends+=(wat)
+fi
+tmp=$(mktemp -d)
+trap 'rm -rf "$tmp"' EXIT
+
+run() { # backend file.pna flags... -> program output on stdout
+  local b=$1 src=$2
+  shift 2
+  case $b in
+    cpp) $PNA "$src" "$@" -o "$tmp/p.cpp" && $CXX -std=c++20 "$tmp/p.cpp" -o "$tmp/p" && "$tmp/p" ;;
+    bundle) $PNA bundle "$src" "$@" --shorten --no-verify -o "$tmp/p.cpp" 2>/dev/null &&
+      $CXX -std=c++17 "$tmp/p.cpp" -o "$tmp/p" && "$tmp/p" ;;
+    c) $PNA "$src" "$@" --emit=c -o "$tmp/p.c" && $CC -std=c99 "$tmp/p.c" -o "$tmp/p" -lm && "$tmp/p" ;;
+    js) $PNA emit-js "$src" "$@" -o "$tmp/p.mjs" && node "$tmp/p.mjs" ;;
+    vm) $PNA "$src" --emit=bc -o "$tmp/p.pnac" && $PNA exec "$tmp/p.pnac" "$@" ;;
+    wat) $PNA "$src" "$@" --emit=wat -o "$tmp/p.wat" && $WASMTIME run "$tmp/p.wat" ;;
+    *) echo "unknown backend $1" >&2; return 2 ;;
+  esac
+}
+
+fail=0
+for src in examples/*.pna boj/*.pna; do
+  exp=${src%.pna}.out
+  [ -f "$exp" ] || continue
+  in=${src%.pna}.in
+  [ -f "$in" ] || in=/dev/null
+  only=$(sed -n 's/^# backends: //p' "$src")
+  read -ra flags <<<"$(sed -n 's/^# flags: //p' "$src")"
+  for b in "${backends[@]}"; do
+    [ -z "$only" ] || [[ " $only " == *" $b "* ]] || continue
+    run "$b" "$src" "${flags[@]}" <"$in" >"$tmp/got" 2>&1
+    if diff -u "$exp" "$tmp/got" >"$tmp/diff"; then
+      echo "ok   $b $src"
+    else
+      echo "FAIL $b $src"
+      cat "$tmp/diff"
+      fail=1
+    fi
+  done
+done
+
+if [[ " ${backends[*]} " == *" cpp "* ]]; then
+  for src in examples/judge/*.pna; do
+    exp=${src%.pna}.out
+    [ -f "$exp" ] || continue
+    read -ra flags <<<"$(sed -n 's/^# flags: //p' "$src")"
+    $PNA judge "$src" "${src%.pna}" "${flags[@]}" 2>&1 |
+      sed -E 's/ +[0-9]+ ms +([0-9.]+ MB|-)$//' >"$tmp/got"
+    if diff -u "$exp" "$tmp/got" >"$tmp/diff"; then
+      echo "ok   judge $src"
+    else
+      echo "FAIL judge $src"
+      cat "$tmp/diff"
+      fail=1
+    fi
+  done
+fi
+exit $fail
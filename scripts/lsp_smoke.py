@@ -0,0 +1,60 @@
+#!/usr/bin/env python3
+"""Scripted JSON-RPC session against `pna-cc lsp`; prints every reply.
+
+usage: scripts/lsp_smoke.py [path/to/pna-cc]
+"""
+import json
+import subprocess
+import sys
+
+SRC = """user: {
+  name: "Bob",
+  age: 17,
+}
+function greet(who: string, n: int) -> string {
+  return "hi ${who} x${n}"
+} end
+msg: greet(user.name, 2)
+log msg
+"""
+
+URI = "file:///smoke.pna"
+
+
+def frame(msg):
+    body = json.dumps(msg).encode()
+    return b"Content-Length: %d\r\n\r\n" % len(body) + body
+
+
+def main():
+    exe = sys.argv[1] if len(sys.argv) > 1 else "target/debug/pna-cc"
+    doc = {"textDocument": {"uri": URI}}
+    msgs = [
+        {"id": 1, "method": "initialize", "params": {}},
+        {"method": "initialized", "params": {}},
+        {"method": "textDocument/didOpen",
+         "params": {"textDocument": {"uri": URI, "languageId": "pna", "version": 1, "text": SRC}}},
+        # hover over `greet` in the call, then the `who` parameter
+        {"id": 2, "method": "textDocument/hover", "params": {**doc, "position": {"line": 7, "character": 6}}},
+        {"id": 3, "method": "textDocument/hover", "params": {**doc, "position": {"line": 5, "character": 16}}},
+        {"id": 4, "method": "textDocument/hover", "params": {**doc, "position": {"line": 8, "character": 5}}},
+        {"id": 5, "method": "textDocument/definition", "params": {**doc, "position": {"line": 7, "character": 18}}},
+        {"id": 6, "method": "textDocument/documentSymbol", "params": doc},
+        # mid-edit the text does not parse; completion uses the last good parse
+        {"method": "textDocument/didChange",
+         "params": {**doc, "contentChanges": [{"text": SRC.replace("log msg", "log msg, user.")}]}},
+        {"id": 7, "method": "textDocument/completion", "params": {**doc, "position": {"line": 8, "character": 14}}},
+        {"id": 8, "method": "shutdown"},
+        {"method": "exit"},
+    ]
+    data = b"".join(frame({"jsonrpc": "2.0", **m}) for m in msgs)
+    out = subprocess.run([exe, "lsp"], input=data, capture_output=True, check=True).stdout
+    while out:
+        head, _, rest = out.partition(b"\r\n\r\n")
+        n = int(head.split(b":")[1])
+        print(json.dumps(json.loads(rest[:n])))
+        out = rest[n:]
+
+
+if __name__ == "__main__":
+    main()
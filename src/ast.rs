@@ -1,40 +1,101 @@
-#[derive(Debug, Clone)]
+//! Syntax tree produced by `parser::parse`.
+//!
+//! Invariants the parser guarantees (and later passes rely on):
+//! - every name (`Ident`, `Call::name`, object keys, targets) is a non-empty
+//!   ASCII identifier: `[A-Za-z_][A-Za-z0-9_]*`, except that `link::link`
+//!   names the functions of imported files `module::name`;
+//! - `Stmt::span` / `Item::Func::span` point at the first token of the
+//!   construct;
+//! - nested blocks are `Vec<Stmt>` in source order and may be empty.
+
+/// Source position (1-based line and column, in characters). `file` is 0
+/// for the file being compiled and N for the Nth file it imports (see
+/// `Session`).
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct Span {
+    pub line: usize,
+    pub col: usize,
+    pub file: usize,
+}
+
+/// Declared type of a function parameter, return value or record field.
+#[derive(Debug, Clone, PartialEq)]
 pub enum Ty {
     Double,
     Int,
     String,
+    /// Only meaningful as a return type; `typeck` rejects `void` parameters
+    /// and fields.
     Void,
+    /// A record declared with `type` or an enum declared with `enum`;
+    /// `typeck` rejects undeclared names.
+    Named(String),
+}
+
+impl std::fmt::Display for Ty {
+    fn fmt(&self, f: &mut std::fmt::Formatter) -> std::fmt::Result {
+        f.write_str(match self {
+            Ty::Double => "double",
+            Ty::Int => "int",
+            Ty::String => "string",
+            Ty::Void => "void",
+            Ty::Named(n) => n,
+        })
+    }
 }
 
 #[derive(Debug, Clone)]
 pub struct Param {
     pub name: String,
-    #[allow(dead_code)]
     pub ty: Ty,
 }
 
 #[derive(Debug, Clone)]
 pub enum Expr {
+    /// Numbers are always `f64`; integer literals have no fractional part.
     Num(f64),
+    /// Escapes are already decoded; may contain any Unicode text, even NUL.
     Str(String),
     Bool(bool),
     Ident(String),
+    /// `base.key`
     Member(Box<Expr>, String),
+    /// `base[index]`
+    Index(Box<Expr>, Box<Expr>),
+    /// `op` is always `"!"`.
     Unary {
         op: String,
         rhs: Box<Expr>,
     },
+    /// `op` is one of `+ - * / % ** == != < <= > >= && ||`.
     Binary {
         op: String,
         lhs: Box<Expr>,
         rhs: Box<Expr>,
     },
+    /// A user function or a builtin (`format`, `len`, `keys`, ...).
     Call {
         name: String,
         args: Vec<Expr>,
     },
+    /// `"a ${x} b"`: contains at least one `InterpPart::Expr` and never two
+    /// adjacent `InterpPart::Lit`s.
+    Interp(Vec<InterpPart>),
+    /// `Enum::Variant` or `Enum::Variant(args...)`.
+    Variant {
+        ty: String,
+        name: String,
+        args: Vec<Expr>,
+    },
+}
+
+#[derive(Debug, Clone)]
+pub enum InterpPart {
+    Lit(String),
+    Expr(Expr),
 }
 
+/// Destination of `input`.
 #[derive(Debug, Clone)]
 pub enum Target {
     Var(String),
@@ -42,21 +103,42 @@ pub enum Target {
 }
 
 #[derive(Debug, Clone)]
-pub enum Stmt {
+pub struct Stmt {
+    pub kind: StmtKind,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum StmtKind {
+    /// `name: { k: e, ... }`, or `name: T { ... }` for a typed literal;
+    /// fields keep source order, later duplicates win.
     ObjBlock {
         name: String,
+        ty: Option<String>,
         fields: Vec<(String, Expr)>,
     },
+    /// `base.key: expr`
     PropAssign {
         base: String,
         key: String,
         expr: Expr,
     },
+    /// `base[index]: expr`: the key is the string form of `index`, which is
+    /// evaluated first.
+    IndexAssign {
+        base: String,
+        index: Expr,
+        expr: Expr,
+    },
+    /// `name: expr`
     VarAssign {
         name: String,
         expr: Expr,
     },
-    Log(Expr),
+    /// `log a, b, ...`: at least one expression.
+    Log(Vec<Expr>),
+    /// `print a, b, ...`: like `Log` without the trailing newline.
+    Print(Vec<Expr>),
     Cond {
         cond: Expr,
         then_blk: Vec<Stmt>,
@@ -66,6 +148,7 @@ pub enum Stmt {
         cond: Expr,
         body: Vec<Stmt>,
     },
+    /// `ended` runs when the loop exits without `break`; `None` if absent.
     While {
         cond: Expr,
         body: Vec<Stmt>,
@@ -75,23 +158,91 @@ pub enum Stmt {
         prompt: String,
         dst: Target,
     },
+    /// `match expr -> { arms } end`; arms are tried in source order.
+    Match {
+        expr: Expr,
+        arms: Vec<Arm>,
+    },
     Break,
     Continue,
     Return(Option<Expr>),
+    /// `cpp { ... } end`: C++ statements copied into the generated code
+    /// as written; only the C++ backend accepts it.
+    Cpp(String),
+}
+
+/// `Pat -> { body }` in a `match`.
+#[derive(Debug, Clone)]
+pub struct Arm {
+    pub pat: Pat,
+    pub body: Vec<Stmt>,
+    pub span: Span,
+}
+
+#[derive(Debug, Clone)]
+pub enum Pat {
+    /// `Variant` or `Variant(a, b)`: the payload is assigned to the names,
+    /// like `a: ...` would.
+    Variant { name: String, binds: Vec<String> },
+    /// `_`
+    Wild,
+}
+
+/// One alternative of an `enum`, with the types of its payload.
+#[derive(Debug, Clone)]
+pub struct Variant {
+    pub name: String,
+    pub fields: Vec<Ty>,
 }
 
 #[derive(Debug, Clone)]
 pub enum Item {
     Stmt(Stmt),
+    /// Functions are only allowed at the top level.
     Func {
         name: String,
         params: Vec<Param>,
-        #[allow(dead_code)]
         ret: Ty,
         body: Vec<Stmt>,
+        span: Span,
+    },
+    /// `type Name { field: ty, ... }`, only allowed at the top level.
+    Type {
+        name: String,
+        fields: Vec<(String, Ty)>,
+        span: Span,
+    },
+    /// `enum Name { A, B(ty, ...), ... }`, only allowed at the top level.
+    /// Variant names are unique across all enums of a program.
+    Enum {
+        name: String,
+        variants: Vec<Variant>,
+        span: Span,
+    },
+    /// `import "path"` or `import alias from "path"`, only allowed at the
+    /// top level; `link::link` replaces it with the imported items.
+    Import {
+        path: String,
+        alias: Option<String>,
+        span: Span,
+    },
+    /// `extern function name(params) -> ret`: a C++ function the program
+    /// calls, declared in a `cpp` block or an `--include`d header.
+    Extern {
+        name: String,
+        params: Vec<Param>,
+        ret: Ty,
+        span: Span,
+    },
+    /// A `cpp { ... } end` block at the top level: C++ declarations placed
+    /// before the program's functions.
+    Cpp {
+        code: String,
+        span: Span,
     },
 }
 
+/// A whole source file; items are in source order.
 #[derive(Debug, Clone)]
 pub struct Program {
     pub items: Vec<Item>,
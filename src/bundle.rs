@@ -0,0 +1,392 @@
+//! `pna-cc bundle`: the C++ translation unit cut down for judges that take
+//! one pasted file.
+//!
+//! Works on the text `codegen_cpp` produced: it is split into top-level
+//! declarations, and only those reachable from `main` (by the identifiers
+//! they mention) are kept, so unused runtime functions drop out. Code between
+//! [`USER_BEGIN`] and [`USER_END`] (`cpp` blocks and `--include` headers) is
+//! always kept, since its initializers may run for effect. Comments,
+//! `#line` directives and layout are dropped; each declaration is printed on
+//! one line with only the spaces C++ needs. With `shorten`, every name the
+//! generated code declares at the top level (`fn_*`, `Value`, ...), every
+//! `pna_*` name and every generated local (`__s`, `__a0`, ...) gets a short
+//! name, most used first; names that also follow a `.`, `->` or `::` are
+//! kept, and so are `__` names only user code mentions
+//! (`__builtin_popcountll`). The result depends only on the input.
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+use std::io::Write;
+use std::process::{Command, Stdio};
+
+/// Comment lines `codegen_cpp` puts around code the user wrote.
+pub const USER_BEGIN: &str = "// pna: user code begin";
+pub const USER_END: &str = "// pna: user code end";
+
+/// A bundled translation unit and how much of the input survived.
+#[derive(Debug, Clone)]
+pub struct Bundle {
+    pub code: String,
+    /// Top-level declarations kept and found.
+    pub kept: usize,
+    pub total: usize,
+}
+
+/// Bundles `cpp`, the output of `codegen_cpp::compile_to_cpp`.
+pub fn bundle(cpp: &str, shorten: bool) -> Bundle {
+    let items = split(&lex(cpp));
+    let total = items.iter().filter(|it| it.name.is_some()).count();
+    let keep = reachable(&items);
+    let mut items: Vec<Item> = items
+        .into_iter()
+        .zip(keep)
+        .filter_map(|(it, k)| k.then_some(it))
+        .collect();
+    let kept = items.iter().filter(|it| it.name.is_some()).count();
+    if shorten {
+        rename(&mut items);
+    }
+    let mut code = String::new();
+    for it in &items {
+        let mut prev: Option<&Tok> = None;
+        for t in &it.toks {
+            if prev.is_some_and(|p| needs_space(p, t)) {
+                code.push(' ');
+            }
+            code.push_str(&t.text);
+            prev = Some(t);
+        }
+        code.push('\n');
+    }
+    Bundle { code, kept, total }
+}
+
+/// Runs `cxx -std=<std> -fsyntax-only` on `code`; the error is the
+/// compiler's output.
+pub fn check_compiles(code: &str, cxx: &str, std: &str) -> Result<(), String> {
+    let mut child = Command::new(cxx)
+        .args([&format!("-std={}", std), "-fsyntax-only", "-x", "c++", "-"])
+        .stdin(Stdio::piped())
+        .stdout(Stdio::null())
+        .stderr(Stdio::piped())
+        .spawn()
+        .map_err(|e| format!("cannot run {}: {}", cxx, e))?;
+    child
+        .stdin
+        .take()
+        .expect("piped")
+        .write_all(code.as_bytes())
+        .map_err(|e| format!("{}: {}", cxx, e))?;
+    let out = child
+        .wait_with_output()
+        .map_err(|e| format!("{}: {}", cxx, e))?;
+    if out.status.success() {
+        Ok(())
+    } else {
+        Err(String::from_utf8_lossy(&out.stderr).into_owned())
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Kind {
+    Word,
+    Number,
+    /// A string or character literal.
+    Lit,
+    Punct,
+    /// A whole preprocessor line.
+    Directive,
+}
+
+#[derive(Debug, Clone)]
+struct Tok {
+    kind: Kind,
+    text: String,
+    /// Between `USER_BEGIN` and `USER_END`.
+    user: bool,
+}
+
+/// Multi-character punctuators, longest first.
+const PUNCTS: &[&str] = &[
+    ">>=", "<<=", "...", "->*", "::", "->", "++", "--", "<<", ">>", "<=", ">=", "==", "!=", "&&",
+    "||", "+=", "-=", "*=", "/=", "%=", "&=", "|=", "^=", ".*", "##",
+];
+
+/// C++ tokens of `src`, without comments and `#line` directives.
+fn lex(src: &str) -> Vec<Tok> {
+    let s: Vec<char> = src.chars().collect();
+    let mut toks = Vec::new();
+    let mut line_start = true;
+    let mut user = false;
+    let mut i = 0;
+    while i < s.len() {
+        let c = s[i];
+        let start = i;
+        let kind = if c == '\n' {
+            line_start = true;
+            i += 1;
+            continue;
+        } else if c.is_whitespace() {
+            i += 1;
+            continue;
+        } else if s[i..].starts_with(&['/', '/']) {
+            while i < s.len() && s[i] != '\n' {
+                i += 1;
+            }
+            let comment: String = s[start..i].iter().collect();
+            match comment.trim_end() {
+                USER_BEGIN => user = true,
+                USER_END => user = false,
+                _ => {}
+            }
+            continue;
+        } else if s[i..].starts_with(&['/', '*']) {
+            i += 2;
+            while i < s.len() && !s[i..].starts_with(&['*', '/']) {
+                i += 1;
+            }
+            i = (i + 2).min(s.len());
+            continue;
+        } else if c == '#' && line_start {
+            while i < s.len() && s[i] != '\n' {
+                i += 1;
+            }
+            Kind::Directive
+        } else if c.is_ascii_alphabetic() || c == '_' {
+            while i < s.len() && (s[i].is_ascii_alphanumeric() || s[i] == '_') {
+                i += 1;
+            }
+            Kind::Word
+        } else if c.is_ascii_digit() || (c == '.' && s.get(i + 1).is_some_and(char::is_ascii_digit))
+        {
+            let hex = s[i..].starts_with(&['0', 'x']);
+            i += 1;
+            // `1e-9`: a sign after an exponent is part of the number
+            while i < s.len()
+                && (s[i].is_ascii_alphanumeric()
+                    || s[i] == '_'
+                    || s[i] == '.'
+                    || (matches!(s[i], '+' | '-') && matches!(s[i - 1], 'e' | 'E') && !hex))
+            {
+                i += 1;
+            }
+            Kind::Number
+        } else if c == '"' || c == '\'' {
+            i += 1;
+            while i < s.len() && s[i] != c {
+                i += if s[i] == '\\' { 2 } else { 1 };
+            }
+            i = (i + 1).min(s.len());
+            Kind::Lit
+        } else {
+            let rest: String = s[i..s.len().min(i + 3)].iter().collect();
+            i += PUNCTS
+                .iter()
+                .find(|p| rest.starts_with(*p))
+                .map_or(1, |p| p.len());
+            Kind::Punct
+        };
+        line_start = false;
+        let text: String = s[start..i].iter().collect();
+        if kind == Kind::Directive && text.starts_with("#line") {
+            continue;
+        }
+        toks.push(Tok { kind, text, user });
+    }
+    toks
+}
+
+/// Whether `a` and `b` would run together without a space between them.
+fn needs_space(a: &Tok, b: &Tok) -> bool {
+    let wordy = |t: &Tok| matches!(t.kind, Kind::Word | Kind::Number);
+    match (a.kind, b.kind) {
+        (Kind::Directive, _) | (_, Kind::Directive) => false,
+        _ if wordy(a) && wordy(b) => true,
+        // `1'000` is a digit separator, `"s"x` a user-defined literal and
+        // `u8"s"` a prefixed one
+        (Kind::Number, Kind::Lit) | (Kind::Lit, Kind::Word) => true,
+        (Kind::Word, Kind::Lit) => matches!(a.text.as_str(), "L" | "u" | "U" | "u8" | "R"),
+        (Kind::Punct, Kind::Punct) => {
+            let joined = format!("{}{}", a.text, b.text);
+            joined.starts_with("//")
+                || joined.starts_with("/*")
+                || PUNCTS
+                    .iter()
+                    .any(|p| p.len() > a.text.len() && joined.starts_with(p))
+        }
+        _ => false,
+    }
+}
+
+/// A top-level declaration (or preprocessor line) and the name it declares.
+#[derive(Debug)]
+struct Item {
+    toks: Vec<Tok>,
+    /// `None` for directives and namespaces, which are always kept.
+    name: Option<String>,
+    /// From a `cpp` block or header, so always kept.
+    user: bool,
+}
+
+/// Splits the tokens into top-level items: each ends at a `;` or at a `}`
+/// that closes its outermost brace, unless a `;` follows.
+fn split(toks: &[Tok]) -> Vec<Item> {
+    let mut items = Vec::new();
+    let mut cur: Vec<Tok> = Vec::new();
+    let mut depth = 0usize;
+    for (i, t) in toks.iter().enumerate() {
+        if t.kind == Kind::Directive {
+            items.push(Item {
+                toks: vec![t.clone()],
+                name: None,
+                user: t.user,
+            });
+            continue;
+        }
+        cur.push(t.clone());
+        let end = match t.text.as_str() {
+            "{" if t.kind == Kind::Punct => {
+                depth += 1;
+                false
+            }
+            "}" if t.kind == Kind::Punct => {
+                depth = depth.saturating_sub(1);
+                depth == 0 && toks.get(i + 1).is_none_or(|n| n.text != ";")
+            }
+            ";" => depth == 0,
+            _ => false,
+        };
+        if end {
+            let toks = std::mem::take(&mut cur);
+            let name = declared_name(&toks);
+            let user = toks.iter().any(|t| t.user);
+            items.push(Item { toks, name, user });
+        }
+    }
+    if !cur.is_empty() {
+        items.push(Item {
+            user: cur.iter().any(|t| t.user),
+            toks: cur,
+            name: None,
+        });
+    }
+    items
+}
+
+/// The name a declaration introduces: the last word before its first `(`,
+/// `=`, `:`, `;` or `{`. Namespaces have none.
+fn declared_name(toks: &[Tok]) -> Option<String> {
+    if toks.first().is_some_and(|t| t.text == "namespace") {
+        return None;
+    }
+    toks.iter()
+        .take_while(|t| {
+            !(t.kind == Kind::Punct && matches!(t.text.as_str(), "(" | "=" | ":" | ";" | "{"))
+        })
+        .filter(|t| t.kind == Kind::Word)
+        .last()
+        .map(|t| t.text.clone())
+}
+
+/// Which items `main`, the namespaces, the directives and user code need,
+/// directly or through the items they name.
+fn reachable(items: &[Item]) -> Vec<bool> {
+    let mut by_name: HashMap<&str, Vec<usize>> = HashMap::new();
+    for (i, it) in items.iter().enumerate() {
+        if let Some(n) = &it.name {
+            by_name.entry(n.as_str()).or_default().push(i);
+        }
+    }
+    let mut keep = vec![false; items.len()];
+    let mut work: Vec<usize> = (0..items.len())
+        .filter(|&i| items[i].user || matches!(items[i].name.as_deref(), None | Some("main")))
+        .collect();
+    while let Some(i) = work.pop() {
+        if std::mem::replace(&mut keep[i], true) {
+            continue;
+        }
+        for t in items[i].toks.iter().filter(|t| t.kind == Kind::Word) {
+            for &j in by_name.get(t.text.as_str()).into_iter().flatten() {
+                if !keep[j] {
+                    work.push(j);
+                }
+            }
+        }
+    }
+    keep
+}
+
+/// Short names in order: `a`..`Z`, then `aa`, `ab`, ...
+fn short_name(mut n: usize) -> String {
+    const ALPHA: &[u8] = b"abcdefghijklmnopqrstuvwxyzABCDEFGHIJKLMNOPQRSTUVWXYZ";
+    let mut s = Vec::new();
+    loop {
+        s.push(ALPHA[n % ALPHA.len()]);
+        n /= ALPHA.len();
+        if n == 0 {
+            break;
+        }
+        n -= 1;
+    }
+    s.reverse();
+    String::from_utf8(s).expect("ASCII")
+}
+
+/// Short words that must not be handed out even though the code does not
+/// use them: keywords and functions `<bits/stdc++.h>` puts in the global
+/// namespace.
+const RESERVED: &[&str] = &[
+    "and", "asm", "do", "for", "if", "int", "jn", "new", "not", "or", "tm", "try", "xor", "yn",
+];
+
+/// Gives the generated names short ones, most frequent first.
+fn rename(items: &mut [Item]) {
+    let ours: HashSet<String> = items
+        .iter()
+        .filter_map(|it| it.name.clone())
+        .filter(|n| n != "main")
+        .collect();
+    // a name also used as a member or in another namespace (a `cpp` block
+    // declaring `size`, say) is not only ours, so it keeps its spelling
+    let toks: Vec<&Tok> = items.iter().flat_map(|it| &it.toks).collect();
+    let foreign: HashSet<&str> = toks
+        .windows(2)
+        .filter(|w| w[0].kind == Kind::Punct && matches!(w[0].text.as_str(), "." | "->" | "::"))
+        .map(|w| w[1].text.as_str())
+        .filter(|n| !n.starts_with("pna_"))
+        .collect();
+    // `__` names are ours only if generated code uses them: user code may
+    // call compiler builtins
+    let generated: HashSet<&str> = toks
+        .iter()
+        .filter(|t| !t.user && t.text.starts_with("__"))
+        .map(|t| t.text.as_str())
+        .collect();
+    let mut counts: BTreeMap<&str, usize> = BTreeMap::new();
+    let mut taken = HashSet::new();
+    for t in toks {
+        if t.kind != Kind::Word {
+            continue;
+        }
+        let own = (ours.contains(&t.text) || generated.contains(t.text.as_str()))
+            && !foreign.contains(t.text.as_str());
+        if own || t.text.starts_with("pna_") {
+            *counts.entry(t.text.as_str()).or_default() += 1;
+        } else {
+            taken.insert(t.text.as_str());
+        }
+    }
+    let mut order: Vec<(&str, usize)> = counts.into_iter().collect();
+    order.sort_by_key(|&(_, n)| std::cmp::Reverse(n));
+    let mut names = (0..)
+        .map(short_name)
+        .filter(|s| !taken.contains(s.as_str()) && !RESERVED.contains(&s.as_str()));
+    let map: HashMap<String, String> = order
+        .into_iter()
+        .map(|(n, _)| (n.to_string(), names.next().expect("infinite")))
+        .collect();
+    for t in items.iter_mut().flat_map(|it| &mut it.toks) {
+        if let Some(short) = map.get(&t.text).filter(|_| t.kind == Kind::Word) {
+            t.text = short.clone();
+        }
+    }
+}
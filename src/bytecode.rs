@@ -0,0 +1,399 @@
+//! Bytecode run by `vm`, produced by `codegen_bc`, and its `.pnac` file
+//! format.
+//!
+//! Each function is a flat list of stack instructions. Variables the
+//! function assigns (and its parameters) live in numbered local slots; any
+//! other name is looked up by name through the caller frames, which is how
+//! the C++ backend's `Env` chain behaves.
+
+use crate::math::{self, Math};
+use crate::value::Value;
+
+/// Binary operators, in the spelling of `Expr::Binary::op`.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum BinOp {
+    Add,
+    Sub,
+    Mul,
+    Div,
+    Mod,
+    Eq,
+    Ne,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    And,
+    Or,
+    Pow,
+}
+
+const BIN_OPS: &[(BinOp, &str)] = &[
+    (BinOp::Add, "+"),
+    (BinOp::Sub, "-"),
+    (BinOp::Mul, "*"),
+    (BinOp::Div, "/"),
+    (BinOp::Mod, "%"),
+    (BinOp::Eq, "=="),
+    (BinOp::Ne, "!="),
+    (BinOp::Lt, "<"),
+    (BinOp::Le, "<="),
+    (BinOp::Gt, ">"),
+    (BinOp::Ge, ">="),
+    (BinOp::And, "&&"),
+    (BinOp::Or, "||"),
+    (BinOp::Pow, "**"),
+];
+
+impl BinOp {
+    pub fn parse(op: &str) -> Option<BinOp> {
+        BIN_OPS.iter().find(|(_, s)| *s == op).map(|(b, _)| *b)
+    }
+
+    /// Lower-case name, as used by the runtimes (`pna_add`, `$add`, ...).
+    pub fn name(self) -> &'static str {
+        match self {
+            BinOp::Add => "add",
+            BinOp::Sub => "sub",
+            BinOp::Mul => "mul",
+            BinOp::Div => "div",
+            BinOp::Mod => "mod",
+            BinOp::Eq => "eq",
+            BinOp::Ne => "neq",
+            BinOp::Lt => "lt",
+            BinOp::Le => "le",
+            BinOp::Gt => "gt",
+            BinOp::Ge => "ge",
+            BinOp::And => "and",
+            BinOp::Or => "or",
+            BinOp::Pow => "pow",
+        }
+    }
+
+    pub fn as_str(self) -> &'static str {
+        BIN_OPS
+            .iter()
+            .find(|(b, _)| *b == self)
+            .map_or("?", |(_, s)| s)
+    }
+}
+
+/// One instruction. Operands index the module's constant pool (`k`), the
+/// current function's slots, the module's functions, or code (`target`).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Op {
+    /// push `consts[k]`
+    Const(u32),
+    Pop,
+    /// push a local; unset slots fall back to a lookup by name
+    LoadLocal(u32),
+    StoreLocal(u32),
+    /// `[v, k] -> []`: sets field `k` of the object in a local in place; an
+    /// unset slot starts from the callers' value (`Undef` if missing, even in
+    /// strict mode)
+    SetLocalField(u32),
+    /// `[k, v] -> []`: like `SetLocalField` with the key pushed first
+    SetLocalIndex(u32),
+    /// push the variable named `consts[k]`, found through the caller frames
+    LoadName(u32),
+    /// `[obj] -> [obj.consts[k]]`
+    Member(u32),
+    /// `[base, i] -> [base[i]]`
+    Index,
+    /// push an empty object
+    NewObj,
+    /// `[obj, v] -> [obj']` with field `consts[k]` set to `v`
+    SetField(u32),
+    Bin(BinOp),
+    Not,
+    Len,
+    /// `[obj, k] -> [bool]`
+    Has,
+    /// `[obj, k] -> [obj']` without field `k`
+    Remove,
+    /// `[obj] -> [list]` of its field names
+    Keys,
+    /// `[obj] -> [list]` of its field values
+    Values,
+    /// `[args...] -> [result]`, as many arguments as the builtin takes
+    Math(Math),
+    /// `[fmt, args...] -> [s]`, `n` values in total
+    Format(u32),
+    /// concatenate the string forms of the top `n` values
+    Concat(u32),
+    /// call `funcs[f]` with its arguments on the stack
+    Call(u32),
+    /// return the top value from the current function
+    Ret,
+    /// write the top `n` values separated by spaces, with a newline
+    Log(u32),
+    /// like `Log` without the newline
+    Print(u32),
+    /// show prompt `consts[k]`, push the next input token
+    Input(u32),
+    Jump(u32),
+    /// pop, and jump unless truthy
+    JumpIfFalse(u32),
+}
+
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Function {
+    pub name: String,
+    /// Parameters occupy the first `params` slots.
+    pub params: u32,
+    /// Name of each local slot.
+    pub slots: Vec<String>,
+    pub code: Vec<Op>,
+    /// Source line of each instruction, for runtime errors.
+    pub lines: Vec<u32>,
+    /// The file those lines are in: 0 for the main file, N for
+    /// `Module::files[N - 1]`.
+    pub file: u32,
+}
+
+/// A compiled program. `main` holds the top-level statements; its slots are
+/// the program's global variables.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Module {
+    pub consts: Vec<Value>,
+    pub funcs: Vec<Function>,
+    pub main: Function,
+    /// Names of the imported files, for runtime errors.
+    pub files: Vec<String>,
+}
+
+const MAGIC: &[u8; 4] = b"PNAC";
+const VERSION: u32 = 5;
+
+impl Module {
+    /// Serializes to the `.pnac` format (little-endian, length-prefixed).
+    pub fn encode(&self) -> Vec<u8> {
+        let mut w = Vec::new();
+        w.extend_from_slice(MAGIC);
+        put_u32(&mut w, VERSION);
+        put_u32(&mut w, self.consts.len() as u32);
+        for c in &self.consts {
+            match c {
+                Value::Num(d) => {
+                    w.push(0);
+                    w.extend_from_slice(&d.to_le_bytes());
+                }
+                Value::Str(s) => {
+                    w.push(1);
+                    put_str(&mut w, s);
+                }
+                Value::Bool(b) => w.extend_from_slice(&[2, *b as u8]),
+                Value::Undef | Value::Obj(_) => w.push(3),
+            }
+        }
+        put_u32(&mut w, self.files.len() as u32);
+        for f in &self.files {
+            put_str(&mut w, f);
+        }
+        put_u32(&mut w, self.funcs.len() as u32);
+        for f in self.funcs.iter().chain([&self.main]) {
+            put_fn(&mut w, f);
+        }
+        w
+    }
+
+    pub fn decode(b: &[u8]) -> Result<Module, String> {
+        let mut r = Reader { b, at: 0 };
+        if r.take(4)? != MAGIC {
+            return Err("not a .pnac file".into());
+        }
+        let v = r.u32()?;
+        if v != VERSION {
+            return Err(format!("unsupported .pnac version {}", v));
+        }
+        let mut m = Module::default();
+        for _ in 0..r.u32()? {
+            let c = match r.u8()? {
+                0 => Value::Num(f64::from_le_bytes(r.take(8)?.try_into().unwrap())),
+                1 => Value::Str(r.str()?),
+                2 => Value::Bool(r.u8()? != 0),
+                3 => Value::Undef,
+                t => return Err(format!("bad constant tag {}", t)),
+            };
+            m.consts.push(c);
+        }
+        for _ in 0..r.u32()? {
+            m.files.push(r.str()?);
+        }
+        for _ in 0..r.u32()? {
+            m.funcs.push(r.func()?);
+        }
+        m.main = r.func()?;
+        if r.at != b.len() {
+            return Err("trailing bytes after module".into());
+        }
+        m.validate()?;
+        Ok(m)
+    }
+
+    /// Checks that every operand is in range, so the VM can index freely.
+    fn validate(&self) -> Result<(), String> {
+        for f in self.funcs.iter().chain([&self.main]) {
+            if f.params as usize > f.slots.len() {
+                return Err(format!("`{}`: more parameters than slots", f.name));
+            }
+            if f.file as usize > self.files.len() {
+                return Err(format!("`{}`: file {} out of range", f.name, f.file));
+            }
+            for op in &f.code {
+                let ok = match *op {
+                    Op::Const(k)
+                    | Op::LoadName(k)
+                    | Op::Member(k)
+                    | Op::SetField(k)
+                    | Op::Input(k) => (k as usize) < self.consts.len(),
+                    Op::LoadLocal(s)
+                    | Op::StoreLocal(s)
+                    | Op::SetLocalField(s)
+                    | Op::SetLocalIndex(s) => (s as usize) < f.slots.len(),
+                    Op::Call(c) => (c as usize) < self.funcs.len(),
+                    Op::Jump(t) | Op::JumpIfFalse(t) => (t as usize) <= f.code.len(),
+                    _ => true,
+                };
+                if !ok {
+                    return Err(format!("`{}`: operand out of range in {:?}", f.name, op));
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+fn put_u32(w: &mut Vec<u8>, v: u32) {
+    w.extend_from_slice(&v.to_le_bytes());
+}
+
+fn put_str(w: &mut Vec<u8>, s: &str) {
+    put_u32(w, s.len() as u32);
+    w.extend_from_slice(s.as_bytes());
+}
+
+fn put_fn(w: &mut Vec<u8>, f: &Function) {
+    put_str(w, &f.name);
+    put_u32(w, f.params);
+    put_u32(w, f.file);
+    put_u32(w, f.slots.len() as u32);
+    for s in &f.slots {
+        put_str(w, s);
+    }
+    put_u32(w, f.code.len() as u32);
+    for (op, line) in f.code.iter().zip(&f.lines) {
+        let (code, arg) = match *op {
+            Op::Const(k) => (0, k),
+            Op::Pop => (1, 0),
+            Op::LoadLocal(s) => (2, s),
+            Op::StoreLocal(s) => (3, s),
+            Op::LoadName(k) => (4, k),
+            Op::Member(k) => (5, k),
+            Op::Index => (6, 0),
+            Op::NewObj => (7, 0),
+            Op::SetField(k) => (8, k),
+            Op::Bin(b) => (9, b as u32),
+            Op::Not => (10, 0),
+            Op::Len => (11, 0),
+            Op::Format(n) => (12, n),
+            Op::Concat(n) => (13, n),
+            Op::Call(f) => (14, f),
+            Op::Ret => (15, 0),
+            Op::Log(n) => (16, n),
+            Op::Print(n) => (17, n),
+            Op::Input(k) => (18, k),
+            Op::Jump(t) => (19, t),
+            Op::JumpIfFalse(t) => (20, t),
+            Op::SetLocalField(s) => (21, s),
+            Op::SetLocalIndex(s) => (22, s),
+            Op::Has => (23, 0),
+            Op::Remove => (24, 0),
+            Op::Keys => (25, 0),
+            Op::Values => (26, 0),
+            Op::Math(m) => (27, math::ALL.iter().position(|x| *x == m).unwrap() as u32),
+        };
+        w.push(code);
+        put_u32(w, arg);
+        put_u32(w, *line);
+    }
+}
+
+struct Reader<'a> {
+    b: &'a [u8],
+    at: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], String> {
+        let s = self
+            .b
+            .get(self.at..self.at + n)
+            .ok_or("unexpected end of .pnac file")?;
+        self.at += n;
+        Ok(s)
+    }
+
+    fn u8(&mut self) -> Result<u8, String> {
+        Ok(self.take(1)?[0])
+    }
+
+    fn u32(&mut self) -> Result<u32, String> {
+        Ok(u32::from_le_bytes(self.take(4)?.try_into().unwrap()))
+    }
+
+    fn str(&mut self) -> Result<String, String> {
+        let n = self.u32()? as usize;
+        String::from_utf8(self.take(n)?.to_vec()).map_err(|e| e.to_string())
+    }
+
+    fn func(&mut self) -> Result<Function, String> {
+        let mut f = Function {
+            name: self.str()?,
+            params: self.u32()?,
+            file: self.u32()?,
+            ..Default::default()
+        };
+        for _ in 0..self.u32()? {
+            f.slots.push(self.str()?);
+        }
+        for _ in 0..self.u32()? {
+            let code = self.u8()?;
+            let a = self.u32()?;
+            let op = match code {
+                0 => Op::Const(a),
+                1 => Op::Pop,
+                2 => Op::LoadLocal(a),
+                3 => Op::StoreLocal(a),
+                4 => Op::LoadName(a),
+                5 => Op::Member(a),
+                6 => Op::Index,
+                7 => Op::NewObj,
+                8 => Op::SetField(a),
+                9 => Op::Bin(BIN_OPS.get(a as usize).ok_or("bad operator")?.0),
+                10 => Op::Not,
+                11 => Op::Len,
+                12 => Op::Format(a),
+                13 => Op::Concat(a),
+                14 => Op::Call(a),
+                15 => Op::Ret,
+                16 => Op::Log(a),
+                17 => Op::Print(a),
+                18 => Op::Input(a),
+                19 => Op::Jump(a),
+                20 => Op::JumpIfFalse(a),
+                21 => Op::SetLocalField(a),
+                22 => Op::SetLocalIndex(a),
+                23 => Op::Has,
+                24 => Op::Remove,
+                25 => Op::Keys,
+                26 => Op::Values,
+                27 => Op::Math(*math::ALL.get(a as usize).ok_or("bad math builtin")?),
+                c => return Err(format!("bad opcode {}", c)),
+            };
+            f.code.push(op);
+            f.lines.push(self.u32()?);
+        }
+        Ok(f)
+    }
+}
@@ -0,0 +1,352 @@
+//! IR → bytecode (`bytecode::Module`) for the `vm` backend.
+
+use crate::ast::Span;
+use crate::bytecode::{Function, Module, Op};
+use crate::diag::Diagnostic;
+use crate::ir::{self, Const, Func, Inst, Operand, Rv, Temp};
+use crate::value::Value;
+use std::collections::HashMap;
+
+/// Constant-pool key; `f64` is keyed by its bits.
+#[derive(PartialEq, Eq, Hash)]
+enum ConstKey {
+    Num(u64),
+    Str(String),
+    Bool(bool),
+    Undef,
+}
+
+struct Ctx {
+    consts: Vec<Value>,
+    const_ix: HashMap<ConstKey, u32>,
+    /// name -> (index, parameter count)
+    funcs: HashMap<String, (u32, usize)>,
+}
+
+impl Ctx {
+    fn konst(&mut self, v: Value) -> u32 {
+        let key = match &v {
+            Value::Num(d) => ConstKey::Num(d.to_bits()),
+            Value::Str(s) => ConstKey::Str(s.clone()),
+            Value::Bool(b) => ConstKey::Bool(*b),
+            Value::Undef | Value::Obj(_) => ConstKey::Undef,
+        };
+        let next = self.consts.len() as u32;
+        *self.const_ix.entry(key).or_insert_with(|| {
+            self.consts.push(v);
+            next
+        })
+    }
+
+    fn str(&mut self, s: &str) -> u32 {
+        self.konst(Value::Str(s.to_string()))
+    }
+}
+
+/// Jump fix-ups for an enclosing loop.
+struct LoopCtx {
+    continue_to: u32,
+    breaks: Vec<usize>,
+}
+
+struct FnBuilder<'c, 'f> {
+    cx: &'c mut Ctx,
+    f: Function,
+    /// `Let`s waiting for their single use; compiled where they are used so
+    /// operands land on the stack in order.
+    temps: HashMap<Temp, &'f Rv>,
+    /// Enclosing loops by IR loop id, innermost last.
+    loops: Vec<(usize, LoopCtx)>,
+    line: u32,
+}
+
+pub fn compile(m: &ir::Module) -> Result<Module, Diagnostic> {
+    if let Some(at) = m.native() {
+        let msg = "--emit=bc cannot run `cpp` blocks or call `extern` functions";
+        return Err(Diagnostic::new(msg, at));
+    }
+    let m = &ir::expand_switches(m.clone());
+    let mut cx = Ctx {
+        consts: Vec::new(),
+        const_ix: HashMap::new(),
+        funcs: m
+            .funcs
+            .iter()
+            .enumerate()
+            .map(|(i, f)| (f.name.clone(), (i as u32, f.params)))
+            .collect(),
+    };
+    let mut funcs = Vec::new();
+    for f in &m.funcs {
+        funcs.push(FnBuilder::build(&mut cx, &f.name, f)?);
+    }
+    let main = FnBuilder::build(&mut cx, "<main>", &m.main)?;
+    Ok(Module {
+        consts: cx.consts,
+        funcs,
+        main,
+        files: m.files.clone(),
+    })
+}
+
+impl<'c, 'f> FnBuilder<'c, 'f> {
+    fn build(cx: &'c mut Ctx, name: &str, func: &'f Func) -> Result<Function, Diagnostic> {
+        let mut b = FnBuilder {
+            cx,
+            f: Function {
+                name: name.to_string(),
+                params: func.params as u32,
+                slots: func.slots.clone(),
+                code: Vec::new(),
+                lines: Vec::new(),
+                file: func.span.file as u32,
+            },
+            temps: HashMap::new(),
+            loops: Vec::new(),
+            line: 0,
+        };
+        b.block(&func.body).map_err(|e| {
+            let line = b.line as usize;
+            Diagnostic::new(e, Span { line, ..func.span })
+        })?;
+        let undef = b.cx.konst(Value::Undef);
+        b.emit(Op::Const(undef));
+        b.emit(Op::Ret);
+        Ok(b.f)
+    }
+
+    fn emit(&mut self, op: Op) -> usize {
+        self.f.code.push(op);
+        self.f.lines.push(self.line);
+        self.f.code.len() - 1
+    }
+
+    fn here(&self) -> u32 {
+        self.f.code.len() as u32
+    }
+
+    /// Points the jump at `at` to the current position.
+    fn patch(&mut self, at: usize) {
+        let to = self.here();
+        match &mut self.f.code[at] {
+            Op::Jump(t) | Op::JumpIfFalse(t) => *t = to,
+            op => unreachable!("patching {:?}", op),
+        }
+    }
+
+    fn block(&mut self, b: &'f [Inst]) -> Result<(), String> {
+        for i in b {
+            self.inst(i)?;
+        }
+        Ok(())
+    }
+
+    fn inst(&mut self, i: &'f Inst) -> Result<(), String> {
+        match i {
+            // a function's statements all come from the file declaring it
+            Inst::Line(sp) => self.line = sp.line as u32,
+            Inst::Switch { .. } => unreachable!("switches are expanded first"),
+            Inst::Cpp(_) => unreachable!("native modules are rejected up front"),
+            Inst::Let(t, rv) => {
+                self.temps.insert(*t, rv);
+            }
+            Inst::Store(s, v) => {
+                self.operand(v)?;
+                self.emit(Op::StoreLocal(*s as u32));
+            }
+            Inst::SetField(s, key, v) => {
+                self.operand(v)?;
+                let k = self.cx.str(key);
+                self.emit(Op::Const(k));
+                self.emit(Op::SetLocalField(*s as u32));
+            }
+            Inst::SetIndex(s, k, v) => {
+                self.operand(k)?;
+                self.operand(v)?;
+                self.emit(Op::SetLocalIndex(*s as u32));
+            }
+            Inst::Out { args, newline } => {
+                self.operands(args)?;
+                let n = args.len() as u32;
+                self.emit(if *newline { Op::Log(n) } else { Op::Print(n) });
+            }
+            Inst::If {
+                cond,
+                then_blk,
+                else_blk,
+            } => {
+                self.operand(cond)?;
+                let to_else = self.emit(Op::JumpIfFalse(0));
+                self.block(then_blk)?;
+                if else_blk.is_empty() {
+                    self.patch(to_else);
+                } else {
+                    let to_end = self.emit(Op::Jump(0));
+                    self.patch(to_else);
+                    self.block(else_blk)?;
+                    self.patch(to_end);
+                }
+            }
+            Inst::Loop {
+                id,
+                header,
+                cond,
+                body,
+                ended,
+            } => {
+                // `ended` runs when the condition turns false; `break` skips it.
+                let top = self.here();
+                self.block(header)?;
+                let line = self.line;
+                self.operand(cond)?;
+                let exit = self.emit(Op::JumpIfFalse(0));
+                self.loops.push((
+                    *id,
+                    LoopCtx {
+                        continue_to: top,
+                        breaks: Vec::new(),
+                    },
+                ));
+                self.block(body)?;
+                self.line = line;
+                self.emit(Op::Jump(top));
+                let (_, lp) = self.loops.pop().expect("pushed above");
+                self.patch(exit);
+                self.block(ended)?;
+                for at in lp.breaks {
+                    self.patch(at);
+                }
+            }
+            Inst::Break(id) => {
+                let at = self.emit(Op::Jump(0));
+                self.enclosing(*id).breaks.push(at);
+            }
+            Inst::Continue(id) => {
+                let to = self.enclosing(*id).continue_to;
+                self.emit(Op::Jump(to));
+            }
+            Inst::Return(v) => {
+                self.operand(v)?;
+                self.emit(Op::Ret);
+            }
+        }
+        Ok(())
+    }
+
+    fn enclosing(&mut self, id: usize) -> &mut LoopCtx {
+        let (_, lp) = self
+            .loops
+            .iter_mut()
+            .rev()
+            .find(|(i, _)| *i == id)
+            .expect("the IR only breaks out of enclosing loops");
+        lp
+    }
+
+    fn operands(&mut self, xs: &[Operand]) -> Result<(), String> {
+        for x in xs {
+            self.operand(x)?;
+        }
+        Ok(())
+    }
+
+    fn operand(&mut self, o: &Operand) -> Result<(), String> {
+        let k = match o {
+            Operand::Temp(t) => {
+                let rv = self.temps.remove(t).expect("temporaries are used once");
+                return self.rv(rv);
+            }
+            Operand::Const(Const::Num(d)) => self.cx.konst(Value::Num(*d)),
+            Operand::Const(Const::Str(s)) => self.cx.str(s),
+            Operand::Const(Const::Bool(b)) => self.cx.konst(Value::Bool(*b)),
+            Operand::Const(Const::Undef) => self.cx.konst(Value::Undef),
+        };
+        self.emit(Op::Const(k));
+        Ok(())
+    }
+
+    fn rv(&mut self, rv: &Rv) -> Result<(), String> {
+        match rv {
+            Rv::Local(s) => {
+                self.emit(Op::LoadLocal(*s as u32));
+            }
+            Rv::Name(n) => {
+                let k = self.cx.str(n);
+                self.emit(Op::LoadName(k));
+            }
+            Rv::Not(a) => {
+                self.operand(a)?;
+                self.emit(Op::Not);
+            }
+            Rv::Bin(op, a, b) => {
+                self.operand(a)?;
+                self.operand(b)?;
+                self.emit(Op::Bin(*op));
+            }
+            Rv::Member(a, key) => {
+                self.operand(a)?;
+                let k = self.cx.str(key);
+                self.emit(Op::Member(k));
+            }
+            Rv::Index(a, b) => {
+                self.operand(a)?;
+                self.operand(b)?;
+                self.emit(Op::Index);
+            }
+            Rv::NewObj => {
+                self.emit(Op::NewObj);
+            }
+            Rv::SetField(o, key, v) => {
+                self.operand(o)?;
+                self.operand(v)?;
+                let k = self.cx.str(key);
+                self.emit(Op::SetField(k));
+            }
+            Rv::Concat(xs) => {
+                self.operands(xs)?;
+                self.emit(Op::Concat(xs.len() as u32));
+            }
+            Rv::Format(xs) => {
+                self.operands(xs)?;
+                self.emit(Op::Format(xs.len() as u32));
+            }
+            Rv::Len(a) => {
+                self.operand(a)?;
+                self.emit(Op::Len);
+            }
+            Rv::Has(a, k) => {
+                self.operand(a)?;
+                self.operand(k)?;
+                self.emit(Op::Has);
+            }
+            Rv::Remove(a, k) => {
+                self.operand(a)?;
+                self.operand(k)?;
+                self.emit(Op::Remove);
+            }
+            Rv::Keys(a) => {
+                self.operand(a)?;
+                self.emit(Op::Keys);
+            }
+            Rv::Values(a) => {
+                self.operand(a)?;
+                self.emit(Op::Values);
+            }
+            Rv::Math(m, xs) => {
+                self.operands(xs)?;
+                self.emit(Op::Math(*m));
+            }
+            Rv::Call(name, xs) => {
+                self.operands(xs)?;
+                let (ix, _) = self.cx.funcs[name];
+                self.emit(Op::Call(ix));
+            }
+            Rv::Input(p) => {
+                let k = self.cx.str(p);
+                self.emit(Op::Input(k));
+            }
+            Rv::Extern(..) => unreachable!("native modules are rejected up front"),
+        }
+        Ok(())
+    }
+}
@@ -0,0 +1,494 @@
+//! IR → C99 with an embedded runtime, for judges and embedded targets that
+//! only have a C compiler.
+//!
+//! The output behaves like `codegen_cpp`'s: the same `Env` chain, operators,
+//! number formatting and `--strict` messages. `Value` is a tagged union that
+//! owns its string or object; every runtime function consumes its `Value`
+//! arguments and returns a fresh one, so generated expressions nest without
+//! temporaries and nothing leaks.
+
+use crate::ast::Span;
+use crate::codegen_cpp::{esc, indent, line_reset, mark, CppOptions};
+use crate::diag::Diagnostic;
+use crate::ir::*;
+use crate::math::{self, Math};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub fn compile_to_c(m: &Module, opts: &CppOptions) -> Result<String, Diagnostic> {
+    if let Some(at) = m.native() {
+        let msg = "--emit=c cannot run `cpp` blocks or call `extern` functions";
+        return Err(Diagnostic::new(msg, at));
+    }
+    let m = &expand_switches(m.clone());
+    let mut out = String::new();
+
+    out.push_str("#include <stdio.h>\n#include <stdlib.h>\n#include <string.h>\n#include <stdarg.h>\n#include <math.h>\n#include <ctype.h>\n");
+    write!(out, "static const int pna_strict={};", opts.strict as i32).unwrap();
+    out.push_str("static int pna_line=0,pna_file=0;");
+    out.push_str("static const char*const*pna_where=0;");
+    out.push_str("static inline void pna_fail(const char*m){fflush(stdout);if(pna_where)fprintf(stderr,\"%s:%d: runtime error: %s\\n\",pna_where[pna_file],pna_line,m);else fprintf(stderr,\"line %d: runtime error: %s\\n\",pna_line,m);exit(1);}");
+    out.push_str("static inline void*pna_realloc(void*p,size_t n){p=realloc(p,n?n:1);if(!p){fputs(\"out of memory\\n\",stderr);exit(1);}return p;}");
+    out.push_str("static inline void*pna_alloc(size_t n){return pna_realloc(0,n);}");
+    out.push_str("typedef enum{PNA_UNDEF,PNA_NUM,PNA_STR,PNA_BOOL,PNA_OBJ}PnaTag;");
+    out.push_str("typedef struct PnaObj PnaObj;");
+    out.push_str("typedef struct{PnaTag t;union{double n;struct{char*p;size_t len;}s;int b;PnaObj*o;}u;}Value;");
+    out.push_str("typedef struct{char*k;Value v;}PnaField;");
+    out.push_str("struct PnaObj{PnaField*f;size_t len,cap;};");
+    out.push_str("static inline Value pna_undef(void){Value v;v.t=PNA_UNDEF;v.u.n=0;return v;}");
+    out.push_str("static inline Value pna_num(double d){Value v;v.t=PNA_NUM;v.u.n=d;return v;}");
+    out.push_str("static inline Value pna_bool(int b){Value v;v.t=PNA_BOOL;v.u.b=b!=0;return v;}");
+    out.push_str("static inline Value pna_str(const char*s,size_t n){Value v;v.t=PNA_STR;v.u.s.p=pna_alloc(n+1);memcpy(v.u.s.p,s,n);v.u.s.p[n]=0;v.u.s.len=n;return v;}");
+    out.push_str("static inline Value pna_cstr(const char*s){return pna_str(s,strlen(s));}");
+    out.push_str("static inline Value pna_make_obj(void){Value v;v.t=PNA_OBJ;v.u.o=pna_alloc(sizeof(PnaObj));v.u.o->f=0;v.u.o->len=v.u.o->cap=0;return v;}");
+    out.push_str("static inline void pna_drop(Value v){size_t i;if(v.t==PNA_STR)free(v.u.s.p);else if(v.t==PNA_OBJ){for(i=0;i<v.u.o->len;++i){free(v.u.o->f[i].k);pna_drop(v.u.o->f[i].v);}free(v.u.o->f);free(v.u.o);}}");
+    out.push_str("static inline char*pna_strdup(const char*s){size_t n=strlen(s)+1;char*p=pna_alloc(n);memcpy(p,s,n);return p;}");
+    out.push_str("static inline Value pna_dup(Value v){size_t i;Value r;if(v.t==PNA_STR)return pna_str(v.u.s.p,v.u.s.len);if(v.t!=PNA_OBJ)return v;r=pna_make_obj();r.u.o->f=pna_alloc(v.u.o->len*sizeof(PnaField));r.u.o->len=r.u.o->cap=v.u.o->len;for(i=0;i<v.u.o->len;++i){r.u.o->f[i].k=pna_strdup(v.u.o->f[i].k);r.u.o->f[i].v=pna_dup(v.u.o->f[i].v);}return r;}");
+    out.push_str("static inline PnaField*pna_field(PnaObj*o,const char*k,size_t*at){size_t lo=0,hi=o->len;while(lo<hi){size_t mid=(lo+hi)/2;int c=strcmp(o->f[mid].k,k);if(c==0)return &o->f[mid];if(c<0)lo=mid+1;else hi=mid;}if(at)*at=lo;return 0;}");
+    out.push_str("static inline void pna_obj_set(Value*o,const char*k,Value v){size_t at;PnaField*f;if(o->t!=PNA_OBJ){pna_drop(*o);*o=pna_make_obj();}f=pna_field(o->u.o,k,&at);if(f){pna_drop(f->v);f->v=v;return;}if(o->u.o->len==o->u.o->cap){o->u.o->cap=o->u.o->cap?o->u.o->cap*2:4;o->u.o->f=pna_realloc(o->u.o->f,o->u.o->cap*sizeof(PnaField));}memmove(o->u.o->f+at+1,o->u.o->f+at,(o->u.o->len-at)*sizeof(PnaField));o->u.o->f[at].k=pna_strdup(k);o->u.o->f[at].v=v;o->u.o->len++;}");
+    out.push_str("typedef struct{char*p;size_t len,cap;}PnaBuf;");
+    out.push_str("static inline void pna_buf_put(PnaBuf*b,const char*s,size_t n){if(b->len+n+1>b->cap){while(b->len+n+1>b->cap)b->cap=b->cap?b->cap*2:32;b->p=pna_realloc(b->p,b->cap);}memcpy(b->p+b->len,s,n);b->len+=n;b->p[b->len]=0;}");
+    out.push_str(
+        "static inline void pna_buf_puts(PnaBuf*b,const char*s){pna_buf_put(b,s,strlen(s));}",
+    );
+    out.push_str("static inline Value pna_buf_take(PnaBuf*b){Value v;if(!b->p)return pna_str(\"\",0);v.t=PNA_STR;v.u.s.p=b->p;v.u.s.len=b->len;return v;}");
+    out.push_str("static inline void pna_num_put(PnaBuf*b,double d){char s[512];double rd=round(d);size_t n;if(fabs(d-rd)<1e-9){snprintf(s,sizeof s,\"%.0f\",rd);pna_buf_puts(b,s);return;}snprintf(s,sizeof s,\"%.12f\",d);n=strlen(s);while(n&&s[n-1]=='0')--n;if(n&&s[n-1]=='.')--n;pna_buf_put(b,s,n);}");
+    out.push_str("static inline void pna_str_put(PnaBuf*b,Value x){size_t i;switch(x.t){case PNA_STR:pna_buf_put(b,x.u.s.p,x.u.s.len);break;case PNA_NUM:pna_num_put(b,x.u.n);break;case PNA_BOOL:pna_buf_puts(b,x.u.b?\"true\":\"false\");break;case PNA_OBJ:pna_buf_puts(b,\"{\");for(i=0;i<x.u.o->len;++i){if(i)pna_buf_puts(b,\", \");pna_buf_puts(b,x.u.o->f[i].k);pna_buf_puts(b,\":\");pna_str_put(b,x.u.o->f[i].v);}pna_buf_puts(b,\"}\");break;default:break;}}");
+    out.push_str("static inline Value pna_as_str(Value x){PnaBuf b={0,0,0};if(x.t==PNA_STR)return x;pna_str_put(&b,x);pna_drop(x);return pna_buf_take(&b);}");
+    out.push_str("static inline double pna_as_num(Value v){double d=0;if(v.t==PNA_NUM)return v.u.n;if(v.t==PNA_BOOL)return v.u.b?1.0:0.0;if(v.t==PNA_STR){char*end=0;d=strtod(v.u.s.p,&end);if(end!=v.u.s.p&&*end=='\\0'){pna_drop(v);return d;}if(pna_strict){PnaBuf b={0,0,0};pna_buf_puts(&b,\"cannot convert \\\"\");pna_buf_puts(&b,v.u.s.p);pna_buf_puts(&b,\"\\\" to a number\");pna_fail(b.p);}pna_drop(v);return 0.0;}if(pna_strict)pna_fail(v.t==PNA_OBJ?\"object used as a number\":\"undefined value used as a number\");pna_drop(v);return 0.0;}");
+    out.push_str("static inline int pna_truthy(Value x){int r=0;switch(x.t){case PNA_BOOL:r=x.u.b;break;case PNA_NUM:r=x.u.n!=0;break;case PNA_STR:r=x.u.s.len!=0;break;case PNA_OBJ:r=x.u.o->len!=0;break;default:break;}pna_drop(x);return r;}");
+    out.push_str("typedef struct{char*k;Value v;}PnaSlot;");
+    out.push_str("typedef struct Env{PnaSlot*s;size_t len,cap;struct Env*parent;}Env;");
+    out.push_str("static inline void pna_env_init(Env*e,Env*parent){e->s=0;e->len=e->cap=0;e->parent=parent;}");
+    out.push_str("static inline void pna_env_free(Env*e){size_t i;for(i=0;i<e->cap;++i)if(e->s[i].k){free(e->s[i].k);pna_drop(e->s[i].v);}free(e->s);e->s=0;e->len=e->cap=0;}");
+    out.push_str("static inline size_t pna_hash(const char*k){size_t h=2166136261u;while(*k)h=(h^(unsigned char)*k++)*16777619u;return h;}");
+    out.push_str("static inline PnaSlot*pna_slot(Env*e,const char*k){size_t i;if(!e->cap)return 0;for(i=pna_hash(k)&(e->cap-1);e->s[i].k;i=(i+1)&(e->cap-1))if(strcmp(e->s[i].k,k)==0)return &e->s[i];return &e->s[i];}");
+    out.push_str("static inline Value*pna_find(Env*e,const char*k){for(;e;e=e->parent){PnaSlot*s=pna_slot(e,k);if(s&&s->k)return &s->v;}return 0;}");
+    out.push_str("static inline Value pna_get(Env*e,const char*k){Value*p=pna_find(e,k);if(p)return pna_dup(*p);if(pna_strict){PnaBuf b={0,0,0};pna_buf_puts(&b,\"undefined variable `\");pna_buf_puts(&b,k);pna_buf_puts(&b,\"`\");pna_fail(b.p);}return pna_undef();}");
+    out.push_str("static inline void pna_set(Env*e,const char*k,Value v){PnaSlot*s;if((e->len+1)*2>e->cap){PnaSlot*old=e->s;size_t i,n=e->cap;e->cap=n?n*2:8;e->s=pna_alloc(e->cap*sizeof(PnaSlot));memset(e->s,0,e->cap*sizeof(PnaSlot));for(i=0;i<n;++i)if(old[i].k)*pna_slot(e,old[i].k)=old[i];free(old);}s=pna_slot(e,k);if(s->k){pna_drop(s->v);s->v=v;return;}s->k=pna_strdup(k);s->v=v;e->len++;}");
+    out.push_str("static inline Value pna_get_prop(Value o,const char*k){PnaField*f;Value r;if(o.t!=PNA_OBJ){if(pna_strict){PnaBuf b={0,0,0};pna_buf_puts(&b,\"cannot read property `\");pna_buf_puts(&b,k);pna_buf_puts(&b,\"` of a non-object\");pna_fail(b.p);}pna_drop(o);return pna_undef();}f=pna_field(o.u.o,k,0);if(f){r=f->v;f->v=pna_undef();pna_drop(o);return r;}if(pna_strict){PnaBuf b={0,0,0};pna_buf_puts(&b,\"undefined property `\");pna_buf_puts(&b,k);pna_buf_puts(&b,\"`\");pna_fail(b.p);}pna_drop(o);return pna_undef();}");
+    out.push_str("static inline Value pna_get_base(Env*e,const char*k){Value*p=pna_find(e,k);return p?pna_dup(*p):pna_undef();}static inline Value pna_with(Value o,const char*k,Value v){pna_obj_set(&o,k,v);return o;}");
+    out.push_str("static inline Value pna_input(const char*prompt){PnaBuf b={0,0,0};int c;char*end=0;double d;if(prompt[0]){fputs(prompt,stdout);}fflush(stdout);while((c=getchar())!=EOF&&isspace(c)){}while(c!=EOF&&!isspace(c)){char ch=(char)c;pna_buf_put(&b,&ch,1);c=getchar();}if(c!=EOF)ungetc(c,stdin);if(!b.p)return pna_str(\"\",0);d=strtod(b.p,&end);if(end!=b.p&&*end=='\\0'){free(b.p);return pna_num(d);}return pna_buf_take(&b);}");
+    out.push_str("static inline void pna_write(Value v){v=pna_as_str(v);fwrite(v.u.s.p,1,v.u.s.len,stdout);pna_drop(v);}");
+    out.push_str("static inline void pna_log(Value v){pna_write(v);putchar('\\n');}");
+    out.push_str("static inline void pna_out(int nl,int n,...){va_list ap;int i;va_start(ap,n);for(i=0;i<n;++i){if(i)putchar(' ');pna_write(va_arg(ap,Value));}va_end(ap);if(nl)putchar('\\n');}");
+    out.push_str("static inline size_t pna_u8_clen(unsigned char c){return c<0x80?1:(c>>5)==0x6?2:(c>>4)==0xE?3:(c>>3)==0x1E?4:1;}");
+    out.push_str("static inline size_t pna_u8_len(const char*s,size_t n){size_t i,k=0;for(i=0;i<n;i+=pna_u8_clen((unsigned char)s[i]))++k;return k;}");
+    out.push_str("static inline size_t pna_u8_off(const char*s,size_t n,size_t cp){size_t i=0;while(cp>0&&i<n){i+=pna_u8_clen((unsigned char)s[i]);--cp;}return i<n?i:n;}");
+    out.push_str("static inline Value pna_len(Value v){double n=0;if(v.t==PNA_STR)n=(double)pna_u8_len(v.u.s.p,v.u.s.len);else if(v.t==PNA_OBJ)n=(double)v.u.o->len;pna_drop(v);return pna_num(n);}");
+    out.push_str("static inline Value pna_index(Value b,Value i){double d;size_t at,cl;Value r;if(b.t==PNA_OBJ){i=pna_as_str(i);r=pna_get_prop(b,i.u.s.p);pna_drop(i);return r;}if(b.t!=PNA_STR){pna_drop(b);pna_drop(i);return pna_undef();}d=pna_as_num(pna_dup(i));if(d<0||d!=floor(d)||d>=(double)pna_u8_len(b.u.s.p,b.u.s.len)){if(pna_strict){PnaBuf m={0,0,0};pna_buf_puts(&m,\"string index \");pna_str_put(&m,i);pna_buf_puts(&m,\" out of range\");pna_fail(m.p);}pna_drop(b);pna_drop(i);return pna_undef();}at=pna_u8_off(b.u.s.p,b.u.s.len,(size_t)d);cl=pna_u8_clen((unsigned char)b.u.s.p[at]);if(at+cl>b.u.s.len)cl=b.u.s.len-at;r=pna_str(b.u.s.p+at,cl);pna_drop(b);pna_drop(i);return r;}");
+    out.push_str("static inline Value pna_with_key(Value o,Value k,Value v){k=pna_as_str(k);pna_obj_set(&o,k.u.s.p,v);pna_drop(k);return o;}");
+    out.push_str("static inline Value pna_has(Value o,Value k){int r;k=pna_as_str(k);r=o.t==PNA_OBJ&&pna_field(o.u.o,k.u.s.p,0)!=0;pna_drop(o);pna_drop(k);return pna_bool(r);}");
+    out.push_str("static inline Value pna_remove(Value o,Value k){PnaField*f;k=pna_as_str(k);if(o.t==PNA_OBJ&&(f=pna_field(o.u.o,k.u.s.p,0))!=0){free(f->k);pna_drop(f->v);memmove(f,f+1,(size_t)(o.u.o->f+o.u.o->len-f-1)*sizeof(PnaField));o.u.o->len--;}pna_drop(k);return o;}");
+    out.push_str("static inline void pna_push(Value*l,Value v){char k[32];snprintf(k,sizeof k,\"%lu\",(unsigned long)l->u.o->len);pna_obj_set(l,k,v);}");
+    out.push_str("static inline Value pna_keys(Value o){Value r=pna_make_obj();size_t i;if(o.t==PNA_OBJ)for(i=0;i<o.u.o->len;++i)pna_push(&r,pna_cstr(o.u.o->f[i].k));pna_drop(o);return r;}");
+    out.push_str("static inline Value pna_values(Value o){Value r=pna_make_obj();size_t i;if(o.t==PNA_OBJ)for(i=0;i<o.u.o->len;++i){pna_push(&r,o.u.o->f[i].v);o.u.o->f[i].v=pna_undef();}pna_drop(o);return r;}");
+    out.push_str("static inline Value pna_add(Value a,Value b){if(a.t==PNA_STR||b.t==PNA_STR){PnaBuf s={0,0,0};pna_str_put(&s,a);pna_str_put(&s,b);pna_drop(a);pna_drop(b);return pna_buf_take(&s);}return pna_num(pna_as_num(a)+pna_as_num(b));}");
+    out.push_str("static inline Value pna_sub(Value a,Value b){double x=pna_as_num(a);return pna_num(x-pna_as_num(b));}");
+    out.push_str("static inline Value pna_mul(Value a,Value b){if(a.t==PNA_STR&&b.t==PNA_NUM){PnaBuf o={0,0,0};long long r=llround(b.u.n);size_t i,cl;long long k;for(i=0;i<a.u.s.len;i+=cl){cl=pna_u8_clen((unsigned char)a.u.s.p[i]);if(i+cl>a.u.s.len)cl=a.u.s.len-i;for(k=0;k<r;++k)pna_buf_put(&o,a.u.s.p+i,cl);}pna_drop(a);return pna_buf_take(&o);}if(a.t==PNA_NUM&&b.t==PNA_STR)return pna_mul(b,a);{double x=pna_as_num(a);return pna_num(x*pna_as_num(b));}}");
+    out.push_str("static inline Value pna_div(Value a,Value b){double r=pna_as_num(b),x;if(r==0.0&&pna_strict)pna_fail(\"division by zero\");x=pna_as_num(a);return pna_num(r==0.0?0.0:x/r);}");
+    out.push_str("static inline Value pna_mod(Value a,Value b){double x=pna_as_num(a),y=pna_as_num(b),q,r;if(y==0.0){if(pna_strict)pna_fail(\"modulo by zero\");return pna_num(0.0);}q=floor((x/y)+1e-12);r=x-q*y;if(fabs(r)<1e-12)r=0.0;return pna_num(r);}");
+    out.push_str("static inline Value pna_math(const char*n,double r,int k,double a,double b){PnaBuf m={0,0,0};if(r==r)return pna_num(r+0.0);if(pna_strict){pna_buf_puts(&m,n);pna_buf_puts(&m,\"(\");pna_num_put(&m,a);if(k>1){pna_buf_puts(&m,\", \");pna_num_put(&m,b);}pna_buf_puts(&m,\") is undefined\");pna_fail(m.p);}return pna_num(0.0);}");
+    out.push_str("static inline double pna_igcd(long long x,long long y){unsigned long long a=x<0?0-(unsigned long long)x:(unsigned long long)x,b=y<0?0-(unsigned long long)y:(unsigned long long)y,t;while(b){t=a%b;a=b;b=t;}return (double)a;}");
+    emit_math(&mut out);
+    out.push_str("static inline int pna_same(Value a,Value b){int r;if(a.t==PNA_NUM&&b.t==PNA_NUM)return a.u.n==b.u.n;a=pna_as_str(a);b=pna_as_str(b);r=a.u.s.len==b.u.s.len&&memcmp(a.u.s.p,b.u.s.p,a.u.s.len)==0;pna_drop(a);pna_drop(b);return r;}");
+    out.push_str("static inline Value pna_eq(Value a,Value b){return pna_bool(pna_same(a,b));}");
+    out.push_str("static inline Value pna_neq(Value a,Value b){return pna_bool(!pna_same(a,b));}");
+    out.push_str("static inline Value pna_lt(Value a,Value b){double x=pna_as_num(a);return pna_bool(x<pna_as_num(b));}");
+    out.push_str("static inline Value pna_le(Value a,Value b){double x=pna_as_num(a);return pna_bool(x<=pna_as_num(b));}");
+    out.push_str("static inline Value pna_gt(Value a,Value b){double x=pna_as_num(a);return pna_bool(x>pna_as_num(b));}");
+    out.push_str("static inline Value pna_ge(Value a,Value b){double x=pna_as_num(a);return pna_bool(x>=pna_as_num(b));}");
+    out.push_str("static inline Value pna_and(Value a,Value b){int x=pna_truthy(a);return pna_bool(pna_truthy(b)&&x);}");
+    out.push_str("static inline Value pna_or(Value a,Value b){int x=pna_truthy(a);return pna_bool(pna_truthy(b)||x);}");
+    out.push_str("static inline Value pna_not(Value a){return pna_bool(!pna_truthy(a));}");
+    out.push_str("static inline Value pna_interp(int n,...){PnaBuf b={0,0,0};va_list ap;int i;va_start(ap,n);for(i=0;i<n;++i){Value v=va_arg(ap,Value);pna_str_put(&b,v);pna_drop(v);}va_end(ap);return pna_buf_take(&b);}");
+    out.push_str("static inline void pna_fmt_one(PnaBuf*out,Value v,const char*fill,size_t fl,char align,int zero,int width,int prec){PnaBuf s={0,0,0};int pad,k,isn=v.t==PNA_NUM;char a;if(prec>=0&&isn){char t[512];snprintf(t,sizeof t,\"%.*f\",prec,v.u.n);pna_buf_puts(&s,t);}else{pna_str_put(&s,v);if(prec>=0&&s.p){s.len=pna_u8_off(s.p,s.len,(size_t)prec);s.p[s.len]=0;}}pna_drop(v);pad=width-(int)pna_u8_len(s.p?s.p:\"\",s.len);if(pad>0&&zero&&isn){size_t at=(s.len&&s.p[0]=='-')?1:0;pna_buf_put(out,s.p,at);for(k=0;k<pad;++k)pna_buf_put(out,\"0\",1);pna_buf_put(out,s.p+at,s.len-at);free(s.p);return;}if(pad<0)pad=0;a=align?align:(isn?'>':'<');if(a=='>')for(k=0;k<pad;++k)pna_buf_put(out,fill,fl);if(a=='^')for(k=0;k<pad/2;++k)pna_buf_put(out,fill,fl);if(s.p)pna_buf_put(out,s.p,s.len);if(a=='<')for(k=0;k<pad;++k)pna_buf_put(out,fill,fl);if(a=='^')for(k=0;k<pad-pad/2;++k)pna_buf_put(out,fill,fl);free(s.p);}");
+    out.push_str("static inline int pna_isal(char c){return c=='<'||c=='>'||c=='^';}");
+    out.push_str("static inline Value pna_format(Value f,int n,...){PnaBuf out={0,0,0};va_list ap;Value*args=pna_alloc((n?n:1)*sizeof(Value));int ai=0,i;size_t j=0,len;const char*fs;f=pna_as_str(f);fs=f.u.s.p;len=f.u.s.len;va_start(ap,n);for(i=0;i<n;++i)args[i]=va_arg(ap,Value);va_end(ap);while(j<len){char c=fs[j];const char*cl;const char*in;size_t inl,k=0,fl=1;const char*fill=\" \";char align=0;int zero=0,width=0,prec=-1,ok;if((c=='{'||c=='}')&&j+1<len&&fs[j+1]==c){pna_buf_put(&out,&c,1);j+=2;continue;}if(c!='{'){pna_buf_put(&out,&c,1);++j;continue;}cl=memchr(fs+j+1,'}',len-j-1);if(!cl){pna_buf_put(&out,fs+j,len-j);break;}in=fs+j+1;inl=(size_t)(cl-in);j=(size_t)(cl-fs)+1;ok=inl==0||in[0]==':';if(inl&&ok){size_t c8;k=1;c8=k<inl?pna_u8_clen((unsigned char)in[k]):1;if(inl>=k+c8+1&&pna_isal(in[k+c8])){fill=in+k;fl=c8;align=in[k+c8];k+=c8+1;}else if(inl>k&&pna_isal(in[k])){align=in[k];k+=1;}if(k<inl&&in[k]=='0'){zero=1;++k;}while(k<inl&&isdigit((unsigned char)in[k])){width=width*10+(in[k]-'0');++k;}if(k<inl&&in[k]=='.'){size_t st=++k;prec=0;while(k<inl&&isdigit((unsigned char)in[k])){prec=prec*10+(in[k]-'0');++k;}if(k==st)ok=0;}if(k!=inl)ok=0;}if(!ok){pna_buf_put(&out,\"{\",1);pna_buf_put(&out,in,inl);pna_buf_put(&out,\"}\",1);continue;}pna_fmt_one(&out,ai<n?args[ai]:pna_undef(),fill,fl,align,zero,width,prec);if(ai<n)args[ai]=pna_undef();++ai;}for(i=0;i<n;++i)pna_drop(args[i]);free(args);pna_drop(f);return pna_buf_take(&out);}");
+    out.push('\n');
+
+    let funcs: Vec<&Func> = m.funcs.iter().collect();
+    for f in &funcs {
+        writeln!(out, "{};", signature(f)).unwrap();
+    }
+    for f in &funcs {
+        let mut cg = CgState::new(opts, f, &m.files, true);
+        cg.line_directive(&mut out, f.span);
+        writeln!(out, "{}{{", signature(f)).unwrap();
+        out.push_str("  Env __env; Env*env=&__env; pna_env_init(env,caller);\n");
+        for p in &f.slots[..f.params] {
+            writeln!(out, "  pna_set(env,\"{}\",p_{});", p, p).unwrap();
+        }
+        if opts.strict {
+            // errors after the call returns are the caller's
+            out.push_str("  int __line=pna_line,__file=pna_file;\n");
+        }
+        emit_block(&mut out, &f.body, 1, &mut cg);
+        line_reset(&mut out, opts);
+        writeln!(
+            out,
+            "  {}pna_env_free(env);\n  return pna_undef();\n}}",
+            cg.leave()
+        )
+        .unwrap();
+    }
+
+    out.push_str("int main(void){\n");
+    if opts.strict {
+        if let Some(f) = &opts.file {
+            let names: Vec<String> = std::iter::once(f)
+                .chain(&m.files)
+                .map(|f| format!("\"{}\"", c_esc(f)))
+                .collect();
+            writeln!(
+                out,
+                "  static const char*const pna_files[]={{{}}};",
+                names.join(",")
+            )
+            .unwrap();
+            out.push_str("  pna_where=pna_files;\n");
+        }
+    }
+    out.push_str("  Env __env; Env*env=&__env; pna_env_init(env,0);\n");
+    let mut cg = CgState::new(opts, &m.main, &m.files, false);
+    emit_block(&mut out, &m.main.body, 1, &mut cg);
+    line_reset(&mut out, opts);
+    out.push_str("  pna_env_free(env);\n  return 0;\n}\n");
+    Ok(out)
+}
+
+/// `pna_abs`, `pna_pow`, ...: one runtime helper per math builtin, which
+/// `pna_math` checks for an undefined result (and `**` shares `pna_pow`).
+fn emit_math(out: &mut String) {
+    for &m in math::ALL {
+        let call = match m {
+            Math::Abs => "fabs(a)",
+            Math::Min => "fmin(a,b)",
+            Math::Max => "fmax(a,b)",
+            Math::Sqrt => "sqrt(a)",
+            Math::Pow => "pow(a,b)",
+            Math::Floor => "floor(a)",
+            Math::Ceil => "ceil(a)",
+            Math::Round => "round(a)",
+            Math::Gcd => "pna_igcd((long long)a,(long long)b)",
+            Math::Sin => "sin(a)",
+            Math::Cos => "cos(a)",
+            Math::Tan => "tan(a)",
+            Math::Asin => "asin(a)",
+            Math::Acos => "acos(a)",
+            Math::Atan => "atan(a)",
+            Math::Atan2 => "atan2(a,b)",
+        };
+        match m.params().len() {
+            1 => write!(
+                out,
+                "static inline Value pna_{0}(Value x){{double a=pna_as_num(x);return pna_math(\"{0}\",{1},1,a,0);}}",
+                m.name(),
+                call
+            ),
+            _ => write!(
+                out,
+                "static inline Value pna_{0}(Value x,Value y){{double a=pna_as_num(x),b=pna_as_num(y);return pna_math(\"{0}\",{1},2,a,b);}}",
+                m.name(),
+                call
+            ),
+        }
+        .unwrap();
+    }
+}
+
+fn signature(f: &Func) -> String {
+    let mut s = format!("static Value fn_{}(Env*caller", f.name);
+    for p in &f.slots[..f.params] {
+        write!(s, ",Value p_{}", p).unwrap();
+    }
+    s.push(')');
+    s
+}
+
+/// `esc` plus `?`, so that `??=`-style trigraphs stay literal under `-std=c99`.
+fn c_esc(s: &str) -> String {
+    esc(s).replace('?', "\\?")
+}
+
+/// An owned `Value` holding the string literal `s` (which may contain NULs).
+fn str_value(s: &str) -> String {
+    format!("pna_str(\"{}\",{})", c_esc(s), s.len())
+}
+
+struct CgState<'f> {
+    f: &'f Func,
+    /// Names of the imported files (`Module::files`).
+    files: &'f [String],
+    strict: bool,
+    /// Main source file for `#line` directives, when enabled.
+    line_file: Option<String>,
+    /// Inside a function body, where `return` hands back a `Value`.
+    in_func: bool,
+    /// Rendered temporaries, waiting for their single use.
+    temps: HashMap<Temp, String>,
+    /// Enclosing loops, innermost last, with whether they have `ended`.
+    loops: Vec<(usize, bool)>,
+}
+
+impl<'f> CgState<'f> {
+    fn new(opts: &CppOptions, f: &'f Func, files: &'f [String], in_func: bool) -> Self {
+        let line_file = if opts.line_directives {
+            Some(opts.file.clone().unwrap_or_else(|| "input.pna".into()))
+        } else {
+            None
+        };
+        CgState {
+            f,
+            files,
+            strict: opts.strict,
+            line_file,
+            in_func,
+            temps: HashMap::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    /// Puts back the caller's `pna_line` and `pna_file` before a function
+    /// returns, in strict mode.
+    fn leave(&self) -> &'static str {
+        if self.strict && self.in_func {
+            "pna_line=__line; pna_file=__file; "
+        } else {
+            ""
+        }
+    }
+
+    /// `#line` for code from `sp`, in the file it comes from.
+    fn line_directive(&self, out: &mut String, sp: Span) {
+        if let Some(main) = &self.line_file {
+            let file = match sp.file.checked_sub(1) {
+                Some(i) => &self.files[i],
+                None => main,
+            };
+            writeln!(out, "#line {} \"{}\"", sp.line, esc(file)).unwrap();
+        }
+    }
+
+    fn operand(&mut self, o: &Operand) -> String {
+        match o {
+            Operand::Temp(t) => self.temps.remove(t).expect("temporaries are used once"),
+            Operand::Const(Const::Num(n)) => format!("pna_num({:?})", n),
+            Operand::Const(Const::Str(s)) => str_value(s),
+            Operand::Const(Const::Bool(b)) => format!("pna_bool({})", *b as i32),
+            Operand::Const(Const::Undef) => "pna_undef()".to_string(),
+        }
+    }
+
+    /// `n,a,b,...` for the runtime's variadic functions.
+    fn list(&mut self, xs: &[Operand]) -> String {
+        let mut s = xs.len().to_string();
+        for x in xs {
+            s.push(',');
+            s.push_str(&self.operand(x));
+        }
+        s
+    }
+
+    fn rv(&mut self, rv: &Rv) -> String {
+        match rv {
+            Rv::Local(s) => format!("pna_get(env,\"{}\")", self.f.slots[*s]),
+            Rv::Name(n) => format!("pna_get(env,\"{}\")", n),
+            Rv::Not(a) => format!("pna_not({})", self.operand(a)),
+            Rv::Bin(op, a, b) => {
+                let a = self.operand(a);
+                format!("pna_{}({},{})", op.name(), a, self.operand(b))
+            }
+            Rv::Member(a, k) => format!("pna_get_prop({},\"{}\")", self.operand(a), c_esc(k)),
+            Rv::Index(a, b) => {
+                let a = self.operand(a);
+                format!("pna_index({},{})", a, self.operand(b))
+            }
+            Rv::NewObj => "pna_make_obj()".to_string(),
+            Rv::SetField(o, k, v) => {
+                let o = self.operand(o);
+                format!("pna_with({},\"{}\",{})", o, c_esc(k), self.operand(v))
+            }
+            Rv::Concat(parts) => format!("pna_interp({})", self.list(parts)),
+            Rv::Format(xs) => {
+                let f = self.operand(&xs[0]);
+                format!("pna_format({},{})", f, self.list(&xs[1..]))
+            }
+            Rv::Len(a) => format!("pna_len({})", self.operand(a)),
+            Rv::Has(a, k) => {
+                let a = self.operand(a);
+                format!("pna_has({},{})", a, self.operand(k))
+            }
+            Rv::Remove(a, k) => {
+                let a = self.operand(a);
+                format!("pna_remove({},{})", a, self.operand(k))
+            }
+            Rv::Keys(a) => format!("pna_keys({})", self.operand(a)),
+            Rv::Values(a) => format!("pna_values({})", self.operand(a)),
+            Rv::Math(m, xs) => {
+                let xs: Vec<String> = xs.iter().map(|x| self.operand(x)).collect();
+                format!("pna_{}({})", m.name(), xs.join(","))
+            }
+            Rv::Call(name, xs) => {
+                let mut s = format!("fn_{}(env", name);
+                for x in xs {
+                    s.push(',');
+                    s.push_str(&self.operand(x));
+                }
+                s.push(')');
+                s
+            }
+            Rv::Input(p) => format!("pna_input(\"{}\")", c_esc(p)),
+            Rv::Extern(..) => unreachable!("native modules are rejected up front"),
+        }
+    }
+}
+
+fn emit_block(out: &mut String, b: &[Inst], lvl: usize, cg: &mut CgState) {
+    for i in b {
+        emit_inst(out, i, lvl, cg);
+    }
+}
+
+fn emit_inst(out: &mut String, i: &Inst, lvl: usize, cg: &mut CgState) {
+    let ind = indent(lvl);
+    match i {
+        Inst::Line(sp) => {
+            cg.line_directive(out, *sp);
+            if cg.strict {
+                writeln!(out, "{}{};", ind, mark(cg.files, *sp)).unwrap();
+            }
+        }
+        Inst::Let(t, rv) => {
+            let x = cg.rv(rv);
+            cg.temps.insert(*t, x);
+        }
+        Inst::Store(s, v) => {
+            let v = cg.operand(v);
+            writeln!(out, "{}pna_set(env,\"{}\",{});", ind, cg.f.slots[*s], v).unwrap();
+        }
+        Inst::SetField(s, k, v) => {
+            let v = cg.operand(v);
+            let x = &cg.f.slots[*s];
+            writeln!(
+                out,
+                "{}pna_set(env,\"{}\",pna_with(pna_get_base(env,\"{}\"),\"{}\",{}));",
+                ind,
+                x,
+                x,
+                c_esc(k),
+                v
+            )
+            .unwrap();
+        }
+        Inst::SetIndex(s, k, v) => {
+            let k = cg.operand(k);
+            let v = cg.operand(v);
+            let x = &cg.f.slots[*s];
+            writeln!(
+                out,
+                "{}pna_set(env,\"{}\",pna_with_key(pna_get_base(env,\"{}\"),{},{}));",
+                ind, x, x, k, v
+            )
+            .unwrap();
+        }
+        Inst::Out { args, newline } => match args.as_slice() {
+            [x] if *newline => {
+                let x = cg.operand(x);
+                writeln!(out, "{}pna_log({});", ind, x).unwrap();
+            }
+            _ => {
+                let xs = cg.list(args);
+                writeln!(out, "{}pna_out({},{});", ind, *newline as i32, xs).unwrap();
+            }
+        },
+        Inst::If {
+            cond,
+            then_blk,
+            else_blk,
+        } => {
+            let c = cg.operand(cond);
+            writeln!(out, "{}if(pna_truthy({})){{", ind, c).unwrap();
+            emit_block(out, then_blk, lvl + 1, cg);
+            if !else_blk.is_empty() {
+                writeln!(out, "{}}} else {{", ind).unwrap();
+                emit_block(out, else_blk, lvl + 1, cg);
+            }
+            writeln!(out, "{}}}", ind).unwrap();
+        }
+        Inst::Loop {
+            id,
+            header,
+            cond,
+            body,
+            ended,
+        } => {
+            // The condition is re-evaluated after the body, so in strict mode
+            // it resets `pna_line` to the loop header itself.
+            let mut line = None;
+            for h in header {
+                match h {
+                    Inst::Line(sp) => line = Some(*sp),
+                    Inst::Let(t, rv) => {
+                        let x = cg.rv(rv);
+                        cg.temps.insert(*t, x);
+                    }
+                    _ => unreachable!("loop headers only compute the condition"),
+                }
+            }
+            let mut c = cg.operand(cond);
+            if let (Some(sp), true) = (line, cg.strict) {
+                c = format!("({}, {})", mark(cg.files, sp), c);
+            }
+            if ended.is_empty() {
+                writeln!(out, "{}while(pna_truthy({})){{", ind, c).unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "{}{{ int __broke_{}=0; while(pna_truthy({})){{",
+                    ind, id, c
+                )
+                .unwrap();
+            }
+            cg.loops.push((*id, !ended.is_empty()));
+            emit_block(out, body, lvl + 1, cg);
+            cg.loops.pop();
+            if ended.is_empty() {
+                writeln!(out, "{}}}", ind).unwrap();
+            } else {
+                writeln!(out, "{}}} if(!__broke_{}){{", ind, id).unwrap();
+                emit_block(out, ended, lvl + 1, cg);
+                writeln!(out, "{}}} }}", ind).unwrap();
+            }
+        }
+        Inst::Break(id) => {
+            if cg.loops.contains(&(*id, true)) {
+                writeln!(out, "{}__broke_{}=1;", ind, id).unwrap();
+            }
+            writeln!(out, "{}break;", ind).unwrap();
+        }
+        Inst::Continue(_) => writeln!(out, "{}continue;", ind).unwrap(),
+        Inst::Switch { .. } => unreachable!("switches are expanded first"),
+        Inst::Cpp(_) => unreachable!("native modules are rejected up front"),
+        Inst::Return(v) => {
+            let v = cg.operand(v);
+            if cg.in_func {
+                writeln!(
+                    out,
+                    "{}{{ Value __r={}; {}pna_env_free(env); return __r; }}",
+                    ind,
+                    v,
+                    cg.leave()
+                )
+                .unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "{}{{ pna_drop({}); pna_env_free(env); return 0; }}",
+                    ind, v
+                )
+                .unwrap();
+            }
+        }
+    }
+}
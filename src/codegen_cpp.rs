@@ -1,281 +1,778 @@
-use crate::ast::*;
+use crate::ast::{Span, Ty};
+use crate::bundle;
+use crate::diag::Diagnostic;
+use crate::ir::*;
+use crate::math::{self, Math};
+use std::collections::{BTreeSet, HashMap};
 use std::fmt::Write;
 
-pub fn compile_to_cpp(p: &Program) -> Result<String, String> {
+#[derive(Debug, Clone, Default)]
+pub struct CppOptions {
+    /// Raise `pna_error` on divide-by-zero, bad conversions and undefined
+    /// names instead of falling back to silent defaults.
+    pub strict: bool,
+    /// Source file name used in runtime error messages and `#line` directives.
+    pub file: Option<String>,
+    /// Emit `#line N "file.pna"` before each statement so compiler errors,
+    /// sanitizers and debuggers point at the PNA source.
+    pub line_directives: bool,
+    /// Name of the generated file; when known, `#line` directives switch back
+    /// to it after each PNA-mapped region.
+    pub cpp_file: Option<String>,
+    /// Also emit a C++ struct per `type` declaration (in `pna_types`), with
+    /// conversions from and to `Value`, for host code using the program.
+    pub structs: bool,
+    /// Headers to `#include "..."` after the runtime, for `extern`
+    /// functions and `cpp` blocks.
+    pub includes: Vec<String>,
+}
+
+pub fn compile_to_cpp(m: &Module, opts: &CppOptions) -> Result<String, Diagnostic> {
     let mut out = String::new();
 
     out.push_str("#include <bits/stdc++.h>\n");
-    out.push_str("struct Value{std::variant<std::monostate,double,std::string,bool,std::map<std::string,Value>> v;Value():v(std::monostate{}){}Value(double d):v(d){}Value(const char*s):v(std::string(s)){}Value(const std::string&s):v(s){}Value(bool b):v(b){}Value(std::map<std::string,Value> o):v(std::move(o)){}template<class T,class=std::enable_if_t<std::is_integral_v<T>&& !std::is_same_v<T,bool>>>Value(T x):v(static_cast<double>(x)){} };");
-    out.push_str("struct Env{std::unordered_map<std::string,Value> vars;Env* parent=nullptr;};");
-    out.push_str("inline bool is_num(const Value& x){return std::holds_alternative<double>(x.v);}inline bool is_str(const Value& x){return std::holds_alternative<std::string>(x.v);}inline bool is_bool(const Value& x){return std::holds_alternative<bool>(x.v);}inline bool is_obj(const Value& x){return std::holds_alternative<std::map<std::string,Value>>(x.v);}");
-    out.push_str("inline std::string as_str(const Value& x){if(is_str(x))return std::get<std::string>(x.v);if(is_num(x)){double d=std::get<double>(x.v);double rd=std::round(d);if(std::fabs(d-rd)<1e-9){std::ostringstream oss;oss.setf(std::ios::fixed,std::ios::floatfield);oss<<std::setprecision(0)<<rd;return oss.str();}std::ostringstream oss;oss.setf(std::ios::fixed,std::ios::floatfield);oss<<std::setprecision(12)<<d;auto s=oss.str();while(!s.empty()&&s.back()=='0')s.pop_back();if(!s.empty()&&s.back()=='.')s.pop_back();return s;}if(is_bool(x))return std::get<bool>(x.v)?\"true\":\"false\";if(is_obj(x)){std::string s=\"{\";bool first=true;for(auto&kv:std::get<std::map<std::string,Value>>(x.v)){if(!first)s+=\", \";first=false;s+=kv.first+\":\"+as_str(kv.second);}s+=\"}\";return s;}return \"\";}");
-    out.push_str("inline double as_num(const Value& v){if(is_num(v))return std::get<double>(v.v);if(is_bool(v))return std::get<bool>(v.v)?1.0:0.0;if(is_str(v)){const auto&s=std::get<std::string>(v.v);char*end=nullptr;double d=std::strtod(s.c_str(),&end);if(end!=s.c_str()&&*end=='\\0')return d;return 0.0;}return 0.0;}");
-    out.push_str("inline bool truthy(const Value& x){if(is_bool(x))return std::get<bool>(x.v);if(is_num(x))return std::get<double>(x.v)!=0;if(is_str(x))return !std::get<std::string>(x.v).empty();if(is_obj(x))return !std::get<std::map<std::string,Value>>(x.v).empty();return false;}");
-    out.push_str("inline Value pna_get(Env&e,const std::string&k){auto it=e.vars.find(k);if(it!=e.vars.end())return it->second;if(e.parent)return pna_get(*e.parent,k);return Value();}inline void pna_set(Env&e,const std::string&k,const Value&v){e.vars[k]=v;}");
-    out.push_str("inline Value pna_make_obj(){return Value(std::map<std::string,Value>{});}inline void pna_obj_set(Value&o,const std::string&k,const Value&v){if(!is_obj(o))o=std::map<std::string,Value>{};std::get<std::map<std::string,Value>>(o.v)[k]=v;}");
-    out.push_str("inline Value pna_get_prop(const Value&o,const std::string&k){if(!is_obj(o))return Value();auto&m=std::get<std::map<std::string,Value>>(const_cast<Value&>(o).v);auto it=m.find(k);if(it!=m.end())return it->second;return Value();}inline void pna_set_prop(Env&e,const std::string&base,const std::string&key,const Value&v){Value b=pna_get(e,base);if(!is_obj(b))b=std::map<std::string,Value>{};auto m=std::get<std::map<std::string,Value>>(b.v);m[key]=v;b=Value(m);pna_set(e,base,b);}");
+    write!(out, "inline const bool pna_strict={};", opts.strict).unwrap();
+    out.push_str("inline int pna_line=0,pna_file=0;struct pna_error:std::runtime_error{int line,file;pna_error(const std::string&m,int l,int f):std::runtime_error(m),line(l),file(f){}};[[noreturn]] inline void pna_fail(const std::string&m){throw pna_error(m,pna_line,pna_file);}struct pna_at{int line=pna_line,file=pna_file;~pna_at(){pna_line=line;pna_file=file;}};");
+    out.push_str("struct Value;using Obj=std::unordered_map<std::string,Value>;struct Value{std::variant<std::monostate,double,std::string,bool,std::shared_ptr<Obj>> v;Value():v(std::monostate{}){}Value(double d):v(d){}Value(const char*s):v(std::string(s)){}Value(const std::string&s):v(s){}Value(bool b):v(b){}Value(Obj o):v(std::make_shared<Obj>(std::move(o))){}template<class T,class=std::enable_if_t<std::is_integral_v<T>&& !std::is_same_v<T,bool>>>Value(T x):v(static_cast<double>(x)){} };");
+    out.push_str(
+        "struct Env{const Env*parent;const int*ids;int n;const Value*slots;const bool*set;};",
+    );
+    out.push_str("inline bool is_num(const Value& x){return std::holds_alternative<double>(x.v);}inline bool is_str(const Value& x){return std::holds_alternative<std::string>(x.v);}inline bool is_bool(const Value& x){return std::holds_alternative<bool>(x.v);}inline bool is_obj(const Value& x){return std::holds_alternative<std::shared_ptr<Obj>>(x.v);}inline const Obj& pna_obj(const Value& x){return *std::get<std::shared_ptr<Obj>>(x.v);}inline Obj& pna_obj_mut(Value& x){auto&p=std::get<std::shared_ptr<Obj>>(x.v);if(p.use_count()>1)p=std::make_shared<Obj>(*p);return *p;}");
+    out.push_str("inline std::vector<const Obj::value_type*> pna_sorted(const Obj&m){std::vector<const Obj::value_type*> r;r.reserve(m.size());for(auto&kv:m)r.push_back(&kv);std::sort(r.begin(),r.end(),[](auto a,auto b){return a->first<b->first;});return r;}");
+    out.push_str("inline std::string as_str(const Value& x){if(is_str(x))return std::get<std::string>(x.v);if(is_num(x)){double d=std::get<double>(x.v);double rd=std::round(d);if(std::fabs(d-rd)<1e-9){std::ostringstream oss;oss.setf(std::ios::fixed,std::ios::floatfield);oss<<std::setprecision(0)<<rd;return oss.str();}std::ostringstream oss;oss.setf(std::ios::fixed,std::ios::floatfield);oss<<std::setprecision(12)<<d;auto s=oss.str();while(!s.empty()&&s.back()=='0')s.pop_back();if(!s.empty()&&s.back()=='.')s.pop_back();return s;}if(is_bool(x))return std::get<bool>(x.v)?\"true\":\"false\";if(is_obj(x)){std::string s=\"{\";bool first=true;for(auto*kv:pna_sorted(pna_obj(x))){if(!first)s+=\", \";first=false;s+=kv->first+\":\"+as_str(kv->second);}s+=\"}\";return s;}return \"\";}");
+    out.push_str("inline double as_num(const Value& v){if(is_num(v))return std::get<double>(v.v);if(is_bool(v))return std::get<bool>(v.v)?1.0:0.0;if(is_str(v)){const auto&s=std::get<std::string>(v.v);char*end=nullptr;double d=std::strtod(s.c_str(),&end);if(end!=s.c_str()&&*end=='\\0')return d;if(pna_strict)pna_fail(\"cannot convert \\\"\"+s+\"\\\" to a number\");return 0.0;}if(pna_strict)pna_fail(is_obj(v)?\"object used as a number\":\"undefined value used as a number\");return 0.0;}");
+    out.push_str("inline bool truthy(const Value& x){if(is_bool(x))return std::get<bool>(x.v);if(is_num(x))return std::get<double>(x.v)!=0;if(is_str(x))return !std::get<std::string>(x.v).empty();if(is_obj(x))return !pna_obj(x).empty();return false;}");
+    out.push_str("inline Value pna_lookup(const Env*e,int id,const char*k,bool req){for(;e;e=e->parent)for(int i=0;i<e->n;++i)if(e->ids[i]==id&&e->set[i])return e->slots[i];if(req&&pna_strict)pna_fail(std::string(\"undefined variable `\")+k+\"`\");return Value();}");
+    out.push_str("inline Value pna_make_obj(){return Value(Obj{});}inline void pna_obj_set(Value&o,const std::string&k,Value v){if(!is_obj(o))o=Obj{};pna_obj_mut(o)[k]=std::move(v);}inline Value pna_with(Value o,const std::string&k,Value v){pna_obj_set(o,k,std::move(v));return o;}");
+    out.push_str("inline Value pna_get_prop(const Value&o,const std::string&k){if(!is_obj(o)){if(pna_strict)pna_fail(\"cannot read property `\"+k+\"` of a non-object\");return Value();}auto&m=pna_obj(o);auto it=m.find(k);if(it!=m.end())return it->second;if(pna_strict)pna_fail(\"undefined property `\"+k+\"`\");return Value();}");
     out.push_str("inline Value pna_input(const char*prompt){if(prompt&&prompt[0]!='\\0'){std::cout<<prompt;std::cout.flush();}std::string s;if(!(std::cin>>s))s=\"\";char*end=nullptr;double d=std::strtod(s.c_str(),&end);if(end!=s.c_str()&&*end=='\\0')return Value(d);return Value(s);}inline void pna_log(const Value&v){std::cout<<as_str(v)<<'\\n';}");
-    out.push_str("inline Value pna_add(const Value&a,const Value&b){if(is_str(a)||is_str(b))return Value(as_str(a)+as_str(b));return Value(as_num(a)+as_num(b));}inline Value pna_sub(const Value&a,const Value&b){return Value(as_num(a)-as_num(b));}inline Value pna_mul(const Value&a,const Value&b){if(is_str(a)&&is_num(b)){const std::string&s=std::get<std::string>(a.v);long long rll=(long long)std::llround(as_num(b));int r=(rll<0)?0:(int)rll;std::string out;out.reserve(s.size()*(size_t)std::max(r,0));for(char c:s){for(int i=0;i<r;++i)out.push_back(c);}return Value(out);}if(is_num(a)&&is_str(b))return pna_mul(b,a);return Value(as_num(a)*as_num(b));}");
-    out.push_str("inline Value pna_div(const Value&a,const Value&b){double r=as_num(b);return Value(r==0.0?0.0:as_num(a)/r);}inline Value pna_mod(const Value&a,const Value&b){double x=as_num(a),y=as_num(b);if(y==0.0)return Value(0.0);double q=std::floor((x/y)+1e-12);double r=x-q*y;if(std::fabs(r)<1e-12)r=0.0;return Value(r);}inline Value pna_eq(const Value&a,const Value&b){if(is_num(a)&&is_num(b))return Value(as_num(a)==as_num(b));return Value(as_str(a)==as_str(b));}inline Value pna_neq(const Value&a,const Value&b){if(is_num(a)&&is_num(b))return Value(as_num(a)!=as_num(b));return Value(as_str(a)!=as_str(b));}inline Value pna_lt(const Value&a,const Value&b){return Value(as_num(a)<as_num(b));}inline Value pna_le(const Value&a,const Value&b){return Value(as_num(a)<=as_num(b));}inline Value pna_gt(const Value&a,const Value&b){return Value(as_num(a)>as_num(b));}inline Value pna_ge(const Value&a,const Value&b){return Value(as_num(a)>=as_num(b));}inline Value pna_and(const Value&a,const Value&b){return Value(truthy(a)&&truthy(b));}inline Value pna_or(const Value&a,const Value&b){return Value(truthy(a)||truthy(b));}inline Value pna_not(const Value&a){return Value(!truthy(a));}inline bool pna_truthy(const Value&v){return truthy(v);}");
+    out.push_str("inline size_t pna_u8_clen(unsigned char c){return c<0x80?1:(c>>5)==0x6?2:(c>>4)==0xE?3:(c>>3)==0x1E?4:1;}inline size_t pna_u8_len(const std::string&s){size_t n=0;for(size_t i=0;i<s.size();i+=pna_u8_clen((unsigned char)s[i]))++n;return n;}inline size_t pna_u8_off(const std::string&s,size_t cp){size_t i=0;while(cp>0&&i<s.size()){i+=pna_u8_clen((unsigned char)s[i]);--cp;}return std::min(i,s.size());}");
+    out.push_str("inline Value pna_len(const Value&v){if(is_str(v))return Value((double)pna_u8_len(std::get<std::string>(v.v)));if(is_obj(v))return Value((double)pna_obj(v).size());return Value(0.0);}inline Value pna_index(const Value&b,const Value&i){if(is_obj(b))return pna_get_prop(b,as_str(i));if(!is_str(b))return Value();const std::string&s=std::get<std::string>(b.v);double d=as_num(i);if(d<0||d!=std::floor(d)||d>=(double)pna_u8_len(s)){if(pna_strict)pna_fail(\"string index \"+as_str(i)+\" out of range\");return Value();}size_t at=pna_u8_off(s,(size_t)d);return Value(s.substr(at,pna_u8_clen((unsigned char)s[at])));}");
+    out.push_str("inline Value pna_has(const Value&o,const Value&k){return Value(is_obj(o)&&pna_obj(o).count(as_str(k))>0);}inline Value pna_remove(Value o,const Value&k){if(is_obj(o)){std::string key=as_str(k);if(pna_obj(o).count(key))pna_obj_mut(o).erase(key);}return o;}inline Value pna_keys(const Value&o){Obj r;if(is_obj(o))for(auto*kv:pna_sorted(pna_obj(o)))r.emplace(std::to_string(r.size()),Value(kv->first));return Value(std::move(r));}inline Value pna_values(const Value&o){Obj r;if(is_obj(o))for(auto*kv:pna_sorted(pna_obj(o)))r.emplace(std::to_string(r.size()),kv->second);return Value(std::move(r));}");
+    out.push_str("inline Value pna_add(const Value&a,const Value&b){if(is_str(a)||is_str(b))return Value(as_str(a)+as_str(b));return Value(as_num(a)+as_num(b));}inline Value pna_sub(const Value&a,const Value&b){return Value(as_num(a)-as_num(b));}inline Value pna_mul(const Value&a,const Value&b){if(is_str(a)&&is_num(b)){const std::string&s=std::get<std::string>(a.v);long long rll=(long long)std::llround(as_num(b));int r=(rll<0)?0:(int)rll;std::string out;out.reserve(s.size()*(size_t)std::max(r,0));for(size_t i=0;i<s.size();){size_t cl=pna_u8_clen((unsigned char)s[i]);for(int k=0;k<r;++k)out.append(s,i,cl);i+=cl;}return Value(out);}if(is_num(a)&&is_str(b))return pna_mul(b,a);return Value(as_num(a)*as_num(b));}");
+    out.push_str("inline Value pna_div(const Value&a,const Value&b){double r=as_num(b);if(r==0.0&&pna_strict)pna_fail(\"division by zero\");return Value(r==0.0?0.0:as_num(a)/r);}inline Value pna_mod(const Value&a,const Value&b){double x=as_num(a),y=as_num(b);if(y==0.0){if(pna_strict)pna_fail(\"modulo by zero\");return Value(0.0);}double q=std::floor((x/y)+1e-12);double r=x-q*y;if(std::fabs(r)<1e-12)r=0.0;return Value(r);}inline Value pna_eq(const Value&a,const Value&b){if(is_num(a)&&is_num(b))return Value(as_num(a)==as_num(b));return Value(as_str(a)==as_str(b));}inline Value pna_neq(const Value&a,const Value&b){if(is_num(a)&&is_num(b))return Value(as_num(a)!=as_num(b));return Value(as_str(a)!=as_str(b));}inline Value pna_lt(const Value&a,const Value&b){return Value(as_num(a)<as_num(b));}inline Value pna_le(const Value&a,const Value&b){return Value(as_num(a)<=as_num(b));}inline Value pna_gt(const Value&a,const Value&b){return Value(as_num(a)>as_num(b));}inline Value pna_ge(const Value&a,const Value&b){return Value(as_num(a)>=as_num(b));}inline Value pna_and(const Value&a,const Value&b){return Value(truthy(a)&&truthy(b));}inline Value pna_or(const Value&a,const Value&b){return Value(truthy(a)||truthy(b));}inline Value pna_not(const Value&a){return Value(!truthy(a));}inline bool pna_truthy(const Value&v){return truthy(v);}");
+    out.push_str("inline Value pna_math(const char*n,double r,std::initializer_list<double>xs){if(!std::isnan(r))return Value(r+0.0);if(pna_strict){std::string m=std::string(n)+\"(\";const char*sep=\"\";for(double x:xs){m+=sep+as_str(Value(x));sep=\", \";}pna_fail(m+\") is undefined\");}return Value(0.0);}");
+    emit_math(&mut out);
+    out.push_str("inline void pna_cat(std::string&s,const char*x){s+=x;}inline void pna_cat(std::string&s,const Value&v){if(is_str(v))s+=std::get<std::string>(v.v);else s+=as_str(v);}template<class...A>inline Value pna_interp(const A&...a){std::string s;(pna_cat(s,a),...);return Value(std::move(s));}");
+    out.push_str("inline void pna_out(const std::vector<Value>&xs,bool nl){for(size_t i=0;i<xs.size();++i){if(i)std::cout<<' ';std::cout<<as_str(xs[i]);}if(nl)std::cout<<'\\n';}");
+    out.push_str("inline std::string pna_fmt_one(const Value&v,const std::string&fill,char align,bool zero,int width,int prec){std::string s;if(prec>=0&&is_num(v)){std::ostringstream oss;oss.setf(std::ios::fixed,std::ios::floatfield);oss<<std::setprecision(prec)<<std::get<double>(v.v);s=oss.str();}else{s=as_str(v);if(prec>=0)s.resize(pna_u8_off(s,(size_t)prec));}int pad=width-(int)pna_u8_len(s);if(pad<=0)return s;if(zero&&is_num(v)){size_t at=(!s.empty()&&s[0]=='-')?1:0;s.insert(at,(size_t)pad,'0');return s;}auto rep=[&](int n){std::string r;for(int k=0;k<n;++k)r+=fill;return r;};char a=align?align:(is_num(v)?'>':'<');if(a=='<')return s+rep(pad);if(a=='>')return rep(pad)+s;return rep(pad/2)+s+rep(pad-pad/2);}");
+    out.push_str("inline Value pna_format(const Value&f,const std::vector<Value>&args){const std::string fs=as_str(f);std::string out;size_t ai=0,i=0,n=fs.size();auto isal=[](char c){return c=='<'||c=='>'||c=='^';};while(i<n){char c=fs[i];if((c=='{'||c=='}')&&i+1<n&&fs[i+1]==c){out.push_back(c);i+=2;continue;}if(c!='{'){out.push_back(c);++i;continue;}size_t close=fs.find('}',i+1);if(close==std::string::npos){out+=fs.substr(i);break;}std::string in=fs.substr(i+1,close-i-1);i=close+1;std::string fill=\" \";char align=0;bool zero=false;int width=0,prec=-1;size_t k=0;bool ok=in.empty()||in[0]==':';if(!in.empty()&&ok){k=1;size_t cl=k<in.size()?pna_u8_clen((unsigned char)in[k]):1;if(in.size()>=k+cl+1&&isal(in[k+cl])){fill=in.substr(k,cl);align=in[k+cl];k+=cl+1;}else if(in.size()>k&&isal(in[k])){align=in[k];k+=1;}if(k<in.size()&&in[k]=='0'){zero=true;++k;}while(k<in.size()&&std::isdigit((unsigned char)in[k])){width=width*10+(in[k]-'0');++k;}if(k<in.size()&&in[k]=='.'){++k;size_t st=k;prec=0;while(k<in.size()&&std::isdigit((unsigned char)in[k])){prec=prec*10+(in[k]-'0');++k;}if(k==st)ok=false;}if(k!=in.size())ok=false;}if(!ok){out+='{'+in+'}';continue;}Value v=ai<args.size()?args[ai]:Value();++ai;out+=pna_fmt_one(v,fill,align,zero,width,prec);}return Value(out);}");
+    out.push('\n');
 
-    for it in &p.items {
-        if let Item::Func { .. } = it {
-            emit_func(&mut out, it)?;
-        }
+    // Every variable name gets an id; frames list the ids of their slots so
+    // reads that fall through to the callers can find them without hashing.
+    let mut names = BTreeSet::new();
+    for f in m.funcs.iter().chain([&m.main]) {
+        names.extend(f.slots.iter().cloned());
+        walk(&f.body, &mut |i| {
+            if let Inst::Let(_, Rv::Name(n)) = i {
+                names.insert(n.clone());
+            }
+        });
     }
+    let ids: HashMap<String, usize> = names.into_iter().zip(0..).collect();
 
-    out.push_str("namespace pna_prog {\nint pna_main(){ Env env;\n");
-    let mut cg = CgState::default();
-    for it in &p.items {
-        if let Item::Stmt(s) = it {
-            emit_stmt(&mut out, s, "env", 1, &mut cg);
+    // marked so that `bundle` keeps all of it
+    if !opts.includes.is_empty() {
+        writeln!(out, "{}", bundle::USER_BEGIN).unwrap();
+        for h in &opts.includes {
+            writeln!(out, "#include \"{}\"", esc(h)).unwrap();
         }
+        writeln!(out, "{}", bundle::USER_END).unwrap();
+    }
+    if opts.structs {
+        emit_structs(&mut out, &m.types);
+    }
+    if !m.cpp.is_empty() {
+        writeln!(out, "{}", bundle::USER_BEGIN).unwrap();
+        for (code, _) in &m.cpp {
+            writeln!(out, "{}", code.trim()).unwrap();
+        }
+        writeln!(out, "{}", bundle::USER_END).unwrap();
+    }
+    for f in &m.funcs {
+        emit_func(&mut out, f, m, &ids, opts)?;
+    }
+
+    out.push_str("namespace pna_prog {\nint pna_main(){\n");
+    frame(&mut out, &m.main, &ids, "nullptr", "env");
+    let mut cg = CgState::new(opts, &m.main, m, &ids, "env");
+    emit_block(&mut out, &m.main.body, 1, &mut cg);
+    line_reset(&mut out, opts);
+    out.push_str("return 0; }\n} // namespace pna_prog\n");
+    if opts.strict {
+        let (files, at) = match &opts.file {
+            Some(f) if !m.files.is_empty() => (
+                format!(
+                    "static const char*const pna_files[]={{{}}}; ",
+                    file_list(m, f)
+                ),
+                "pna_files[e.file]<<\":\"<<e.line".to_string(),
+            ),
+            Some(f) => (String::new(), format!("\"{}:\"<<e.line", esc(f))),
+            None => (String::new(), "\"line \"<<e.line".to_string()),
+        };
+        writeln!(
+            out,
+            "int main(){{ {}try{{ return pna_prog::pna_main(); }}catch(const pna_error&e){{ std::cout.flush(); std::cerr<<{}<<\": runtime error: \"<<e.what()<<'\\n'; return 1; }} }}",
+            files, at
+        )
+        .unwrap();
+    } else {
+        out.push_str("int main(){ return pna_prog::pna_main(); }\n");
     }
-    out.push_str(
-        "return 0; }\n} // namespace pna_prog\nint main(){ return pna_prog::pna_main(); }\n",
-    );
     Ok(out)
 }
 
-fn esc(s: &str) -> String {
+/// The names of the main file (`main`) and the imported ones, as C string
+/// literals separated by commas; `pna_file` indexes them.
+fn file_list(m: &Module, main: &str) -> String {
+    let names: Vec<String> = std::iter::once(main)
+        .chain(m.files.iter().map(String::as_str))
+        .map(|f| format!("\"{}\"", esc(f)))
+        .collect();
+    names.join(",")
+}
+
+/// The assignment that records `sp` for `--strict` errors: `pna_line`, and
+/// `pna_file` as well once the program imports files.
+pub(crate) fn mark(m_files: &[String], sp: Span) -> String {
+    if m_files.is_empty() {
+        format!("pna_line={}", sp.line)
+    } else {
+        format!("pna_file={},pna_line={}", sp.file, sp.line)
+    }
+}
+
+pub(crate) fn esc(s: &str) -> String {
     s.chars()
         .flat_map(|c| match c {
             '\\' => "\\\\".chars().collect::<Vec<_>>(),
             '"' => "\\\"".chars().collect(),
             '\n' => "\\n".chars().collect(),
+            '\t' => "\\t".chars().collect(),
+            '\r' => "\\r".chars().collect(),
+            x if (x as u32) < 0x20 || x == '\x7f' => {
+                format!("\\{:03o}", x as u32).chars().collect()
+            }
             x => vec![x],
         })
         .collect()
 }
 
-#[derive(Default)]
-struct CgState {
-    while_id: usize,
-    while_stack: Vec<usize>,
+/// C++ expression for a string literal; embedded NULs need an explicit length.
+fn str_lit(s: &str) -> String {
+    if s.contains('\0') {
+        format!("std::string(\"{}\", {})", esc(s), s.len())
+    } else {
+        format!("\"{}\"", esc(s))
+    }
 }
 
-fn indent(n: usize) -> String {
-    "  ".repeat(n)
+/// C++ keywords that are valid PNA names; a trailing `_` avoids them.
+const CPP_KEYWORDS: &[&str] = &[
+    "auto",
+    "bool",
+    "case",
+    "catch",
+    "char",
+    "class",
+    "const",
+    "default",
+    "delete",
+    "do",
+    "enum",
+    "explicit",
+    "extern",
+    "float",
+    "for",
+    "friend",
+    "goto",
+    "if",
+    "inline",
+    "long",
+    "namespace",
+    "new",
+    "operator",
+    "private",
+    "protected",
+    "public",
+    "register",
+    "short",
+    "signed",
+    "sizeof",
+    "static",
+    "struct",
+    "switch",
+    "template",
+    "this",
+    "throw",
+    "try",
+    "typedef",
+    "typename",
+    "union",
+    "unsigned",
+    "using",
+    "virtual",
+    "volatile",
+];
+
+fn cpp_ident(s: &str) -> String {
+    if CPP_KEYWORDS.contains(&s) {
+        format!("{}_", s)
+    } else {
+        s.to_string()
+    }
 }
 
-fn emit_func(out: &mut String, it: &Item) -> Result<(), String> {
-    let (name, params, body) = match it {
-        Item::Func {
-            name, params, body, ..
-        } => (name, params, body),
-        _ => return Ok(()),
-    };
-    write!(out, "static Value fn_{}(Env& env", esc(name)).unwrap();
-    for p in params {
-        write!(out, ", Value {}", p.name).unwrap();
+/// `pna_abs`, `pna_pow`, ...: one runtime helper per math builtin, which
+/// `pna_math` checks for an undefined result (and `**` shares `pna_pow`).
+fn emit_math(out: &mut String) {
+    for &m in math::ALL {
+        let call = match m {
+            Math::Abs => "std::fabs(a)",
+            Math::Min => "std::fmin(a,b)",
+            Math::Max => "std::fmax(a,b)",
+            Math::Sqrt => "std::sqrt(a)",
+            Math::Pow => "std::pow(a,b)",
+            Math::Floor => "std::floor(a)",
+            Math::Ceil => "std::ceil(a)",
+            Math::Round => "std::round(a)",
+            Math::Gcd => "(double)std::gcd((long long)a,(long long)b)",
+            Math::Sin => "std::sin(a)",
+            Math::Cos => "std::cos(a)",
+            Math::Tan => "std::tan(a)",
+            Math::Asin => "std::asin(a)",
+            Math::Acos => "std::acos(a)",
+            Math::Atan => "std::atan(a)",
+            Math::Atan2 => "std::atan2(a,b)",
+        };
+        match m.params().len() {
+            1 => write!(
+                out,
+                "inline Value pna_{0}(const Value&x){{double a=as_num(x);return pna_math(\"{0}\",{1},{{a}});}}",
+                m.name(),
+                call
+            ),
+            _ => write!(
+                out,
+                "inline Value pna_{0}(const Value&x,const Value&y){{double a=as_num(x),b=as_num(y);return pna_math(\"{0}\",{1},{{a,b}});}}",
+                m.name(),
+                call
+            ),
+        }
+        .unwrap();
     }
-    out.push_str("){\n  Env __fenv; __fenv.parent=&env;\n");
-    for p in params {
-        writeln!(out, "  pna_set(__fenv,\"{}\",{});\n", esc(&p.name), p.name).unwrap();
+}
+
+/// One struct per record in `namespace pna_types`, with `from(Value)` and
+/// `to_value()`. Fields a record uses come first, so members are complete
+/// types (`typeck` rejects records that contain themselves).
+fn emit_structs(out: &mut String, types: &[TypeDef]) {
+    fn emit<'t>(out: &mut String, t: &'t TypeDef, types: &'t [TypeDef], seen: &mut Vec<&'t str>) {
+        if seen.contains(&t.name.as_str()) {
+            return;
+        }
+        seen.push(&t.name);
+        for (_, ty) in &t.fields {
+            if let Some(dep) = types.iter().find(|d| *ty == Ty::Named(d.name.clone())) {
+                emit(out, dep, types, seen);
+            }
+        }
+        let name = cpp_ident(&t.name);
+        write!(out, "struct {}{{", name).unwrap();
+        for (k, ty) in &t.fields {
+            let cty = match ty {
+                Ty::Int => "long long".to_string(),
+                Ty::Double => "double".to_string(),
+                Ty::String => "std::string".to_string(),
+                Ty::Named(n) => cpp_ident(n),
+                Ty::Void => "Value".to_string(),
+            };
+            write!(out, "{} {};", cty, cpp_ident(k)).unwrap();
+        }
+        write!(out, "static {} from(const Value&v){{{} r;", name, name).unwrap();
+        for (k, ty) in &t.fields {
+            let get = format!("pna_get_prop(v,\"{}\")", esc(k));
+            let conv = match ty {
+                Ty::Int => format!("(long long)as_num({})", get),
+                Ty::Double => format!("as_num({})", get),
+                Ty::String => format!("as_str({})", get),
+                Ty::Named(n) => format!("{}::from({})", cpp_ident(n), get),
+                Ty::Void => get,
+            };
+            write!(out, "r.{}={};", cpp_ident(k), conv).unwrap();
+        }
+        out.push_str("return r;}Value to_value()const{Value o=pna_make_obj();");
+        for (k, ty) in &t.fields {
+            let v = match ty {
+                Ty::Int => format!("Value((double){})", cpp_ident(k)),
+                Ty::Named(_) => format!("{}.to_value()", cpp_ident(k)),
+                _ => format!("Value({})", cpp_ident(k)),
+            };
+            write!(out, "pna_obj_set(o,\"{}\",{});", esc(k), v).unwrap();
+        }
+        out.push_str("return o;}};\n");
     }
-    let mut cg = CgState::default();
-    for s in body {
-        emit_stmt(out, s, "__fenv", 1, &mut cg);
+    if types.is_empty() {
+        return;
     }
-    out.push_str("  return Value();\n}\n");
-    Ok(())
+    out.push_str("namespace pna_types {\n");
+    let mut seen = Vec::new();
+    for t in types {
+        emit(out, t, types, &mut seen);
+    }
+    out.push_str("} // namespace pna_types\n");
 }
 
-fn emit_stmt(out: &mut String, s: &Stmt, env: &str, lvl: usize, cg: &mut CgState) {
-    match s {
-        Stmt::ObjBlock { name, fields } => {
-            out.push_str(&format!("{}{{ Value __o=pna_make_obj();\n", indent(lvl)));
-            for (k, e) in fields {
-                let expr = emit_expr(e, env);
-                out.push_str(&format!(
-                    "{}pna_obj_set(__o,\"{}\",{});\n",
-                    indent(lvl + 1),
-                    esc(k),
-                    expr
-                ));
-            }
-            out.push_str(&format!(
-                "{}pna_set({},\"{}\",__o); }}\n",
-                indent(lvl + 1),
-                env,
-                esc(name)
-            ));
+struct CgState<'f> {
+    f: &'f Func,
+    externs: &'f [Extern],
+    /// Names of the imported files (`Module::files`).
+    files: &'f [String],
+    ids: &'f HashMap<String, usize>,
+    strict: bool,
+    /// Main source file for `#line` directives, when enabled.
+    line_file: Option<String>,
+    /// This function's frame: `env` at the top level, `__fenv` inside
+    /// functions.
+    env: &'static str,
+    /// Rendered temporaries, waiting for their single use.
+    temps: HashMap<Temp, String>,
+    /// Enclosing loops, innermost last, with whether they have `ended`.
+    loops: Vec<(usize, bool)>,
+    /// Number of enclosing loops at each enclosing `switch`: a `break` there
+    /// would only leave the `switch`, so it jumps past its loop instead.
+    switches: Vec<usize>,
+    /// Loops that need a `__brk_N` label after them.
+    labels: BTreeSet<usize>,
+    /// Slots that are certainly set at this point, so reads skip the
+    /// fall-through to the callers.
+    known: Vec<bool>,
+}
+
+impl<'f> CgState<'f> {
+    fn new(
+        opts: &CppOptions,
+        f: &'f Func,
+        m: &'f Module,
+        ids: &'f HashMap<String, usize>,
+        env: &'static str,
+    ) -> Self {
+        let line_file = if opts.line_directives {
+            Some(opts.file.clone().unwrap_or_else(|| "input.pna".into()))
+        } else {
+            None
+        };
+        let mut known = vec![false; f.slots.len()];
+        known[..f.params].fill(true);
+        CgState {
+            f,
+            externs: &m.externs,
+            files: &m.files,
+            ids,
+            strict: opts.strict,
+            line_file,
+            env,
+            temps: HashMap::new(),
+            loops: Vec::new(),
+            switches: Vec::new(),
+            labels: BTreeSet::new(),
+            known,
         }
-        Stmt::PropAssign { base, key, expr } => {
-            let e = emit_expr(expr, env);
-            out.push_str(&format!(
-                "{}pna_set_prop({},\"{}\",\"{}\",{});\n",
-                indent(lvl),
-                env,
-                esc(base),
-                esc(key),
-                e
-            ));
+    }
+
+    /// `#line` for code from `sp`, in the file it comes from.
+    fn line_directive(&self, out: &mut String, sp: Span) {
+        if let Some(main) = &self.line_file {
+            let file = match sp.file.checked_sub(1) {
+                Some(i) => &self.files[i],
+                None => main,
+            };
+            writeln!(out, "#line {} \"{}\"", sp.line, esc(file)).unwrap();
         }
-        Stmt::VarAssign { name, expr } => {
-            let e = emit_expr(expr, env);
-            out.push_str(&format!(
-                "{}pna_set({},\"{}\",{});\n",
-                indent(lvl),
-                env,
-                esc(name),
-                e
-            ));
+    }
+
+    /// `name` looked up through the callers' frames.
+    fn lookup(&self, name: &str, required: bool) -> String {
+        let up = if self.env == "env" { "nullptr" } else { "&env" };
+        format!(
+            "pna_lookup({},{},\"{}\",{})",
+            up,
+            self.ids[name],
+            esc(name),
+            required
+        )
+    }
+
+    /// Reads slot `s`; until it is set, the name is looked up in the callers.
+    fn slot(&self, s: Slot) -> String {
+        if self.known[s] {
+            format!("__s[{}]", s)
+        } else {
+            format!(
+                "(__set[{}]?__s[{}]:{})",
+                s,
+                s,
+                self.lookup(&self.f.slots[s], true)
+            )
         }
-        Stmt::Log(e) => {
-            let x = emit_expr(e, env);
-            out.push_str(&format!("{}pna_log({});\n", indent(lvl), x));
+    }
+
+    fn operand(&mut self, o: &Operand) -> String {
+        match o {
+            Operand::Temp(t) => self.temps.remove(t).expect("temporaries are used once"),
+            Operand::Const(Const::Num(n)) => format!("Value({:?})", n),
+            Operand::Const(Const::Str(s)) => format!("Value({})", str_lit(s)),
+            Operand::Const(Const::Bool(b)) => format!("Value({})", b),
+            Operand::Const(Const::Undef) => "Value()".to_string(),
         }
-        Stmt::Cond {
-            cond,
-            then_blk,
-            else_blk,
-        } => {
-            let c = emit_expr(cond, env);
-            out.push_str(&format!("{}if(pna_truthy({})){{\n", indent(lvl), c));
-            for st in then_blk {
-                emit_stmt(out, st, env, lvl + 1, cg);
+    }
+
+    fn operands(&mut self, xs: &[Operand]) -> String {
+        xs.iter()
+            .map(|x| self.operand(x))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn rv(&mut self, rv: &Rv) -> String {
+        let env = self.env;
+        match rv {
+            Rv::Local(s) => self.slot(*s),
+            Rv::Name(n) => self.lookup(n, true),
+            Rv::Not(a) => format!("pna_not({})", self.operand(a)),
+            Rv::Bin(op, a, b) => {
+                let a = self.operand(a);
+                format!("pna_{}({}, {})", op.name(), a, self.operand(b))
             }
-            out.push_str(&format!("{}}}", indent(lvl)));
-            if let Some(eb) = else_blk {
-                out.push_str(" else {\n");
-                for st in eb {
-                    emit_stmt(out, st, env, lvl + 1, cg);
-                }
-                out.push_str(&format!("{}}}\n", indent(lvl)));
-            } else {
-                out.push('\n');
+            Rv::Member(a, k) => format!("pna_get_prop({},\"{}\")", self.operand(a), esc(k)),
+            Rv::Index(a, b) => {
+                let a = self.operand(a);
+                format!("pna_index({}, {})", a, self.operand(b))
             }
-        }
-        Stmt::Loop { cond, body } => {
-            let c = emit_expr(cond, env);
-            out.push_str(&format!("{}while(pna_truthy({})){{\n", indent(lvl), c));
-            for st in body {
-                emit_stmt(out, st, env, lvl + 1, cg);
+            Rv::NewObj => "pna_make_obj()".to_string(),
+            Rv::SetField(o, k, v) => {
+                let o = self.operand(o);
+                format!("pna_with({},\"{}\",{})", o, esc(k), self.operand(v))
             }
-            out.push_str(&format!("{}}}\n", indent(lvl)));
-        }
-        Stmt::While { cond, body, ended } => {
-            let c = emit_expr(cond, env);
-            let id = cg.while_id;
-            cg.while_id += 1;
-            let broke = format!("__broke_{}", id);
-            out.push_str(&format!(
-                "{}bool {}=false, __ran_{}=false; while(pna_truthy({})){{ __ran_{}=true;\n",
-                indent(lvl),
-                broke,
-                id,
-                c,
-                id
-            ));
-            cg.while_stack.push(id);
-            for st in body {
-                emit_stmt(out, st, env, lvl + 1, cg);
+            Rv::Concat(parts) => {
+                let xs: Vec<String> = parts
+                    .iter()
+                    .map(|p| match p {
+                        Operand::Const(Const::Str(s)) => str_lit(s),
+                        p => self.operand(p),
+                    })
+                    .collect();
+                format!("pna_interp({})", xs.join(", "))
+            }
+            Rv::Format(xs) => {
+                let f = self.operand(&xs[0]);
+                format!("pna_format({}, {{{}}})", f, self.operands(&xs[1..]))
+            }
+            Rv::Len(a) => format!("pna_len({})", self.operand(a)),
+            Rv::Has(a, k) => {
+                let a = self.operand(a);
+                format!("pna_has({}, {})", a, self.operand(k))
             }
-            cg.while_stack.pop();
-            out.push_str(&format!("{}}} if(!{}){{\n", indent(lvl), broke));
-            if let Some(eb) = ended {
-                for st in eb {
-                    emit_stmt(out, st, env, lvl + 1, cg);
+            Rv::Remove(a, k) => {
+                let a = self.operand(a);
+                format!("pna_remove({}, {})", a, self.operand(k))
+            }
+            Rv::Keys(a) => format!("pna_keys({})", self.operand(a)),
+            Rv::Values(a) => format!("pna_values({})", self.operand(a)),
+            Rv::Math(m, xs) => format!("pna_{}({})", m.name(), self.operands(xs)),
+            Rv::Call(name, xs) => {
+                if xs.is_empty() {
+                    format!("fn_{}({})", name, env)
+                } else {
+                    format!("fn_{}({}, {})", name, env, self.operands(xs))
+                }
+            }
+            Rv::Extern(name, xs) => {
+                let x = self
+                    .externs
+                    .iter()
+                    .find(|x| x.name == *name)
+                    .expect("lowering only emits declared externs");
+                let args: Vec<String> = xs
+                    .iter()
+                    .zip(&x.params)
+                    .map(|(a, ty)| {
+                        let a = self.operand(a);
+                        match ty {
+                            Ty::Int => format!("(long long)as_num({})", a),
+                            Ty::Double => format!("as_num({})", a),
+                            Ty::String => format!("as_str({})", a),
+                            Ty::Named(_) | Ty::Void => a,
+                        }
+                    })
+                    .collect();
+                let call = format!("{}({})", name, args.join(", "));
+                match x.ret {
+                    Ty::Void => format!("({},Value())", call),
+                    _ => format!("Value({})", call),
                 }
             }
-            out.push_str(&format!("{}}}\n", indent(lvl)));
+            Rv::Input(p) => format!("pna_input(\"{}\")", esc(p)),
         }
-        Stmt::Input { prompt, dst } => {
-            out.push_str(&format!(
-                "{}{{ Value __in=pna_input(\"{}\");\n",
-                indent(lvl),
-                esc(prompt)
-            ));
-            match dst {
-                Target::Var(v) => out.push_str(&format!(
-                    "{}pna_set({},\"{}\",__in);\n",
-                    indent(lvl + 1),
-                    env,
-                    esc(v)
-                )),
-                Target::Prop { base, key } => out.push_str(&format!(
-                    "{}pna_set_prop({},\"{}\",\"{}\",__in);\n",
-                    indent(lvl + 1),
-                    env,
-                    esc(base),
-                    esc(key)
-                )),
+    }
+}
+
+/// Points following lines back at the generated file after a `#line`-mapped region.
+pub(crate) fn line_reset(out: &mut String, opts: &CppOptions) {
+    if !opts.line_directives {
+        return;
+    }
+    if let Some(f) = &opts.cpp_file {
+        let next = out.matches('\n').count() + 2;
+        writeln!(out, "#line {} \"{}\"", next, esc(f)).unwrap();
+    }
+}
+
+pub(crate) fn indent(n: usize) -> String {
+    "  ".repeat(n)
+}
+
+fn emit_func(
+    out: &mut String,
+    f: &Func,
+    m: &Module,
+    ids: &HashMap<String, usize>,
+    opts: &CppOptions,
+) -> Result<(), Diagnostic> {
+    let mut cg = CgState::new(opts, f, m, ids, "__fenv");
+    cg.line_directive(out, f.span);
+    write!(out, "static Value fn_{}(const Env& env", esc(&f.name)).unwrap();
+    for i in 0..f.params {
+        write!(out, ", Value __a{}", i).unwrap();
+    }
+    out.push_str("){\n");
+    frame(out, f, ids, "&env", "__fenv");
+    if opts.strict {
+        // errors after the call returns are the caller's
+        out.push_str("  pna_at __at;\n");
+    }
+    emit_block(out, &f.body, 1, &mut cg);
+    line_reset(out, opts);
+    out.push_str("  return Value();\n}\n");
+    Ok(())
+}
+
+/// Declares the slots of `f` (parameters already set) and the frame `env`
+/// that describes them to callees.
+fn frame(out: &mut String, f: &Func, ids: &HashMap<String, usize>, up: &str, env: &str) {
+    let n = f.slots.len();
+    let slot_ids: Vec<String> = f.slots.iter().map(|s| ids[s].to_string()).collect();
+    let args: Vec<String> = (0..f.params)
+        .map(|i| format!("std::move(__a{})", i))
+        .collect();
+    writeln!(
+        out,
+        "  static const int __ids[]={{{}}};\n  Value __s[{}]={{{}}}; bool __set[{}]={{{}}};",
+        if n == 0 {
+            "-1".into()
+        } else {
+            slot_ids.join(",")
+        },
+        n.max(1),
+        args.join(","),
+        n.max(1),
+        vec!["true"; f.params].join(",")
+    )
+    .unwrap();
+    writeln!(out, "  const Env {}{{{},__ids,{},__s,__set}};", env, up, n).unwrap();
+}
+
+fn emit_block(out: &mut String, b: &[Inst], lvl: usize, cg: &mut CgState) {
+    for i in b {
+        emit_inst(out, i, lvl, cg);
+    }
+}
+
+/// Before a field of slot `s` is set in place: an unset slot starts from the
+/// callers' value.
+fn set_base(out: &mut String, ind: &str, s: Slot, cg: &mut CgState) {
+    if cg.known[s] {
+        return;
+    }
+    let base = cg.lookup(&cg.f.slots[s], false);
+    writeln!(
+        out,
+        "{}if(!__set[{}]){{__s[{}]={}; __set[{}]=true;}}",
+        ind, s, s, base, s
+    )
+    .unwrap();
+    cg.known[s] = true;
+}
+
+fn emit_inst(out: &mut String, i: &Inst, lvl: usize, cg: &mut CgState) {
+    let ind = indent(lvl);
+    let env = cg.env;
+    match i {
+        Inst::Line(sp) => {
+            cg.line_directive(out, *sp);
+            if cg.strict {
+                writeln!(out, "{}{};", ind, mark(cg.files, *sp)).unwrap();
             }
-            out.push_str(&format!("{}}}\n", indent(lvl)));
         }
-        Stmt::Break => {
-            if let Some(&wid) = cg.while_stack.last() {
-                out.push_str(&format!("{}__broke_{}=true;\n", indent(lvl), wid));
+        Inst::Let(t, rv) => {
+            let x = cg.rv(rv);
+            cg.temps.insert(*t, x);
+        }
+        Inst::Store(s, v) => {
+            let v = cg.operand(v);
+            if cg.known[*s] {
+                writeln!(out, "{}__s[{}]={};", ind, s, v).unwrap();
+            } else {
+                writeln!(out, "{}__s[{}]={}; __set[{}]=true;", ind, s, v, s).unwrap();
+                cg.known[*s] = true;
             }
-            out.push_str(&format!("{}break;\n", indent(lvl)));
         }
-        Stmt::Continue => {
-            out.push_str(&format!("{}continue;\n", indent(lvl)));
+        Inst::SetField(s, k, v) => {
+            set_base(out, &ind, *s, cg);
+            let v = cg.operand(v);
+            writeln!(out, "{}pna_obj_set(__s[{}],\"{}\",{});", ind, s, esc(k), v).unwrap();
+        }
+        Inst::SetIndex(s, k, v) => {
+            set_base(out, &ind, *s, cg);
+            let k = cg.operand(k);
+            let v = cg.operand(v);
+            writeln!(out, "{}pna_obj_set(__s[{}],as_str({}),{});", ind, s, k, v).unwrap();
         }
-        Stmt::Return(opt) => match opt {
-            Some(e) => {
-                let r = emit_expr(e, env);
-                out.push_str(&format!("{}return {};\n", indent(lvl), r));
+        Inst::Out { args, newline } => match args.as_slice() {
+            [x] if *newline => {
+                let x = cg.operand(x);
+                writeln!(out, "{}pna_log({});", ind, x).unwrap();
             }
-            None => {
-                out.push_str(&format!("{}return Value();\n", indent(lvl)));
+            _ => {
+                let xs = cg.operands(args);
+                writeln!(out, "{}pna_out({{{}}}, {});", ind, xs, newline).unwrap();
             }
         },
-    }
-}
-
-fn emit_expr(e: &Expr, env: &str) -> String {
-    match e {
-        Expr::Num(n) => {
-            let n = *n;
-            if n.is_finite() && n.fract() == 0.0 {
-                format!("Value({}.0)", n as i64)
+        Inst::If {
+            cond,
+            then_blk,
+            else_blk,
+        } => {
+            let c = cg.operand(cond);
+            writeln!(out, "{}if(pna_truthy({})){{", ind, c).unwrap();
+            let before = cg.known.clone();
+            emit_block(out, then_blk, lvl + 1, cg);
+            let then_known = std::mem::replace(&mut cg.known, before);
+            if else_blk.is_empty() {
+                writeln!(out, "{}}}", ind).unwrap();
             } else {
-                format!("Value({})", n)
+                writeln!(out, "{}}} else {{", ind).unwrap();
+                emit_block(out, else_blk, lvl + 1, cg);
+                writeln!(out, "{}}}", ind).unwrap();
+            }
+            // Set after the `if` only if both branches set it.
+            for (k, t) in cg.known.iter_mut().zip(then_known) {
+                *k &= t;
             }
         }
-        Expr::Str(s) => format!("Value(\"{}\")", esc(s)),
-        Expr::Bool(b) => format!("Value({})", b),
-        Expr::Ident(id) => format!("pna_get({},\"{}\")", env, esc(id)),
-        Expr::Member(base, key) => {
-            let b = emit_expr(base, env);
-            format!("pna_get_prop({},\"{}\")", b, esc(key))
+        Inst::Loop {
+            id,
+            header,
+            cond,
+            body,
+            ended,
+        } => {
+            // The condition is re-evaluated after the body, so in strict mode
+            // it resets `pna_line` to the loop header itself.
+            let mut line = None;
+            for h in header {
+                match h {
+                    Inst::Line(sp) => line = Some(*sp),
+                    Inst::Let(t, rv) => {
+                        let x = cg.rv(rv);
+                        cg.temps.insert(*t, x);
+                    }
+                    _ => unreachable!("loop headers only compute the condition"),
+                }
+            }
+            let mut c = cg.operand(cond);
+            if let (Some(sp), true) = (line, cg.strict) {
+                c = format!("({}, {})", mark(cg.files, sp), c);
+            }
+            if !ended.is_empty() {
+                write!(out, "{}bool __broke_{}=false; ", ind, id).unwrap();
+            } else {
+                out.push_str(&ind);
+            }
+            writeln!(out, "while(pna_truthy({})){{", c).unwrap();
+            // Neither the body nor `ended` certainly runs.
+            let before = cg.known.clone();
+            cg.loops.push((*id, !ended.is_empty()));
+            emit_block(out, body, lvl + 1, cg);
+            cg.loops.pop();
+            cg.known.clone_from(&before);
+            if ended.is_empty() {
+                writeln!(out, "{}}}", ind).unwrap();
+            } else {
+                writeln!(out, "{}}} if(!__broke_{}){{", ind, id).unwrap();
+                emit_block(out, ended, lvl + 1, cg);
+                writeln!(out, "{}}}", ind).unwrap();
+            }
+            if cg.labels.remove(id) {
+                writeln!(out, "{}__brk_{}:;", ind, id).unwrap();
+            }
+            cg.known = before;
         }
-        Expr::Unary { op, rhs } => {
-            let r = emit_expr(rhs, env);
-            match op.as_str() {
-                "!" => format!("pna_not({})", r),
-                _ => r,
+        Inst::Switch {
+            slot,
+            cases,
+            default,
+        } => {
+            let v = cg.rv(&Rv::Local(*slot));
+            writeln!(
+                out,
+                "{}switch((long long)as_num(pna_get_prop({},\"tag\"))){{",
+                ind, v
+            )
+            .unwrap();
+            let before = cg.known.clone();
+            let mut after = vec![true; before.len()];
+            cg.switches.push(cg.loops.len());
+            let arms = cases
+                .iter()
+                .map(|c| (format!("case {}: {{ // {}", c.tag, c.variant), &c.body))
+                .chain([("default: {".to_string(), default)]);
+            for (head, body) in arms {
+                writeln!(out, "{}{}", ind, head).unwrap();
+                cg.known.clone_from(&before);
+                emit_block(out, body, lvl + 1, cg);
+                writeln!(out, "{}}} break;", ind).unwrap();
+                // Set after the `switch` only if every arm sets it.
+                for (a, k) in after.iter_mut().zip(&cg.known) {
+                    *a &= k;
+                }
             }
+            cg.switches.pop();
+            writeln!(out, "{}}}", ind).unwrap();
+            cg.known = after;
         }
-        Expr::Binary { op, lhs, rhs } => {
-            let a = emit_expr(lhs, env);
-            let b = emit_expr(rhs, env);
-            match op.as_str() {
-                "+" => format!("pna_add({}, {})", a, b),
-                "-" => format!("pna_sub({}, {})", a, b),
-                "*" => format!("pna_mul({}, {})", a, b),
-                "/" => format!("pna_div({}, {})", a, b),
-                "%" => format!("pna_mod({}, {})", a, b),
-                "==" => format!("pna_eq({}, {})", a, b),
-                "!=" => format!("pna_neq({}, {})", a, b),
-                "<" => format!("pna_lt({}, {})", a, b),
-                "<=" => format!("pna_le({}, {})", a, b),
-                ">" => format!("pna_gt({}, {})", a, b),
-                ">=" => format!("pna_ge({}, {})", a, b),
-                "&&" => format!("pna_and({}, {})", a, b),
-                "||" => format!("pna_or({}, {})", a, b),
-                _ => a,
+        Inst::Break(id) => {
+            if cg.loops.contains(&(*id, true)) {
+                writeln!(out, "{}__broke_{}=true;", ind, id).unwrap();
+            }
+            if cg.switches.last() == Some(&cg.loops.len()) {
+                cg.labels.insert(*id);
+                writeln!(out, "{}goto __brk_{};", ind, id).unwrap();
+            } else {
+                writeln!(out, "{}break;", ind).unwrap();
             }
         }
-        Expr::Call { name, args } => {
-            let xs: Vec<String> = args.iter().map(|e| emit_expr(e, env)).collect();
-            if xs.is_empty() {
-                format!("fn_{}({})", name, env)
+        Inst::Continue(_) => writeln!(out, "{}continue;", ind).unwrap(),
+        Inst::Return(v) => {
+            let v = cg.operand(v);
+            if env == "env" {
+                writeln!(out, "{}{{ (void)({}); return 0; }}", ind, v).unwrap();
             } else {
-                format!("fn_{}({}, {})", name, env, xs.join(", "))
+                writeln!(out, "{}return {};", ind, v).unwrap();
             }
         }
+        Inst::Cpp(code) => writeln!(out, "{}{{{}}}", ind, code).unwrap(),
     }
 }
@@ -0,0 +1,382 @@
+//! IR → a self-contained JavaScript ES module, for running PNA in browsers
+//! and Node without a native toolchain.
+//!
+//! The embedded runtime mirrors the C++ one (`pna_add`, `pna_mul`, `pna_mod`,
+//! `as_str`, ...). Objects are `Map`s that are never mutated in place, which
+//! gives the C++ copy-on-assign behavior for free. The module exports
+//! `run(io)`: `io.write(text)` receives output, `io.input()` returns the next
+//! whitespace-delimited token (or a promise of one, `""` at end of input) and
+//! `io.error(line)` receives `--strict` runtime errors; it resolves to the
+//! exit code. Run directly with `node`, the module reads stdin and writes
+//! stdout itself.
+
+use crate::codegen_cpp::{indent, mark, CppOptions};
+use crate::diag::Diagnostic;
+use crate::ir::*;
+use crate::math::{self, Math};
+use std::collections::HashMap;
+use std::fmt::Write;
+
+pub fn compile_to_js(m: &Module, opts: &CppOptions) -> Result<String, Diagnostic> {
+    if let Some(at) = m.native() {
+        let msg = "--emit=js cannot run `cpp` blocks or call `extern` functions";
+        return Err(Diagnostic::new(msg, at));
+    }
+    let m = &expand_switches(m.clone());
+    let mut out = String::new();
+    if let Some(f) = &opts.file {
+        writeln!(out, "// Generated by pna-cc from {}", f).unwrap();
+    }
+    write!(out, "const pna_strict={};", opts.strict).unwrap();
+    out.push_str("class PnaError extends Error{constructor(m,line,file){super(m);this.line=line;this.file=file;}}");
+    out.push_str(
+        "let pna_line=0,pna_file=0;const pna_fail=m=>{throw new PnaError(m,pna_line,pna_file);};",
+    );
+    out.push_str("class Env{constructor(parent){this.vars=new Map();this.parent=parent;}}");
+    out.push_str("const is_obj=x=>x instanceof Map;");
+    out.push_str("const as_str=x=>{if(typeof x===\"string\")return x;if(typeof x===\"number\")return pna_num_str(x);if(typeof x===\"boolean\")return x?\"true\":\"false\";if(is_obj(x))return \"{\"+[...x.keys()].sort().map(k=>k+\":\"+as_str(x.get(k))).join(\", \")+\"}\";return \"\";};");
+    out.push_str("const pna_num_str=d=>{if(Number.isNaN(d))return \"nan\";if(!Number.isFinite(d))return d<0?\"-inf\":\"inf\";const rd=Math.sign(d)*Math.round(Math.abs(d));if(Math.abs(d-rd)<1e-9)return Object.is(rd,-0)?\"-0\":BigInt(rd).toString();return d.toFixed(12).replace(/0+$/,\"\").replace(/\\.$/,\"\");};");
+    out.push_str("const pna_strtod=s=>{const t=s.replace(/^[ \\t\\n\\v\\f\\r]+/,\"\");if(/^[+-]?((\\d+\\.?\\d*|\\.\\d+)([eE][+-]?\\d+)?)$/.test(t))return Number(t);if(/^[+-]?0[xX][0-9a-fA-F]+$/.test(t))return (t[0]===\"-\"?-1:1)*parseInt(t.replace(/^[+-]/,\"\"),16);const m=/^([+-]?)(inf|infinity|nan)$/i.exec(t);if(m)return m[2].toLowerCase()===\"nan\"?NaN:(m[1]===\"-\"?-Infinity:Infinity);return null;};");
+    out.push_str("const as_num=v=>{if(typeof v===\"number\")return v;if(typeof v===\"boolean\")return v?1:0;if(typeof v===\"string\"){const d=pna_strtod(v);if(d!==null)return d;if(pna_strict)pna_fail(\"cannot convert \\\"\"+v+\"\\\" to a number\");return 0;}if(pna_strict)pna_fail(is_obj(v)?\"object used as a number\":\"undefined value used as a number\");return 0;};");
+    out.push_str("const truthy=x=>{if(typeof x===\"boolean\")return x;if(typeof x===\"number\")return x!==0;if(typeof x===\"string\")return x.length>0;if(is_obj(x))return x.size>0;return false;};");
+    out.push_str("const pna_get=(e,k)=>{for(let s=e;s;s=s.parent)if(s.vars.has(k))return s.vars.get(k);if(pna_strict)pna_fail(\"undefined variable `\"+k+\"`\");return undefined;};");
+    out.push_str("const pna_set=(e,k,v)=>{e.vars.set(k,v);};");
+    out.push_str("const pna_get_prop=(o,k)=>{if(!is_obj(o)){if(pna_strict)pna_fail(\"cannot read property `\"+k+\"` of a non-object\");return undefined;}if(o.has(k))return o.get(k);if(pna_strict)pna_fail(\"undefined property `\"+k+\"`\");return undefined;};");
+    out.push_str("const pna_get_base=(e,k)=>{for(let s=e;s;s=s.parent)if(s.vars.has(k))return s.vars.get(k);return undefined;};");
+    out.push_str(
+        "const pna_with=(o,k,v)=>{const m=is_obj(o)?new Map(o):new Map();m.set(k,v);return m;};",
+    );
+    out.push_str("const pna_len=v=>typeof v===\"string\"?[...v].length:is_obj(v)?v.size:0;");
+    out.push_str("const pna_index=(b,i)=>{if(is_obj(b))return pna_get_prop(b,as_str(i));if(typeof b!==\"string\")return undefined;const cs=[...b];const d=as_num(i);if(d<0||d!==Math.floor(d)||d>=cs.length){if(pna_strict)pna_fail(\"string index \"+as_str(i)+\" out of range\");return undefined;}return cs[d];};");
+    out.push_str("const pna_has=(o,k)=>is_obj(o)&&o.has(as_str(k));");
+    out.push_str("const pna_remove=(o,k)=>{k=as_str(k);if(!is_obj(o)||!o.has(k))return o;const m=new Map(o);m.delete(k);return m;};");
+    out.push_str("const pna_list=xs=>new Map(xs.map((x,i)=>[String(i),x]));");
+    out.push_str("const pna_keys=o=>pna_list(is_obj(o)?[...o.keys()].sort():[]);");
+    out.push_str(
+        "const pna_values=o=>pna_list(is_obj(o)?[...o.keys()].sort().map(k=>o.get(k)):[]);",
+    );
+    out.push_str("const pna_add=(a,b)=>typeof a===\"string\"||typeof b===\"string\"?as_str(a)+as_str(b):as_num(a)+as_num(b);");
+    out.push_str("const pna_sub=(a,b)=>as_num(a)-as_num(b);");
+    out.push_str("const pna_mul=(a,b)=>{if(typeof a===\"string\"&&typeof b===\"number\"){const r=Math.max(0,Math.sign(b)*Math.round(Math.abs(b)));return [...a].map(c=>c.repeat(r)).join(\"\");}if(typeof a===\"number\"&&typeof b===\"string\")return pna_mul(b,a);return as_num(a)*as_num(b);};");
+    out.push_str("const pna_div=(a,b)=>{const r=as_num(b);if(r===0&&pna_strict)pna_fail(\"division by zero\");const x=as_num(a);return r===0?0:x/r;};");
+    out.push_str("const pna_mod=(a,b)=>{const x=as_num(a),y=as_num(b);if(y===0){if(pna_strict)pna_fail(\"modulo by zero\");return 0;}const q=Math.floor(x/y+1e-12);const r=x-q*y;return Math.abs(r)<1e-12?0:r;};");
+    out.push_str("const pna_math=(n,r,...xs)=>{if(!Number.isNaN(r))return r+0;if(pna_strict)pna_fail(n+\"(\"+xs.map(pna_num_str).join(\", \")+\") is undefined\");return 0;};");
+    out.push_str("const pna_igcd=(a,b)=>{a=Math.abs(Math.trunc(a));b=Math.abs(Math.trunc(b));while(b)[a,b]=[b,a%b];return a;};");
+    emit_math(&mut out);
+    out.push_str("const pna_eq=(a,b)=>typeof a===\"number\"&&typeof b===\"number\"?a===b:as_str(a)===as_str(b);");
+    out.push_str("const pna_neq=(a,b)=>!pna_eq(a,b);");
+    out.push_str("const pna_and=(a,b)=>truthy(a)&&truthy(b),pna_or=(a,b)=>truthy(a)||truthy(b),pna_not=a=>!truthy(a);");
+    out.push_str("const pna_lt=(a,b)=>as_num(a)<as_num(b),pna_le=(a,b)=>as_num(a)<=as_num(b),pna_gt=(a,b)=>as_num(a)>as_num(b),pna_ge=(a,b)=>as_num(a)>=as_num(b);");
+    out.push_str("const pna_interp=(...xs)=>xs.map(as_str).join(\"\");");
+    out.push_str("const pna_fmt_one=(v,fill,align,zero,width,prec)=>{const isn=typeof v===\"number\";let s;if(prec>=0&&isn)s=pna_fixed(v,prec);else{s=as_str(v);if(prec>=0)s=[...s].slice(0,prec).join(\"\");}const pad=width-[...s].length;if(pad<=0)return s;if(zero&&isn){const at=s[0]===\"-\"?1:0;return s.slice(0,at)+\"0\".repeat(pad)+s.slice(at);}const a=align||(isn?\">\":\"<\");if(a===\"<\")return s+fill.repeat(pad);if(a===\">\")return fill.repeat(pad)+s;return fill.repeat(pad>>1)+s+fill.repeat(pad-(pad>>1));};");
+    out.push_str("const pna_fixed=(d,p)=>Number.isNaN(d)?\"nan\":!Number.isFinite(d)?(d<0?\"-inf\":\"inf\"):p<=100&&Math.abs(d)<1e21?d.toFixed(p):d.toFixed(Math.min(p,100));");
+    out.push_str("const pna_format=(f,...args)=>{const fs=[...as_str(f)];let out=\"\",ai=0,i=0;const n=fs.length;const isal=c=>c===\"<\"||c===\">\"||c===\"^\";while(i<n){const c=fs[i];if((c===\"{\"||c===\"}\")&&fs[i+1]===c){out+=c;i+=2;continue;}if(c!==\"{\"){out+=c;++i;continue;}const close=fs.indexOf(\"}\",i+1);if(close<0){out+=fs.slice(i).join(\"\");break;}const inn=fs.slice(i+1,close);i=close+1;let fill=\" \",align=\"\",zero=false,width=0,prec=-1,k=0,ok=inn.length===0||inn[0]===\":\";if(inn.length&&ok){k=1;if(inn.length>=k+2&&isal(inn[k+1])){fill=inn[k];align=inn[k+1];k+=2;}else if(inn.length>k&&isal(inn[k])){align=inn[k];k+=1;}if(inn[k]===\"0\"){zero=true;++k;}while(k<inn.length&&/\\d/.test(inn[k]))width=width*10+Number(inn[k++]);if(inn[k]===\".\"){const st=++k;prec=0;while(k<inn.length&&/\\d/.test(inn[k]))prec=prec*10+Number(inn[k++]);if(k===st)ok=false;}if(k!==inn.length)ok=false;}if(!ok){out+=\"{\"+inn.join(\"\")+\"}\";continue;}out+=pna_fmt_one(ai<args.length?args[ai]:undefined,fill,align,zero,width,prec);++ai;}return out;};");
+    out.push_str("let pna_io=null,pna_buf=\"\";");
+    out.push_str("const pna_flush=()=>{if(pna_buf){pna_io.write(pna_buf);pna_buf=\"\";}};");
+    out.push_str("const pna_out=(xs,nl)=>{pna_buf+=xs.map(as_str).join(\" \")+(nl?\"\\n\":\"\");if(pna_buf.length>65536)pna_flush();};");
+    out.push_str("const pna_input=async prompt=>{if(prompt)pna_buf+=prompt;pna_flush();const s=(await pna_io.input())??\"\";const d=pna_strtod(s);return s!==\"\"&&d!==null?d:s;};");
+    out.push_str("const pna_node_io=()=>{let buf=\"\",eof=false;process.stdin.setEncoding(\"utf8\");const it=process.stdin[Symbol.asyncIterator]();return{write:s=>process.stdout.write(s),error:s=>process.stderr.write(s+\"\\n\"),input:async()=>{for(;;){const m=/^\\s*(\\S+)(\\s)/.exec(buf);if(m){buf=buf.slice(m[0].length-1);return m[1];}if(eof){const t=buf.trim();buf=\"\";return t;}const r=await it.next();if(r.done)eof=true;else buf+=r.value.toString();}}};};");
+    out.push('\n');
+
+    for f in &m.funcs {
+        let mut cg = CgState::new(opts, f, &m.files);
+        write!(out, "async function fn_{}(caller", f.name).unwrap();
+        for p in &f.slots[..f.params] {
+            write!(out, ",p_{}", p).unwrap();
+        }
+        out.push_str("){\n  const env=new Env(caller);\n");
+        for p in &f.slots[..f.params] {
+            writeln!(out, "  pna_set(env,{},p_{});", lit(p), p).unwrap();
+        }
+        if opts.strict {
+            // errors after the call returns are the caller's
+            out.push_str("  const __line=pna_line,__file=pna_file;try{\n");
+        }
+        emit_block(&mut out, &f.body, 1, &mut cg);
+        out.push_str("  return undefined;\n");
+        if opts.strict {
+            out.push_str("  }finally{pna_line=__line;pna_file=__file;}\n");
+        }
+        out.push_str("}\n");
+    }
+
+    out.push_str("async function pna_main(env){\n");
+    let mut cg = CgState::new(opts, &m.main, &m.files);
+    emit_block(&mut out, &m.main.body, 1, &mut cg);
+    out.push_str("}\n");
+    let at = match &opts.file {
+        Some(f) if !m.files.is_empty() => {
+            let names: Vec<String> = std::iter::once(f).chain(&m.files).map(|f| lit(f)).collect();
+            format!("[{}][e.file]+\":\"", names.join(","))
+        }
+        Some(f) => format!("{}+\":\"", lit(f)),
+        None => "\"line \"".to_string(),
+    };
+    writeln!(
+        out,
+        "export async function run(io){{pna_io=io??pna_node_io();pna_buf=\"\";pna_line=0;pna_file=0;try{{await pna_main(new Env(null));}}catch(e){{pna_flush();if(!(e instanceof PnaError))throw e;(pna_io.error??console.error)({}+e.line+\": runtime error: \"+e.message);return 1;}}pna_flush();return 0;}}",
+        at
+    )
+    .unwrap();
+    out.push_str("if(globalThis.process?.argv?.[1]){const{pathToFileURL}=await import(\"node:url\");if(import.meta.url===pathToFileURL(process.argv[1]).href)process.exitCode=await run();}\n");
+    Ok(out)
+}
+
+/// A JavaScript string literal for `s`.
+/// `pna_abs`, `pna_pow`, ...: one runtime helper per math builtin, which
+/// `pna_math` checks for an undefined result (and `**` shares `pna_pow`).
+fn emit_math(out: &mut String) {
+    for &m in math::ALL {
+        let call = match m {
+            Math::Abs => "Math.abs(a)",
+            Math::Min => "Math.min(a,b)",
+            Math::Max => "Math.max(a,b)",
+            Math::Sqrt => "Math.sqrt(a)",
+            Math::Pow => "Math.pow(a,b)",
+            Math::Floor => "Math.floor(a)",
+            Math::Ceil => "Math.ceil(a)",
+            // `Math.round` rounds halves up, not away from zero
+            Math::Round => "Math.sign(a)*Math.round(Math.abs(a))",
+            Math::Gcd => "pna_igcd(a,b)",
+            Math::Sin => "Math.sin(a)",
+            Math::Cos => "Math.cos(a)",
+            Math::Tan => "Math.tan(a)",
+            Math::Asin => "Math.asin(a)",
+            Math::Acos => "Math.acos(a)",
+            Math::Atan => "Math.atan(a)",
+            Math::Atan2 => "Math.atan2(a,b)",
+        };
+        match m.params().len() {
+            1 => write!(
+                out,
+                "const pna_{0}=x=>{{const a=as_num(x);return pna_math(\"{0}\",{1},a);}};",
+                m.name(),
+                call
+            ),
+            _ => write!(
+                out,
+                "const pna_{0}=(x,y)=>{{const a=as_num(x),b=as_num(y);return pna_math(\"{0}\",{1},a,b);}};",
+                m.name(),
+                call
+            ),
+        }
+        .unwrap();
+    }
+}
+
+fn lit(s: &str) -> String {
+    serde_json::to_string(s).expect("strings always serialize")
+}
+
+struct CgState<'f> {
+    f: &'f Func,
+    /// Names of the imported files (`Module::files`).
+    files: &'f [String],
+    strict: bool,
+    /// Rendered temporaries, waiting for their single use.
+    temps: HashMap<Temp, String>,
+    /// Enclosing loops, innermost last, with whether they have `ended`.
+    loops: Vec<(usize, bool)>,
+}
+
+impl<'f> CgState<'f> {
+    fn new(opts: &CppOptions, f: &'f Func, files: &'f [String]) -> Self {
+        CgState {
+            f,
+            files,
+            strict: opts.strict,
+            temps: HashMap::new(),
+            loops: Vec::new(),
+        }
+    }
+
+    fn operand(&mut self, o: &Operand) -> String {
+        match o {
+            Operand::Temp(t) => self.temps.remove(t).expect("temporaries are used once"),
+            Operand::Const(Const::Num(n)) => format!("({:?})", n),
+            Operand::Const(Const::Str(s)) => lit(s),
+            Operand::Const(Const::Bool(b)) => b.to_string(),
+            Operand::Const(Const::Undef) => "undefined".to_string(),
+        }
+    }
+
+    fn list(&mut self, xs: &[Operand]) -> String {
+        xs.iter()
+            .map(|x| self.operand(x))
+            .collect::<Vec<_>>()
+            .join(",")
+    }
+
+    fn rv(&mut self, rv: &Rv) -> String {
+        match rv {
+            Rv::Local(s) => format!("pna_get(env,{})", lit(&self.f.slots[*s])),
+            Rv::Name(n) => format!("pna_get(env,{})", lit(n)),
+            Rv::Not(a) => format!("pna_not({})", self.operand(a)),
+            Rv::Bin(op, a, b) => {
+                let a = self.operand(a);
+                format!("pna_{}({},{})", op.name(), a, self.operand(b))
+            }
+            Rv::Member(a, k) => format!("pna_get_prop({},{})", self.operand(a), lit(k)),
+            Rv::Index(a, b) => {
+                let a = self.operand(a);
+                format!("pna_index({},{})", a, self.operand(b))
+            }
+            Rv::NewObj => "new Map()".to_string(),
+            Rv::SetField(o, k, v) => {
+                let o = self.operand(o);
+                format!("pna_with({},{},{})", o, lit(k), self.operand(v))
+            }
+            Rv::Concat(parts) => format!("pna_interp({})", self.list(parts)),
+            Rv::Format(xs) => format!("pna_format({})", self.list(xs)),
+            Rv::Len(a) => format!("pna_len({})", self.operand(a)),
+            Rv::Has(a, k) => {
+                let a = self.operand(a);
+                format!("pna_has({},{})", a, self.operand(k))
+            }
+            Rv::Remove(a, k) => {
+                let a = self.operand(a);
+                format!("pna_remove({},{})", a, self.operand(k))
+            }
+            Rv::Keys(a) => format!("pna_keys({})", self.operand(a)),
+            Rv::Values(a) => format!("pna_values({})", self.operand(a)),
+            Rv::Math(m, xs) => format!("pna_{}({})", m.name(), self.list(xs)),
+            Rv::Call(name, xs) => {
+                let mut s = format!("(await fn_{}(env", name);
+                for x in xs {
+                    s.push(',');
+                    s.push_str(&self.operand(x));
+                }
+                s.push_str("))");
+                s
+            }
+            Rv::Input(p) => format!("await pna_input({})", lit(p)),
+            Rv::Extern(..) => unreachable!("native modules are rejected up front"),
+        }
+    }
+}
+
+fn emit_block(out: &mut String, b: &[Inst], lvl: usize, cg: &mut CgState) {
+    for i in b {
+        emit_inst(out, i, lvl, cg);
+    }
+}
+
+fn emit_inst(out: &mut String, i: &Inst, lvl: usize, cg: &mut CgState) {
+    let ind = indent(lvl);
+    match i {
+        Inst::Line(sp) => {
+            if cg.strict {
+                writeln!(out, "{}{};", ind, mark(cg.files, *sp)).unwrap();
+            }
+        }
+        Inst::Let(t, rv) => {
+            let x = cg.rv(rv);
+            cg.temps.insert(*t, x);
+        }
+        Inst::Store(s, v) => {
+            let v = cg.operand(v);
+            writeln!(out, "{}pna_set(env,{},{});", ind, lit(&cg.f.slots[*s]), v).unwrap();
+        }
+        Inst::SetField(s, k, v) => {
+            let v = cg.operand(v);
+            let x = lit(&cg.f.slots[*s]);
+            writeln!(
+                out,
+                "{}pna_set(env,{},pna_with(pna_get_base(env,{}),{},{}));",
+                ind,
+                x,
+                x,
+                lit(k),
+                v
+            )
+            .unwrap();
+        }
+        Inst::SetIndex(s, k, v) => {
+            let k = cg.operand(k);
+            let v = cg.operand(v);
+            let x = lit(&cg.f.slots[*s]);
+            writeln!(
+                out,
+                "{}pna_set(env,{},pna_with(pna_get_base(env,{}),as_str({}),{}));",
+                ind, x, x, k, v
+            )
+            .unwrap();
+        }
+        Inst::Out { args, newline } => {
+            let xs = cg.list(args);
+            writeln!(out, "{}pna_out([{}],{});", ind, xs, newline).unwrap();
+        }
+        Inst::If {
+            cond,
+            then_blk,
+            else_blk,
+        } => {
+            let c = cg.operand(cond);
+            writeln!(out, "{}if(truthy({})){{", ind, c).unwrap();
+            emit_block(out, then_blk, lvl + 1, cg);
+            if !else_blk.is_empty() {
+                writeln!(out, "{}}}else{{", ind).unwrap();
+                emit_block(out, else_blk, lvl + 1, cg);
+            }
+            writeln!(out, "{}}}", ind).unwrap();
+        }
+        Inst::Loop {
+            id,
+            header,
+            cond,
+            body,
+            ended,
+        } => {
+            // The condition is re-evaluated after the body, so in strict mode
+            // it resets `pna_line` to the loop header itself.
+            let mut line = None;
+            for h in header {
+                match h {
+                    Inst::Line(sp) => line = Some(*sp),
+                    Inst::Let(t, rv) => {
+                        let x = cg.rv(rv);
+                        cg.temps.insert(*t, x);
+                    }
+                    _ => unreachable!("loop headers only compute the condition"),
+                }
+            }
+            let mut c = cg.operand(cond);
+            if let (Some(sp), true) = (line, cg.strict) {
+                c = format!("({}, {})", mark(cg.files, sp), c);
+            }
+            if ended.is_empty() {
+                writeln!(out, "{}while(truthy({})){{", ind, c).unwrap();
+            } else {
+                writeln!(
+                    out,
+                    "{}{{let __broke_{}=false;while(truthy({})){{",
+                    ind, id, c
+                )
+                .unwrap();
+            }
+            cg.loops.push((*id, !ended.is_empty()));
+            emit_block(out, body, lvl + 1, cg);
+            cg.loops.pop();
+            if ended.is_empty() {
+                writeln!(out, "{}}}", ind).unwrap();
+            } else {
+                writeln!(out, "{}}}if(!__broke_{}){{", ind, id).unwrap();
+                emit_block(out, ended, lvl + 1, cg);
+                writeln!(out, "{}}}}}", ind).unwrap();
+            }
+        }
+        Inst::Break(id) => {
+            if cg.loops.contains(&(*id, true)) {
+                writeln!(out, "{}__broke_{}=true;", ind, id).unwrap();
+            }
+            writeln!(out, "{}break;", ind).unwrap();
+        }
+        Inst::Continue(_) => writeln!(out, "{}continue;", ind).unwrap(),
+        Inst::Switch { .. } => unreachable!("switches are expanded first"),
+        Inst::Cpp(_) => unreachable!("native modules are rejected up front"),
+        Inst::Return(v) => {
+            let v = cg.operand(v);
+            writeln!(out, "{}return {};", ind, v).unwrap();
+        }
+    }
+}
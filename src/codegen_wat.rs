@@ -0,0 +1,643 @@
+//! IR → WebAssembly text (WAT) for WASI runtimes such as wasmtime.
+//!
+//! Values are NaN-boxed `i64`s: anything that is not a quiet NaN with the
+//! `0x7FFC` prefix is a number, the rest carry a tag (undefined, bool,
+//! string, object) and a pointer into linear memory. Strings are
+//! `[len i32][bytes]`; objects are immutable arrays of `(name id, value)`
+//! pairs sorted by id, so assignment copies for free. Memory is a bump heap
+//! that is never freed, which suits judge-sized programs.
+//!
+//! Every identifier and field name gets an id in byte order, so sorting by id
+//! prints object fields in the same order as the C++ runtime. A function's
+//! frame lives on a shadow stack and holds one slot per variable it assigns
+//! (its IR slots); reads of other names walk the caller frames, which
+//! keeps the dynamic scoping of the C++ `Env` chain. `log`/`print` go through
+//! a buffer flushed with `fd_write`, `input` reads tokens with `fd_read`.
+//!
+//! `format` needs a literal format string: it is split with `format_spec` at
+//! compile time instead of embedding a runtime parser.
+//! Field ids are fixed at compile time too, so `x[k]` finds a field by
+//! comparing names, and setting or listing fields by a computed key is a
+//! compile error.
+
+use crate::ast::Span;
+use crate::bytecode::BinOp;
+use crate::codegen_cpp::{indent, CppOptions};
+use crate::diag::Diagnostic;
+use crate::format_spec::{self, Piece};
+use crate::ir::*;
+use crate::math::Math;
+use std::collections::{BTreeSet, HashMap};
+use std::fmt::Write;
+
+/// Where string literals and tables start; everything below is runtime
+/// scratch space (output/input buffers, bignum digits, the frame stack).
+const DATA_BASE: u32 = 0x120000;
+const UNDEF: &str = "(i64.const 0x7FFC000000000000)";
+
+/// Objects only hold field names known at compile time, so `x[k]` can read
+/// them but nothing may add or list fields by a computed name.
+const COMPUTED_KEYS: &str =
+    "--emit=wat cannot set or list fields by a computed key (`x[k]: v`, `has`, `remove`, `keys`, `values`)";
+
+/// WebAssembly has no instructions for these and the module imports no math
+/// library.
+const NO_LIBM: &str = "--emit=wat has no `pow`, `**` or trigonometric functions";
+
+/// Messages and fragments the runtime refers to as `$rs_NAME`.
+const RUNTIME_STRS: &[(&str, &str)] = &[
+    ("true", "true"),
+    ("false", "false"),
+    ("nan", "nan"),
+    ("ninf", "-inf"),
+    ("colon", ":"),
+    ("line", "line "),
+    ("rterr", ": runtime error: "),
+    ("nl", "\n"),
+    ("sp", " "),
+    ("zero", "0"),
+    ("lbrace", "{"),
+    ("rbrace", "}"),
+    ("sep", ", "),
+    ("tick", "`"),
+    ("oom", "out of memory\n"),
+    ("stack", "stack overflow"),
+    ("conv1", "cannot convert \""),
+    ("conv2", "\" to a number"),
+    ("objnum", "object used as a number"),
+    ("undefnum", "undefined value used as a number"),
+    ("div0", "division by zero"),
+    ("mod0", "modulo by zero"),
+    ("idx1", "string index "),
+    ("idx2", " out of range"),
+    ("prop1", "cannot read property `"),
+    ("prop2", "` of a non-object"),
+    ("prop3", "undefined property `"),
+    ("undef1", "undefined variable `"),
+    ("sqrt1", "sqrt("),
+    ("undefd", ") is undefined"),
+];
+
+pub fn compile_to_wat(m: &Module, opts: &CppOptions) -> Result<String, Diagnostic> {
+    if let Some(at) = m.native() {
+        let msg = "--emit=wat cannot run `cpp` blocks or call `extern` functions";
+        return Err(Diagnostic::new(msg, at));
+    }
+    let m = &expand_switches(m.clone());
+    let mut names = BTreeSet::new();
+    for f in m.funcs.iter().chain([&m.main]) {
+        names.extend(f.slots.iter().cloned());
+        collect_names(&f.body, &mut names);
+    }
+    let mut cx = Ctx {
+        data: Vec::new(),
+        strs: HashMap::new(),
+        ids: names
+            .iter()
+            .enumerate()
+            .map(|(i, n)| (n.clone(), i))
+            .collect(),
+        strict: opts.strict,
+        files: Vec::new(),
+    };
+    if let (Some(f), true) = (&opts.file, opts.strict && !m.files.is_empty()) {
+        cx.files = std::iter::once(f)
+            .chain(&m.files)
+            .map(|f| cx.str(f))
+            .collect();
+    }
+
+    let mut funcs = String::new();
+    for f in &m.funcs {
+        emit_func(&mut funcs, &mut cx, &format!("fn_{}", f.name), f)?;
+    }
+    emit_func(&mut funcs, &mut cx, "pna_main", &m.main)?;
+
+    let mut out = String::new();
+    if let Some(f) = &opts.file {
+        writeln!(out, ";; Generated by pna-cc from {}", f).unwrap();
+    }
+    out.push_str("(module\n");
+    out.push_str("(import \"wasi_snapshot_preview1\" \"fd_write\" (func $fd_write (param i32 i32 i32 i32) (result i32)))");
+    out.push_str("(import \"wasi_snapshot_preview1\" \"fd_read\" (func $fd_read (param i32 i32 i32 i32) (result i32)))");
+    out.push_str("(import \"wasi_snapshot_preview1\" \"proc_exit\" (func $proc_exit (param i32)))");
+    out.push_str("(global $outlen (mut i32) (i32.const 0))");
+    out.push_str("(global $inpos (mut i32) (i32.const 0))");
+    out.push_str("(global $inlen (mut i32) (i32.const 0))");
+    out.push_str("(global $ineof (mut i32) (i32.const 0))");
+    out.push_str("(global $sink (mut i32) (i32.const 0))");
+    out.push_str("(global $line (mut i32) (i32.const 0))");
+    out.push_str("(global $fp (mut i32) (i32.const 0))");
+    out.push_str("(global $fsp (mut i32) (i32.const 95232))");
+    out.push_str("(global $nl (mut i32) (i32.const 0))");
+    out.push_str("(global $fx_ptr (mut i32) (i32.const 0))");
+    out.push_str("(global $fx_len (mut i32) (i32.const 0))");
+    out.push_str("(func $tag (param $v i64) (result i32) (if (result i32) (i64.eq (i64.shr_u (local.get $v) (i64.const 48)) (i64.const 0x7FFC)) (then (i32.add (i32.and (i32.wrap_i64 (i64.shr_u (local.get $v) (i64.const 32))) (i32.const 0xFFFF)) (i32.const 1))) (else (i32.const 0))))");
+    out.push_str("(func $ptr (param $v i64) (result i32) (i32.wrap_i64 (local.get $v)))");
+    out.push_str("(func $f (param $v i64) (result f64) (f64.reinterpret_i64 (local.get $v)))");
+    out.push_str("(func $num (param $d f64) (result i64) (i64.reinterpret_f64 (local.get $d)))");
+    out.push_str("(func $bool (param $b i32) (result i64) (i64.or (i64.const 0x7FFC000100000000) (i64.extend_i32_u (i32.ne (local.get $b) (i32.const 0)))))");
+    out.push_str("(func $str (param $p i32) (result i64) (i64.or (i64.const 0x7FFC000200000000) (i64.extend_i32_u (local.get $p))))");
+    out.push_str("(func $obj (param $p i32) (result i64) (i64.or (i64.const 0x7FFC000300000000) (i64.extend_i32_u (local.get $p))))");
+    out.push_str("(func $fd_write_all (param $fd i32) (param $p i32) (param $n i32) (loop $again (if (i32.gt_s (local.get $n) (i32.const 0)) (then (i32.store (i32.const 0) (local.get $p)) (i32.store (i32.const 4) (local.get $n)) (if (call $fd_write (local.get $fd) (i32.const 0) (i32.const 1) (i32.const 8)) (then (return))) (local.set $p (i32.add (local.get $p) (i32.load (i32.const 8)))) (local.set $n (i32.sub (local.get $n) (i32.load (i32.const 8)))) (br $again)))))");
+    out.push_str("(func $flush (call $fd_write_all (i32.const 1) (i32.const 1024) (global.get $outlen)) (global.set $outlen (i32.const 0)))");
+    out.push_str("(func $ensure (param $end i32) (local $have i32) (local.set $have (i32.shl (memory.size) (i32.const 16))) (if (i32.gt_u (local.get $end) (local.get $have)) (then (if (i32.lt_s (memory.grow (i32.add (i32.shr_u (i32.sub (local.get $end) (local.get $have)) (i32.const 16)) (i32.const 1))) (i32.const 0)) (then (call $flush) (call $fd_write_all (i32.const 2) (i32.add (global.get $rs_oom) (i32.const 4)) (i32.load (global.get $rs_oom))) (call $proc_exit (i32.const 1)))))))");
+    out.push_str("(func $alloc (param $n i32) (result i32) (local $p i32) (local.set $p (i32.and (i32.add (global.get $hp) (i32.const 7)) (i32.const -8))) (global.set $hp (i32.add (local.get $p) (local.get $n))) (call $ensure (global.get $hp)) (local.get $p))");
+    out.push_str("(func $sb_begin (result i32) (call $alloc (i32.const 4)))");
+    out.push_str("(func $sb_put (param $p i32) (param $n i32) (call $ensure (i32.add (global.get $hp) (local.get $n))) (memory.copy (global.get $hp) (local.get $p) (local.get $n)) (global.set $hp (i32.add (global.get $hp) (local.get $n))))");
+    out.push_str("(func $sb_end (param $s i32) (result i32) (i32.store (local.get $s) (i32.sub (i32.sub (global.get $hp) (local.get $s)) (i32.const 4))) (local.get $s))");
+    out.push_str("(func $out (param $p i32) (param $n i32) (if (global.get $sink) (then (call $sb_put (local.get $p) (local.get $n)) (return))) (if (i32.gt_u (i32.add (global.get $outlen) (local.get $n)) (i32.const 65536)) (then (call $flush))) (if (i32.gt_u (local.get $n) (i32.const 65536)) (then (call $fd_write_all (i32.const 1) (local.get $p) (local.get $n)) (return))) (memory.copy (i32.add (i32.const 1024) (global.get $outlen)) (local.get $p) (local.get $n)) (global.set $outlen (i32.add (global.get $outlen) (local.get $n))))");
+    out.push_str("(func $out_s (param $s i32) (call $out (i32.add (local.get $s) (i32.const 4)) (i32.load (local.get $s))))");
+    out.push_str("(func $concat (param $a i32) (param $b i32) (result i32) (local $s i32) (local.set $s (call $sb_begin)) (call $sb_put (i32.add (local.get $a) (i32.const 4)) (i32.load (local.get $a))) (call $sb_put (i32.add (local.get $b) (i32.const 4)) (i32.load (local.get $b))) (call $sb_end (local.get $s)))");
+    out.push_str("(func $fail (param $m i32) (call $flush) (if (global.get $where) (then (call $fd_write_all (i32.const 2) (i32.add (global.get $where) (i32.const 4)) (i32.load (global.get $where))) (call $fd_write_all (i32.const 2) (i32.add (global.get $rs_colon) (i32.const 4)) (i32.const 1))) (else (call $fd_write_all (i32.const 2) (i32.add (global.get $rs_line) (i32.const 4)) (i32.load (global.get $rs_line))))) (call $fmt_fixed (f64.convert_i32_s (global.get $line)) (i32.const 0)) (call $fd_write_all (i32.const 2) (global.get $fx_ptr) (global.get $fx_len)) (call $fd_write_all (i32.const 2) (i32.add (global.get $rs_rterr) (i32.const 4)) (i32.load (global.get $rs_rterr))) (call $fd_write_all (i32.const 2) (i32.add (local.get $m) (i32.const 4)) (i32.load (local.get $m))) (call $fd_write_all (i32.const 2) (i32.add (global.get $rs_nl) (i32.const 4)) (i32.const 1)) (call $proc_exit (i32.const 1)))");
+    out.push_str("(func $fail3 (param $a i32) (param $b i32) (param $c i32) (call $fail (call $concat (call $concat (local.get $a) (local.get $b)) (local.get $c))))");
+    out.push_str("(func $bn_mul (param $m i64) (local $i i32) (local $c i64) (local $x i64) (local.set $i (i32.const 0)) (loop $l (if (i32.lt_u (local.get $i) (global.get $nl)) (then (local.set $x (i64.add (i64.mul (i64.load32_u (i32.add (i32.const 70656) (i32.shl (local.get $i) (i32.const 2)))) (local.get $m)) (local.get $c))) (i64.store32 (i32.add (i32.const 70656) (i32.shl (local.get $i) (i32.const 2))) (i64.rem_u (local.get $x) (i64.const 1000000000))) (local.set $c (i64.div_u (local.get $x) (i64.const 1000000000))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l)))) (loop $l2 (if (i64.ne (local.get $c) (i64.const 0)) (then (i64.store32 (i32.add (i32.const 70656) (i32.shl (global.get $nl) (i32.const 2))) (i64.rem_u (local.get $c) (i64.const 1000000000))) (local.set $c (i64.div_u (local.get $c) (i64.const 1000000000))) (global.set $nl (i32.add (global.get $nl) (i32.const 1))) (br $l2)))))");
+    out.push_str("(func $bn_digits (param $at i32) (result i32) (local $i i32) (local $n i32) (local $x i32) (local $k i32) (local.set $i (global.get $nl)) (loop $l (if (i32.gt_s (local.get $i) (i32.const 0)) (then (local.set $i (i32.sub (local.get $i) (i32.const 1))) (local.set $x (i32.load (i32.add (i32.const 70656) (i32.shl (local.get $i) (i32.const 2))))) (local.set $k (i32.const 9)) (loop $d (if (local.get $k) (then (local.set $k (i32.sub (local.get $k) (i32.const 1))) (i32.store8 (i32.add (i32.add (local.get $at) (local.get $n)) (local.get $k)) (i32.add (i32.const 48) (i32.rem_u (local.get $x) (i32.const 10)))) (local.set $x (i32.div_u (local.get $x) (i32.const 10))) (br $d)))) (local.set $n (i32.add (local.get $n) (i32.const 9))) (br $l)))) (local.set $k (i32.const 0)) (loop $z (if (i32.and (i32.lt_u (local.get $k) (i32.sub (local.get $n) (i32.const 1))) (i32.eq (i32.load8_u (i32.add (local.get $at) (local.get $k))) (i32.const 48))) (then (local.set $k (i32.add (local.get $k) (i32.const 1))) (br $z)))) (memory.copy (local.get $at) (i32.add (local.get $at) (local.get $k)) (i32.sub (local.get $n) (local.get $k))) (i32.sub (local.get $n) (local.get $k)))");
+    out.push_str("(func $fmt_fixed (param $d f64) (param $prec i32) (local $bits i64) (local $m i64) (local $e i32) (local $k i32) (local $n i32) (local $at i32) (local $dig i32) (local $rest i32) (local $up i32) (local $i i32) (local $c i32) (local $neg i32) (local.set $at (i32.const 78850)) (local.set $bits (i64.reinterpret_f64 (local.get $d))) (local.set $neg (i32.wrap_i64 (i64.shr_u (local.get $bits) (i64.const 63)))) (if (f64.ne (local.get $d) (local.get $d)) (then (global.set $fx_ptr (i32.add (global.get $rs_nan) (i32.const 4))) (global.set $fx_len (i32.const 3)) (return))) (if (f64.eq (f64.abs (local.get $d)) (f64.const inf)) (then (global.set $fx_ptr (i32.add (global.get $rs_ninf) (i32.sub (i32.const 5) (local.get $neg)))) (global.set $fx_len (i32.add (i32.const 3) (local.get $neg))) (return))) (if (i32.gt_s (local.get $prec) (i32.const 1000)) (then (local.set $prec (i32.const 1000)))) (local.set $e (i32.wrap_i64 (i64.and (i64.shr_u (local.get $bits) (i64.const 52)) (i64.const 0x7FF)))) (local.set $m (i64.and (local.get $bits) (i64.const 0xFFFFFFFFFFFFF))) (if (local.get $e) (then (local.set $m (i64.or (local.get $m) (i64.const 0x10000000000000))) (local.set $e (i32.sub (local.get $e) (i32.const 1075)))) (else (local.set $e (i32.const -1074)))) (if (i64.eqz (local.get $m)) (then (local.set $e (i32.const 0)))) (loop $norm (if (i32.and (i64.eqz (i64.and (local.get $m) (i64.const 1))) (i32.and (i32.lt_s (local.get $e) (i32.const 0)) (i64.ne (local.get $m) (i64.const 0)))) (then (local.set $m (i64.shr_u (local.get $m) (i64.const 1))) (local.set $e (i32.add (local.get $e) (i32.const 1))) (br $norm)))) (i32.store (i32.const 70656) (i32.wrap_i64 (i64.rem_u (local.get $m) (i64.const 1000000000)))) (i32.store (i32.const 70660) (i32.wrap_i64 (i64.div_u (local.get $m) (i64.const 1000000000)))) (global.set $nl (i32.const 2)) (loop $p2 (if (i32.gt_s (local.get $e) (i32.const 0)) (then (if (i32.ge_s (local.get $e) (i32.const 29)) (then (call $bn_mul (i64.const 536870912)) (local.set $e (i32.sub (local.get $e) (i32.const 29)))) (else (call $bn_mul (i64.shl (i64.const 1) (i64.extend_i32_u (local.get $e)))) (local.set $e (i32.const 0)))) (br $p2)))) (local.set $k (i32.sub (i32.const 0) (local.get $e))) (local.set $i (local.get $k)) (loop $p5 (if (i32.gt_s (local.get $i) (i32.const 0)) (then (if (i32.ge_s (local.get $i) (i32.const 13)) (then (call $bn_mul (i64.const 1220703125)) (local.set $i (i32.sub (local.get $i) (i32.const 13)))) (else (call $bn_mul (i64.const 5)) (local.set $i (i32.sub (local.get $i) (i32.const 1))))) (br $p5)))) (local.set $n (call $bn_digits (local.get $at))) (memory.fill (i32.add (local.get $at) (local.get $n)) (i32.const 48) (local.get $prec)) (local.set $n (i32.add (local.get $n) (local.get $prec))) (if (i32.lt_s (local.get $n) (i32.add (i32.add (local.get $k) (local.get $prec)) (i32.const 1))) (then (local.set $i (i32.sub (i32.add (i32.add (local.get $k) (local.get $prec)) (i32.const 1)) (local.get $n))) (memory.copy (i32.add (local.get $at) (local.get $i)) (local.get $at) (local.get $n)) (memory.fill (local.get $at) (i32.const 48) (local.get $i)) (local.set $n (i32.add (local.get $n) (local.get $i))))) (if (local.get $k) (then (local.set $dig (i32.sub (i32.load8_u (i32.add (local.get $at) (i32.sub (local.get $n) (local.get $k)))) (i32.const 48))) (local.set $i (i32.add (i32.sub (local.get $n) (local.get $k)) (i32.const 1))) (loop $r (if (i32.lt_u (local.get $i) (local.get $n)) (then (if (i32.ne (i32.load8_u (i32.add (local.get $at) (local.get $i))) (i32.const 48)) (then (local.set $rest (i32.const 1)))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $r)))) (local.set $n (i32.sub (local.get $n) (local.get $k))) (local.set $up (i32.or (i32.gt_u (local.get $dig) (i32.const 5)) (i32.and (i32.eq (local.get $dig) (i32.const 5)) (i32.or (local.get $rest) (i32.and (i32.load8_u (i32.add (local.get $at) (i32.sub (local.get $n) (i32.const 1)))) (i32.const 1)))))) (if (local.get $up) (then (local.set $i (local.get $n)) (local.set $c (i32.const 1)) (loop $inc (if (i32.and (local.get $c) (i32.gt_s (local.get $i) (i32.const 0))) (then (local.set $i (i32.sub (local.get $i) (i32.const 1))) (if (i32.eq (i32.load8_u (i32.add (local.get $at) (local.get $i))) (i32.const 57)) (then (i32.store8 (i32.add (local.get $at) (local.get $i)) (i32.const 48))) (else (i32.store8 (i32.add (local.get $at) (local.get $i)) (i32.add (i32.load8_u (i32.add (local.get $at) (local.get $i))) (i32.const 1))) (local.set $c (i32.const 0)))) (br $inc)))) (if (local.get $c) (then (local.set $at (i32.sub (local.get $at) (i32.const 1))) (i32.store8 (local.get $at) (i32.const 49)) (local.set $n (i32.add (local.get $n) (i32.const 1))))))))) (if (local.get $prec) (then (memory.copy (i32.add (i32.add (local.get $at) (i32.sub (local.get $n) (local.get $prec))) (i32.const 1)) (i32.add (local.get $at) (i32.sub (local.get $n) (local.get $prec))) (local.get $prec)) (i32.store8 (i32.add (local.get $at) (i32.sub (local.get $n) (local.get $prec))) (i32.const 46)) (local.set $n (i32.add (local.get $n) (i32.const 1))))) (if (local.get $neg) (then (local.set $at (i32.sub (local.get $at) (i32.const 1))) (i32.store8 (local.get $at) (i32.const 45)) (local.set $n (i32.add (local.get $n) (i32.const 1))))) (global.set $fx_ptr (local.get $at)) (global.set $fx_len (local.get $n)))");
+    out.push_str("(func $num_out (param $d f64) (local $rd f64) (local $n i32) (local.set $rd (local.get $d)) (if (f64.lt (f64.abs (local.get $d)) (f64.const 0x1p52)) (then (local.set $rd (f64.copysign (f64.floor (f64.add (f64.abs (local.get $d)) (f64.const 0.5))) (local.get $d))))) (if (f64.lt (f64.abs (f64.sub (local.get $d) (local.get $rd))) (f64.const 1e-9)) (then (call $fmt_fixed (local.get $rd) (i32.const 0)) (call $out (global.get $fx_ptr) (global.get $fx_len)) (return))) (call $fmt_fixed (local.get $d) (i32.const 12)) (local.set $n (global.get $fx_len)) (if (i32.ne (i32.load8_u (global.get $fx_ptr)) (i32.const 110)) (then (loop $t (if (i32.eq (i32.load8_u (i32.add (global.get $fx_ptr) (i32.sub (local.get $n) (i32.const 1)))) (i32.const 48)) (then (local.set $n (i32.sub (local.get $n) (i32.const 1))) (br $t)))) (if (i32.eq (i32.load8_u (i32.add (global.get $fx_ptr) (i32.sub (local.get $n) (i32.const 1)))) (i32.const 46)) (then (local.set $n (i32.sub (local.get $n) (i32.const 1))))))) (call $out (global.get $fx_ptr) (local.get $n)))");
+    out.push_str("(func $write_val (param $v i64) (local $t i32) (local $p i32) (local $i i32) (local.set $t (call $tag (local.get $v))) (local.set $p (call $ptr (local.get $v))) (if (i32.eqz (local.get $t)) (then (call $num_out (call $f (local.get $v))) (return))) (if (i32.eq (local.get $t) (i32.const 2)) (then (if (local.get $p) (then (call $out_s (global.get $rs_true))) (else (call $out_s (global.get $rs_false)))) (return))) (if (i32.eq (local.get $t) (i32.const 3)) (then (call $out_s (local.get $p)) (return))) (if (i32.eq (local.get $t) (i32.const 4)) (then (call $out_s (global.get $rs_lbrace)) (loop $l (if (i32.lt_u (local.get $i) (i32.load (local.get $p))) (then (if (local.get $i) (then (call $out_s (global.get $rs_sep)))) (call $out_s (i32.load (i32.add (global.get $names) (i32.shl (i32.load (i32.add (i32.add (local.get $p) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4)))) (i32.const 2))))) (call $out_s (global.get $rs_colon)) (call $write_val (i64.load (i32.add (i32.add (local.get $p) (i32.const 16)) (i32.shl (local.get $i) (i32.const 4))))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l)))) (call $out_s (global.get $rs_rbrace)))))");
+    out.push_str("(func $as_str (param $v i64) (result i32) (local $s i32) (local $sk i32) (if (i32.eq (call $tag (local.get $v)) (i32.const 3)) (then (return (call $ptr (local.get $v))))) (local.set $sk (global.get $sink)) (global.set $sink (i32.const 1)) (local.set $s (call $sb_begin)) (call $write_val (local.get $v)) (global.set $sink (local.get $sk)) (call $sb_end (local.get $s)))");
+    out.push_str("(global $pn (mut f64) (f64.const 0))");
+    out.push_str("(func $is_space (param $c i32) (result i32) (i32.or (i32.eq (local.get $c) (i32.const 32)) (i32.lt_u (i32.sub (local.get $c) (i32.const 9)) (i32.const 5))))");
+    out.push_str("(func $scale (param $d f64) (param $e i32) (result f64) (local $p f64) (local $neg i32) (loop $hi (if (i32.gt_s (local.get $e) (i32.const 22)) (then (local.set $d (f64.mul (local.get $d) (f64.const 1e22))) (local.set $e (i32.sub (local.get $e) (i32.const 22))) (br $hi)))) (loop $lo (if (i32.lt_s (local.get $e) (i32.const -22)) (then (local.set $d (f64.div (local.get $d) (f64.const 1e22))) (local.set $e (i32.add (local.get $e) (i32.const 22))) (br $lo)))) (local.set $neg (i32.lt_s (local.get $e) (i32.const 0))) (if (local.get $neg) (then (local.set $e (i32.sub (i32.const 0) (local.get $e))))) (local.set $p (f64.const 1)) (loop $m (if (local.get $e) (then (local.set $p (f64.mul (local.get $p) (f64.const 10))) (local.set $e (i32.sub (local.get $e) (i32.const 1))) (br $m)))) (if (result f64) (local.get $neg) (then (f64.div (local.get $d) (local.get $p))) (else (f64.mul (local.get $d) (local.get $p)))))");
+    out.push_str("(func $lower (param $p i32) (result i32) (i32.or (i32.load8_u (local.get $p)) (i32.const 32)))");
+    out.push_str("(func $parse_num (param $p i32) (param $n i32) (result i32) (local $i i32) (local $c i32) (local $neg i32) (local $m i64) (local $e i32) (local $nd i32) (local $dot i32) (local $x i32) (local $xneg i32) (local $xd i32) (local $end i32) (local.set $end (i32.add (local.get $p) (local.get $n))) (loop $ws (if (i32.and (i32.lt_u (local.get $p) (local.get $end)) (call $is_space (i32.load8_u (local.get $p)))) (then (local.set $p (i32.add (local.get $p) (i32.const 1))) (br $ws)))) (if (i32.ge_u (local.get $p) (local.get $end)) (then (return (i32.const 0)))) (local.set $c (i32.load8_u (local.get $p))) (if (i32.or (i32.eq (local.get $c) (i32.const 43)) (i32.eq (local.get $c) (i32.const 45))) (then (local.set $neg (i32.eq (local.get $c) (i32.const 45))) (local.set $p (i32.add (local.get $p) (i32.const 1))))) (local.set $n (i32.sub (local.get $end) (local.get $p))) (if (i32.and (i32.ge_u (local.get $n) (i32.const 3)) (i32.and (i32.eq (call $lower (local.get $p)) (i32.const 110)) (i32.and (i32.eq (call $lower (i32.add (local.get $p) (i32.const 1))) (i32.const 97)) (i32.eq (call $lower (i32.add (local.get $p) (i32.const 2))) (i32.const 110))))) (then (global.set $pn (f64.const nan)) (return (i32.eq (local.get $n) (i32.const 3))))) (if (i32.and (i32.ge_u (local.get $n) (i32.const 3)) (i32.and (i32.eq (call $lower (local.get $p)) (i32.const 105)) (i32.and (i32.eq (call $lower (i32.add (local.get $p) (i32.const 1))) (i32.const 110)) (i32.eq (call $lower (i32.add (local.get $p) (i32.const 2))) (i32.const 102))))) (then (global.set $pn (f64.copysign (f64.const inf) (if (result f64) (local.get $neg) (then (f64.const -1)) (else (f64.const 1))))) (if (i32.eq (local.get $n) (i32.const 3)) (then (return (i32.const 1)))) (if (i32.ne (local.get $n) (i32.const 8)) (then (return (i32.const 0)))) (return (i32.and (i32.and (i32.and (i32.eq (call $lower (i32.add (local.get $p) (i32.const 3))) (i32.const 105)) (i32.eq (call $lower (i32.add (local.get $p) (i32.const 4))) (i32.const 110))) (i32.and (i32.eq (call $lower (i32.add (local.get $p) (i32.const 5))) (i32.const 105)) (i32.eq (call $lower (i32.add (local.get $p) (i32.const 6))) (i32.const 116)))) (i32.eq (call $lower (i32.add (local.get $p) (i32.const 7))) (i32.const 121)))))) (block $mant (loop $dl (br_if $mant (i32.ge_u (local.get $p) (local.get $end))) (local.set $c (i32.load8_u (local.get $p))) (if (i32.and (i32.eq (local.get $c) (i32.const 46)) (i32.eqz (local.get $dot))) (then (local.set $dot (i32.const 1)) (local.set $p (i32.add (local.get $p) (i32.const 1))) (br $dl))) (br_if $mant (i32.gt_u (i32.sub (local.get $c) (i32.const 48)) (i32.const 9))) (local.set $nd (i32.add (local.get $nd) (i32.const 1))) (if (i64.lt_u (local.get $m) (i64.const 100000000000000000)) (then (local.set $m (i64.add (i64.mul (local.get $m) (i64.const 10)) (i64.extend_i32_u (i32.sub (local.get $c) (i32.const 48))))) (if (local.get $dot) (then (local.set $e (i32.sub (local.get $e) (i32.const 1)))))) (else (if (i32.eqz (local.get $dot)) (then (local.set $e (i32.add (local.get $e) (i32.const 1))))))) (local.set $p (i32.add (local.get $p) (i32.const 1))) (br $dl))) (if (i32.eqz (local.get $nd)) (then (return (i32.const 0)))) (if (i32.and (i32.lt_u (local.get $p) (local.get $end)) (i32.eq (call $lower (local.get $p)) (i32.const 101))) (then (local.set $p (i32.add (local.get $p) (i32.const 1))) (if (i32.lt_u (local.get $p) (local.get $end)) (then (local.set $c (i32.load8_u (local.get $p))) (if (i32.or (i32.eq (local.get $c) (i32.const 43)) (i32.eq (local.get $c) (i32.const 45))) (then (local.set $xneg (i32.eq (local.get $c) (i32.const 45))) (local.set $p (i32.add (local.get $p) (i32.const 1))))))) (block $xd_ (loop $xl (br_if $xd_ (i32.ge_u (local.get $p) (local.get $end))) (local.set $c (i32.load8_u (local.get $p))) (br_if $xd_ (i32.gt_u (i32.sub (local.get $c) (i32.const 48)) (i32.const 9))) (if (i32.lt_s (local.get $x) (i32.const 100000)) (then (local.set $x (i32.add (i32.mul (local.get $x) (i32.const 10)) (i32.sub (local.get $c) (i32.const 48)))))) (local.set $xd (i32.const 1)) (local.set $p (i32.add (local.get $p) (i32.const 1))) (br $xl))) (if (i32.eqz (local.get $xd)) (then (return (i32.const 0)))) (local.set $e (i32.add (local.get $e) (if (result i32) (local.get $xneg) (then (i32.sub (i32.const 0) (local.get $x))) (else (local.get $x))))))) (if (i32.ne (local.get $p) (local.get $end)) (then (return (i32.const 0)))) (global.set $pn (call $scale (f64.convert_i64_u (local.get $m)) (local.get $e))) (if (local.get $neg) (then (global.set $pn (f64.neg (global.get $pn))))) (i32.const 1))");
+    out.push_str("(func $name (param $k i32) (result i32) (i32.load (i32.add (global.get $names) (i32.shl (local.get $k) (i32.const 2)))))");
+    out.push_str("(func $as_num (param $v i64) (result f64) (local $t i32) (local $p i32) (local.set $t (call $tag (local.get $v))) (local.set $p (call $ptr (local.get $v))) (if (i32.eqz (local.get $t)) (then (return (call $f (local.get $v))))) (if (i32.eq (local.get $t) (i32.const 2)) (then (return (f64.convert_i32_u (local.get $p))))) (if (i32.eq (local.get $t) (i32.const 3)) (then (if (call $parse_num (i32.add (local.get $p) (i32.const 4)) (i32.load (local.get $p))) (then (return (global.get $pn)))) (if (global.get $strict) (then (call $fail3 (global.get $rs_conv1) (local.get $p) (global.get $rs_conv2)))) (return (f64.const 0)))) (if (global.get $strict) (then (call $fail (if (result i32) (i32.eq (local.get $t) (i32.const 4)) (then (global.get $rs_objnum)) (else (global.get $rs_undefnum)))))) (f64.const 0))");
+    out.push_str("(func $truthy (param $v i64) (result i32) (local $t i32) (local $p i32) (local.set $t (call $tag (local.get $v))) (local.set $p (call $ptr (local.get $v))) (if (i32.eqz (local.get $t)) (then (return (f64.ne (call $f (local.get $v)) (f64.const 0))))) (if (i32.eq (local.get $t) (i32.const 1)) (then (return (i32.const 0)))) (if (i32.eq (local.get $t) (i32.const 2)) (then (return (local.get $p)))) (i32.ne (i32.load (local.get $p)) (i32.const 0)))");
+    out.push_str("(func $str_eq (param $a i32) (param $b i32) (result i32) (local $i i32) (local $n i32) (local.set $n (i32.load (local.get $a))) (if (i32.ne (local.get $n) (i32.load (local.get $b))) (then (return (i32.const 0)))) (loop $l (if (i32.lt_u (local.get $i) (local.get $n)) (then (if (i32.ne (i32.load8_u (i32.add (i32.add (local.get $a) (i32.const 4)) (local.get $i))) (i32.load8_u (i32.add (i32.add (local.get $b) (i32.const 4)) (local.get $i)))) (then (return (i32.const 0)))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l)))) (i32.const 1))");
+    out.push_str("(func $same (param $a i64) (param $b i64) (result i32) (if (i32.eqz (i32.or (call $tag (local.get $a)) (call $tag (local.get $b)))) (then (return (f64.eq (call $f (local.get $a)) (call $f (local.get $b)))))) (call $str_eq (call $as_str (local.get $a)) (call $as_str (local.get $b))))");
+    out.push_str("(func $eq (param $a i64) (param $b i64) (result i64) (call $bool (call $same (local.get $a) (local.get $b))))");
+    out.push_str("(func $neq (param $a i64) (param $b i64) (result i64) (call $bool (i32.eqz (call $same (local.get $a) (local.get $b)))))");
+    out.push_str("(func $lt (param $a i64) (param $b i64) (result i64) (call $bool (f64.lt (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $le (param $a i64) (param $b i64) (result i64) (call $bool (f64.le (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $gt (param $a i64) (param $b i64) (result i64) (call $bool (f64.gt (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $ge (param $a i64) (param $b i64) (result i64) (call $bool (f64.ge (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $and (param $a i64) (param $b i64) (result i64) (call $bool (i32.and (call $truthy (local.get $a)) (call $truthy (local.get $b)))))");
+    out.push_str("(func $or (param $a i64) (param $b i64) (result i64) (call $bool (i32.or (call $truthy (local.get $a)) (call $truthy (local.get $b)))))");
+    out.push_str("(func $not (param $a i64) (result i64) (call $bool (i32.eqz (call $truthy (local.get $a)))))");
+    out.push_str("(func $add (param $a i64) (param $b i64) (result i64) (if (i32.or (i32.eq (call $tag (local.get $a)) (i32.const 3)) (i32.eq (call $tag (local.get $b)) (i32.const 3))) (then (return (call $str (call $concat (call $as_str (local.get $a)) (call $as_str (local.get $b))))))) (call $num (f64.add (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $sub (param $a i64) (param $b i64) (result i64) (call $num (f64.sub (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $clen (param $c i32) (result i32) (if (result i32) (i32.lt_u (local.get $c) (i32.const 0x80)) (then (i32.const 1)) (else (if (result i32) (i32.eq (i32.shr_u (local.get $c) (i32.const 5)) (i32.const 6)) (then (i32.const 2)) (else (if (result i32) (i32.eq (i32.shr_u (local.get $c) (i32.const 4)) (i32.const 14)) (then (i32.const 3)) (else (if (result i32) (i32.eq (i32.shr_u (local.get $c) (i32.const 3)) (i32.const 30)) (then (i32.const 4)) (else (i32.const 1))))))))))");
+    out.push_str("(func $u8len (param $p i32) (param $n i32) (result i32) (local $i i32) (local $k i32) (loop $l (if (i32.lt_u (local.get $i) (local.get $n)) (then (local.set $i (i32.add (local.get $i) (call $clen (i32.load8_u (i32.add (local.get $p) (local.get $i)))))) (local.set $k (i32.add (local.get $k) (i32.const 1))) (br $l)))) (local.get $k))");
+    out.push_str("(func $u8off (param $p i32) (param $n i32) (param $cp i32) (result i32) (local $i i32) (loop $l (if (i32.and (i32.gt_s (local.get $cp) (i32.const 0)) (i32.lt_u (local.get $i) (local.get $n))) (then (local.set $i (i32.add (local.get $i) (call $clen (i32.load8_u (i32.add (local.get $p) (local.get $i)))))) (local.set $cp (i32.sub (local.get $cp) (i32.const 1))) (br $l)))) (if (result i32) (i32.lt_u (local.get $i) (local.get $n)) (then (local.get $i)) (else (local.get $n))))");
+    out.push_str("(func $round (param $d f64) (result f64) (if (result f64) (f64.lt (f64.abs (local.get $d)) (f64.const 0x1p52)) (then (f64.copysign (f64.floor (f64.add (f64.abs (local.get $d)) (f64.const 0.5))) (local.get $d))) (else (local.get $d))))");
+    out.push_str("(func $repeat (param $s i32) (param $d f64) (result i64) (local $r i32) (local $i i32) (local $cl i32) (local $k i32) (local $n i32) (local $o i32) (local.set $d (call $round (local.get $d))) (if (f64.gt (local.get $d) (f64.const 0)) (then (local.set $r (i32.trunc_sat_f64_u (local.get $d))))) (local.set $n (i32.load (local.get $s))) (local.set $s (i32.add (local.get $s) (i32.const 4))) (local.set $o (call $sb_begin)) (loop $l (if (i32.lt_u (local.get $i) (local.get $n)) (then (local.set $cl (call $clen (i32.load8_u (i32.add (local.get $s) (local.get $i))))) (if (i32.gt_u (i32.add (local.get $i) (local.get $cl)) (local.get $n)) (then (local.set $cl (i32.sub (local.get $n) (local.get $i))))) (local.set $k (i32.const 0)) (loop $rep (if (i32.lt_u (local.get $k) (local.get $r)) (then (call $sb_put (i32.add (local.get $s) (local.get $i)) (local.get $cl)) (local.set $k (i32.add (local.get $k) (i32.const 1))) (br $rep)))) (local.set $i (i32.add (local.get $i) (local.get $cl))) (br $l)))) (call $str (call $sb_end (local.get $o))))");
+    out.push_str("(func $mul (param $a i64) (param $b i64) (result i64) (if (i32.and (i32.eq (call $tag (local.get $a)) (i32.const 3)) (i32.eqz (call $tag (local.get $b)))) (then (return (call $repeat (call $ptr (local.get $a)) (call $f (local.get $b)))))) (if (i32.and (i32.eqz (call $tag (local.get $a))) (i32.eq (call $tag (local.get $b)) (i32.const 3))) (then (return (call $repeat (call $ptr (local.get $b)) (call $f (local.get $a)))))) (call $num (f64.mul (call $as_num (local.get $a)) (call $as_num (local.get $b)))))");
+    out.push_str("(func $div (param $a i64) (param $b i64) (result i64) (local $r f64) (local $x f64) (local.set $r (call $as_num (local.get $b))) (if (i32.and (f64.eq (local.get $r) (f64.const 0)) (global.get $strict)) (then (call $fail (global.get $rs_div0)))) (local.set $x (call $as_num (local.get $a))) (call $num (if (result f64) (f64.eq (local.get $r) (f64.const 0)) (then (f64.const 0)) (else (f64.div (local.get $x) (local.get $r))))))");
+    out.push_str("(func $mod (param $a i64) (param $b i64) (result i64) (local $x f64) (local $y f64) (local $r f64) (local.set $x (call $as_num (local.get $a))) (local.set $y (call $as_num (local.get $b))) (if (f64.eq (local.get $y) (f64.const 0)) (then (if (global.get $strict) (then (call $fail (global.get $rs_mod0)))) (return (call $num (f64.const 0))))) (local.set $r (f64.sub (local.get $x) (f64.mul (f64.floor (f64.add (f64.div (local.get $x) (local.get $y)) (f64.const 1e-12))) (local.get $y)))) (if (f64.lt (f64.abs (local.get $r)) (f64.const 1e-12)) (then (local.set $r (f64.const 0)))) (call $num (local.get $r)))");
+    out.push_str("(func $math (param $d f64) (result i64) (call $num (f64.add (local.get $d) (f64.const 0))))");
+    out.push_str("(func $sqrt (param $a i64) (result i64) (local $x f64) (local.set $x (call $as_num (local.get $a))) (if (f64.lt (local.get $x) (f64.const 0)) (then (if (global.get $strict) (then (call $fail3 (global.get $rs_sqrt1) (call $as_str (call $num (local.get $x))) (global.get $rs_undefd)))) (return (call $num (f64.const 0))))) (call $math (f64.sqrt (local.get $x))))");
+    out.push_str("(func $gcd (param $a i64) (param $b i64) (result i64) (local $x i64) (local $y i64) (local $t i64) (local.set $x (i64.trunc_sat_f64_s (call $as_num (local.get $a)))) (local.set $y (i64.trunc_sat_f64_s (call $as_num (local.get $b)))) (if (i64.lt_s (local.get $x) (i64.const 0)) (then (local.set $x (i64.sub (i64.const 0) (local.get $x))))) (if (i64.lt_s (local.get $y) (i64.const 0)) (then (local.set $y (i64.sub (i64.const 0) (local.get $y))))) (loop $l (if (i64.ne (local.get $y) (i64.const 0)) (then (local.set $t (i64.rem_u (local.get $x) (local.get $y))) (local.set $x (local.get $y)) (local.set $y (local.get $t)) (br $l)))) (call $num (f64.convert_i64_u (local.get $x))))");
+    out.push_str("(func $len (param $v i64) (result i64) (local $t i32) (local $p i32) (local.set $t (call $tag (local.get $v))) (local.set $p (call $ptr (local.get $v))) (if (i32.eq (local.get $t) (i32.const 3)) (then (return (call $num (f64.convert_i32_u (call $u8len (i32.add (local.get $p) (i32.const 4)) (i32.load (local.get $p)))))))) (if (i32.eq (local.get $t) (i32.const 4)) (then (return (call $num (f64.convert_i32_u (i32.load (local.get $p))))))) (call $num (f64.const 0)))");
+    out.push_str("(func $substr (param $p i32) (param $n i32) (result i32) (local $s i32) (local.set $s (call $sb_begin)) (call $sb_put (local.get $p) (local.get $n)) (call $sb_end (local.get $s)))");
+    out.push_str("(func $index (param $b i64) (param $i i64) (result i64) (local $p i32) (local $n i32) (local $d f64) (local $at i32) (local $cl i32) (if (i32.eq (call $tag (local.get $b)) (i32.const 4)) (then (return (call $get_key (local.get $b) (call $as_str (local.get $i)))))) (if (i32.ne (call $tag (local.get $b)) (i32.const 3)) (then (return (i64.const 0x7FFC000000000000)))) (local.set $p (i32.add (call $ptr (local.get $b)) (i32.const 4))) (local.set $n (i32.load (call $ptr (local.get $b)))) (local.set $d (call $as_num (local.get $i))) (if (i32.or (i32.or (f64.lt (local.get $d) (f64.const 0)) (f64.ne (local.get $d) (f64.floor (local.get $d)))) (f64.ge (local.get $d) (f64.convert_i32_u (call $u8len (local.get $p) (local.get $n))))) (then (if (global.get $strict) (then (call $fail3 (global.get $rs_idx1) (call $as_str (local.get $i)) (global.get $rs_idx2)))) (return (i64.const 0x7FFC000000000000)))) (local.set $at (call $u8off (local.get $p) (local.get $n) (i32.trunc_sat_f64_u (local.get $d)))) (local.set $cl (call $clen (i32.load8_u (i32.add (local.get $p) (local.get $at))))) (if (i32.gt_u (i32.add (local.get $at) (local.get $cl)) (local.get $n)) (then (local.set $cl (i32.sub (local.get $n) (local.get $at))))) (call $str (call $substr (i32.add (local.get $p) (local.get $at)) (local.get $cl))))");
+    out.push_str("(func $obj_new (result i64) (local $p i32) (local.set $p (call $alloc (i32.const 8))) (i32.store (local.get $p) (i32.const 0)) (call $obj (local.get $p)))");
+    out.push_str("(func $obj_set (param $o i64) (param $k i32) (param $v i64) (result i64) (local $p i32) (local $n i32) (local $i i32) (local $rep i32) (local $q i32) (if (i32.eq (call $tag (local.get $o)) (i32.const 4)) (then (local.set $p (call $ptr (local.get $o))) (local.set $n (i32.load (local.get $p))))) (loop $f (if (i32.and (i32.lt_u (local.get $i) (local.get $n)) (i32.lt_u (i32.load (i32.add (i32.add (local.get $p) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4)))) (local.get $k))) (then (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $f)))) (local.set $rep (i32.and (i32.lt_u (local.get $i) (local.get $n)) (i32.eq (i32.load (i32.add (i32.add (local.get $p) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4)))) (local.get $k)))) (local.set $q (call $alloc (i32.add (i32.const 8) (i32.shl (i32.sub (i32.add (local.get $n) (i32.const 1)) (local.get $rep)) (i32.const 4))))) (i32.store (local.get $q) (i32.sub (i32.add (local.get $n) (i32.const 1)) (local.get $rep))) (memory.copy (i32.add (local.get $q) (i32.const 8)) (i32.add (local.get $p) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4))) (i32.store (i32.add (i32.add (local.get $q) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4))) (local.get $k)) (i64.store (i32.add (i32.add (local.get $q) (i32.const 16)) (i32.shl (local.get $i) (i32.const 4))) (local.get $v)) (memory.copy (i32.add (i32.add (local.get $q) (i32.const 8)) (i32.shl (i32.add (local.get $i) (i32.const 1)) (i32.const 4))) (i32.add (i32.add (local.get $p) (i32.const 8)) (i32.shl (i32.add (local.get $i) (local.get $rep)) (i32.const 4))) (i32.shl (i32.sub (i32.sub (local.get $n) (local.get $i)) (local.get $rep)) (i32.const 4))) (call $obj (local.get $q)))");
+    out.push_str("(func $get_prop (param $o i64) (param $k i32) (result i64) (local $p i32) (local $i i32) (if (i32.ne (call $tag (local.get $o)) (i32.const 4)) (then (if (global.get $strict) (then (call $fail3 (global.get $rs_prop1) (call $name (local.get $k)) (global.get $rs_prop2)))) (return (i64.const 0x7FFC000000000000)))) (local.set $p (call $ptr (local.get $o))) (loop $l (if (i32.lt_u (local.get $i) (i32.load (local.get $p))) (then (if (i32.eq (i32.load (i32.add (i32.add (local.get $p) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4)))) (local.get $k)) (then (return (i64.load (i32.add (i32.add (local.get $p) (i32.const 16)) (i32.shl (local.get $i) (i32.const 4))))))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l)))) (if (global.get $strict) (then (call $fail3 (global.get $rs_prop3) (call $name (local.get $k)) (global.get $rs_tick)))) (i64.const 0x7FFC000000000000))");
+    out.push_str("(func $get_key (param $o i64) (param $k i32) (result i64) (local $p i32) (local $i i32) (local.set $p (call $ptr (local.get $o))) (loop $l (if (i32.lt_u (local.get $i) (i32.load (local.get $p))) (then (if (call $str_eq (call $name (i32.load (i32.add (i32.add (local.get $p) (i32.const 8)) (i32.shl (local.get $i) (i32.const 4))))) (local.get $k)) (then (return (i64.load (i32.add (i32.add (local.get $p) (i32.const 16)) (i32.shl (local.get $i) (i32.const 4))))))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l)))) (if (global.get $strict) (then (call $fail3 (global.get $rs_prop3) (local.get $k) (global.get $rs_tick)))) (i64.const 0x7FFC000000000000))");
+    out.push_str("(func $enter (param $names i32) (result i32) (local $f i32) (local $n i32) (local $i i32) (local.set $f (global.get $fsp)) (local.set $n (i32.load (local.get $names))) (if (i32.gt_u (i32.add (local.get $f) (i32.add (i32.const 8) (i32.shl (local.get $n) (i32.const 3)))) (i32.const 1143808)) (then (call $fail (global.get $rs_stack)))) (i32.store (local.get $f) (global.get $fp)) (i32.store (i32.add (local.get $f) (i32.const 4)) (local.get $names)) (loop $l (if (i32.lt_u (local.get $i) (local.get $n)) (then (i64.store (i32.add (i32.add (local.get $f) (i32.const 8)) (i32.shl (local.get $i) (i32.const 3))) (i64.const 0x7FFD000000000000)) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l)))) (global.set $fsp (i32.add (local.get $f) (i32.add (i32.const 8) (i32.shl (local.get $n) (i32.const 3))))) (global.set $fp (local.get $f)) (local.get $f))");
+    out.push_str("(func $leave (param $f i32) (global.set $fsp (local.get $f)) (global.set $fp (i32.load (local.get $f))))");
+    out.push_str("(func $lookup (param $f i32) (param $k i32) (param $req i32) (result i64) (local $ns i32) (local $i i32) (local $v i64) (block $miss (loop $frames (br_if $miss (i32.eqz (local.get $f))) (local.set $ns (i32.load (i32.add (local.get $f) (i32.const 4)))) (local.set $i (i32.const 0)) (block $next (loop $l (br_if $next (i32.ge_u (local.get $i) (i32.load (local.get $ns)))) (if (i32.eq (i32.load (i32.add (i32.add (local.get $ns) (i32.const 4)) (i32.shl (local.get $i) (i32.const 2)))) (local.get $k)) (then (local.set $v (i64.load (i32.add (i32.add (local.get $f) (i32.const 8)) (i32.shl (local.get $i) (i32.const 3))))) (br_if $next (i64.eq (local.get $v) (i64.const 0x7FFD000000000000))) (return (local.get $v)))) (local.set $i (i32.add (local.get $i) (i32.const 1))) (br $l))) (local.set $f (i32.load (local.get $f))) (br $frames))) (if (i32.and (global.get $strict) (local.get $req)) (then (call $fail3 (global.get $rs_undef1) (call $name (local.get $k)) (global.get $rs_tick)))) (i64.const 0x7FFC000000000000))");
+    out.push_str("(func $get (param $s i32) (param $k i32) (result i64) (local $v i64) (local.set $v (i64.load (i32.add (i32.add (global.get $fp) (i32.const 8)) (i32.shl (local.get $s) (i32.const 3))))) (if (result i64) (i64.eq (local.get $v) (i64.const 0x7FFD000000000000)) (then (call $lookup (i32.load (global.get $fp)) (local.get $k) (i32.const 1))) (else (local.get $v))))");
+    out.push_str("(func $get_base (param $s i32) (param $k i32) (result i64) (local $v i64) (local.set $v (i64.load (i32.add (i32.add (global.get $fp) (i32.const 8)) (i32.shl (local.get $s) (i32.const 3))))) (if (result i64) (i64.eq (local.get $v) (i64.const 0x7FFD000000000000)) (then (call $lookup (i32.load (global.get $fp)) (local.get $k) (i32.const 0))) (else (local.get $v))))");
+    out.push_str("(func $get_name (param $k i32) (result i64) (call $lookup (global.get $fp) (local.get $k) (i32.const 1)))");
+    out.push_str("(func $set (param $s i32) (param $v i64) (i64.store (i32.add (i32.add (global.get $fp) (i32.const 8)) (i32.shl (local.get $s) (i32.const 3))) (local.get $v)))");
+    out.push_str("(func $getc (result i32) (local $c i32) (if (i32.ge_u (global.get $inpos) (global.get $inlen)) (then (if (global.get $ineof) (then (return (i32.const -1)))) (i32.store (i32.const 0) (i32.const 66560)) (i32.store (i32.const 4) (i32.const 4096)) (if (i32.or (call $fd_read (i32.const 0) (i32.const 0) (i32.const 1) (i32.const 8)) (i32.eqz (i32.load (i32.const 8)))) (then (global.set $ineof (i32.const 1)) (return (i32.const -1)))) (global.set $inlen (i32.load (i32.const 8))) (global.set $inpos (i32.const 0)))) (local.set $c (i32.load8_u (i32.add (i32.const 66560) (global.get $inpos)))) (global.set $inpos (i32.add (global.get $inpos) (i32.const 1))) (local.get $c))");
+    out.push_str("(func $input (param $prompt i32) (result i64) (local $c i32) (local $s i32) (call $out_s (local.get $prompt)) (call $flush) (loop $ws (local.set $c (call $getc)) (br_if $ws (i32.and (i32.ge_s (local.get $c) (i32.const 0)) (call $is_space (local.get $c))))) (local.set $s (call $sb_begin)) (loop $tok (if (i32.and (i32.ge_s (local.get $c) (i32.const 0)) (i32.eqz (call $is_space (local.get $c)))) (then (i32.store8 (i32.const 32) (local.get $c)) (call $sb_put (i32.const 32) (i32.const 1)) (local.set $c (call $getc)) (br $tok)))) (local.set $s (call $sb_end (local.get $s))) (if (result i64) (i32.and (i32.ne (i32.load (local.get $s)) (i32.const 0)) (call $parse_num (i32.add (local.get $s) (i32.const 4)) (i32.load (local.get $s)))) (then (call $num (global.get $pn))) (else (call $str (local.get $s)))))");
+    out.push_str("(func $fill (param $f i32) (param $k i32) (loop $l (if (i32.gt_s (local.get $k) (i32.const 0)) (then (call $sb_put (i32.add (local.get $f) (i32.const 4)) (i32.load (local.get $f))) (local.set $k (i32.sub (local.get $k) (i32.const 1))) (br $l)))))");
+    out.push_str("(func $fmt_one (param $v i64) (param $fl i32) (param $align i32) (param $zero i32) (param $width i32) (param $prec i32) (result i32) (local $isn i32) (local $s i32) (local $n i32) (local $pad i32) (local $o i32) (local $at i32) (local $left i32) (local.set $isn (i32.eqz (call $tag (local.get $v)))) (if (i32.and (i32.ge_s (local.get $prec) (i32.const 0)) (local.get $isn)) (then (call $fmt_fixed (call $f (local.get $v)) (local.get $prec)) (local.set $s (call $substr (global.get $fx_ptr) (global.get $fx_len)))) (else (local.set $s (call $as_str (local.get $v))) (if (i32.ge_s (local.get $prec) (i32.const 0)) (then (local.set $n (call $u8off (i32.add (local.get $s) (i32.const 4)) (i32.load (local.get $s)) (local.get $prec))) (if (i32.lt_u (local.get $n) (i32.load (local.get $s))) (then (local.set $s (call $substr (i32.add (local.get $s) (i32.const 4)) (local.get $n))))))))) (local.set $n (i32.load (local.get $s))) (local.set $pad (i32.sub (local.get $width) (call $u8len (i32.add (local.get $s) (i32.const 4)) (local.get $n)))) (if (i32.le_s (local.get $pad) (i32.const 0)) (then (return (local.get $s)))) (local.set $o (call $sb_begin)) (if (i32.and (local.get $zero) (local.get $isn)) (then (local.set $at (i32.and (i32.ne (local.get $n) (i32.const 0)) (i32.eq (i32.load8_u (i32.add (local.get $s) (i32.const 4))) (i32.const 45)))) (call $sb_put (i32.add (local.get $s) (i32.const 4)) (local.get $at)) (call $fill (global.get $rs_zero) (local.get $pad)) (call $sb_put (i32.add (i32.add (local.get $s) (i32.const 4)) (local.get $at)) (i32.sub (local.get $n) (local.get $at))) (return (call $sb_end (local.get $o))))) (if (i32.eqz (local.get $align)) (then (local.set $align (if (result i32) (local.get $isn) (then (i32.const 62)) (else (i32.const 60)))))) (local.set $left (if (result i32) (i32.eq (local.get $align) (i32.const 62)) (then (local.get $pad)) (else (if (result i32) (i32.eq (local.get $align) (i32.const 94)) (then (i32.shr_u (local.get $pad) (i32.const 1))) (else (i32.const 0)))))) (call $fill (local.get $fl) (local.get $left)) (call $sb_put (i32.add (local.get $s) (i32.const 4)) (local.get $n)) (call $fill (local.get $fl) (i32.sub (local.get $pad) (local.get $left))) (call $sb_end (local.get $o)))");
+    out.push('\n');
+
+    for (k, s) in RUNTIME_STRS {
+        let at = cx.str(s);
+        writeln!(out, "(global $rs_{} i32 (i32.const {}))", k, at).unwrap();
+    }
+    let where_at = match (&opts.file, opts.strict) {
+        (Some(f), true) => cx.str(f),
+        _ => 0,
+    };
+    let name_ptrs: Vec<u32> = names.iter().map(|n| cx.str(n)).collect();
+    let table = cx.table(&name_ptrs);
+    let heap = (DATA_BASE + cx.data.len() as u32 + 7) & !7;
+    writeln!(
+        out,
+        "(global $strict i32 (i32.const {}))",
+        opts.strict as i32
+    )
+    .unwrap();
+    writeln!(out, "(global $where (mut i32) (i32.const {}))", where_at).unwrap();
+    writeln!(out, "(global $names i32 (i32.const {}))", table).unwrap();
+    writeln!(out, "(global $hp (mut i32) (i32.const {}))", heap).unwrap();
+    writeln!(out, "(memory (export \"memory\") {})", (heap >> 16) + 2).unwrap();
+    writeln!(
+        out,
+        "(data (i32.const {}) \"{}\")",
+        DATA_BASE,
+        wat_bytes(&cx.data)
+    )
+    .unwrap();
+    out.push_str(&funcs);
+    out.push_str("(func (export \"_start\") (drop (call $pna_main)) (call $flush))\n)\n");
+    Ok(out)
+}
+
+/// Every name read through the callers and every field name, for the id
+/// table (slot names are added by the caller).
+fn collect_names(b: &[Inst], out: &mut BTreeSet<String>) {
+    walk(b, &mut |i| {
+        if let Inst::Let(_, Rv::Name(n) | Rv::Member(_, n) | Rv::SetField(_, n, _))
+        | Inst::SetField(_, n, _) = i
+        {
+            out.insert(n.clone());
+        }
+    });
+}
+
+/// A WAT string literal holding `bs`.
+fn wat_bytes(bs: &[u8]) -> String {
+    let mut s = String::new();
+    for &b in bs {
+        if (0x20..0x7f).contains(&b) && b != b'"' && b != b'\\' {
+            s.push(b as char);
+        } else {
+            write!(s, "\\{:02x}", b).unwrap();
+        }
+    }
+    s
+}
+
+/// Module-wide state: the data segment and the name ids.
+struct Ctx {
+    data: Vec<u8>,
+    strs: HashMap<String, u32>,
+    ids: HashMap<String, usize>,
+    strict: bool,
+    /// The name of each file, by `Span::file`, once the program imports
+    /// files; each statement then points `$where` at its own.
+    files: Vec<u32>,
+}
+
+impl Ctx {
+    fn align(&mut self) -> u32 {
+        while !self.data.len().is_multiple_of(4) {
+            self.data.push(0);
+        }
+        DATA_BASE + self.data.len() as u32
+    }
+
+    /// Address of the runtime string `s`, interned in the data segment.
+    fn str(&mut self, s: &str) -> u32 {
+        if let Some(&at) = self.strs.get(s) {
+            return at;
+        }
+        let at = self.align();
+        self.data.extend_from_slice(&(s.len() as u32).to_le_bytes());
+        self.data.extend_from_slice(s.as_bytes());
+        self.strs.insert(s.to_string(), at);
+        at
+    }
+
+    /// Address of a table of `i32`s in the data segment.
+    fn table(&mut self, xs: &[u32]) -> u32 {
+        let at = self.align();
+        for x in xs {
+            self.data.extend_from_slice(&x.to_le_bytes());
+        }
+        at
+    }
+
+    fn id(&self, name: &str) -> usize {
+        self.ids[name]
+    }
+}
+
+/// Per-function state.
+struct FnGen<'c, 'f> {
+    cx: &'c mut Ctx,
+    f: &'f Func,
+    /// The statement being compiled, for errors.
+    at: Span,
+    /// Rendered temporaries, waiting for their single use.
+    temps: HashMap<Temp, String>,
+    /// Wasm locals `$v0..` for values that must be evaluated up front.
+    locals: usize,
+}
+
+fn emit_func(out: &mut String, cx: &mut Ctx, name: &str, func: &Func) -> Result<(), Diagnostic> {
+    let mut ids = vec![func.slots.len() as u32];
+    ids.extend(func.slots.iter().map(|s| cx.id(s) as u32));
+    let frame = cx.table(&ids);
+    let mut f = FnGen {
+        cx,
+        f: func,
+        at: func.span,
+        temps: HashMap::new(),
+        locals: 0,
+    };
+    let mut code = String::new();
+    f.block(&mut code, &func.body, 2)
+        .map_err(|e| Diagnostic::new(e, f.at))?;
+
+    let params = &func.slots[..func.params];
+    write!(out, "(func ${}", name).unwrap();
+    for p in params {
+        write!(out, " (param $p_{} i64)", p).unwrap();
+    }
+    out.push_str(" (result i64) (local $fr i32) (local $r i64)");
+    for i in 0..f.locals {
+        write!(out, " (local $v{} i64)", i).unwrap();
+    }
+    let strict = f.cx.strict;
+    if strict {
+        // errors after the call returns are the caller's
+        out.push_str(" (local $line i32) (local $where i32)");
+        out.push_str(
+            "\n  (local.set $line (global.get $line)) (local.set $where (global.get $where))",
+        );
+    }
+    writeln!(
+        out,
+        "\n  (local.set $fr (call $enter (i32.const {})))",
+        frame
+    )
+    .unwrap();
+    for (i, p) in params.iter().enumerate() {
+        writeln!(out, "  (call $set (i32.const {}) (local.get $p_{}))", i, p).unwrap();
+    }
+    out.push_str("  (local.set $r (block $ret (result i64)\n");
+    out.push_str(&code);
+    writeln!(out, "    {}))", UNDEF).unwrap();
+    if strict {
+        out.push_str(
+            "  (global.set $line (local.get $line)) (global.set $where (local.get $where))\n",
+        );
+    }
+    out.push_str("  (call $leave (local.get $fr))\n  (local.get $r))\n");
+    Ok(())
+}
+
+impl FnGen<'_, '_> {
+    fn local(&mut self) -> String {
+        self.locals += 1;
+        format!("$v{}", self.locals - 1)
+    }
+
+    fn slot_id(&self, s: Slot) -> usize {
+        self.cx.id(&self.f.slots[s])
+    }
+
+    fn block(&mut self, out: &mut String, b: &[Inst], lvl: usize) -> Result<(), String> {
+        for i in b {
+            self.inst(out, i, lvl)?;
+        }
+        Ok(())
+    }
+
+    fn inst(&mut self, out: &mut String, i: &Inst, lvl: usize) -> Result<(), String> {
+        let ind = indent(lvl);
+        match i {
+            Inst::Line(sp) => {
+                self.at = *sp;
+                if let Some(at) = self.cx.files.get(sp.file) {
+                    writeln!(out, "{}(global.set $where (i32.const {}))", ind, at).unwrap();
+                }
+                if self.cx.strict {
+                    writeln!(out, "{}(global.set $line (i32.const {}))", ind, sp.line).unwrap();
+                }
+            }
+            Inst::Let(t, rv) => {
+                let x = self.rv(rv)?;
+                self.temps.insert(*t, x);
+            }
+            Inst::Store(s, v) => {
+                let v = self.operand(v);
+                writeln!(out, "{}(call $set (i32.const {}) {})", ind, s, v).unwrap();
+            }
+            Inst::SetField(s, k, v) => {
+                let v = self.operand(v);
+                writeln!(
+                    out,
+                    "{}(call $set (i32.const {}) (call $obj_set (call $get_base (i32.const {}) (i32.const {})) (i32.const {}) {}))",
+                    ind,
+                    s,
+                    s,
+                    self.slot_id(*s),
+                    self.cx.id(k),
+                    v
+                )
+                .unwrap();
+            }
+            Inst::SetIndex(..) => return Err(COMPUTED_KEYS.into()),
+            Inst::Out { args, newline } => {
+                // Evaluate everything first so a failing argument prints nothing.
+                let mut vs = Vec::new();
+                for a in args {
+                    let v = self.local();
+                    writeln!(out, "{}(local.set {} {})", ind, v, self.operand(a)).unwrap();
+                    vs.push(v);
+                }
+                for (i, v) in vs.iter().enumerate() {
+                    if i > 0 {
+                        writeln!(out, "{}(call $out_s (global.get $rs_sp))", ind).unwrap();
+                    }
+                    writeln!(out, "{}(call $write_val (local.get {}))", ind, v).unwrap();
+                }
+                if *newline {
+                    writeln!(out, "{}(call $out_s (global.get $rs_nl))", ind).unwrap();
+                }
+            }
+            Inst::If {
+                cond,
+                then_blk,
+                else_blk,
+            } => {
+                let c = self.operand(cond);
+                writeln!(out, "{}(if (call $truthy {}) (then", ind, c).unwrap();
+                self.block(out, then_blk, lvl + 1)?;
+                if !else_blk.is_empty() {
+                    writeln!(out, "{}) (else", ind).unwrap();
+                    self.block(out, else_blk, lvl + 1)?;
+                }
+                writeln!(out, "{}))", ind).unwrap();
+            }
+            Inst::Loop {
+                id,
+                header,
+                cond,
+                body,
+                ended,
+            } => {
+                // `break` leaves `$b`, skipping `ended`; a false condition
+                // only leaves `$e`. The header re-runs before every
+                // iteration, so in strict mode it resets `$line` to the loop.
+                writeln!(out, "{}(block $b{} (block $e{} (loop $c{}", ind, id, id, id).unwrap();
+                self.block(out, header, lvl + 1)?;
+                let c = self.operand(cond);
+                writeln!(
+                    out,
+                    "{}  (br_if $e{} (i32.eqz (call $truthy {})))",
+                    ind, id, c
+                )
+                .unwrap();
+                self.block(out, body, lvl + 1)?;
+                writeln!(out, "{}  (br $c{})))", ind, id).unwrap();
+                self.block(out, ended, lvl + 1)?;
+                writeln!(out, "{})", ind).unwrap();
+            }
+            Inst::Break(id) => writeln!(out, "{}(br $b{})", ind, id).unwrap(),
+            Inst::Switch { .. } => unreachable!("switches are expanded first"),
+            Inst::Cpp(_) => unreachable!("native modules are rejected up front"),
+            Inst::Continue(id) => writeln!(out, "{}(br $c{})", ind, id).unwrap(),
+            Inst::Return(v) => {
+                let v = self.operand(v);
+                writeln!(out, "{}(br $ret {})", ind, v).unwrap();
+            }
+        }
+        Ok(())
+    }
+
+    /// A string pointer (`i32`) for `s`.
+    fn str_ptr(&mut self, s: &str) -> String {
+        format!("(i32.const {})", self.cx.str(s))
+    }
+
+    fn operand(&mut self, o: &Operand) -> String {
+        match o {
+            Operand::Temp(t) => self.temps.remove(t).expect("temporaries are used once"),
+            Operand::Const(Const::Num(n)) => format!("(i64.reinterpret_f64 (f64.const {:?}))", n),
+            Operand::Const(Const::Str(s)) => format!("(call $str {})", self.str_ptr(s)),
+            Operand::Const(Const::Bool(b)) => format!("(call $bool (i32.const {}))", *b as i32),
+            Operand::Const(Const::Undef) => UNDEF.to_string(),
+        }
+    }
+
+    fn rv(&mut self, rv: &Rv) -> Result<String, String> {
+        Ok(match rv {
+            Rv::Local(s) => format!(
+                "(call $get (i32.const {}) (i32.const {}))",
+                s,
+                self.slot_id(*s)
+            ),
+            Rv::Name(n) => format!("(call $get_name (i32.const {}))", self.cx.id(n)),
+            Rv::Not(a) => format!("(call $not {})", self.operand(a)),
+            Rv::Bin(BinOp::Pow, ..) => return Err(NO_LIBM.into()),
+            Rv::Bin(op, a, b) => {
+                let a = self.operand(a);
+                format!("(call ${} {} {})", op.name(), a, self.operand(b))
+            }
+            Rv::Member(a, k) => format!(
+                "(call $get_prop {} (i32.const {}))",
+                self.operand(a),
+                self.cx.id(k)
+            ),
+            Rv::Index(a, b) => {
+                let a = self.operand(a);
+                format!("(call $index {} {})", a, self.operand(b))
+            }
+            Rv::NewObj => "(call $obj_new)".to_string(),
+            Rv::SetField(o, k, v) => {
+                let o = self.operand(o);
+                format!(
+                    "(call $obj_set {} (i32.const {}) {})",
+                    o,
+                    self.cx.id(k),
+                    self.operand(v)
+                )
+            }
+            Rv::Concat(parts) => {
+                let xs = parts
+                    .iter()
+                    .map(|p| match p {
+                        Operand::Const(Const::Str(s)) => self.str_ptr(s),
+                        p => format!("(call $as_str {})", self.operand(p)),
+                    })
+                    .collect();
+                format!("(call $str {})", self.concat(xs))
+            }
+            Rv::Format(xs) => self.format(xs)?,
+            Rv::Len(a) => format!("(call $len {})", self.operand(a)),
+            Rv::Has(..) | Rv::Remove(..) | Rv::Keys(_) | Rv::Values(_) => {
+                return Err(COMPUTED_KEYS.into())
+            }
+            Rv::Math(m, xs) => {
+                let xs: Vec<String> = xs.iter().map(|x| self.operand(x)).collect();
+                let num = |x: &String| format!("(call $as_num {})", x);
+                match m {
+                    Math::Sqrt => format!("(call $sqrt {})", xs[0]),
+                    Math::Gcd => format!("(call $gcd {} {})", xs[0], xs[1]),
+                    Math::Abs | Math::Floor | Math::Ceil => {
+                        format!("(call $math (f64.{} {}))", m.name(), num(&xs[0]))
+                    }
+                    Math::Min | Math::Max => {
+                        format!(
+                            "(call $math (f64.{} {} {}))",
+                            m.name(),
+                            num(&xs[0]),
+                            num(&xs[1])
+                        )
+                    }
+                    Math::Round => format!("(call $math (call $round {}))", num(&xs[0])),
+                    _ => return Err(NO_LIBM.into()),
+                }
+            }
+            Rv::Call(name, xs) => {
+                let mut s = format!("(call $fn_{}", name);
+                for x in xs {
+                    s.push(' ');
+                    s.push_str(&self.operand(x));
+                }
+                s.push(')');
+                s
+            }
+            Rv::Input(p) => format!("(call $input {})", self.str_ptr(p)),
+            Rv::Extern(..) => unreachable!("native modules are rejected up front"),
+        })
+    }
+
+    /// Joins string pointers with `$concat`.
+    fn concat(&mut self, xs: Vec<String>) -> String {
+        let mut it = xs.into_iter();
+        let first = it.next().unwrap_or_else(|| self.str_ptr(""));
+        it.fold(first, |acc, x| format!("(call $concat {} {})", acc, x))
+    }
+
+    /// `format` with a literal format string: the pieces are known here, so
+    /// only the holes are formatted at run time (`$fmt_one`).
+    fn format(&mut self, xs: &[Operand]) -> Result<String, String> {
+        let pieces = match xs.first() {
+            Some(Operand::Const(Const::Str(s))) => format_spec::parse(s)?,
+            _ => return Err("--emit=wat needs a string literal as the format string".into()),
+        };
+        let mut s = "(block (result i32)".to_string();
+        let mut vs = Vec::new();
+        for a in &xs[1..] {
+            let v = self.local();
+            write!(s, " (local.set {} {})", v, self.operand(a)).unwrap();
+            vs.push(v);
+        }
+        let mut parts = Vec::new();
+        let mut next = vs.iter();
+        for p in &pieces {
+            parts.push(match p {
+                Piece::Lit(l) => self.str_ptr(l),
+                Piece::Hole(spec) => {
+                    let v = next
+                        .next()
+                        .map_or(UNDEF.to_string(), |v| format!("(local.get {})", v));
+                    format!(
+                        "(call $fmt_one {} {} (i32.const {}) (i32.const {}) (i32.const {}) (i32.const {}))",
+                        v,
+                        self.str_ptr(&spec.fill.to_string()),
+                        spec.align.map_or(0, |c| c as u32),
+                        spec.zero as i32,
+                        spec.width,
+                        spec.precision.map_or(-1, |p| p as i64)
+                    )
+                }
+            });
+        }
+        write!(s, " {})", self.concat(parts)).unwrap();
+        Ok(format!("(call $str {})", s))
+    }
+}
@@ -0,0 +1,19 @@
+use crate::ast::Span;
+use thiserror::Error;
+
+/// An error reported against a source position.
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("{}:{}: {message}", span.line, span.col)]
+pub struct Diagnostic {
+    pub message: String,
+    pub span: Span,
+}
+
+impl Diagnostic {
+    pub fn new(message: impl Into<String>, span: Span) -> Self {
+        Diagnostic {
+            message: message.into(),
+            span,
+        }
+    }
+}
@@ -0,0 +1,579 @@
+//! `pna-cc fmt`: prints a program back out in the canonical layout.
+//!
+//! The layout comes from the AST; the token stream is only consulted for
+//! source lines, so that comments (lexer trivia) and single blank lines stay
+//! where they were. Canonical form: two-space indentation, `name: value`,
+//! ` -> ` between a header and its block, one field per line with a trailing
+//! comma in object blocks, and `} end` closing every block statement.
+
+use crate::ast::*;
+use crate::diag::Diagnostic;
+use crate::lexer;
+use crate::parser;
+use crate::token::{Comment, Tok};
+use std::collections::HashMap;
+
+/// Formats a whole source file. Fails only if it does not parse.
+pub fn format_source(src: &str) -> Result<String, Diagnostic> {
+    let (toks, comments) =
+        lexer::lex_with_comments(src).map_err(|e| Diagnostic::new(e.kind.to_string(), e.span))?;
+    let prog = parser::parse(toks.clone())?;
+    let mut p = Printer {
+        toks: &toks,
+        comments: &comments,
+        ci: 0,
+        out: String::new(),
+        indent: 0,
+        src_lines: src.lines().collect(),
+        block_start: true,
+        lits: literals(src, &toks),
+    };
+    for (k, it) in prog.items.iter().enumerate() {
+        let next = match prog.items.get(k + 1) {
+            Some(n) => p.tok_at(item_span(n)),
+            None => toks.len(),
+        };
+        p.item(it, next);
+    }
+    p.flush_before(usize::MAX);
+    Ok(p.out)
+}
+
+/// Source text of every plain string literal, keyed by its value.
+fn literals(src: &str, toks: &[(Tok, Span)]) -> HashMap<String, String> {
+    let lines: Vec<Vec<char>> = src.lines().map(|l| l.chars().collect()).collect();
+    let mut lits = HashMap::new();
+    for (t, sp) in toks {
+        let Tok::String(v) = t else { continue };
+        // literals that run onto the next line are re-escaped instead
+        let Some(line) = lines.get(sp.line - 1) else {
+            continue;
+        };
+        let q = line[sp.col - 1];
+        let mut j = sp.col;
+        while j < line.len() && line[j] != q {
+            j += if line[j] == '\\' { 2 } else { 1 };
+        }
+        if j >= line.len() {
+            continue;
+        }
+        let text: String = line[sp.col - 1..=j].iter().collect();
+        lits.entry(v.clone()).or_insert(text);
+    }
+    lits
+}
+
+fn item_span(it: &Item) -> Span {
+    match it {
+        Item::Stmt(s) => s.span,
+        Item::Func { span, .. }
+        | Item::Type { span, .. }
+        | Item::Enum { span, .. }
+        | Item::Import { span, .. }
+        | Item::Extern { span, .. }
+        | Item::Cpp { span, .. } => *span,
+    }
+}
+
+/// `function name(params) -> ret`
+fn signature(name: &str, params: &[Param], ret: &Ty) -> String {
+    let ps: Vec<String> = params
+        .iter()
+        .map(|p| format!("{}: {}", p.name, p.ty))
+        .collect();
+    format!("function {}({}) -> {}", name, ps.join(", "), ret)
+}
+
+/// A `cpp` block; the C++ text is kept exactly as written.
+fn cpp_block(code: &str) -> String {
+    format!("cpp {{{}}} end", code)
+}
+
+struct Printer<'a> {
+    toks: &'a [(Tok, Span)],
+    comments: &'a [Comment],
+    /// Next comment to print.
+    ci: usize,
+    out: String,
+    indent: usize,
+    src_lines: Vec<&'a str>,
+    /// Nothing printed yet in the current block.
+    block_start: bool,
+    /// Decoded value -> source text of plain string literals.
+    lits: HashMap<String, String>,
+}
+
+impl Printer<'_> {
+    /// Index of the token starting at `sp`.
+    fn tok_at(&self, sp: Span) -> usize {
+        self.toks
+            .partition_point(|(_, s)| (s.line, s.col) < (sp.line, sp.col))
+    }
+
+    fn line_of(&self, i: usize) -> usize {
+        self.toks.get(i).map_or(usize::MAX, |(_, s)| s.line)
+    }
+
+    /// A comment is trailing when code precedes it on its line.
+    fn is_trailing(&self, c: &Comment) -> bool {
+        let i = self.tok_at(c.span);
+        i > 0 && self.toks[i - 1].1.line == c.span.line
+    }
+
+    /// Keeps one blank line where the source had one or more before `line`.
+    fn gap(&mut self, line: usize) {
+        let blank = line
+            .checked_sub(2)
+            .and_then(|l| self.src_lines.get(l))
+            .is_some_and(|l| l.trim().is_empty());
+        if !self.block_start && blank {
+            self.out.push('\n');
+        }
+    }
+
+    /// Prints the comments that start before source line `line`.
+    fn flush_before(&mut self, line: usize) {
+        while let Some(c) = self.comments.get(self.ci) {
+            if c.span.line >= line {
+                break;
+            }
+            self.gap(c.span.line);
+            self.push_indent();
+            self.out.push_str(&c.text);
+            self.out.push('\n');
+            self.block_start = false;
+            self.ci += 1;
+        }
+    }
+
+    fn push_indent(&mut self) {
+        self.out.push_str(&"  ".repeat(self.indent));
+    }
+
+    /// Prints one output line that ends at source line `src_line`, followed
+    /// by any trailing comments up to that line.
+    fn line(&mut self, text: &str, src_line: usize) {
+        self.push_indent();
+        self.out.push_str(text);
+        while let Some(c) = self.comments.get(self.ci) {
+            if c.span.line > src_line || !self.is_trailing(c) {
+                break;
+            }
+            self.out.push(' ');
+            self.out.push_str(&c.text);
+            self.ci += 1;
+        }
+        self.out.push('\n');
+        self.block_start = false;
+    }
+
+    /// Brace pairs `(open, close)` at depth 0 in tokens `from..to`.
+    fn braces(&self, from: usize, to: usize) -> Vec<(usize, usize)> {
+        let mut pairs = Vec::new();
+        let mut depth = 0usize;
+        let mut open = from;
+        for i in from..to.min(self.toks.len()) {
+            match self.toks[i].0 {
+                Tok::LBrace => {
+                    if depth == 0 {
+                        open = i;
+                    }
+                    depth += 1;
+                }
+                Tok::RBrace => {
+                    depth = depth.saturating_sub(1);
+                    if depth == 0 {
+                        pairs.push((open, i));
+                    }
+                }
+                _ => {}
+            }
+        }
+        pairs
+    }
+
+    fn item(&mut self, it: &Item, next: usize) {
+        match it {
+            Item::Stmt(s) => self.stmt(s, next),
+            Item::Import { path, alias, span } => {
+                self.flush_before(span.line);
+                self.gap(span.line);
+                let text = match alias {
+                    Some(a) => format!("import {} from {}", a, self.quote(path)),
+                    None => format!("import {}", self.quote(path)),
+                };
+                self.line(&text, self.line_of(next - 1));
+            }
+            Item::Extern {
+                name,
+                params,
+                ret,
+                span,
+            } => {
+                self.flush_before(span.line);
+                self.gap(span.line);
+                let text = format!("extern {}", signature(name, params, ret));
+                self.line(&text, self.line_of(next - 1));
+            }
+            Item::Cpp { code, span } => {
+                self.flush_before(span.line);
+                self.gap(span.line);
+                self.line(&cpp_block(code), self.line_of(next - 1));
+            }
+            Item::Func {
+                name,
+                params,
+                ret,
+                body,
+                span,
+            } => {
+                self.flush_before(span.line);
+                self.gap(span.line);
+                let start = self.tok_at(*span);
+                let (open, close) = self.braces(start, next)[0];
+                let head = format!("{} {{", signature(name, params, ret));
+                self.line(&head, self.line_of(open));
+                self.block(body, close);
+                self.line("} end", self.line_of(next - 1));
+            }
+            Item::Type { name, fields, span } => {
+                self.flush_before(span.line);
+                self.gap(span.line);
+                let start = self.tok_at(*span);
+                let fields: Vec<(&str, String)> = fields
+                    .iter()
+                    .map(|(k, t)| (k.as_str(), t.to_string()))
+                    .collect();
+                let br = self.braces(start, next)[0];
+                self.fields(&format!("type {}", name), &fields, br);
+            }
+            Item::Enum {
+                name,
+                variants,
+                span,
+            } => {
+                self.flush_before(span.line);
+                self.gap(span.line);
+                let start = self.tok_at(*span);
+                let (open, close) = self.braces(start, next)[0];
+                if variants.is_empty() {
+                    self.line(&format!("enum {} {{}}", name), self.line_of(close));
+                    return;
+                }
+                self.line(&format!("enum {} {{", name), self.line_of(open));
+                // a variant starts at an identifier after `{` or a `,`
+                // outside its payload
+                let mut depth = 0;
+                let mut at = Vec::new();
+                for i in open + 1..close {
+                    match self.toks[i].0 {
+                        Tok::LParen => depth += 1,
+                        Tok::RParen => depth -= 1,
+                        Tok::Ident(_)
+                            if depth == 0
+                                && matches!(self.toks[i - 1].0, Tok::LBrace | Tok::Comma) =>
+                        {
+                            at.push(i)
+                        }
+                        _ => {}
+                    }
+                }
+                self.indent += 1;
+                self.block_start = true;
+                for (k, v) in variants.iter().enumerate() {
+                    let here = at.get(k).copied().unwrap_or(open);
+                    let next = at.get(k + 1).copied().unwrap_or(close);
+                    self.flush_before(self.line_of(here));
+                    self.gap(self.line_of(here));
+                    let text = if v.fields.is_empty() {
+                        format!("{},", v.name)
+                    } else {
+                        let tys: Vec<String> = v.fields.iter().map(|t| t.to_string()).collect();
+                        format!("{}({}),", v.name, tys.join(", "))
+                    };
+                    self.line(&text, self.line_of(next - 1));
+                }
+                self.flush_before(self.line_of(close));
+                self.indent -= 1;
+                self.line("}", self.line_of(close));
+            }
+        }
+    }
+
+    /// Prints `stmts` one level deeper; `close` is the index of their `}`.
+    fn block(&mut self, stmts: &[Stmt], close: usize) {
+        self.indent += 1;
+        self.block_start = true;
+        for (k, s) in stmts.iter().enumerate() {
+            let next = match stmts.get(k + 1) {
+                Some(n) => self.tok_at(n.span),
+                None => close,
+            };
+            self.stmt(s, next);
+        }
+        self.flush_before(self.line_of(close));
+        self.indent -= 1;
+    }
+
+    /// Prints `s`, whose tokens end just before index `next`.
+    fn stmt(&mut self, s: &Stmt, next: usize) {
+        self.flush_before(s.span.line);
+        self.gap(s.span.line);
+        let start = self.tok_at(s.span);
+        let end_line = self.line_of(next - 1);
+        if let Some(text) = self.simple(&s.kind) {
+            self.line(&text, end_line);
+            return;
+        }
+
+        let br = self.braces(start, next);
+        match &s.kind {
+            StmtKind::ObjBlock { name, ty, fields } => {
+                let head = match ty {
+                    Some(t) => format!("{}: {}", name, t),
+                    None => format!("{}:", name),
+                };
+                let fields: Vec<(&str, String)> = fields
+                    .iter()
+                    .map(|(k, e)| (k.as_str(), self.expr(e)))
+                    .collect();
+                self.fields(&head, &fields, br[0])
+            }
+            StmtKind::Cond {
+                cond,
+                then_blk,
+                else_blk,
+            } => {
+                self.line(
+                    &format!("cond ({}) -> {{", self.expr(cond)),
+                    self.line_of(br[0].0),
+                );
+                self.block(then_blk, br[0].1);
+                if let Some(eb) = else_blk {
+                    self.line("} else -> {", self.line_of(br[1].0));
+                    self.block(eb, br[1].1);
+                }
+                self.line("} end", end_line);
+            }
+            StmtKind::Loop { cond, body } => {
+                self.line(
+                    &format!("loop ({}) -> {{", self.expr(cond)),
+                    self.line_of(br[0].0),
+                );
+                self.block(body, br[0].1);
+                self.line("} end", end_line);
+            }
+            StmtKind::While { cond, body, ended } => {
+                self.line(
+                    &format!("while ({}) -> {{", self.expr(cond)),
+                    self.line_of(br[0].0),
+                );
+                self.block(body, br[0].1);
+                if let Some(eb) = ended {
+                    self.line("} ended {", self.line_of(br[1].0));
+                    self.block(eb, br[1].1);
+                }
+                self.line("} end", end_line);
+            }
+            StmtKind::Match { expr, arms } => {
+                let (open, close) = br[0];
+                self.line(
+                    &format!("match {} -> {{", self.expr(expr)),
+                    self.line_of(open),
+                );
+                let bodies = self.braces(open + 1, close);
+                self.indent += 1;
+                self.block_start = true;
+                for (arm, (o, c)) in arms.iter().zip(bodies) {
+                    self.flush_before(arm.span.line);
+                    self.gap(arm.span.line);
+                    let pat = match &arm.pat {
+                        Pat::Variant { name, binds } if binds.is_empty() => name.clone(),
+                        Pat::Variant { name, binds } => format!("{}({})", name, binds.join(", ")),
+                        Pat::Wild => "_".into(),
+                    };
+                    self.line(&format!("{} -> {{", pat), self.line_of(o));
+                    self.block(&arm.body, c);
+                    self.line("}", self.line_of(c));
+                }
+                self.flush_before(self.line_of(close));
+                self.indent -= 1;
+                self.line("} end", end_line);
+            }
+            _ => unreachable!("simple statements are printed above"),
+        }
+    }
+
+    /// An object literal or `type` body: `head {`, one `key: value,` per
+    /// line, `}`.
+    fn fields(&mut self, head: &str, fields: &[(&str, String)], (open, close): (usize, usize)) {
+        let has_comments = self
+            .comments
+            .get(self.ci)
+            .is_some_and(|c| c.span.line <= self.line_of(close));
+        if fields.is_empty() && !has_comments {
+            self.line(&format!("{} {{}}", head), self.line_of(close));
+            return;
+        }
+        self.line(&format!("{} {{", head), self.line_of(open));
+        // a field starts at an identifier directly followed by `:`
+        let keys: Vec<usize> = (open + 1..close)
+            .filter(|&i| {
+                matches!(self.toks[i].0, Tok::Ident(_))
+                    && matches!(self.toks.get(i + 1), Some((Tok::Colon, _)))
+            })
+            .collect();
+        self.indent += 1;
+        self.block_start = true;
+        for (k, (key, v)) in fields.iter().enumerate() {
+            let at = keys.get(k).copied().unwrap_or(open);
+            let next = keys.get(k + 1).copied().unwrap_or(close);
+            self.flush_before(self.line_of(at));
+            self.gap(self.line_of(at));
+            self.line(&format!("{}: {},", key, v), self.line_of(next - 1));
+        }
+        self.flush_before(self.line_of(close));
+        self.indent -= 1;
+        self.line("}", self.line_of(close));
+    }
+
+    /// One-line statements; `None` for those with blocks.
+    fn simple(&self, k: &StmtKind) -> Option<String> {
+        Some(match k {
+            StmtKind::PropAssign { base, key, expr: e } => {
+                format!("{}.{}: {}", base, key, self.expr(e))
+            }
+            StmtKind::IndexAssign {
+                base,
+                index,
+                expr: e,
+            } => format!("{}[{}]: {}", base, self.expr(index), self.expr(e)),
+            StmtKind::VarAssign { name, expr: e } => format!("{}: {}", name, self.expr(e)),
+            StmtKind::Log(es) => format!("log {}", self.list(es)),
+            StmtKind::Print(es) => format!("print {}", self.list(es)),
+            StmtKind::Input { prompt, dst } => {
+                let dst = match dst {
+                    Target::Var(v) => v.clone(),
+                    Target::Prop { base, key } => format!("{}.{}", base, key),
+                };
+                format!("input {} -> {}", self.quote(prompt), dst)
+            }
+            StmtKind::Break => "break".into(),
+            StmtKind::Continue => "continue".into(),
+            StmtKind::Return(None) => "return".into(),
+            StmtKind::Return(Some(e)) => format!("return {}", self.expr(e)),
+            StmtKind::Cpp(code) => cpp_block(code),
+            _ => return None,
+        })
+    }
+
+    fn list(&self, es: &[Expr]) -> String {
+        es.iter()
+            .map(|e| self.expr(e))
+            .collect::<Vec<_>>()
+            .join(", ")
+    }
+
+    fn wrap(&self, e: &Expr, paren: bool) -> String {
+        if paren {
+            format!("({})", self.expr(e))
+        } else {
+            self.expr(e)
+        }
+    }
+
+    /// Prints an expression with the fewest parentheses that keep its meaning.
+    fn expr(&self, e: &Expr) -> String {
+        match e {
+            Expr::Num(d) => {
+                if d.fract() == 0.0 && d.abs() < 1e15 {
+                    format!("{:.0}", d)
+                } else {
+                    format!("{}", d)
+                }
+            }
+            Expr::Str(s) => self.quote(s),
+            Expr::Bool(b) => b.to_string(),
+            Expr::Ident(id) => id.clone(),
+            Expr::Member(base, key) => format!("{}.{}", self.wrap(base, prec(base) < u8::MAX), key),
+            Expr::Index(base, i) => format!(
+                "{}[{}]",
+                self.wrap(base, prec(base) < u8::MAX),
+                self.expr(i)
+            ),
+            Expr::Unary { op, rhs } => format!("{}{}", op, self.wrap(rhs, prec(rhs) < prec(e))),
+            Expr::Binary { op, lhs, rhs } => {
+                let p = prec(e);
+                // operators are left-associative, except `**`
+                let right = op == "**";
+                format!(
+                    "{} {} {}",
+                    self.wrap(lhs, prec(lhs) < p || right && prec(lhs) == p),
+                    op,
+                    self.wrap(rhs, prec(rhs) < p || !right && prec(rhs) == p)
+                )
+            }
+            Expr::Call { name, args } => format!("{}({})", name, self.list(args)),
+            Expr::Variant { ty, name, args } if args.is_empty() => format!("{}::{}", ty, name),
+            Expr::Variant { ty, name, args } => {
+                format!("{}::{}({})", ty, name, self.list(args))
+            }
+            Expr::Interp(parts) => {
+                let mut s = String::from("\"");
+                for part in parts {
+                    match part {
+                        InterpPart::Lit(l) => s.push_str(&escape(l)),
+                        InterpPart::Expr(e) => s.push_str(&format!("${{{}}}", self.expr(e))),
+                    }
+                }
+                s.push('"');
+                s
+            }
+        }
+    }
+
+    /// The literal as first written in the source when there is one, so
+    /// escapes and quote style survive formatting.
+    fn quote(&self, s: &str) -> String {
+        match self.lits.get(s) {
+            Some(lit) => lit.clone(),
+            None => format!("\"{}\"", escape(s)),
+        }
+    }
+}
+
+/// Binding power of the operator at the root of `e` (see `parser::bp_infix`).
+fn prec(e: &Expr) -> u8 {
+    match e {
+        Expr::Binary { op, .. } => match op.as_str() {
+            "||" => 1,
+            "&&" => 3,
+            "==" | "!=" | "<" | "<=" | ">" | ">=" => 5,
+            "+" | "-" => 9,
+            "**" => 14,
+            _ => 11,
+        },
+        Expr::Unary { .. } => 13,
+        _ => u8::MAX,
+    }
+}
+
+/// Escapes a string literal body so that the lexer reads back `s`.
+fn escape(s: &str) -> String {
+    let mut out = String::new();
+    let mut it = s.chars().peekable();
+    while let Some(c) = it.next() {
+        match c {
+            '\\' => out.push_str("\\\\"),
+            '"' => out.push_str("\\\""),
+            '\n' => out.push_str("\\n"),
+            '\t' => out.push_str("\\t"),
+            '\r' => out.push_str("\\r"),
+            '\0' => out.push_str("\\0"),
+            '$' if it.peek() == Some(&'{') => out.push_str("\\$"),
+            c if c.is_control() => out.push_str(&format!("\\u{{{:x}}}", c as u32)),
+            c => out.push(c),
+        }
+    }
+    out
+}
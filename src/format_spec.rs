@@ -0,0 +1,136 @@
+// Format strings used by the `format` builtin: `"{:.2} {:>8}"`.
+//
+// Placeholder grammar: `{` [`:` [[fill]align] [`0`] [width] [`.` precision]] `}`
+// where align is one of `<`, `>`, `^`. `{{` and `}}` are literal braces.
+// The embedded runtime implements the same grammar in `pna_format`.
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Spec {
+    pub fill: char,
+    pub align: Option<char>,
+    pub zero: bool,
+    pub width: usize,
+    pub precision: Option<usize>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Piece {
+    Lit(String),
+    Hole(Spec),
+}
+
+pub fn parse(s: &str) -> Result<Vec<Piece>, String> {
+    let cs: Vec<char> = s.chars().collect();
+    let mut out = Vec::new();
+    let mut lit = String::new();
+    let mut i = 0usize;
+    while i < cs.len() {
+        let c = cs[i];
+        if c == '{' && cs.get(i + 1) == Some(&'{') {
+            lit.push('{');
+            i += 2;
+            continue;
+        }
+        if c == '}' && cs.get(i + 1) == Some(&'}') {
+            lit.push('}');
+            i += 2;
+            continue;
+        }
+        if c == '}' {
+            return Err("unmatched `}` in format string (use `}}`)".into());
+        }
+        if c != '{' {
+            lit.push(c);
+            i += 1;
+            continue;
+        }
+        let close = match cs[i + 1..].iter().position(|&d| d == '}') {
+            Some(k) => i + 1 + k,
+            None => return Err("unterminated `{` in format string".into()),
+        };
+        let inner: String = cs[i + 1..close].iter().collect();
+        let spec = parse_spec(&inner)?;
+        if !lit.is_empty() {
+            out.push(Piece::Lit(std::mem::take(&mut lit)));
+        }
+        out.push(Piece::Hole(spec));
+        i = close + 1;
+    }
+    if !lit.is_empty() {
+        out.push(Piece::Lit(lit));
+    }
+    Ok(out)
+}
+
+pub fn holes(pieces: &[Piece]) -> usize {
+    pieces
+        .iter()
+        .filter(|p| matches!(p, Piece::Hole(_)))
+        .count()
+}
+
+fn parse_spec(inner: &str) -> Result<Spec, String> {
+    let mut spec = Spec {
+        fill: ' ',
+        align: None,
+        zero: false,
+        width: 0,
+        precision: None,
+    };
+    if inner.is_empty() {
+        return Ok(spec);
+    }
+    let rest = match inner.strip_prefix(':') {
+        Some(r) => r,
+        None => {
+            return Err(format!(
+                "bad placeholder `{{{}}}` (expected `{{}}` or `{{:...}}`)",
+                inner
+            ))
+        }
+    };
+    let cs: Vec<char> = rest.chars().collect();
+    let mut i = 0usize;
+    let is_align = |c: char| matches!(c, '<' | '>' | '^');
+    if cs.len() >= 2 && is_align(cs[1]) {
+        spec.fill = cs[0];
+        spec.align = Some(cs[1]);
+        i = 2;
+    } else if !cs.is_empty() && is_align(cs[0]) {
+        spec.align = Some(cs[0]);
+        i = 1;
+    }
+    if cs.get(i) == Some(&'0') {
+        spec.zero = true;
+        i += 1;
+    }
+    let (w, j) = digits(&cs, i);
+    if let Some(w) = w {
+        spec.width = w;
+    }
+    i = j;
+    if cs.get(i) == Some(&'.') {
+        let (p, j) = digits(&cs, i + 1);
+        match p {
+            Some(p) => spec.precision = Some(p),
+            None => return Err(format!("missing precision after `.` in `{{{}}}`", inner)),
+        }
+        i = j;
+    }
+    if i != cs.len() {
+        return Err(format!("bad format spec `{{{}}}`", inner));
+    }
+    Ok(spec)
+}
+
+fn digits(cs: &[char], mut i: usize) -> (Option<usize>, usize) {
+    let start = i;
+    while i < cs.len() && cs[i].is_ascii_digit() {
+        i += 1;
+    }
+    if i == start {
+        return (None, i);
+    }
+    let n: String = cs[start..i].iter().collect();
+    (n.parse().ok(), i)
+}
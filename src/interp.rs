@@ -0,0 +1,440 @@
+//! Tree-walking evaluator over `ast::Program`.
+//!
+//! Mirrors the generated C++: variables live in a chain of environments
+//! where each call frame's parent is its caller's, reads walk the chain and
+//! writes always land in the innermost frame.
+
+use crate::ast::*;
+use crate::math::{self, Math};
+use crate::value::{self, Value};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::io::Write;
+use std::rc::Rc;
+use thiserror::Error;
+
+#[derive(Error, Debug, Clone, PartialEq)]
+#[error("line {line}: runtime error: {message}")]
+pub struct RuntimeError {
+    pub message: String,
+    pub line: usize,
+    /// The file `line` is in (a `Span::file`); the REPL does not import, so
+    /// its errors are always in file 0.
+    pub file: usize,
+}
+
+/// Source of whitespace-delimited tokens for `input`.
+pub trait Input {
+    fn token(&mut self) -> Option<String>;
+}
+
+/// `input` tokens from stdin, read a line at a time so other readers of
+/// stdin (the REPL) see the remaining lines.
+#[derive(Default)]
+pub struct StdinInput(VecDeque<String>);
+
+impl Input for StdinInput {
+    fn token(&mut self) -> Option<String> {
+        while self.0.is_empty() {
+            let mut line = String::new();
+            if std::io::stdin().read_line(&mut line).ok()? == 0 {
+                return None;
+            }
+            self.0.extend(line.split_whitespace().map(str::to_string));
+        }
+        self.0.pop_front()
+    }
+}
+
+struct Func {
+    params: Vec<Param>,
+    body: Vec<Stmt>,
+}
+
+enum Flow {
+    Next,
+    Break,
+    Continue,
+    Return(Value),
+}
+
+type Res<T> = Result<T, RuntimeError>;
+
+pub struct Interp {
+    strict: bool,
+    funcs: HashMap<String, Rc<Func>>,
+    /// Tag of every enum variant.
+    variants: HashMap<String, usize>,
+    /// `extern` functions, which exist only in compiled C++.
+    externs: HashSet<String>,
+    /// `frames[0]` is the top-level environment.
+    frames: Vec<HashMap<String, Value>>,
+    line: usize,
+    out: Box<dyn Write>,
+    input: Box<dyn Input>,
+}
+
+impl Interp {
+    pub fn new(out: Box<dyn Write>, input: Box<dyn Input>, strict: bool) -> Self {
+        Interp {
+            strict,
+            funcs: HashMap::new(),
+            variants: HashMap::new(),
+            externs: HashSet::new(),
+            frames: vec![HashMap::new()],
+            line: 0,
+            out,
+            input,
+        }
+    }
+
+    /// Registers the program's functions and enums, then runs its top-level
+    /// statements. State is kept, so calling `run` again continues the same
+    /// session.
+    pub fn run(&mut self, p: &Program) -> Res<()> {
+        for it in &p.items {
+            match it {
+                Item::Func {
+                    name, params, body, ..
+                } => {
+                    let f = Func {
+                        params: params.clone(),
+                        body: body.clone(),
+                    };
+                    self.funcs.insert(name.clone(), Rc::new(f));
+                }
+                Item::Enum { variants, .. } => {
+                    for (tag, v) in variants.iter().enumerate() {
+                        self.variants.insert(v.name.clone(), tag);
+                    }
+                }
+                Item::Extern { name, .. } => {
+                    self.externs.insert(name.clone());
+                }
+                Item::Stmt(_) | Item::Type { .. } | Item::Import { .. } | Item::Cpp { .. } => {}
+            }
+        }
+        let r = self.top_level(p);
+        self.flush();
+        r
+    }
+
+    fn top_level(&mut self, p: &Program) -> Res<()> {
+        for it in &p.items {
+            match it {
+                Item::Stmt(s) => {
+                    if let Flow::Return(_) = self.exec(s)? {
+                        break;
+                    }
+                }
+                Item::Cpp { span, .. } => {
+                    self.line = span.line;
+                    return self.fail("`cpp` blocks only run in compiled C++");
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+
+    pub fn flush(&mut self) {
+        let _ = self.out.flush();
+    }
+
+    /// Current value of a top-level variable.
+    pub fn global(&self, name: &str) -> Option<&Value> {
+        self.frames[0].get(name)
+    }
+
+    fn fail<T>(&self, message: impl Into<String>) -> Res<T> {
+        Err(RuntimeError {
+            message: message.into(),
+            line: self.line,
+            file: 0,
+        })
+    }
+
+    fn lift<T>(&self, r: value::R<T>) -> Res<T> {
+        r.or_else(|m| self.fail(m))
+    }
+
+    fn find(&self, name: &str) -> Option<&Value> {
+        self.frames.iter().rev().find_map(|f| f.get(name))
+    }
+
+    fn set(&mut self, name: &str, v: Value) {
+        self.frames
+            .last_mut()
+            .expect("at least one frame")
+            .insert(name.to_string(), v);
+    }
+
+    fn set_prop(&mut self, base: &str, key: &str, v: Value) {
+        // Taking the local out leaves its object unshared, so it is updated
+        // in place.
+        let top = self.frames.last_mut().expect("at least one frame");
+        let mut b = match top.remove(base) {
+            Some(b) => b,
+            None => self.find(base).cloned().unwrap_or_default(),
+        };
+        value::set_prop(&mut b, key, v);
+        self.set(base, b);
+    }
+
+    fn write(&mut self, s: &str) {
+        let _ = self.out.write_all(s.as_bytes());
+    }
+
+    fn block(&mut self, b: &[Stmt]) -> Res<Flow> {
+        for s in b {
+            match self.exec(s)? {
+                Flow::Next => {}
+                f => return Ok(f),
+            }
+        }
+        Ok(Flow::Next)
+    }
+
+    fn cond(&mut self, e: &Expr, line: usize) -> Res<bool> {
+        self.line = line;
+        Ok(value::truthy(&self.eval(e)?))
+    }
+
+    fn exec(&mut self, s: &Stmt) -> Res<Flow> {
+        self.line = s.span.line;
+        match &s.kind {
+            StmtKind::ObjBlock { name, fields, .. } => {
+                let mut o = Value::Obj(Default::default());
+                for (k, e) in fields {
+                    let v = self.eval(e)?;
+                    value::set_prop(&mut o, k, v);
+                }
+                self.set(name, o);
+            }
+            StmtKind::PropAssign { base, key, expr } => {
+                let v = self.eval(expr)?;
+                self.set_prop(base, key, v);
+            }
+            StmtKind::IndexAssign { base, index, expr } => {
+                let k = value::as_str(&self.eval(index)?);
+                let v = self.eval(expr)?;
+                self.set_prop(base, &k, v);
+            }
+            StmtKind::VarAssign { name, expr } => {
+                let v = self.eval(expr)?;
+                self.set(name, v);
+            }
+            StmtKind::Log(es) | StmtKind::Print(es) => {
+                let mut xs = Vec::with_capacity(es.len());
+                for e in es {
+                    xs.push(value::as_str(&self.eval(e)?));
+                }
+                let mut line = xs.join(" ");
+                if let StmtKind::Log(_) = s.kind {
+                    line.push('\n');
+                }
+                self.write(&line);
+            }
+            StmtKind::Cond {
+                cond,
+                then_blk,
+                else_blk,
+            } => {
+                if value::truthy(&self.eval(cond)?) {
+                    return self.block(then_blk);
+                } else if let Some(eb) = else_blk {
+                    return self.block(eb);
+                }
+            }
+            StmtKind::Loop { cond, body } => {
+                while self.cond(cond, s.span.line)? {
+                    match self.block(body)? {
+                        Flow::Break => break,
+                        Flow::Return(v) => return Ok(Flow::Return(v)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+            }
+            StmtKind::While { cond, body, ended } => {
+                let mut broke = false;
+                while self.cond(cond, s.span.line)? {
+                    match self.block(body)? {
+                        Flow::Break => {
+                            broke = true;
+                            break;
+                        }
+                        Flow::Return(v) => return Ok(Flow::Return(v)),
+                        Flow::Next | Flow::Continue => {}
+                    }
+                }
+                if let (false, Some(eb)) = (broke, ended) {
+                    return self.block(eb);
+                }
+            }
+            StmtKind::Input { prompt, dst } => {
+                if !prompt.is_empty() {
+                    self.write(prompt);
+                    self.flush();
+                }
+                let v = value::from_input(&self.input.token().unwrap_or_default());
+                match dst {
+                    Target::Var(name) => self.set(name, v),
+                    Target::Prop { base, key } => self.set_prop(base, key, v),
+                }
+            }
+            StmtKind::Match { expr, arms } => {
+                let v = self.eval(expr)?;
+                let mut tag = None;
+                for arm in arms {
+                    let Pat::Variant { name, binds } = &arm.pat else {
+                        return self.block(&arm.body);
+                    };
+                    let want = Value::Num(self.variant(name)? as f64);
+                    if tag.is_none() {
+                        tag = Some(self.lift(value::get_prop(&v, "tag", self.strict))?);
+                    }
+                    let eq = self.lift(value::binary("==", tag.as_ref().unwrap(), &want, false))?;
+                    if value::truthy(&eq) {
+                        for (i, b) in binds.iter().enumerate() {
+                            let x = self.lift(value::get_prop(&v, &i.to_string(), self.strict))?;
+                            self.set(b, x);
+                        }
+                        return self.block(&arm.body);
+                    }
+                }
+            }
+            StmtKind::Break => return Ok(Flow::Break),
+            StmtKind::Continue => return Ok(Flow::Continue),
+            StmtKind::Return(e) => {
+                let v = match e {
+                    Some(e) => self.eval(e)?,
+                    None => Value::Undef,
+                };
+                return Ok(Flow::Return(v));
+            }
+            StmtKind::Cpp(_) => return self.fail("`cpp` blocks only run in compiled C++"),
+        }
+        Ok(Flow::Next)
+    }
+
+    /// Evaluates a standalone expression; errors report it as line 1.
+    pub fn eval_expr(&mut self, e: &Expr) -> Res<Value> {
+        self.line = 1;
+        let v = self.eval(e);
+        self.flush();
+        v
+    }
+
+    fn eval(&mut self, e: &Expr) -> Res<Value> {
+        let strict = self.strict;
+        Ok(match e {
+            Expr::Num(n) => Value::Num(*n),
+            Expr::Str(s) => Value::Str(s.clone()),
+            Expr::Bool(b) => Value::Bool(*b),
+            Expr::Ident(id) => match (self.find(id), math::constant(id)) {
+                (Some(v), _) => v.clone(),
+                (None, Some(c)) => Value::Num(c),
+                (None, None) if strict => return self.fail(format!("undefined variable `{}`", id)),
+                (None, None) => Value::Undef,
+            },
+            Expr::Member(base, key) => {
+                let b = self.eval(base)?;
+                self.lift(value::get_prop(&b, key, strict))?
+            }
+            Expr::Index(base, idx) => {
+                let b = self.eval(base)?;
+                let i = self.eval(idx)?;
+                self.lift(value::index(&b, &i, strict))?
+            }
+            Expr::Unary { op, rhs } => value::unary(op, &self.eval(rhs)?),
+            Expr::Binary { op, lhs, rhs } => {
+                let a = self.eval(lhs)?;
+                let b = self.eval(rhs)?;
+                self.lift(value::binary(op, &a, &b, strict))?
+            }
+            Expr::Interp(parts) => {
+                let mut s = String::new();
+                for part in parts {
+                    match part {
+                        InterpPart::Lit(l) => s.push_str(l),
+                        InterpPart::Expr(e) => s.push_str(&value::as_str(&self.eval(e)?)),
+                    }
+                }
+                Value::Str(s)
+            }
+            Expr::Variant { name, args, .. } => {
+                let mut o = Value::Obj(Default::default());
+                value::set_prop(&mut o, "tag", Value::Num(self.variant(name)? as f64));
+                for (i, a) in args.iter().enumerate() {
+                    let v = self.eval(a)?;
+                    value::set_prop(&mut o, &i.to_string(), v);
+                }
+                o
+            }
+            Expr::Call { name, args } => {
+                let mut xs = Vec::with_capacity(args.len());
+                for a in args {
+                    xs.push(self.eval(a)?);
+                }
+                self.call(name, xs)?
+            }
+        })
+    }
+
+    fn variant(&self, name: &str) -> Res<usize> {
+        match self.variants.get(name) {
+            Some(&tag) => Ok(tag),
+            None => self.fail(format!("unknown variant `{}`", name)),
+        }
+    }
+
+    fn call(&mut self, name: &str, args: Vec<Value>) -> Res<Value> {
+        match (name, args.as_slice()) {
+            ("format", [f, rest @ ..]) => return Ok(value::format(f, rest)),
+            ("len", [x]) => return Ok(value::len(x)),
+            ("has", [o, k]) => return Ok(value::has(o, k)),
+            ("remove", [o, k]) => return Ok(value::remove(o.clone(), k)),
+            ("keys", [o]) => return Ok(value::keys(o)),
+            ("values", [o]) => return Ok(value::values(o)),
+            _ => {}
+        }
+        let f = match self.funcs.get(name) {
+            Some(f) => f.clone(),
+            None if self.externs.contains(name) => {
+                return self.fail(format!(
+                    "`{}` is an extern function and only runs in compiled C++",
+                    name
+                ))
+            }
+            None => match Math::parse(name) {
+                Some(m) if m.params().len() == args.len() => {
+                    return self.lift(value::math(m, &args, self.strict))
+                }
+                Some(m) => {
+                    return self.fail(format!(
+                        "`{}` takes {} argument(s), got {}",
+                        name,
+                        m.params().len(),
+                        args.len()
+                    ))
+                }
+                None => return self.fail(format!("unknown function `{}`", name)),
+            },
+        };
+        if f.params.len() != args.len() {
+            return self.fail(format!(
+                "`{}` takes {} argument(s), got {}",
+                name,
+                f.params.len(),
+                args.len()
+            ));
+        }
+        let frame = f.params.iter().map(|p| p.name.clone()).zip(args).collect();
+        self.frames.push(frame);
+        let r = self.block(&f.body);
+        self.frames.pop();
+        Ok(match r? {
+            Flow::Return(v) => v,
+            _ => Value::Undef,
+        })
+    }
+}
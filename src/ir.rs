@@ -0,0 +1,979 @@
+//! Lowered intermediate representation between the AST and the backends.
+//!
+//! `lower` turns a checked `Program` into structured three-address code:
+//! expressions become `Let`s of temporaries in evaluation order, variables
+//! are resolved to frame slots (the names a function assigns, as in
+//! `codegen_bc`) or to lookups through the caller frames, and the statements
+//! with hidden steps are spelled out: object blocks build their object field
+//! by field, and `x.k: v` and `input -> x.k` become a `SetField` on the slot
+//! (`x[k]: v` a `SetIndex`), which backends with shared objects do in place.
+//! Loops keep their structure; `break`/`continue` name the loop they leave,
+//! and `ended` is part of every loop (empty for `loop`). Enum values are
+//! objects with a numeric `tag` field and their payload in fields `0`, `1`,
+//! ...; a `match` stores its subject in a hidden slot and becomes a `Switch`
+//! on the tag, which backends without a native switch turn into `if`s with
+//! `expand_switches`. Function names are `link::mangle`d, so backends can use
+//! them as identifiers. Calls to `extern` functions and `cpp` blocks only
+//! mean something to the C++ backend; the others reject modules that have
+//! them (see `Module::native`).
+//!
+//! Each temporary is used exactly once, after its definition, and temporaries
+//! are consumed in the order they were defined. Backends that emit nested
+//! expressions rely on this to fold every `Let` back into its use.
+
+use crate::ast::*;
+use crate::bytecode::BinOp;
+use crate::diag::Diagnostic;
+use crate::link;
+use crate::math::{self, Math};
+use std::collections::HashMap;
+use std::fmt;
+
+pub type Temp = usize;
+pub type Slot = usize;
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Const {
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    Undef,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Operand {
+    Temp(Temp),
+    Const(Const),
+}
+
+/// The right-hand side of a `Let`.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Rv {
+    /// A variable this function assigns. Until the slot is set, reads fall
+    /// through to the callers; in strict mode a missing name is an error.
+    Local(Slot),
+    /// A variable this function never assigns, found through the callers.
+    Name(String),
+    Not(Operand),
+    Bin(BinOp, Operand, Operand),
+    Member(Operand, String),
+    /// A code point of a string, or the field of an object named by the
+    /// string form of the index.
+    Index(Operand, Operand),
+    NewObj,
+    /// A copy of the object with one field set; non-objects start empty.
+    SetField(Operand, String, Operand),
+    /// String interpolation: the string forms of all parts, joined.
+    Concat(Vec<Operand>),
+    /// `format(f, args...)`; the format string comes first.
+    Format(Vec<Operand>),
+    Len(Operand),
+    /// `has(o, k)`: whether `o` is an object with field `k`.
+    Has(Operand, Operand),
+    /// `remove(o, k)`: a copy of the object without field `k`.
+    Remove(Operand, Operand),
+    /// `keys(o)` and `values(o)`: a new object holding the field names or
+    /// values of `o` under `0`, `1`, ..., in the order fields are printed.
+    Keys(Operand),
+    Values(Operand),
+    /// A math builtin; the arguments match its parameters.
+    Math(Math, Vec<Operand>),
+    Call(String, Vec<Operand>),
+    /// A call to an `extern` function, which is C++ code the module does
+    /// not contain.
+    Extern(String, Vec<Operand>),
+    Input(String),
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Inst {
+    /// Where the statement that follows starts, for `--strict` errors and
+    /// `#line` directives.
+    Line(Span),
+    Let(Temp, Rv),
+    Store(Slot, Operand),
+    /// `x.k: v`: sets a field of the object in the slot, after `v` is
+    /// evaluated. An unset slot starts from the callers' value (undefined if
+    /// missing, even in strict mode); a non-object is replaced by an empty
+    /// object first.
+    SetField(Slot, String, Operand),
+    /// `x[k]: v`: like `SetField` with the string form of `k`, which is
+    /// evaluated before `v`.
+    SetIndex(Slot, Operand, Operand),
+    /// `log` (with `newline`) and `print`.
+    Out {
+        args: Vec<Operand>,
+        newline: bool,
+    },
+    If {
+        cond: Operand,
+        then_blk: Vec<Inst>,
+        else_blk: Vec<Inst>,
+    },
+    /// Runs `header` and tests `cond` before each iteration. `ended` runs
+    /// when `cond` turns false, but not after a `Break`.
+    Loop {
+        id: usize,
+        header: Vec<Inst>,
+        cond: Operand,
+        body: Vec<Inst>,
+        ended: Vec<Inst>,
+    },
+    /// `match`: runs the case whose tag equals the `tag` field of the value
+    /// in `slot`, or `default` if none does.
+    Switch {
+        slot: Slot,
+        cases: Vec<Case>,
+        default: Vec<Inst>,
+    },
+    Break(usize),
+    Continue(usize),
+    Return(Operand),
+    /// A `cpp` block: C++ statements, as written.
+    Cpp(String),
+}
+
+/// An arm of a `Switch`; `variant` names the tag, for readers.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Case {
+    pub tag: usize,
+    pub variant: String,
+    pub body: Vec<Inst>,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Func {
+    pub name: String,
+    /// Parameters occupy the first `params` slots.
+    pub params: usize,
+    /// Name of each slot.
+    pub slots: Vec<String>,
+    /// Number of temporaries (`t0` .. `t{temps-1}`).
+    pub temps: usize,
+    /// Where the function is declared; line 0 for the top level.
+    pub span: Span,
+    pub body: Vec<Inst>,
+}
+
+/// A record declared with `type`. Records are ordinary objects at run time;
+/// backends may use the declaration for typed host-side views.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TypeDef {
+    pub name: String,
+    /// Fields in declaration order.
+    pub fields: Vec<(String, Ty)>,
+}
+
+/// An `extern function` declaration.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Extern {
+    pub name: String,
+    pub params: Vec<Ty>,
+    pub ret: Ty,
+}
+
+/// `main` holds the top-level statements; its slots are the globals.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Module {
+    pub types: Vec<TypeDef>,
+    pub externs: Vec<Extern>,
+    /// Top-level `cpp` blocks and where they start, in source order.
+    pub cpp: Vec<(String, Span)>,
+    pub funcs: Vec<Func>,
+    pub main: Func,
+    /// Names of the imported files, in load order; `Span::file` N refers to
+    /// `files[N - 1]` and 0 to the main file (see `Session`).
+    pub files: Vec<String>,
+}
+
+impl Module {
+    /// Where the module first has a `cpp` block or calls an `extern`
+    /// function, if it does, since only the C++ backend can compile that.
+    pub fn native(&self) -> Option<Span> {
+        if let Some((_, span)) = self.cpp.first() {
+            return Some(*span);
+        }
+        let mut found = None;
+        for f in self.funcs.iter().chain(std::iter::once(&self.main)) {
+            let mut at = f.span;
+            walk(&f.body, &mut |i| match i {
+                Inst::Line(sp) => at = *sp,
+                Inst::Cpp(_) | Inst::Let(_, Rv::Extern(..)) => {
+                    found = found.or(Some(at));
+                }
+                _ => {}
+            });
+        }
+        found
+    }
+}
+
+pub fn lower(p: &Program) -> Result<Module, Diagnostic> {
+    let arity: HashMap<&str, usize> = p
+        .items
+        .iter()
+        .filter_map(|it| match it {
+            Item::Func { name, params, .. } | Item::Extern { name, params, .. } => {
+                Some((name.as_str(), params.len()))
+            }
+            Item::Stmt(_)
+            | Item::Type { .. }
+            | Item::Enum { .. }
+            | Item::Import { .. }
+            | Item::Cpp { .. } => None,
+        })
+        .collect();
+    let mut externs = Vec::new();
+    for it in &p.items {
+        if let Item::Extern {
+            name, params, ret, ..
+        } = it
+        {
+            externs.push(Extern {
+                name: name.clone(),
+                params: params.iter().map(|p| p.ty.clone()).collect(),
+                ret: ret.clone(),
+            });
+        }
+    }
+    let mut variants = HashMap::new();
+    for it in &p.items {
+        if let Item::Enum { variants: vs, .. } = it {
+            for (tag, v) in vs.iter().enumerate() {
+                variants.insert(v.name.as_str(), (tag, v.fields.len()));
+            }
+        }
+    }
+    // a program that assigns `pi` anywhere reads its own variable instead
+    let mut vars = Vec::new();
+    for it in &p.items {
+        match it {
+            Item::Func { params, body, .. } => {
+                vars.extend(params.iter().map(|p| p.name.clone()));
+                assigned(body, &mut vars);
+            }
+            Item::Stmt(s) => assigned(std::slice::from_ref(s), &mut vars),
+            _ => {}
+        }
+    }
+    let cx = Names {
+        arity: &arity,
+        vars: &vars,
+        externs: &externs,
+        variants: &variants,
+    };
+    let mut types = Vec::new();
+    let mut cpp = Vec::new();
+    let mut funcs = Vec::new();
+    let mut top = Vec::new();
+    for it in &p.items {
+        match it {
+            Item::Func {
+                name,
+                params,
+                body,
+                span,
+                ..
+            } => {
+                let ps: Vec<String> = params.iter().map(|p| p.name.clone()).collect();
+                funcs.push(Lower::func(&cx, &link::mangle(name), ps, body, *span)?);
+            }
+            Item::Stmt(s) => top.push(s.clone()),
+            Item::Type { name, fields, .. } => types.push(TypeDef {
+                name: name.clone(),
+                fields: fields.clone(),
+            }),
+            Item::Cpp { code, span } => cpp.push((code.clone(), *span)),
+            Item::Enum { .. } | Item::Import { .. } | Item::Extern { .. } => {}
+        }
+    }
+    let main = Lower::func(&cx, "main", Vec::new(), &top, Span::default())?;
+    Ok(Module {
+        types,
+        externs,
+        cpp,
+        funcs,
+        main,
+        files: Vec::new(),
+    })
+}
+
+/// Calls `f` on every instruction of `b`, nested blocks included, in order.
+pub fn walk(b: &[Inst], f: &mut impl FnMut(&Inst)) {
+    for i in b {
+        f(i);
+        match i {
+            Inst::If {
+                then_blk, else_blk, ..
+            } => {
+                walk(then_blk, f);
+                walk(else_blk, f);
+            }
+            Inst::Loop {
+                header,
+                body,
+                ended,
+                ..
+            } => {
+                walk(header, f);
+                walk(body, f);
+                walk(ended, f);
+            }
+            Inst::Switch { cases, default, .. } => {
+                for c in cases {
+                    walk(&c.body, f);
+                }
+                walk(default, f);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// Rewrites every `Switch` into a chain of `If`s comparing the tag, for
+/// backends without a native switch.
+pub fn expand_switches(mut m: Module) -> Module {
+    fn block(b: Vec<Inst>, temps: &mut usize) -> Vec<Inst> {
+        b.into_iter().flat_map(|i| inst(i, temps)).collect()
+    }
+    fn inst(i: Inst, temps: &mut usize) -> Vec<Inst> {
+        match i {
+            Inst::If {
+                cond,
+                then_blk,
+                else_blk,
+            } => vec![Inst::If {
+                cond,
+                then_blk: block(then_blk, temps),
+                else_blk: block(else_blk, temps),
+            }],
+            Inst::Loop {
+                id,
+                header,
+                cond,
+                body,
+                ended,
+            } => vec![Inst::Loop {
+                id,
+                header,
+                cond,
+                body: block(body, temps),
+                ended: block(ended, temps),
+            }],
+            Inst::Switch {
+                slot,
+                cases,
+                default,
+            } => {
+                let mut chain = block(default, temps);
+                for c in cases.into_iter().rev() {
+                    let mut t = || {
+                        *temps += 1;
+                        *temps - 1
+                    };
+                    let (v, tag, eq) = (t(), t(), t());
+                    chain = vec![
+                        Inst::Let(v, Rv::Local(slot)),
+                        Inst::Let(tag, Rv::Member(Operand::Temp(v), "tag".into())),
+                        Inst::Let(
+                            eq,
+                            Rv::Bin(
+                                BinOp::Eq,
+                                Operand::Temp(tag),
+                                Operand::Const(Const::Num(c.tag as f64)),
+                            ),
+                        ),
+                        Inst::If {
+                            cond: Operand::Temp(eq),
+                            then_blk: block(c.body, temps),
+                            else_blk: chain,
+                        },
+                    ];
+                }
+                chain
+            }
+            i => vec![i],
+        }
+    }
+    for f in m.funcs.iter_mut().chain(std::iter::once(&mut m.main)) {
+        let body = std::mem::take(&mut f.body);
+        f.body = block(body, &mut f.temps);
+    }
+    m
+}
+
+/// Names a block assigns to, in first-assignment order.
+fn assigned(b: &[Stmt], out: &mut Vec<String>) {
+    fn add(out: &mut Vec<String>, n: &String) {
+        if !out.contains(n) {
+            out.push(n.clone());
+        }
+    }
+    for s in b {
+        match &s.kind {
+            StmtKind::ObjBlock { name, .. } | StmtKind::VarAssign { name, .. } => add(out, name),
+            StmtKind::PropAssign { base, .. } | StmtKind::IndexAssign { base, .. } => {
+                add(out, base)
+            }
+            StmtKind::Input { dst, .. } => match dst {
+                Target::Var(v) => add(out, v),
+                Target::Prop { base, .. } => add(out, base),
+            },
+            StmtKind::Cond {
+                then_blk, else_blk, ..
+            } => {
+                assigned(then_blk, out);
+                assigned(else_blk.as_deref().unwrap_or_default(), out);
+            }
+            StmtKind::Loop { body, .. } => assigned(body, out),
+            StmtKind::Match { arms, .. } => {
+                for arm in arms {
+                    if let Pat::Variant { binds, .. } = &arm.pat {
+                        binds.iter().for_each(|b| add(out, b));
+                    }
+                    assigned(&arm.body, out);
+                }
+            }
+            StmtKind::While { body, ended, .. } => {
+                assigned(body, out);
+                assigned(ended.as_deref().unwrap_or_default(), out);
+            }
+            _ => {}
+        }
+    }
+}
+
+/// What calls, variants and constants refer to: function arities (of
+/// `extern` functions too), the `extern` functions, each variant's tag and
+/// payload size, and the variables that hide a constant of the same name.
+struct Names<'a> {
+    arity: &'a HashMap<&'a str, usize>,
+    /// Every variable and parameter name in the program.
+    vars: &'a [String],
+    externs: &'a [Extern],
+    variants: &'a HashMap<&'a str, (usize, usize)>,
+}
+
+struct Lower<'a> {
+    names: &'a Names<'a>,
+    /// The statement being lowered, for errors.
+    at: Span,
+    slots: Vec<String>,
+    temps: usize,
+    loop_ids: usize,
+    loops: Vec<usize>,
+}
+
+impl<'a> Lower<'a> {
+    fn func(
+        names: &'a Names<'a>,
+        name: &str,
+        params: Vec<String>,
+        body: &[Stmt],
+        span: Span,
+    ) -> Result<Func, Diagnostic> {
+        let n = params.len();
+        let mut slots = params;
+        assigned(body, &mut slots);
+        let mut l = Lower {
+            names,
+            at: span,
+            slots,
+            temps: 0,
+            loop_ids: 0,
+            loops: Vec::new(),
+        };
+        let mut code = Vec::new();
+        l.block(&mut code, body)
+            .map_err(|e| Diagnostic::new(e, l.at))?;
+        Ok(Func {
+            name: name.to_string(),
+            params: n,
+            slots: l.slots,
+            temps: l.temps,
+            span,
+            body: code,
+        })
+    }
+
+    fn slot(&self, name: &str) -> Slot {
+        self.slots
+            .iter()
+            .position(|s| s == name)
+            .expect("assigned names have slots")
+    }
+
+    fn temp(&mut self, out: &mut Vec<Inst>, rv: Rv) -> Operand {
+        let t = self.temps;
+        self.temps += 1;
+        out.push(Inst::Let(t, rv));
+        Operand::Temp(t)
+    }
+
+    fn block(&mut self, out: &mut Vec<Inst>, b: &[Stmt]) -> Result<(), String> {
+        for s in b {
+            self.stmt(out, s)?;
+        }
+        Ok(())
+    }
+
+    /// `base.key: v`, given the code computing `v`.
+    fn set_prop(
+        &mut self,
+        out: &mut Vec<Inst>,
+        base: &str,
+        key: &str,
+        value: impl FnOnce(&mut Self, &mut Vec<Inst>) -> Result<Operand, String>,
+    ) -> Result<(), String> {
+        let v = value(self, out)?;
+        out.push(Inst::SetField(self.slot(base), key.to_string(), v));
+        Ok(())
+    }
+
+    fn stmt(&mut self, out: &mut Vec<Inst>, s: &Stmt) -> Result<(), String> {
+        self.at = s.span;
+        out.push(Inst::Line(s.span));
+        match &s.kind {
+            StmtKind::ObjBlock { name, fields, .. } => {
+                let mut o = self.temp(out, Rv::NewObj);
+                for (k, e) in fields {
+                    let v = self.expr(out, e)?;
+                    o = self.temp(out, Rv::SetField(o, k.clone(), v));
+                }
+                out.push(Inst::Store(self.slot(name), o));
+            }
+            StmtKind::PropAssign { base, key, expr } => {
+                self.set_prop(out, base, key, |l, out| l.expr(out, expr))?
+            }
+            StmtKind::IndexAssign { base, index, expr } => {
+                let k = self.expr(out, index)?;
+                let v = self.expr(out, expr)?;
+                out.push(Inst::SetIndex(self.slot(base), k, v));
+            }
+            StmtKind::VarAssign { name, expr } => {
+                let v = self.expr(out, expr)?;
+                out.push(Inst::Store(self.slot(name), v));
+            }
+            StmtKind::Log(es) | StmtKind::Print(es) => {
+                let args = self.exprs(out, es)?;
+                out.push(Inst::Out {
+                    args,
+                    newline: matches!(s.kind, StmtKind::Log(_)),
+                });
+            }
+            StmtKind::Cond {
+                cond,
+                then_blk,
+                else_blk,
+            } => {
+                let cond = self.expr(out, cond)?;
+                let mut t = Vec::new();
+                self.block(&mut t, then_blk)?;
+                let mut e = Vec::new();
+                self.block(&mut e, else_blk.as_deref().unwrap_or_default())?;
+                out.push(Inst::If {
+                    cond,
+                    then_blk: t,
+                    else_blk: e,
+                });
+            }
+            StmtKind::Loop { cond, body } => self.looping(out, s, cond, body, &[])?,
+            StmtKind::While { cond, body, ended } => {
+                self.looping(out, s, cond, body, ended.as_deref().unwrap_or_default())?
+            }
+            StmtKind::Input { prompt, dst } => {
+                let input =
+                    |l: &mut Self, out: &mut Vec<Inst>| Ok(l.temp(out, Rv::Input(prompt.clone())));
+                match dst {
+                    Target::Var(v) => {
+                        let x = input(self, out)?;
+                        out.push(Inst::Store(self.slot(v), x));
+                    }
+                    Target::Prop { base, key } => self.set_prop(out, base, key, input)?,
+                }
+            }
+            StmtKind::Match { expr, arms } => {
+                let v = self.expr(out, expr)?;
+                let slot = self.slots.len();
+                self.slots.push(format!("match#{}", slot));
+                out.push(Inst::Store(slot, v));
+                let mut cases = Vec::new();
+                let mut default = Vec::new();
+                for arm in arms {
+                    let mut body = Vec::new();
+                    match &arm.pat {
+                        Pat::Variant { name, binds } => {
+                            let (tag, _) = self.variant(name)?;
+                            for (i, b) in binds.iter().enumerate() {
+                                let o = self.temp(&mut body, Rv::Local(slot));
+                                let x = self.temp(&mut body, Rv::Member(o, i.to_string()));
+                                body.push(Inst::Store(self.slot(b), x));
+                            }
+                            self.block(&mut body, &arm.body)?;
+                            cases.push(Case {
+                                tag,
+                                variant: name.clone(),
+                                body,
+                            });
+                        }
+                        Pat::Wild => {
+                            self.block(&mut body, &arm.body)?;
+                            default = body;
+                            break;
+                        }
+                    }
+                }
+                out.push(Inst::Switch {
+                    slot,
+                    cases,
+                    default,
+                });
+            }
+            StmtKind::Break | StmtKind::Continue => {
+                let id = *self
+                    .loops
+                    .last()
+                    .ok_or("`break`/`continue` outside of a loop")?;
+                out.push(match s.kind {
+                    StmtKind::Break => Inst::Break(id),
+                    _ => Inst::Continue(id),
+                });
+            }
+            StmtKind::Return(e) => {
+                let v = match e {
+                    Some(e) => self.expr(out, e)?,
+                    None => Operand::Const(Const::Undef),
+                };
+                out.push(Inst::Return(v));
+            }
+            StmtKind::Cpp(code) => out.push(Inst::Cpp(code.clone())),
+        }
+        Ok(())
+    }
+
+    fn looping(
+        &mut self,
+        out: &mut Vec<Inst>,
+        s: &Stmt,
+        cond: &Expr,
+        body: &[Stmt],
+        ended: &[Stmt],
+    ) -> Result<(), String> {
+        let id = self.loop_ids;
+        self.loop_ids += 1;
+        let mut header = vec![Inst::Line(s.span)];
+        let cond = self.expr(&mut header, cond)?;
+        self.loops.push(id);
+        let mut b = Vec::new();
+        self.block(&mut b, body)?;
+        self.loops.pop();
+        let mut e = Vec::new();
+        self.block(&mut e, ended)?;
+        out.push(Inst::Loop {
+            id,
+            header,
+            cond,
+            body: b,
+            ended: e,
+        });
+        Ok(())
+    }
+
+    /// Tag and payload size of a variant.
+    fn variant(&self, name: &str) -> Result<(usize, usize), String> {
+        self.names
+            .variants
+            .get(name)
+            .copied()
+            .ok_or_else(|| format!("unknown variant `{}`", name))
+    }
+
+    fn exprs(&mut self, out: &mut Vec<Inst>, es: &[Expr]) -> Result<Vec<Operand>, String> {
+        es.iter().map(|e| self.expr(out, e)).collect()
+    }
+
+    fn expr(&mut self, out: &mut Vec<Inst>, e: &Expr) -> Result<Operand, String> {
+        let rv = match e {
+            Expr::Num(n) => return Ok(Operand::Const(Const::Num(*n))),
+            Expr::Str(s) => return Ok(Operand::Const(Const::Str(s.clone()))),
+            Expr::Bool(b) => return Ok(Operand::Const(Const::Bool(*b))),
+            Expr::Ident(id) => match self.slots.iter().position(|s| s == id) {
+                Some(s) => Rv::Local(s),
+                None => match math::constant(id).filter(|_| !self.names.vars.contains(id)) {
+                    Some(c) => return Ok(Operand::Const(Const::Num(c))),
+                    None => Rv::Name(id.clone()),
+                },
+            },
+            Expr::Member(base, key) => Rv::Member(self.expr(out, base)?, key.clone()),
+            Expr::Index(base, i) => {
+                let b = self.expr(out, base)?;
+                Rv::Index(b, self.expr(out, i)?)
+            }
+            Expr::Unary { rhs, .. } => Rv::Not(self.expr(out, rhs)?),
+            Expr::Binary { op, lhs, rhs } => {
+                let b = BinOp::parse(op).ok_or_else(|| format!("unknown operator {}", op))?;
+                let l = self.expr(out, lhs)?;
+                Rv::Bin(b, l, self.expr(out, rhs)?)
+            }
+            Expr::Interp(parts) => {
+                let mut xs = Vec::new();
+                for part in parts {
+                    xs.push(match part {
+                        InterpPart::Lit(l) => Operand::Const(Const::Str(l.clone())),
+                        InterpPart::Expr(e) => self.expr(out, e)?,
+                    });
+                }
+                Rv::Concat(xs)
+            }
+            Expr::Variant { name, args, .. } => {
+                let (tag, n) = self.variant(name)?;
+                if n != args.len() {
+                    return Err(format!(
+                        "`{}` takes {} value(s), got {}",
+                        name,
+                        n,
+                        args.len()
+                    ));
+                }
+                let o = self.temp(out, Rv::NewObj);
+                let tag = Operand::Const(Const::Num(tag as f64));
+                let mut o = self.temp(out, Rv::SetField(o, "tag".into(), tag));
+                for (i, a) in args.iter().enumerate() {
+                    let v = self.expr(out, a)?;
+                    o = self.temp(out, Rv::SetField(o, i.to_string(), v));
+                }
+                return Ok(o);
+            }
+            Expr::Call { name, args } => {
+                let xs = self.exprs(out, args)?;
+                match (name.as_str(), xs.as_slice()) {
+                    ("format", _) => Rv::Format(xs),
+                    ("len", [a]) => Rv::Len(a.clone()),
+                    ("has", [a, k]) => Rv::Has(a.clone(), k.clone()),
+                    ("remove", [a, k]) => Rv::Remove(a.clone(), k.clone()),
+                    ("keys", [a]) => Rv::Keys(a.clone()),
+                    ("values", [a]) => Rv::Values(a.clone()),
+                    _ => {
+                        // a user or `extern` function hides a math builtin
+                        let m = Math::parse(name)
+                            .filter(|_| !self.names.arity.contains_key(name.as_str()));
+                        let arity = match m {
+                            Some(m) => m.params().len(),
+                            None => *self
+                                .names
+                                .arity
+                                .get(name.as_str())
+                                .ok_or_else(|| format!("unknown function `{}`", name))?,
+                        };
+                        if arity != xs.len() {
+                            return Err(format!(
+                                "`{}` takes {} argument(s), got {}",
+                                name,
+                                arity,
+                                xs.len()
+                            ));
+                        }
+                        if let Some(m) = m {
+                            Rv::Math(m, xs)
+                        } else if self.names.externs.iter().any(|x| x.name == *name) {
+                            Rv::Extern(name.clone(), xs)
+                        } else {
+                            Rv::Call(link::mangle(name), xs)
+                        }
+                    }
+                }
+            }
+        };
+        Ok(self.temp(out, rv))
+    }
+}
+
+impl fmt::Display for Const {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Const::Num(n) => write!(f, "{:?}", n),
+            Const::Str(s) => write!(f, "{:?}", s),
+            Const::Bool(b) => write!(f, "{}", b),
+            Const::Undef => f.write_str("undef"),
+        }
+    }
+}
+
+impl fmt::Display for Operand {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            Operand::Temp(t) => write!(f, "t{}", t),
+            Operand::Const(c) => c.fmt(f),
+        }
+    }
+}
+
+fn list(xs: &[Operand]) -> String {
+    xs.iter()
+        .map(|x| x.to_string())
+        .collect::<Vec<_>>()
+        .join(", ")
+}
+
+impl Func {
+    fn write_rv(&self, f: &mut fmt::Formatter, rv: &Rv) -> fmt::Result {
+        match rv {
+            Rv::Local(s) => write!(f, "local {}", self.slots[*s]),
+            Rv::Name(n) => write!(f, "name {}", n),
+            Rv::Not(a) => write!(f, "not {}", a),
+            Rv::Bin(op, a, b) => write!(f, "{} {}, {}", op.name(), a, b),
+            Rv::Member(a, k) => write!(f, "member {}, {:?}", a, k),
+            Rv::Index(a, b) => write!(f, "index {}, {}", a, b),
+            Rv::NewObj => f.write_str("newobj"),
+            Rv::SetField(o, k, v) => write!(f, "setfield {}, {:?}, {}", o, k, v),
+            Rv::Concat(xs) => write!(f, "concat {}", list(xs)),
+            Rv::Format(xs) => write!(f, "format {}", list(xs)),
+            Rv::Len(a) => write!(f, "len {}", a),
+            Rv::Has(a, k) => write!(f, "has {}, {}", a, k),
+            Rv::Remove(a, k) => write!(f, "remove {}, {}", a, k),
+            Rv::Keys(a) => write!(f, "keys {}", a),
+            Rv::Values(a) => write!(f, "values {}", a),
+            Rv::Math(m, xs) => write!(f, "{} {}", m.name(), list(xs)),
+            Rv::Call(n, xs) => write!(f, "call {}({})", n, list(xs)),
+            Rv::Extern(n, xs) => write!(f, "extern {}({})", n, list(xs)),
+            Rv::Input(p) => write!(f, "input {:?}", p),
+        }
+    }
+
+    fn write_block(&self, f: &mut fmt::Formatter, b: &[Inst], lvl: usize) -> fmt::Result {
+        let ind = "  ".repeat(lvl);
+        for i in b {
+            match i {
+                Inst::Line(sp) if sp.file == 0 => writeln!(f, "{}line {}", ind, sp.line)?,
+                Inst::Line(sp) => writeln!(f, "{}line {}, file {}", ind, sp.line, sp.file)?,
+                Inst::Let(t, rv) => {
+                    write!(f, "{}t{} = ", ind, t)?;
+                    self.write_rv(f, rv)?;
+                    writeln!(f)?;
+                }
+                Inst::Store(s, v) => writeln!(f, "{}store {}, {}", ind, self.slots[*s], v)?,
+                Inst::SetField(s, k, v) => {
+                    writeln!(f, "{}setfield {}, {:?}, {}", ind, self.slots[*s], k, v)?
+                }
+                Inst::SetIndex(s, k, v) => {
+                    writeln!(f, "{}setindex {}, {}, {}", ind, self.slots[*s], k, v)?
+                }
+                Inst::Out { args, newline } => {
+                    let op = if *newline { "log" } else { "print" };
+                    writeln!(f, "{}{} {}", ind, op, list(args))?
+                }
+                Inst::If {
+                    cond,
+                    then_blk,
+                    else_blk,
+                } => {
+                    writeln!(f, "{}if {}", ind, cond)?;
+                    self.write_block(f, then_blk, lvl + 1)?;
+                    if !else_blk.is_empty() {
+                        writeln!(f, "{}else", ind)?;
+                        self.write_block(f, else_blk, lvl + 1)?;
+                    }
+                    writeln!(f, "{}end", ind)?;
+                }
+                Inst::Loop {
+                    id,
+                    header,
+                    cond,
+                    body,
+                    ended,
+                } => {
+                    writeln!(f, "{}loop #{}", ind, id)?;
+                    self.write_block(f, header, lvl + 1)?;
+                    writeln!(f, "{}while {}", ind, cond)?;
+                    self.write_block(f, body, lvl + 1)?;
+                    if !ended.is_empty() {
+                        writeln!(f, "{}ended", ind)?;
+                        self.write_block(f, ended, lvl + 1)?;
+                    }
+                    writeln!(f, "{}end", ind)?;
+                }
+                Inst::Switch {
+                    slot,
+                    cases,
+                    default,
+                } => {
+                    writeln!(f, "{}switch {}", ind, self.slots[*slot])?;
+                    for c in cases {
+                        writeln!(f, "{}case {} {}", ind, c.tag, c.variant)?;
+                        self.write_block(f, &c.body, lvl + 1)?;
+                    }
+                    if !default.is_empty() {
+                        writeln!(f, "{}default", ind)?;
+                        self.write_block(f, default, lvl + 1)?;
+                    }
+                    writeln!(f, "{}end", ind)?;
+                }
+                Inst::Break(id) => writeln!(f, "{}break #{}", ind, id)?,
+                Inst::Continue(id) => writeln!(f, "{}continue #{}", ind, id)?,
+                Inst::Return(v) => writeln!(f, "{}return {}", ind, v)?,
+                Inst::Cpp(code) => writeln!(f, "{}cpp {{{}}}", ind, code)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Display for Func {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let (params, locals) = self.slots.split_at(self.params);
+        writeln!(
+            f,
+            "func {}({}) locals [{}] temps {}",
+            self.name,
+            params.join(", "),
+            locals.join(", "),
+            self.temps
+        )?;
+        self.write_block(f, &self.body, 1)
+    }
+}
+
+impl fmt::Display for TypeDef {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let fields: Vec<String> = self
+            .fields
+            .iter()
+            .map(|(k, t)| format!("{}: {}", k, t))
+            .collect();
+        write!(f, "type {} {{ {} }}", self.name, fields.join(", "))
+    }
+}
+
+impl fmt::Display for Extern {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let params: Vec<String> = self.params.iter().map(Ty::to_string).collect();
+        write!(
+            f,
+            "extern {}({}) -> {}",
+            self.name,
+            params.join(", "),
+            self.ret
+        )
+    }
+}
+
+impl fmt::Display for Module {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for (i, name) in self.files.iter().enumerate() {
+            writeln!(f, "file {} {:?}", i + 1, name)?;
+        }
+        for t in &self.types {
+            writeln!(f, "{}", t)?;
+        }
+        for x in &self.externs {
+            writeln!(f, "{}", x)?;
+        }
+        for (code, _) in &self.cpp {
+            writeln!(f, "cpp {{{}}}", code)?;
+        }
+        for func in &self.funcs {
+            writeln!(f, "{}", func)?;
+        }
+        write!(f, "{}", self.main)
+    }
+}
@@ -0,0 +1,412 @@
+//! `pna-cc judge`: compiles a program to C++ once and runs it on a directory
+//! of test cases the way an online judge does.
+//!
+//! A case is a pair `N.in` / `N.out`. Each run gets the input on stdin and a
+//! time limit (wall clock) and memory limit (address space, set with
+//! `ulimit -v` on Unix, where core dumps are turned off too); peak memory is read from `/proc` where there is
+//! one. The output is compared by a `Checker`.
+
+use crate::diag::Diagnostic;
+use crate::{Compiler, CppOptions, Session};
+use std::fmt;
+use std::fs::{self, File};
+use std::io::{self, Read};
+use std::path::{Path, PathBuf};
+use std::process::{self, Child, Command, ExitStatus, Stdio};
+use std::thread;
+use std::time::{Duration, Instant};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Verdict {
+    Accepted,
+    WrongAnswer,
+    TimeLimit,
+    MemoryLimit,
+    RuntimeError,
+}
+
+impl fmt::Display for Verdict {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match self {
+            Verdict::Accepted => "AC",
+            Verdict::WrongAnswer => "WA",
+            Verdict::TimeLimit => "TLE",
+            Verdict::MemoryLimit => "MLE",
+            Verdict::RuntimeError => "RE",
+        })
+    }
+}
+
+/// How output is compared with the expected answer.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Checker {
+    /// The same whitespace-separated tokens.
+    Tokens,
+    /// Like `Tokens`, but numbers match within this absolute or relative
+    /// error.
+    Float(f64),
+}
+
+impl Checker {
+    pub fn accepts(&self, expected: &str, got: &str) -> bool {
+        let (want, have): (Vec<&str>, Vec<&str>) = (
+            expected.split_whitespace().collect(),
+            got.split_whitespace().collect(),
+        );
+        want.len() == have.len() && want.iter().zip(&have).all(|(w, h)| self.token(w, h))
+    }
+
+    fn token(&self, want: &str, have: &str) -> bool {
+        if want == have {
+            return true;
+        }
+        let Checker::Float(eps) = *self else {
+            return false;
+        };
+        match (want.parse::<f64>(), have.parse::<f64>()) {
+            (Ok(w), Ok(h)) => (w - h).abs() <= eps || (w - h).abs() <= eps * w.abs(),
+            _ => false,
+        }
+    }
+
+    /// The first line whose tokens differ, as `line N: expected .., got ..`.
+    pub fn first_difference(&self, expected: &str, got: &str) -> Option<String> {
+        let want: Vec<&str> = expected.lines().collect();
+        let have: Vec<&str> = got.lines().collect();
+        (0..want.len().max(have.len())).find_map(|i| {
+            let (w, h) = (want.get(i), have.get(i));
+            if self.accepts(w.unwrap_or(&""), h.unwrap_or(&"")) {
+                return None;
+            }
+            let show =
+                |l: Option<&&str>| l.map_or("end of output".to_string(), |l| format!("{:?}", l));
+            Some(format!(
+                "line {}: expected {}, got {}",
+                i + 1,
+                show(w),
+                show(h)
+            ))
+        })
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Limits {
+    pub time: Duration,
+    pub memory_kb: u64,
+}
+
+impl Default for Limits {
+    fn default() -> Self {
+        Limits {
+            time: Duration::from_secs(2),
+            memory_kb: 256 * 1024,
+        }
+    }
+}
+
+/// How `run` builds and judges a program.
+#[derive(Debug, Clone)]
+pub struct Options {
+    pub strict: bool,
+    /// Headers to `#include`, as absolute paths: the program is compiled in
+    /// a temporary directory.
+    pub includes: Vec<String>,
+    /// The C++ compiler, run as `cxx -std=c++17 -O2`.
+    pub cxx: String,
+    pub limits: Limits,
+    pub checker: Checker,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            strict: false,
+            includes: Vec::new(),
+            cxx: "g++".into(),
+            limits: Limits::default(),
+            checker: Checker::Tokens,
+        }
+    }
+}
+
+/// Why `run` judged nothing.
+#[derive(Debug)]
+pub enum Error {
+    /// The test directory has no `N.in` / `N.out` pair.
+    NoCases,
+    /// The program does not compile; the diagnostics belong to the session.
+    Compile(Vec<Diagnostic>),
+    /// The C++ compiler rejected the generated code (its output went to
+    /// stderr).
+    Cxx,
+    Io(io::Error),
+}
+
+impl From<io::Error> for Error {
+    fn from(e: io::Error) -> Self {
+        Error::Io(e)
+    }
+}
+
+/// Every case of a directory and what the program did on it.
+#[derive(Debug)]
+pub struct Report {
+    pub runs: Vec<(Case, Run)>,
+    pub limits: Limits,
+}
+
+impl Report {
+    pub fn passed(&self) -> usize {
+        self.runs
+            .iter()
+            .filter(|(_, r)| r.verdict == Verdict::Accepted)
+            .count()
+    }
+
+    pub fn all_passed(&self) -> bool {
+        self.passed() == self.runs.len()
+    }
+}
+
+/// The verdict table, then what went wrong in each failed case: the first
+/// differing line, the time limit, or the exit status and stderr.
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(
+            f,
+            "{:<12} {:<7} {:>8} {:>10}",
+            "test", "verdict", "time", "memory"
+        )?;
+        for (case, r) in &self.runs {
+            let memory = r.memory_kb.map_or("-".to_string(), |kb| {
+                format!("{:.1} MB", kb as f64 / 1024.0)
+            });
+            let time = format!("{} ms", r.time.as_millis());
+            writeln!(
+                f,
+                "{:<12} {:<7} {:>8} {:>10}",
+                case.name, r.verdict, time, memory
+            )?;
+        }
+        writeln!(f, "{}/{} passed", self.passed(), self.runs.len())?;
+
+        for (case, r) in &self.runs {
+            if r.verdict == Verdict::Accepted {
+                continue;
+            }
+            writeln!(f, "\n--- {} ({})", case.name, r.verdict)?;
+            match r.verdict {
+                Verdict::WrongAnswer => {
+                    if let Some(d) = &r.difference {
+                        writeln!(f, "{}", d)?;
+                    }
+                }
+                Verdict::TimeLimit => {
+                    writeln!(f, "stopped after {} ms", self.limits.time.as_millis())?
+                }
+                _ => {
+                    if let Some(st) = r.status {
+                        writeln!(f, "exited with {}", st)?;
+                    }
+                    for l in r.stderr.lines().take(10) {
+                        writeln!(f, "{}", l)?;
+                    }
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Compiles `sess` with `opts.cxx` and runs it on every case in `dir`.
+pub fn run(sess: &Session, dir: &Path, opts: &Options) -> Result<Report, Error> {
+    let cases = cases(dir)?;
+    if cases.is_empty() {
+        return Err(Error::NoCases);
+    }
+    let cpp = Compiler::new(CppOptions {
+        strict: opts.strict,
+        includes: opts.includes.clone(),
+        ..CppOptions::default()
+    })
+    .compile(sess)
+    .map_err(Error::Compile)?;
+
+    let tmp = TempDir::new()?;
+    let (cpp_path, exe) = (tmp.0.join("prog.cpp"), tmp.0.join("prog"));
+    fs::write(&cpp_path, cpp)?;
+    let built = Command::new(&opts.cxx)
+        .args(["-std=c++17", "-O2", "-o"])
+        .arg(&exe)
+        .arg(&cpp_path)
+        .status()?;
+    if !built.success() {
+        return Err(Error::Cxx);
+    }
+
+    let mut runs = Vec::new();
+    for case in cases {
+        let r = run_case(&exe, &case, &opts.limits, &opts.checker)?;
+        runs.push((case, r));
+    }
+    Ok(Report {
+        runs,
+        limits: opts.limits,
+    })
+}
+
+/// A directory for the compiled program, removed however `run` returns.
+struct TempDir(PathBuf);
+
+impl TempDir {
+    fn new() -> io::Result<TempDir> {
+        let path = std::env::temp_dir().join(format!("pna-judge-{}", process::id()));
+        fs::create_dir_all(&path)?;
+        Ok(TempDir(path))
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        let _ = fs::remove_dir_all(&self.0);
+    }
+}
+
+/// One test case.
+#[derive(Debug, Clone)]
+pub struct Case {
+    pub name: String,
+    pub input: PathBuf,
+    pub output: PathBuf,
+}
+
+/// The `N.in` files of `dir` that have an `N.out`, numeric names first in
+/// numeric order, then the rest by name.
+pub fn cases(dir: &Path) -> io::Result<Vec<Case>> {
+    let mut cases = Vec::new();
+    for entry in fs::read_dir(dir)? {
+        let input = entry?.path();
+        if input.extension().is_none_or(|e| e != "in") {
+            continue;
+        }
+        let output = input.with_extension("out");
+        if !output.is_file() {
+            continue;
+        }
+        let name = input
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        cases.push(Case {
+            name,
+            input,
+            output,
+        });
+    }
+    cases.sort_by_key(|c| {
+        (
+            c.name.parse::<u64>().map_err(|_| c.name.clone()),
+            c.name.clone(),
+        )
+    });
+    Ok(cases)
+}
+
+/// What a single run did.
+#[derive(Debug, Clone)]
+pub struct Run {
+    pub verdict: Verdict,
+    pub time: Duration,
+    /// Peak resident memory, when the platform reports it.
+    pub memory_kb: Option<u64>,
+    pub stdout: String,
+    pub stderr: String,
+    /// `None` when the run was stopped at the time limit.
+    pub status: Option<ExitStatus>,
+    /// For a wrong answer, the first line that differs (see
+    /// `Checker::first_difference`).
+    pub difference: Option<String>,
+}
+
+/// Runs `exe` on `case` and judges its output.
+pub fn run_case(exe: &Path, case: &Case, limits: &Limits, checker: &Checker) -> io::Result<Run> {
+    let expected = fs::read_to_string(&case.output)?;
+    let mut cmd = if cfg!(unix) {
+        let mut c = Command::new("sh");
+        c.args(["-c", "ulimit -v \"$1\" && ulimit -c 0 && exec \"$0\""])
+            .arg(exe)
+            .arg(limits.memory_kb.to_string());
+        c
+    } else {
+        Command::new(exe)
+    };
+    let mut child = cmd
+        .stdin(File::open(&case.input)?)
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()?;
+    let stdout = drain(child.stdout.take());
+    let stderr = drain(child.stderr.take());
+
+    let start = Instant::now();
+    let mut memory_kb = None;
+    let status = loop {
+        if let Some(kb) = peak_memory_kb(&child) {
+            memory_kb = memory_kb.max(Some(kb));
+        }
+        if let Some(st) = child.try_wait()? {
+            break Some(st);
+        }
+        if start.elapsed() > limits.time {
+            child.kill()?;
+            child.wait()?;
+            break None;
+        }
+        thread::sleep(Duration::from_millis(1));
+    };
+    let time = start.elapsed();
+    let stdout = stdout.join().unwrap_or_default();
+    let stderr = stderr.join().unwrap_or_default();
+
+    let verdict = match status {
+        None => Verdict::TimeLimit,
+        _ if memory_kb.is_some_and(|kb| kb > limits.memory_kb) => Verdict::MemoryLimit,
+        Some(st) if !st.success() && stderr.contains("bad_alloc") => Verdict::MemoryLimit,
+        Some(st) if !st.success() => Verdict::RuntimeError,
+        _ if checker.accepts(&expected, &stdout) => Verdict::Accepted,
+        _ => Verdict::WrongAnswer,
+    };
+    let difference = match verdict {
+        Verdict::WrongAnswer => checker.first_difference(&expected, &stdout),
+        _ => None,
+    };
+    Ok(Run {
+        verdict,
+        time,
+        memory_kb,
+        stdout,
+        stderr,
+        status,
+        difference,
+    })
+}
+
+/// Reads a pipe to the end on another thread, so a chatty program cannot
+/// block on a full pipe while it is being timed.
+fn drain(pipe: Option<impl Read + Send + 'static>) -> thread::JoinHandle<String> {
+    thread::spawn(move || {
+        let mut buf = Vec::new();
+        if let Some(mut p) = pipe {
+            let _ = p.read_to_end(&mut buf);
+        }
+        String::from_utf8_lossy(&buf).into_owned()
+    })
+}
+
+/// `VmHWM` of a running process, from `/proc` (Linux only).
+fn peak_memory_kb(child: &Child) -> Option<u64> {
+    let status = fs::read_to_string(format!("/proc/{}/status", child.id())).ok()?;
+    let line = status.lines().find(|l| l.starts_with("VmHWM:"))?;
+    line.split_whitespace().nth(1)?.parse().ok()
+}
@@ -1,116 +1,307 @@
-use crate::token::Tok;
+use crate::ast::Span;
+use crate::token::{Comment, Tok, TplPart};
 use thiserror::Error;
 
 #[derive(Error, Debug)]
-pub enum LexError {
+#[error("{}:{}: {kind}", span.line, span.col)]
+pub struct LexError {
+    pub kind: LexErrorKind,
+    pub span: Span,
+}
+
+#[derive(Error, Debug)]
+pub enum LexErrorKind {
     #[error("unexpected char `{0}`")]
     Unexpected(char),
+    #[error("unterminated `${{` in string literal")]
+    UnterminatedInterp,
+    #[error("unterminated string literal")]
+    UnterminatedString,
+    #[error("unknown escape `\\{0}` in string literal")]
+    BadEscape(char),
+    #[error("invalid unicode escape `\\u{{{0}}}`")]
+    BadUnicode(String),
+    #[error("unterminated `cpp {{` block")]
+    UnterminatedCpp,
+}
+
+/// Index of the `}` closing a `${` whose body starts at `i`, skipping nested
+/// braces and string literals inside the embedded expression.
+fn interp_end(b: &[char], mut i: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    while i < b.len() {
+        match b[i] {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            q @ ('"' | '\'') => {
+                i += 1;
+                while i < b.len() && b[i] != q {
+                    if b[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
+}
+
+/// Index of the `}` closing a `cpp {` block whose body starts at `i`,
+/// skipping braces in C++ string and character literals and comments.
+fn cpp_end(b: &[char], mut i: usize) -> Option<usize> {
+    let mut depth = 0usize;
+    while i < b.len() {
+        match b[i] {
+            '{' => depth += 1,
+            '}' if depth == 0 => return Some(i),
+            '}' => depth -= 1,
+            '/' if b.get(i + 1) == Some(&'/') => {
+                while i < b.len() && b[i] != '\n' {
+                    i += 1;
+                }
+            }
+            '/' if b.get(i + 1) == Some(&'*') => {
+                i += 2;
+                while i + 1 < b.len() && !(b[i] == '*' && b[i + 1] == '/') {
+                    i += 1;
+                }
+                i += 1;
+            }
+            q @ ('"' | '\'') => {
+                i += 1;
+                while i < b.len() && b[i] != q {
+                    if b[i] == '\\' {
+                        i += 1;
+                    }
+                    i += 1;
+                }
+            }
+            _ => {}
+        }
+        i += 1;
+    }
+    None
 }
 
-pub fn lex(s: &str) -> Result<Vec<Tok>, LexError> {
-    let b = s.as_bytes();
+/// Decodes the escape following a `\` at `b[i]`; returns the char and the
+/// index just past the escape.
+fn escape(b: &[char], i: usize) -> Result<(char, usize), LexErrorKind> {
+    let c = *b.get(i).ok_or(LexErrorKind::UnterminatedString)?;
+    let ch = match c {
+        'n' => '\n',
+        't' => '\t',
+        'r' => '\r',
+        '0' => '\0',
+        '\\' | '"' | '\'' | '$' => c,
+        'u' => {
+            if b.get(i + 1) != Some(&'{') {
+                return Err(LexErrorKind::BadUnicode(String::new()));
+            }
+            let close = b[i + 2..]
+                .iter()
+                .position(|&d| d == '}')
+                .map(|k| i + 2 + k)
+                .ok_or(LexErrorKind::UnterminatedString)?;
+            let hex: String = b[i + 2..close].iter().collect();
+            let ch = (1..=6)
+                .contains(&hex.len())
+                .then(|| u32::from_str_radix(&hex, 16).ok())
+                .flatten()
+                .and_then(char::from_u32)
+                .ok_or_else(|| LexErrorKind::BadUnicode(hex.clone()))?;
+            return Ok((ch, close + 1));
+        }
+        x => return Err(LexErrorKind::BadEscape(x)),
+    };
+    Ok((ch, i + 1))
+}
+
+/// Span of char index `i`, given the char indices where each line starts.
+fn span_of(line_starts: &[usize], i: usize) -> Span {
+    let line = line_starts.partition_point(|&st| st <= i);
+    Span {
+        line,
+        col: i - line_starts[line - 1] + 1,
+        file: 0,
+    }
+}
+
+pub fn lex(s: &str) -> Result<Vec<(Tok, Span)>, LexError> {
+    lex_with_comments(s).map(|(toks, _)| toks)
+}
+
+/// Tokens plus the comments between them.
+pub type Lexed = (Vec<(Tok, Span)>, Vec<Comment>);
+
+/// Like `lex`, but also returns the comments (in source order) that `lex`
+/// drops, for tools that print source back out.
+pub fn lex_with_comments(s: &str) -> Result<Lexed, LexError> {
+    let mut comments = Vec::new();
+    let b: Vec<char> = s.chars().collect();
     let mut i = 0usize;
     let mut out = Vec::new();
+    // char index where each token in `out` starts
+    let mut starts: Vec<usize> = Vec::new();
+    let mut tok_start = 0usize;
+    let line_starts: Vec<usize> = std::iter::once(0)
+        .chain(
+            b.iter()
+                .enumerate()
+                .filter(|(_, &c)| c == '\n')
+                .map(|(k, _)| k + 1),
+        )
+        .collect();
+    let err = |kind, at: usize| LexError {
+        kind,
+        span: span_of(&line_starts, at),
+    };
 
-    fn is_id_start(c: u8) -> bool {
-        c.is_ascii_alphabetic() || c == b'_'
+    fn is_id_start(c: char) -> bool {
+        c.is_ascii_alphabetic() || c == '_'
     }
-    fn is_id_body(c: u8) -> bool {
-        is_id_start(c) || c.is_ascii_digit() || c == b'.'
+    fn is_id_body(c: char) -> bool {
+        is_id_start(c) || c.is_ascii_digit()
     }
 
     while i < b.len() {
+        starts.resize(out.len(), tok_start);
+        tok_start = i;
         let c = b[i];
 
-        if c.is_ascii_whitespace() {
+        if c.is_whitespace() {
             i += 1;
             continue;
         }
-        if c == b'#' || (c == b'/' && i + 1 < b.len() && b[i + 1] == b'/') {
-            while i < b.len() && b[i] != b'\n' {
+        if c == '#' || (c == '/' && b.get(i + 1) == Some(&'/')) {
+            while i < b.len() && b[i] != '\n' {
                 i += 1;
             }
+            comments.push(Comment {
+                text: b[tok_start..i].iter().collect::<String>().trim_end().into(),
+                span: span_of(&line_starts, tok_start),
+            });
             continue;
         }
-        if c == b'"' || c == b'\'' {
+        if c == '"' || c == '\'' {
             let q = c;
             i += 1;
             let mut s = String::new();
-            let mut esc = false;
+            let mut parts = Vec::new();
+            let mut closed = false;
             while i < b.len() {
                 let d = b[i];
                 i += 1;
-                if esc {
-                    s.push(match d {
-                        b'n' => '\n',
-                        x => x as char,
-                    });
-                    esc = false;
-                    continue;
-                }
-                if d == b'\\' {
-                    esc = true;
+                if d == '\\' {
+                    let (ch, next) = escape(&b, i).map_err(|k| err(k, i - 1))?;
+                    s.push(ch);
+                    i = next;
                     continue;
                 }
                 if d == q {
+                    closed = true;
                     break;
                 }
-                s.push(d as char);
+                if d == '$' && b.get(i) == Some(&'{') {
+                    let start = i + 1;
+                    let end = interp_end(&b, start)
+                        .ok_or_else(|| err(LexErrorKind::UnterminatedInterp, i - 1))?;
+                    let code: String = b[start..end].iter().collect();
+                    if !s.is_empty() {
+                        parts.push(TplPart::Lit(std::mem::take(&mut s)));
+                    }
+                    let at = span_of(&line_starts, start);
+                    let shift = |sp: Span| {
+                        let col = if sp.line == 1 {
+                            sp.col + at.col - 1
+                        } else {
+                            sp.col
+                        };
+                        let line = sp.line + at.line - 1;
+                        Span { line, col, ..sp }
+                    };
+                    let toks = lex(&code)
+                        .map_err(|e| LexError {
+                            kind: e.kind,
+                            span: shift(e.span),
+                        })?
+                        .into_iter()
+                        .map(|(t, sp)| (t, shift(sp)))
+                        .collect();
+                    parts.push(TplPart::Code(toks));
+                    i = end + 1;
+                    continue;
+                }
+                s.push(d);
+            }
+            if !closed {
+                return Err(err(LexErrorKind::UnterminatedString, tok_start));
+            }
+            if parts.is_empty() {
+                out.push(Tok::String(s));
+            } else {
+                if !s.is_empty() {
+                    parts.push(TplPart::Lit(s));
+                }
+                out.push(Tok::Template(parts));
             }
-            out.push(Tok::String(s));
             continue;
         }
-        if c.is_ascii_digit() || (c == b'-' && i + 1 < b.len() && b[i + 1].is_ascii_digit()) {
+        let next_digit = b.get(i + 1).is_some_and(|d| d.is_ascii_digit());
+        if c.is_ascii_digit() || (c == '-' && next_digit) {
             let mut j = i;
-            if b[j] == b'-' {
+            if b[j] == '-' {
                 j += 1;
             }
             let mut dot = false;
-            while j < b.len() && (b[j].is_ascii_digit() || (!dot && b[j] == b'.')) {
-                if b[j] == b'.' {
+            while j < b.len() && (b[j].is_ascii_digit() || (!dot && b[j] == '.')) {
+                if b[j] == '.' {
                     dot = true;
                 }
                 j += 1;
             }
-            let n = std::str::from_utf8(&b[i..j]).unwrap();
+            let n: String = b[i..j].iter().collect();
             i = j;
             let v: f64 = n.parse().unwrap_or(0.0);
             out.push(Tok::Number(v));
             continue;
         }
-        if i + 1 < b.len() {
-            match &b[i..i + 2] {
-                b"->" => {
+        if let Some(&d) = b.get(i + 1) {
+            match (c, d) {
+                ('-', '>') => {
                     out.push(Tok::Arrow);
                     i += 2;
                     continue;
                 }
-                b"==" => {
+                ('=', '=') => {
                     out.push(Tok::EqEq);
                     i += 2;
                     continue;
                 }
-                b"!=" => {
+                ('!', '=') => {
                     out.push(Tok::NotEq);
                     i += 2;
                     continue;
                 }
-                b"<=" => {
+                ('<', '=') => {
                     out.push(Tok::Le);
                     i += 2;
                     continue;
                 }
-                b">=" => {
+                ('>', '=') => {
                     out.push(Tok::Ge);
                     i += 2;
                     continue;
                 }
-                b"&&" => {
+                ('&', '&') => {
                     out.push(Tok::AndAnd);
                     i += 2;
                     continue;
                 }
-                b"||" => {
+                ('|', '|') => {
                     out.push(Tok::OrOr);
                     i += 2;
                     continue;
@@ -119,63 +310,79 @@ pub fn lex(s: &str) -> Result<Vec<Tok>, LexError> {
             }
         }
         match c {
-            b'{' => {
+            '{' => {
                 out.push(Tok::LBrace);
                 i += 1;
             }
-            b'}' => {
+            '}' => {
                 out.push(Tok::RBrace);
                 i += 1;
             }
-            b'(' => {
+            '[' => {
+                out.push(Tok::LBracket);
+                i += 1;
+            }
+            ']' => {
+                out.push(Tok::RBracket);
+                i += 1;
+            }
+            '(' => {
                 out.push(Tok::LParen);
                 i += 1;
             }
-            b')' => {
+            ')' => {
                 out.push(Tok::RParen);
                 i += 1;
             }
-            b':' => {
+            ':' if b.get(i + 1) == Some(&':') => {
+                out.push(Tok::ColonColon);
+                i += 2;
+            }
+            ':' => {
                 out.push(Tok::Colon);
                 i += 1;
             }
-            b',' => {
+            ',' => {
                 out.push(Tok::Comma);
                 i += 1;
             }
-            b'.' => {
+            '.' => {
                 out.push(Tok::Dot);
                 i += 1;
             }
-            b'+' => {
+            '+' => {
                 out.push(Tok::Plus);
                 i += 1;
             }
-            b'-' => {
+            '-' => {
                 out.push(Tok::Minus);
                 i += 1;
             }
-            b'*' => {
+            '*' if b.get(i + 1) == Some(&'*') => {
+                out.push(Tok::StarStar);
+                i += 2;
+            }
+            '*' => {
                 out.push(Tok::Star);
                 i += 1;
             }
-            b'/' => {
+            '/' => {
                 out.push(Tok::Slash);
                 i += 1;
             }
-            b'%' => {
+            '%' => {
                 out.push(Tok::Percent);
                 i += 1;
             }
-            b'<' => {
+            '<' => {
                 out.push(Tok::Lt);
                 i += 1;
             }
-            b'>' => {
+            '>' => {
                 out.push(Tok::Gt);
                 i += 1;
             }
-            b'!' => {
+            '!' => {
                 out.push(Tok::Bang);
                 i += 1;
             }
@@ -185,12 +392,28 @@ pub fn lex(s: &str) -> Result<Vec<Tok>, LexError> {
                     while j < b.len() && is_id_body(b[j]) {
                         j += 1;
                     }
-                    let id = std::str::from_utf8(&b[i..j]).unwrap().to_string();
+                    let id: String = b[i..j].iter().collect();
                     i = j;
+                    if id == "cpp" {
+                        // `cpp {` takes everything up to the matching `}`
+                        // as C++ text
+                        let mut k = j;
+                        while k < b.len() && b[k].is_whitespace() {
+                            k += 1;
+                        }
+                        if b.get(k) == Some(&'{') {
+                            let close = cpp_end(&b, k + 1)
+                                .ok_or_else(|| err(LexErrorKind::UnterminatedCpp, tok_start))?;
+                            out.push(Tok::Cpp(b[k + 1..close].iter().collect()));
+                            i = close + 1;
+                            continue;
+                        }
+                    }
                     let kw = match id.as_str() {
                         "true" => Some(Tok::True),
                         "false" => Some(Tok::False),
                         "log" => Some(Tok::KwLog),
+                        "print" => Some(Tok::KwPrint),
                         "cond" => Some(Tok::KwCond),
                         "else" => Some(Tok::KwElse),
                         "end" => Some(Tok::KwEnd),
@@ -202,6 +425,10 @@ pub fn lex(s: &str) -> Result<Vec<Tok>, LexError> {
                         "continue" => Some(Tok::KwContinue),
                         "function" => Some(Tok::KwFunction),
                         "return" => Some(Tok::KwReturn),
+                        "type" => Some(Tok::KwType),
+                        "enum" => Some(Tok::KwEnum),
+                        "match" => Some(Tok::KwMatch),
+                        "import" => Some(Tok::KwImport),
                         "double" => Some(Tok::TyDouble),
                         "int" => Some(Tok::TyInt),
                         "string" => Some(Tok::TyString),
@@ -210,11 +437,17 @@ pub fn lex(s: &str) -> Result<Vec<Tok>, LexError> {
                     };
                     out.push(kw.unwrap_or(Tok::Ident(id)));
                 } else {
-                    return Err(LexError::Unexpected(c as char));
+                    return Err(err(LexErrorKind::Unexpected(c), i));
                 }
             }
         }
     }
 
-    Ok(out)
+    starts.resize(out.len(), tok_start);
+    let toks = out
+        .into_iter()
+        .zip(starts)
+        .map(|(t, st)| (t, span_of(&line_starts, st)))
+        .collect();
+    Ok((toks, comments))
 }
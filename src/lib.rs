@@ -0,0 +1,203 @@
+//! PNA compiler as a library.
+//!
+//! The pipeline is `lexer::lex` → `parser::parse` → `link::link` →
+//! `typeck::check` →
+//! `ir::lower` → `opt::optimize` → a backend (`codegen_cpp::compile_to_cpp`,
+//! `codegen_c`, `codegen_js`, `codegen_wat`, `codegen_bc`). [`Session`] and
+//! [`Compiler`] wrap it so tools can go from source text to diagnostics or
+//! C++ without touching the individual passes.
+
+pub mod ast;
+pub mod bundle;
+pub mod bytecode;
+pub mod codegen_bc;
+pub mod codegen_c;
+pub mod codegen_cpp;
+pub mod codegen_js;
+pub mod codegen_wat;
+pub mod diag;
+pub mod fmt;
+pub mod format_spec;
+pub mod interp;
+pub mod ir;
+pub mod judge;
+pub mod lexer;
+pub mod link;
+pub mod lsp;
+pub mod math;
+pub mod opt;
+pub mod parser;
+pub mod repl;
+pub mod token;
+pub mod typeck;
+pub mod value;
+pub mod vm;
+
+pub use codegen_cpp::CppOptions;
+pub use diag::Diagnostic;
+
+use ast::{Program, Span};
+use std::cell::RefCell;
+
+/// A PNA source file and its name, as seen by diagnostics, plus the files it
+/// imports.
+#[derive(Debug, Clone)]
+pub struct Session {
+    file: String,
+    source: String,
+    /// `(name, source)` of each file the last `parse` imported, in load
+    /// order; `Span::file` N refers to `imports[N - 1]`.
+    imports: RefCell<Vec<(String, String)>>,
+}
+
+impl Session {
+    pub fn new(file: impl Into<String>, source: impl Into<String>) -> Self {
+        Session {
+            file: file.into(),
+            source: source.into(),
+            imports: RefCell::default(),
+        }
+    }
+
+    /// Reads `path` from disk.
+    pub fn load(path: &str) -> std::io::Result<Self> {
+        Ok(Session::new(path, std::fs::read_to_string(path)?))
+    }
+
+    pub fn file(&self) -> &str {
+        &self.file
+    }
+
+    pub fn source(&self) -> &str {
+        &self.source
+    }
+
+    /// Names of the files the last `parse` imported, in load order.
+    pub fn imports(&self) -> Vec<String> {
+        self.imports
+            .borrow()
+            .iter()
+            .map(|(name, _)| name.clone())
+            .collect()
+    }
+
+    /// Lexes and parses the source and the files it imports (read from disk
+    /// relative to `file`), then links them. Stops at the first syntax error.
+    pub fn parse(&self) -> Result<Program, Vec<Diagnostic>> {
+        self.imports.borrow_mut().clear();
+        let prog = parse_file(&self.source, 0)?;
+        link::link(prog, &self.file, &mut |path, span| {
+            let source = std::fs::read_to_string(path).map_err(|e| {
+                let msg = format!("cannot read `{}`: {}", path, e);
+                vec![Diagnostic::new(msg, span)]
+            })?;
+            let mut imports = self.imports.borrow_mut();
+            imports.push((path.to_string(), source));
+            parse_file(&imports[imports.len() - 1].1, imports.len())
+        })
+    }
+
+    /// Parses and runs the semantic checks, reporting every error found.
+    pub fn check(&self) -> Result<Program, Vec<Diagnostic>> {
+        let prog = self.parse()?;
+        typeck::check(&prog)?;
+        Ok(prog)
+    }
+
+    /// Formats a diagnostic as `file:line:col: error: message` followed by
+    /// the offending source line and a caret.
+    pub fn render(&self, d: &Diagnostic) -> String {
+        let Span { line, col, file } = d.span;
+        let imports = self.imports.borrow();
+        let (name, source) = match file.checked_sub(1).and_then(|i| imports.get(i)) {
+            Some((name, source)) => (name.as_str(), source.as_str()),
+            None => (self.file.as_str(), self.source.as_str()),
+        };
+        let mut out = format!("{}:{}:{}: error: {}", name, line, col, d.message);
+        if let Some(text) = line.checked_sub(1).and_then(|l| source.lines().nth(l)) {
+            let gutter = line.to_string().len();
+            out.push_str(&format!(
+                "\n{:>w$} | {}\n{:>w$} | {}^",
+                line,
+                text,
+                "",
+                " ".repeat(col.saturating_sub(1)),
+                w = gutter
+            ));
+        }
+        out
+    }
+}
+
+/// Lexes and parses one file, tagging every span with `file`.
+fn parse_file(source: &str, file: usize) -> Result<Program, Vec<Diagnostic>> {
+    let mut toks = lexer::lex(source)
+        .map_err(|e| vec![Diagnostic::new(e.kind.to_string(), Span { file, ..e.span })])?;
+    for (_, sp) in &mut toks {
+        sp.file = file;
+    }
+    parser::parse(toks).map_err(|d| vec![d])
+}
+
+/// Compiles sessions to C++ with a fixed set of options.
+#[derive(Debug, Clone, Default)]
+pub struct Compiler {
+    pub options: CppOptions,
+}
+
+impl Compiler {
+    pub fn new(options: CppOptions) -> Self {
+        Compiler { options }
+    }
+
+    /// Parses, checks, lowers to the IR every backend consumes (see `ir`)
+    /// and optimizes it; `--emit=ir` prints the result.
+    pub fn compile_ir(&self, sess: &Session) -> Result<ir::Module, Vec<Diagnostic>> {
+        let prog = sess.check()?;
+        let mut m = ir::lower(&prog).map_err(|d| vec![d])?;
+        m.files = sess.imports();
+        opt::optimize(&mut m);
+        Ok(m)
+    }
+
+    /// The options with `file` defaulting to the session's file name.
+    fn options_for(&self, sess: &Session) -> CppOptions {
+        let mut opts = self.options.clone();
+        if opts.file.is_none() {
+            opts.file = Some(sess.file().to_string());
+        }
+        opts
+    }
+
+    /// Runs the whole pipeline and returns the generated C++ translation unit.
+    /// `options.file` defaults to the session's file name.
+    pub fn compile(&self, sess: &Session) -> Result<String, Vec<Diagnostic>> {
+        let m = self.compile_ir(sess)?;
+        codegen_cpp::compile_to_cpp(&m, &self.options_for(sess)).map_err(|d| vec![d])
+    }
+
+    /// Like `compile`, but emits a standalone C99 translation unit.
+    pub fn compile_c(&self, sess: &Session) -> Result<String, Vec<Diagnostic>> {
+        let m = self.compile_ir(sess)?;
+        codegen_c::compile_to_c(&m, &self.options_for(sess)).map_err(|d| vec![d])
+    }
+
+    /// Like `compile`, but emits a JavaScript ES module (see `codegen_js`).
+    pub fn compile_js(&self, sess: &Session) -> Result<String, Vec<Diagnostic>> {
+        let m = self.compile_ir(sess)?;
+        codegen_js::compile_to_js(&m, &self.options_for(sess)).map_err(|d| vec![d])
+    }
+
+    /// Like `compile`, but emits WebAssembly text for WASI (see `codegen_wat`).
+    pub fn compile_wat(&self, sess: &Session) -> Result<String, Vec<Diagnostic>> {
+        let m = self.compile_ir(sess)?;
+        codegen_wat::compile_to_wat(&m, &self.options_for(sess)).map_err(|d| vec![d])
+    }
+
+    /// Compiles to bytecode for `vm::Vm` (see `bytecode::Module::encode` for
+    /// the `.pnac` file format).
+    pub fn compile_bytecode(&self, sess: &Session) -> Result<bytecode::Module, Vec<Diagnostic>> {
+        let m = self.compile_ir(sess)?;
+        codegen_bc::compile(&m).map_err(|d| vec![d])
+    }
+}
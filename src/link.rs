@@ -0,0 +1,419 @@
+//! `import`: links a program and the files it imports into one `Program`.
+//!
+//! `import "path"` makes the functions of another file callable by their own
+//! names; `import m from "path"` makes them callable as `m::name` instead.
+//! Paths are relative to the importing file. Each file is loaded once however
+//! often it is imported, its functions are renamed to `module::name`
+//! (`module` is the file stem; the root file's functions keep their names)
+//! and its items come before those of the files importing it, so every
+//! backend still emits a single translation unit. Types, enums, `extern`
+//! functions and `cpp` blocks are shared by all files, and imported files
+//! may only declare things, not run statements.
+
+use crate::ast::*;
+use crate::diag::Diagnostic;
+use crate::math::Math;
+use crate::typeck::BUILTINS;
+use std::collections::HashMap;
+use std::path::{Path, PathBuf};
+
+/// Reads and parses the file at a path; failures are reported at the span of
+/// the `import`.
+pub type Loader<'a> = dyn FnMut(&str, Span) -> Result<Program, Vec<Diagnostic>> + 'a;
+
+/// A linked function name as the backends spell it: `module::name` becomes
+/// `module__name`.
+pub fn mangle(name: &str) -> String {
+    name.replace("::", "__")
+}
+
+/// Loads everything `root` (read from `path`) imports, directly or not, and
+/// returns the whole program without `Item::Import`s.
+pub fn link(root: Program, path: &str, load: &mut Loader) -> Result<Program, Vec<Diagnostic>> {
+    let mut l = Linker {
+        load,
+        files: Vec::new(),
+        loaded: HashMap::new(),
+        stack: Vec::new(),
+        modules: Vec::new(),
+    };
+    l.visit(root, path, None)?;
+    resolve(l.files)
+}
+
+/// A loaded file; `items` has no `Item::Import`.
+struct File {
+    path: String,
+    /// `None` for the root file.
+    module: Option<String>,
+    items: Vec<Item>,
+    /// `(alias, index into the file list, span of the import)`.
+    imports: Vec<(Option<String>, usize, Span)>,
+}
+
+impl File {
+    /// Each function declared here, by its own name, and its linked name.
+    fn exports(&self) -> HashMap<String, String> {
+        let mut out = HashMap::new();
+        for it in &self.items {
+            if let Item::Func { name, .. } = it {
+                out.insert(name.clone(), self.link_name(name));
+            }
+        }
+        out
+    }
+
+    fn link_name(&self, name: &str) -> String {
+        match &self.module {
+            Some(m) => format!("{}::{}", m, name),
+            None => name.to_string(),
+        }
+    }
+}
+
+struct Linker<'a, 'b> {
+    load: &'a mut Loader<'b>,
+    /// Every file after the files it imports.
+    files: Vec<File>,
+    /// Index into `files` by canonical path.
+    loaded: HashMap<PathBuf, usize>,
+    /// Canonical and display paths of the files being loaded, outermost
+    /// first.
+    stack: Vec<(PathBuf, String)>,
+    /// Module names handed out so far.
+    modules: Vec<String>,
+}
+
+impl Linker<'_, '_> {
+    /// Adds `prog` and, first, the files it imports; returns its index.
+    fn visit(
+        &mut self,
+        prog: Program,
+        path: &str,
+        module: Option<String>,
+    ) -> Result<usize, Vec<Diagnostic>> {
+        self.stack.push((canonical(path), path.to_string()));
+        let mut items = Vec::new();
+        let mut imports = Vec::new();
+        for it in prog.items {
+            match it {
+                Item::Import {
+                    path: rel,
+                    alias,
+                    span,
+                } => {
+                    let dep = Path::new(path)
+                        .parent()
+                        .unwrap_or(Path::new(""))
+                        .join(&rel)
+                        .to_string_lossy()
+                        .into_owned();
+                    let key = canonical(&dep);
+                    if let Some(i) = self.stack.iter().position(|(k, _)| *k == key) {
+                        let mut cycle: Vec<&str> =
+                            self.stack[i..].iter().map(|(_, p)| p.as_str()).collect();
+                        cycle.push(&dep);
+                        let msg = format!("import cycle: {}", cycle.join(" -> "));
+                        return Err(vec![Diagnostic::new(msg, span)]);
+                    }
+                    let i = match self.loaded.get(&key) {
+                        Some(&i) => i,
+                        None => {
+                            let prog = (self.load)(&dep, span)?;
+                            let module = self.module_name(&dep);
+                            self.visit(prog, &dep, Some(module))?
+                        }
+                    };
+                    imports.push((alias, i, span));
+                }
+                Item::Stmt(s) if module.is_some() => {
+                    let msg = "an imported file can only declare functions, types and enums";
+                    return Err(vec![Diagnostic::new(msg, s.span)]);
+                }
+                it => items.push(it),
+            }
+        }
+        let (key, _) = self.stack.pop().expect("pushed above");
+        self.loaded.insert(key, self.files.len());
+        self.files.push(File {
+            path: path.to_string(),
+            module,
+            items,
+            imports,
+        });
+        Ok(self.files.len() - 1)
+    }
+
+    /// The stem of `path` as an identifier, numbered when another file
+    /// already has it.
+    fn module_name(&mut self, path: &str) -> String {
+        let stem = Path::new(path)
+            .file_stem()
+            .map(|s| s.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let mut base: String = stem
+            .chars()
+            .map(|c| if c.is_ascii_alphanumeric() { c } else { '_' })
+            .collect();
+        if !base.starts_with(|c: char| c.is_ascii_alphabetic() || c == '_') {
+            base.insert(0, '_');
+        }
+        let mut name = base.clone();
+        let mut n = 1;
+        while self.modules.contains(&name) {
+            n += 1;
+            name = format!("{}{}", base, n);
+        }
+        self.modules.push(name.clone());
+        name
+    }
+}
+
+/// `path` with symlinks and `..` resolved, so one file imported by two
+/// spellings is loaded once; unchanged if it does not exist.
+fn canonical(path: &str) -> PathBuf {
+    std::fs::canonicalize(path).unwrap_or_else(|_| PathBuf::from(path))
+}
+
+/// Renames every function and call to its linked name and concatenates the
+/// files.
+fn resolve(files: Vec<File>) -> Result<Program, Vec<Diagnostic>> {
+    let mut diags = Vec::new();
+    let exports: Vec<HashMap<String, String>> = files.iter().map(File::exports).collect();
+    let enums: Vec<&str> = files
+        .iter()
+        .flat_map(|f| &f.items)
+        .filter_map(|it| match it {
+            Item::Enum { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+    let externs: Vec<&str> = files
+        .iter()
+        .flat_map(|f| &f.items)
+        .filter_map(|it| match it {
+            Item::Extern { name, .. } => Some(name.as_str()),
+            _ => None,
+        })
+        .collect();
+
+    // `a::f` and `a__f` would be the same function in the generated code
+    let mut mangled: HashMap<String, String> = HashMap::new();
+    for (f, ex) in files.iter().zip(&exports) {
+        for it in &f.items {
+            let Item::Func { name, span, .. } = it else {
+                continue;
+            };
+            let link = &ex[name];
+            if let Some(other) = mangled.insert(mangle(link), link.clone()) {
+                if other != *link {
+                    let msg = format!("function `{}` clashes with `{}`", link, other);
+                    diags.push(Diagnostic::new(msg, *span));
+                }
+            }
+        }
+    }
+
+    let mut scopes = Vec::new();
+    for (i, f) in files.iter().enumerate() {
+        let mut scope = Scope {
+            funcs: exports[i].clone(),
+            aliases: HashMap::new(),
+            externs: f.module.is_some().then_some(externs.as_slice()),
+            diags: Vec::new(),
+        };
+        for (alias, dep, span) in &f.imports {
+            let mut err = |msg: String| diags.push(Diagnostic::new(msg, *span));
+            let path = files[*dep].path.as_str();
+            match alias {
+                Some(a) if scope.aliases.contains_key(a) => {
+                    err(format!("`{}` is imported twice", a))
+                }
+                Some(a) if enums.contains(&a.as_str()) => {
+                    err(format!("`{}` is already the name of an enum", a))
+                }
+                Some(a) => {
+                    scope.aliases.insert(a.clone(), (path, &exports[*dep]));
+                }
+                None => {
+                    for (name, link) in &exports[*dep] {
+                        match scope.funcs.get(name) {
+                            Some(l) if l != link => err(format!(
+                                "function `{}` from `{}` clashes with another `{}`",
+                                name, path, name
+                            )),
+                            _ => {
+                                scope.funcs.insert(name.clone(), link.clone());
+                            }
+                        }
+                    }
+                }
+            }
+        }
+        scopes.push(scope);
+    }
+
+    let mut items = Vec::new();
+    for (f, mut scope) in files.iter().zip(scopes) {
+        for it in &f.items {
+            let mut it = it.clone();
+            match &mut it {
+                Item::Stmt(s) => scope.stmt(s),
+                Item::Func { name, body, .. } => {
+                    *name = f.link_name(name);
+                    scope.block(body);
+                }
+                Item::Type { .. }
+                | Item::Enum { .. }
+                | Item::Import { .. }
+                | Item::Extern { .. }
+                | Item::Cpp { .. } => {}
+            }
+            items.push(it);
+        }
+        diags.append(&mut scope.diags);
+    }
+    if diags.is_empty() {
+        Ok(Program { items })
+    } else {
+        Err(diags)
+    }
+}
+
+/// The names a file can call functions by.
+struct Scope<'a> {
+    /// Unqualified name to linked name.
+    funcs: HashMap<String, String>,
+    /// Import alias to the imported file's path and exports.
+    aliases: HashMap<String, (&'a str, &'a HashMap<String, String>)>,
+    /// In an imported file, the `extern` functions; a call that is none of
+    /// these, a builtin or a function in scope is an error here, since it
+    /// would otherwise reach a root-file function of the same name.
+    externs: Option<&'a [&'a str]>,
+    diags: Vec<Diagnostic>,
+}
+
+impl Scope<'_> {
+    fn block(&mut self, b: &mut [Stmt]) {
+        for s in b {
+            self.stmt(s);
+        }
+    }
+
+    fn stmt(&mut self, s: &mut Stmt) {
+        let sp = s.span;
+        match &mut s.kind {
+            StmtKind::ObjBlock { fields, .. } => {
+                for (_, e) in fields {
+                    self.expr(e, sp);
+                }
+            }
+            StmtKind::PropAssign { expr, .. } | StmtKind::VarAssign { expr, .. } => {
+                self.expr(expr, sp)
+            }
+            StmtKind::IndexAssign { index, expr, .. } => {
+                self.expr(index, sp);
+                self.expr(expr, sp);
+            }
+            StmtKind::Log(es) | StmtKind::Print(es) => {
+                for e in es {
+                    self.expr(e, sp);
+                }
+            }
+            StmtKind::Cond {
+                cond,
+                then_blk,
+                else_blk,
+            } => {
+                self.expr(cond, sp);
+                self.block(then_blk);
+                if let Some(b) = else_blk {
+                    self.block(b);
+                }
+            }
+            StmtKind::Loop { cond, body } => {
+                self.expr(cond, sp);
+                self.block(body);
+            }
+            StmtKind::While { cond, body, ended } => {
+                self.expr(cond, sp);
+                self.block(body);
+                if let Some(b) = ended {
+                    self.block(b);
+                }
+            }
+            StmtKind::Match { expr, arms } => {
+                self.expr(expr, sp);
+                for a in arms {
+                    self.block(&mut a.body);
+                }
+            }
+            StmtKind::Return(Some(e)) => self.expr(e, sp),
+            StmtKind::Input { .. }
+            | StmtKind::Break
+            | StmtKind::Continue
+            | StmtKind::Return(None)
+            | StmtKind::Cpp(_) => {}
+        }
+    }
+
+    /// `sp` is the enclosing statement's span; expressions have none.
+    fn expr(&mut self, e: &mut Expr, sp: Span) {
+        match e {
+            Expr::Call { name, args } => {
+                for a in args.iter_mut() {
+                    self.expr(a, sp);
+                }
+                if let Some(link) = self.funcs.get(name.as_str()) {
+                    *name = link.clone();
+                } else if let Some(externs) = self.externs {
+                    let known = BUILTINS.contains(&name.as_str())
+                        || Math::parse(name).is_some()
+                        || externs.contains(&name.as_str());
+                    if !known {
+                        let msg = format!("unknown function `{}`", name);
+                        self.diags.push(Diagnostic::new(msg, sp));
+                    }
+                }
+            }
+            Expr::Variant { ty, name, args } => {
+                for a in args.iter_mut() {
+                    self.expr(a, sp);
+                }
+                let Some((path, exports)) = self.aliases.get(ty.as_str()) else {
+                    return;
+                };
+                match exports.get(name.as_str()) {
+                    Some(link) => {
+                        *e = Expr::Call {
+                            name: link.clone(),
+                            args: std::mem::take(args),
+                        }
+                    }
+                    None => {
+                        let msg = format!("`{}` has no function `{}`", path, name);
+                        self.diags.push(Diagnostic::new(msg, sp));
+                    }
+                }
+            }
+            Expr::Member(b, _) => self.expr(b, sp),
+            Expr::Index(b, i) => {
+                self.expr(b, sp);
+                self.expr(i, sp);
+            }
+            Expr::Unary { rhs, .. } => self.expr(rhs, sp),
+            Expr::Binary { lhs, rhs, .. } => {
+                self.expr(lhs, sp);
+                self.expr(rhs, sp);
+            }
+            Expr::Interp(parts) => {
+                for p in parts {
+                    if let InterpPart::Expr(e) = p {
+                        self.expr(e, sp);
+                    }
+                }
+            }
+            Expr::Num(_) | Expr::Str(_) | Expr::Bool(_) | Expr::Ident(_) => {}
+        }
+    }
+}
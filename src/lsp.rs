@@ -0,0 +1,727 @@
+//! `pna-cc lsp`: a Language Server Protocol server over stdio.
+//!
+//! Documents are kept in full (`TextDocumentSyncKind::Full`) and re-analysed
+//! on every change. Expressions carry no spans, so positions are resolved on
+//! the token stream and meaning is looked up in the AST and `typeck::infer`.
+//! When a document stops parsing, the last program that did parse keeps
+//! hover, definition and completion working.
+
+use crate::ast::*;
+use crate::lexer;
+use crate::math::{self, Math};
+use crate::token::{Tok, TplPart};
+use crate::typeck::{self, Type, Types};
+use crate::Session;
+use serde_json::{json, Value};
+use std::collections::HashMap;
+use std::io::{self, BufRead, Write};
+
+const KEYWORDS: &[&str] = &[
+    "log", "print", "cond", "else", "end", "loop", "while", "ended", "input", "break", "continue",
+    "function", "return", "type", "enum", "match", "true", "false", "double", "int", "string",
+    "void",
+];
+
+const BUILTINS: &[(&str, &str)] = &[
+    ("format", "format(fmt: string, args...) -> string"),
+    ("len", "len(x) -> number"),
+    ("has", "has(obj, key) -> bool"),
+    ("remove", "remove(obj, key) -> object"),
+    ("keys", "keys(obj) -> object"),
+    ("values", "values(obj) -> object"),
+];
+
+// LSP enum values
+const SEVERITY_ERROR: u8 = 1;
+const SYNC_FULL: u8 = 1;
+const SYMBOL_ENUM: u8 = 10;
+const SYMBOL_FUNCTION: u8 = 12;
+const SYMBOL_STRUCT: u8 = 23;
+const ITEM_FUNCTION: u8 = 3;
+const ITEM_FIELD: u8 = 5;
+const ITEM_VARIABLE: u8 = 6;
+const ITEM_KEYWORD: u8 = 14;
+
+struct Doc {
+    text: String,
+    /// The most recent version of the text that parsed.
+    prog: Option<Program>,
+}
+
+/// Serves one client on stdin/stdout until `exit`.
+pub fn run() -> io::Result<()> {
+    let stdin = io::stdin();
+    let mut input = stdin.lock();
+    let mut out = io::stdout();
+    let mut docs: HashMap<String, Doc> = HashMap::new();
+    let mut shutdown = false;
+    while let Some(msg) = read_message(&mut input)? {
+        let method = msg["method"].as_str().unwrap_or_default();
+        let params = &msg["params"];
+        let result = match method {
+            "initialize" => json!({
+                "capabilities": {
+                    "textDocumentSync": SYNC_FULL,
+                    "definitionProvider": true,
+                    "hoverProvider": true,
+                    "completionProvider": { "triggerCharacters": ["."] },
+                    "documentSymbolProvider": true,
+                },
+                "serverInfo": { "name": "pna-cc" },
+            }),
+            "shutdown" => {
+                shutdown = true;
+                Value::Null
+            }
+            "exit" => break,
+            "textDocument/didOpen" | "textDocument/didChange" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                let text = match method {
+                    "textDocument/didOpen" => params["textDocument"]["text"].as_str(),
+                    _ => params["contentChanges"]
+                        .as_array()
+                        .and_then(|c| c.last())
+                        .and_then(|c| c["text"].as_str()),
+                };
+                let doc = docs.entry(uri.to_string()).or_insert(Doc {
+                    text: String::new(),
+                    prog: None,
+                });
+                doc.text = text.unwrap_or_default().to_string();
+                let diags = diagnostics(uri, doc);
+                send(
+                    &mut out,
+                    &notification("textDocument/publishDiagnostics", diags),
+                )?;
+                continue;
+            }
+            "textDocument/didClose" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                docs.remove(uri);
+                let clear = json!({ "uri": uri, "diagnostics": [] });
+                send(
+                    &mut out,
+                    &notification("textDocument/publishDiagnostics", clear),
+                )?;
+                continue;
+            }
+            "textDocument/definition"
+            | "textDocument/hover"
+            | "textDocument/completion"
+            | "textDocument/documentSymbol" => {
+                let uri = params["textDocument"]["uri"].as_str().unwrap_or_default();
+                match docs.get(uri) {
+                    Some(doc) => query(method, uri, doc, &params["position"]),
+                    None => Value::Null,
+                }
+            }
+            _ if msg.get("id").is_none() => continue,
+            _ => {
+                let err =
+                    json!({ "code": -32601, "message": format!("unknown method {}", method) });
+                send(
+                    &mut out,
+                    &json!({ "jsonrpc": "2.0", "id": msg["id"], "error": err }),
+                )?;
+                continue;
+            }
+        };
+        if msg.get("id").is_some() {
+            send(
+                &mut out,
+                &json!({ "jsonrpc": "2.0", "id": msg["id"], "result": result }),
+            )?;
+        }
+    }
+    if !shutdown {
+        std::process::exit(1);
+    }
+    Ok(())
+}
+
+fn read_message(r: &mut impl BufRead) -> io::Result<Option<Value>> {
+    let mut len = None;
+    loop {
+        let mut line = String::new();
+        if r.read_line(&mut line)? == 0 {
+            return Ok(None);
+        }
+        let line = line.trim_end();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(v) = line.strip_prefix("Content-Length:") {
+            len = v.trim().parse::<usize>().ok();
+        }
+    }
+    let mut body = vec![0; len.unwrap_or(0)];
+    r.read_exact(&mut body)?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| io::Error::new(io::ErrorKind::InvalidData, e))
+}
+
+fn send(w: &mut impl Write, msg: &Value) -> io::Result<()> {
+    let body = msg.to_string();
+    write!(w, "Content-Length: {}\r\n\r\n{}", body.len(), body)?;
+    w.flush()
+}
+
+fn notification(method: &str, params: Value) -> Value {
+    json!({ "jsonrpc": "2.0", "method": method, "params": params })
+}
+
+/// Re-checks `doc`, remembering its program if it parses, and returns the
+/// `publishDiagnostics` params.
+fn diagnostics(uri: &str, doc: &mut Doc) -> Value {
+    // imports are read relative to the document's path
+    let path = uri.strip_prefix("file://").unwrap_or(uri);
+    let sess = Session::new(path, doc.text.as_str());
+    if let Ok(p) = sess.parse() {
+        doc.prog = Some(p);
+    }
+    let diags = match sess.check() {
+        Ok(_) => Vec::new(),
+        // errors in imported files belong to those documents
+        Err(ds) => ds
+            .iter()
+            .filter(|d| d.span.file == 0)
+            .map(|d| {
+                let start = Span {
+                    line: d.span.line.max(1),
+                    col: d.span.col.max(1),
+                    file: 0,
+                };
+                let end = Span {
+                    col: start.col + 1,
+                    ..start
+                };
+                json!({
+                    "range": range(&doc.text, start, end),
+                    "severity": SEVERITY_ERROR,
+                    "source": "pna",
+                    "message": d.message,
+                })
+            })
+            .collect(),
+    };
+    json!({ "uri": uri, "diagnostics": diags })
+}
+
+/// LSP position (0-based line, UTF-16 column) of a 1-based char span.
+fn position(text: &str, sp: Span) -> Value {
+    let line = text
+        .lines()
+        .nth(sp.line.saturating_sub(1))
+        .unwrap_or_default();
+    let character: usize = line
+        .chars()
+        .take(sp.col.saturating_sub(1))
+        .map(char::len_utf16)
+        .sum();
+    json!({ "line": sp.line.saturating_sub(1), "character": character })
+}
+
+fn range(text: &str, start: Span, end: Span) -> Value {
+    json!({ "start": position(text, start), "end": position(text, end) })
+}
+
+/// Inverse of `position`.
+fn span_at(text: &str, pos: &Value) -> Span {
+    let line = pos["line"].as_u64().unwrap_or(0) as usize;
+    let mut units = pos["character"].as_u64().unwrap_or(0) as usize;
+    let mut col = 1;
+    for c in text.lines().nth(line).unwrap_or_default().chars() {
+        if units < c.len_utf16() {
+            break;
+        }
+        units -= c.len_utf16();
+        col += 1;
+    }
+    Span {
+        line: line + 1,
+        col,
+        file: 0,
+    }
+}
+
+fn tok_len(t: &Tok) -> usize {
+    match t {
+        Tok::Ident(s) => s.chars().count(),
+        _ => 1,
+    }
+}
+
+/// Tokens in source order, including those inside `${...}`.
+fn flatten(toks: Vec<(Tok, Span)>, out: &mut Vec<(Tok, Span)>) {
+    for (t, sp) in toks {
+        if let Tok::Template(parts) = &t {
+            for part in parts {
+                if let TplPart::Code(inner) = part {
+                    flatten(inner.clone(), out);
+                }
+            }
+        }
+        out.push((t, sp));
+    }
+    out.sort_by_key(|(_, sp)| (sp.line, sp.col));
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum DefKind {
+    Func,
+    Param(Ty),
+    Var,
+    /// A field of the object in the named variable.
+    Field(String),
+    /// A record declared with `type`.
+    Type,
+}
+
+/// A name introduced somewhere in the document.
+#[derive(Debug, Clone)]
+struct Def {
+    name: String,
+    kind: DefKind,
+    /// Where the name itself is written.
+    span: Span,
+    /// Enclosing function, `None` at the top level.
+    scope: Option<String>,
+}
+
+/// Everything the queries need about one document.
+struct Analysis<'a> {
+    text: &'a str,
+    toks: Vec<(Tok, Span)>,
+    prog: &'a Program,
+    types: Types,
+    defs: Vec<Def>,
+    /// `(name, first token, last token)` of every function.
+    funcs: Vec<(String, usize, usize)>,
+    /// `(name, first token, closing brace)` of every `type`.
+    records: Vec<(String, usize, usize)>,
+    /// Likewise for every `enum`.
+    enums: Vec<(String, usize, usize)>,
+}
+
+impl<'a> Analysis<'a> {
+    fn new(text: &'a str, prog: &'a Program) -> Self {
+        let mut toks = Vec::new();
+        flatten(lexer::lex(text).unwrap_or_default(), &mut toks);
+        let mut a = Analysis {
+            text,
+            toks,
+            prog,
+            types: typeck::infer(prog),
+            defs: Vec::new(),
+            funcs: Vec::new(),
+            records: Vec::new(),
+            enums: Vec::new(),
+        };
+        a.collect();
+        a
+    }
+
+    /// Index of the token starting at `sp`, if there is one.
+    fn tok_at(&self, sp: Span) -> Option<usize> {
+        let i = self
+            .toks
+            .partition_point(|(_, s)| (s.line, s.col) < (sp.line, sp.col));
+        (self.toks.get(i)?.1 == sp).then_some(i)
+    }
+
+    fn ident(&self, i: usize) -> Option<&str> {
+        match self.toks.get(i) {
+            Some((Tok::Ident(s), _)) => Some(s),
+            _ => None,
+        }
+    }
+
+    fn is(&self, i: usize, t: Tok) -> bool {
+        self.toks
+            .get(i)
+            .is_some_and(|(x, _)| std::mem::discriminant(x) == std::mem::discriminant(&t))
+    }
+
+    fn def(&mut self, name: &str, kind: DefKind, i: usize, scope: Option<&str>) {
+        if let Some((_, span)) = self.toks.get(i) {
+            self.defs.push(Def {
+                name: name.to_string(),
+                kind,
+                span: *span,
+                scope: scope.map(str::to_string),
+            });
+        }
+    }
+
+    fn collect(&mut self) {
+        let prog = self.prog;
+        for it in &prog.items {
+            match it {
+                Item::Stmt(s) => self.stmt(s, None),
+                Item::Func {
+                    name,
+                    params,
+                    body,
+                    span,
+                    ..
+                } => {
+                    let Some(start) = self.tok_at(*span) else {
+                        continue;
+                    };
+                    self.def(name, DefKind::Func, start + 1, None);
+                    // parameters: `name: type` pairs between the parentheses
+                    let mut i = start + 3;
+                    for p in params {
+                        self.def(&p.name, DefKind::Param(p.ty.clone()), i, Some(name));
+                        i += 4;
+                    }
+                    let mut end = start;
+                    let mut depth = 0usize;
+                    while end + 1 < self.toks.len() {
+                        end += 1;
+                        match self.toks[end].0 {
+                            Tok::LBrace => depth += 1,
+                            Tok::RBrace => depth = depth.saturating_sub(1),
+                            Tok::KwEnd if depth == 0 => break,
+                            _ => {}
+                        }
+                    }
+                    self.funcs.push((name.clone(), start, end));
+                    for s in body {
+                        self.stmt(s, Some(name));
+                    }
+                }
+                Item::Type { name, span, .. } => {
+                    let Some(start) = self.tok_at(*span) else {
+                        continue;
+                    };
+                    self.def(name, DefKind::Type, start + 1, None);
+                    let mut end = start;
+                    while end + 1 < self.toks.len() && !self.is(end, Tok::RBrace) {
+                        end += 1;
+                    }
+                    self.records.push((name.clone(), start, end));
+                }
+                Item::Enum { name, span, .. } => {
+                    let Some(start) = self.tok_at(*span) else {
+                        continue;
+                    };
+                    self.def(name, DefKind::Type, start + 1, None);
+                    let mut end = start;
+                    while end + 1 < self.toks.len() && !self.is(end, Tok::RBrace) {
+                        end += 1;
+                    }
+                    self.enums.push((name.clone(), start, end));
+                }
+                Item::Extern { name, span, .. } => {
+                    // `extern function name`
+                    if let Some(start) = self.tok_at(*span) {
+                        self.def(name, DefKind::Func, start + 2, None);
+                    }
+                }
+                Item::Import { .. } | Item::Cpp { .. } => {}
+            }
+        }
+    }
+
+    fn stmt(&mut self, s: &Stmt, scope: Option<&str>) {
+        let Some(i) = self.tok_at(s.span) else {
+            return;
+        };
+        match &s.kind {
+            StmtKind::VarAssign { name, .. } => self.def(name, DefKind::Var, i, scope),
+            StmtKind::ObjBlock { name, .. } => {
+                self.def(name, DefKind::Var, i, scope);
+                let mut j = i + 3;
+                while j < self.toks.len() && !self.is(j, Tok::RBrace) {
+                    if let (Some(k), true) = (self.ident(j), self.is(j + 1, Tok::Colon)) {
+                        let k = k.to_string();
+                        self.def(&k, DefKind::Field(name.clone()), j, scope);
+                    }
+                    j += 1;
+                }
+            }
+            StmtKind::PropAssign { base, key, .. } => {
+                self.def(key, DefKind::Field(base.clone()), i + 2, scope)
+            }
+            StmtKind::Input { dst, .. } => {
+                // `input "prompt" -> dst`
+                match dst {
+                    Target::Var(v) => self.def(v, DefKind::Var, i + 3, scope),
+                    Target::Prop { base, key } => {
+                        self.def(key, DefKind::Field(base.clone()), i + 5, scope)
+                    }
+                }
+            }
+            StmtKind::Cond {
+                then_blk, else_blk, ..
+            } => {
+                for s in then_blk.iter().chain(else_blk.iter().flatten()) {
+                    self.stmt(s, scope);
+                }
+            }
+            StmtKind::Loop { body, .. } => {
+                for s in body {
+                    self.stmt(s, scope);
+                }
+            }
+            StmtKind::While { body, ended, .. } => {
+                for s in body.iter().chain(ended.iter().flatten()) {
+                    self.stmt(s, scope);
+                }
+            }
+            StmtKind::Match { arms, .. } => {
+                for arm in arms {
+                    // `Variant(a, b) -> {`
+                    if let (Pat::Variant { binds, .. }, Some(j)) = (&arm.pat, self.tok_at(arm.span))
+                    {
+                        for (k, b) in binds.iter().enumerate() {
+                            self.def(b, DefKind::Var, j + 2 + 2 * k, scope);
+                        }
+                    }
+                    for s in &arm.body {
+                        self.stmt(s, scope);
+                    }
+                }
+            }
+            _ => {}
+        }
+    }
+
+    /// The function whose body contains `sp`.
+    fn scope_at(&self, sp: Span) -> Option<&str> {
+        let key = (sp.line, sp.col);
+        self.funcs
+            .iter()
+            .find(|(_, a, b)| {
+                let (sa, sb) = (self.toks[*a].1, self.toks[*b].1);
+                (sa.line, sa.col) <= key && key <= (sb.line, sb.col)
+            })
+            .map(|(n, _, _)| n.as_str())
+    }
+
+    /// Index of the identifier token under `sp`.
+    fn ident_under(&self, sp: Span) -> Option<usize> {
+        self.toks.iter().position(|(t, s)| {
+            matches!(t, Tok::Ident(_))
+                && s.line == sp.line
+                && s.col <= sp.col
+                && sp.col <= s.col + tok_len(t)
+        })
+    }
+
+    /// The definition the identifier at token `i` refers to.
+    fn resolve(&self, i: usize) -> Option<&Def> {
+        let name = self.ident(i)?;
+        let scope = self.scope_at(self.toks[i].1);
+        if i >= 2 && self.is(i - 1, Tok::Dot) {
+            let base = self.ident(i - 2)?;
+            return self
+                .defs
+                .iter()
+                .find(|d| d.name == name && d.kind == DefKind::Field(base.to_string()));
+        }
+        if self.is(i + 1, Tok::LParen) {
+            return self
+                .defs
+                .iter()
+                .find(|d| d.name == name && d.kind == DefKind::Func);
+        }
+        let var = |d: &&Def| d.name == name && matches!(d.kind, DefKind::Var | DefKind::Param(_));
+        let local = self
+            .defs
+            .iter()
+            .filter(var)
+            .find(|d| scope.is_some() && d.scope.as_deref() == scope);
+        local
+            .or_else(|| self.defs.iter().filter(var).find(|d| d.scope.is_none()))
+            .or_else(|| {
+                self.defs
+                    .iter()
+                    .find(|d| d.name == name && d.kind == DefKind::Type)
+            })
+    }
+
+    fn hover(&self, sp: Span) -> Option<String> {
+        let i = self.ident_under(sp)?;
+        let name = self.ident(i)?;
+        if self.is(i + 1, Tok::LParen) {
+            if let Some((_, sig)) = BUILTINS.iter().find(|(b, _)| *b == name) {
+                return Some(sig.to_string());
+            }
+            if let Some(m) = Math::parse(name).filter(|_| self.resolve(i).is_none()) {
+                return Some(m.signature());
+            }
+        }
+        let def = self.resolve(i)?;
+        let scope = def.scope.as_deref();
+        Some(match &def.kind {
+            DefKind::Func => self.signature(name)?,
+            DefKind::Param(t) => format!("{}: {}", name, t),
+            DefKind::Var => format!("{}: {}", name, self.types.var(scope, name)),
+            DefKind::Field(base) => {
+                let t = self.types.var(self.scope_at(sp), base);
+                let ft = self.types.field(&t, name).unwrap_or(Type::Unknown);
+                format!("{}.{}: {}", base, name, ft)
+            }
+            DefKind::Type => self.type_sig(name)?,
+        })
+    }
+
+    /// `type Name { ... }` or `enum Name { ... }` on one line.
+    fn type_sig(&self, name: &str) -> Option<String> {
+        if let Some(vs) = self.types.enum_variants(name) {
+            let vs: Vec<String> = vs
+                .iter()
+                .map(|v| match &v.fields[..] {
+                    [] => v.name.clone(),
+                    fs => {
+                        let ts: Vec<String> = fs.iter().map(|t| t.to_string()).collect();
+                        format!("{}({})", v.name, ts.join(", "))
+                    }
+                })
+                .collect();
+            return Some(format!("enum {} {{ {} }}", name, vs.join(", ")));
+        }
+        let fs: Vec<String> = self
+            .types
+            .record(name)?
+            .iter()
+            .map(|(k, t)| format!("{}: {}", k, t))
+            .collect();
+        Some(format!("type {} {{ {} }}", name, fs.join(", ")))
+    }
+
+    fn signature(&self, name: &str) -> Option<String> {
+        self.prog.items.iter().find_map(|it| match it {
+            Item::Func {
+                name: n,
+                params,
+                ret,
+                ..
+            } if n == name => {
+                let ps: Vec<String> = params
+                    .iter()
+                    .map(|p| format!("{}: {}", p.name, p.ty))
+                    .collect();
+                Some(format!("function {}({}) -> {}", n, ps.join(", "), ret))
+            }
+            _ => None,
+        })
+    }
+
+    fn completion(&self, sp: Span) -> Vec<Value> {
+        let line = self.text.lines().nth(sp.line - 1).unwrap_or_default();
+        let before: String = line.chars().take(sp.col - 1).collect();
+        let word = before.trim_end_matches(|c: char| c.is_ascii_alphanumeric() || c == '_');
+        let scope = self.scope_at(sp);
+        if let Some(rest) = word.strip_suffix('.') {
+            let base: String = rest
+                .chars()
+                .rev()
+                .take_while(|c| c.is_ascii_alphanumeric() || *c == '_')
+                .collect::<Vec<_>>()
+                .into_iter()
+                .rev()
+                .collect();
+            let t = self.types.var(scope, &base);
+            return self
+                .types
+                .fields(&t)
+                .iter()
+                .map(|(k, t)| item(k, ITEM_FIELD, &t.to_string()))
+                .collect();
+        }
+        let mut items: Vec<Value> = KEYWORDS
+            .iter()
+            .map(|k| item(k, ITEM_KEYWORD, "keyword"))
+            .collect();
+        items.extend(BUILTINS.iter().map(|(b, sig)| item(b, ITEM_FUNCTION, sig)));
+        items.extend(
+            math::ALL
+                .iter()
+                .map(|m| item(m.name(), ITEM_FUNCTION, &m.signature())),
+        );
+        for it in &self.prog.items {
+            if let Item::Func { name, .. } = it {
+                let sig = self.signature(name).unwrap_or_default();
+                items.push(item(name, ITEM_FUNCTION, &sig));
+            }
+        }
+        for (name, t) in self.types.visible(scope) {
+            items.push(item(name, ITEM_VARIABLE, &t.to_string()));
+        }
+        items
+    }
+
+    fn symbols(&self) -> Vec<Value> {
+        // functions end at `end`, records and enums at their `}`
+        let funcs = self.funcs.iter().map(|f| (f, SYMBOL_FUNCTION, "end".len()));
+        let records = self.records.iter().map(|r| (r, SYMBOL_STRUCT, 1));
+        let enums = self.enums.iter().map(|e| (e, SYMBOL_ENUM, 1));
+        let mut syms: Vec<_> = funcs.chain(records).chain(enums).collect();
+        syms.sort_by_key(|((_, a, _), _, _)| *a);
+        syms.into_iter()
+            .map(|((name, a, b), kind, end_len)| {
+                let (start, end) = (self.toks[*a].1, self.toks[*b].1);
+                let end = Span {
+                    col: end.col + end_len,
+                    ..end
+                };
+                let sel = self.toks[*a + 1].1;
+                let sel_end = Span {
+                    col: sel.col + name.chars().count(),
+                    ..sel
+                };
+                let detail = match kind {
+                    SYMBOL_FUNCTION => self.signature(name),
+                    _ => self.type_sig(name),
+                };
+                json!({
+                    "name": name,
+                    "detail": detail,
+                    "kind": kind,
+                    "range": range(self.text, start, end),
+                    "selectionRange": range(self.text, sel, sel_end),
+                })
+            })
+            .collect()
+    }
+}
+
+fn item(label: &str, kind: u8, detail: &str) -> Value {
+    json!({ "label": label, "kind": kind, "detail": detail })
+}
+
+fn query(method: &str, uri: &str, doc: &Doc, pos: &Value) -> Value {
+    let Some(prog) = &doc.prog else {
+        return Value::Null;
+    };
+    let a = Analysis::new(&doc.text, prog);
+    let sp = span_at(&doc.text, pos);
+    match method {
+        "textDocument/definition" => {
+            let def = a.ident_under(sp).and_then(|i| a.resolve(i));
+            match def {
+                Some(d) => {
+                    let end = Span {
+                        col: d.span.col + d.name.chars().count(),
+                        ..d.span
+                    };
+                    json!({ "uri": uri, "range": range(&doc.text, d.span, end) })
+                }
+                None => Value::Null,
+            }
+        }
+        "textDocument/hover" => match a.hover(sp) {
+            Some(h) => json!({
+                "contents": { "kind": "markdown", "value": format!("```pna\n{}\n```", h) },
+            }),
+            None => Value::Null,
+        },
+        "textDocument/completion" => Value::Array(a.completion(sp)),
+        "textDocument/documentSymbol" => Value::Array(a.symbols()),
+        _ => Value::Null,
+    }
+}
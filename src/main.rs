@@ -1,21 +1,62 @@
-mod ast;
-mod codegen_cpp;
-mod lexer;
-mod parser;
-mod token;
-mod typeck;
+use pna_cc::bytecode::Module;
+use pna_cc::interp::StdinInput;
+use pna_cc::vm::Vm;
+use pna_cc::{Compiler, CppOptions, Session};
+use std::{env, fs, io, process};
 
-use std::{env, fs, process};
+fn usage() -> ! {
+    eprintln!(
+        "usage: pna-cc <input.pna> [-o out] [--emit=cpp|c|js|wat|bc|ir] [--strict] [--line-directives] [--structs] [--include h.hpp]..."
+    );
+    eprintln!("       pna-cc emit-js <input.pna> [-o out.mjs] [--strict]");
+    eprintln!(
+        "       pna-cc bundle <input.pna> [-o out.cpp] [--strict] [--shorten] [--no-verify] [--include h.hpp]..."
+    );
+    eprintln!("       pna-cc exec <prog.pnac|prog.pna> [--strict]");
+    eprintln!("       pna-cc judge <prog.pna> <tests/> [--strict] [--time-limit=SECS] [--memory-limit=MB] [--float[=EPS]] [--include h.hpp]...");
+    eprintln!("       pna-cc repl [--strict]");
+    eprintln!("       pna-cc fmt [--check] <file.pna>...");
+    eprintln!("       pna-cc lsp");
+    process::exit(2);
+}
 
 fn main() -> Result<(), Box<dyn std::error::Error>> {
     let args = env::args().skip(1).collect::<Vec<_>>();
     if args.is_empty() {
-        eprintln!("usage: pna-cc <input.pna> [-o out.cpp]");
-        process::exit(2);
+        usage();
+    }
+    if args[0] == "fmt" {
+        process::exit(fmt(&args[1..])?);
+    }
+    if args[0] == "exec" {
+        process::exit(exec(&args[1..])?);
+    }
+    if args[0] == "bundle" {
+        process::exit(bundle(&args[1..])?);
+    }
+    if args[0] == "judge" {
+        process::exit(judge(&args[1..])?);
+    }
+    if args[0] == "lsp" {
+        pna_cc::lsp::run()?;
+        return Ok(());
+    }
+    if args[0] == "repl" {
+        pna_cc::repl::run(args[1..].iter().any(|a| a == "--strict"))?;
+        return Ok(());
     }
 
+    // `emit-js` is `--emit=js` spelled as a subcommand.
+    let (args, mut emit) = match args[0].as_str() {
+        "emit-js" => (&args[1..], String::from("js")),
+        _ => (&args[..], String::from("cpp")),
+    };
     let mut infile: Option<String> = None;
     let mut outfile: Option<String> = None;
+    let mut strict = false;
+    let mut line_directives = false;
+    let mut structs = false;
+    let mut includes = Vec::new();
 
     let mut i = 0usize;
     while i < args.len() {
@@ -28,25 +69,308 @@ fn main() -> Result<(), Box<dyn std::error::Error>> {
             i += 2;
             continue;
         }
+        if args[i] == "--strict" {
+            strict = true;
+            i += 1;
+            continue;
+        }
+        if let Some(e) = args[i].strip_prefix("--emit=") {
+            emit = e.to_string();
+            i += 1;
+            continue;
+        }
+        if args[i] == "--line-directives" {
+            line_directives = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--structs" {
+            structs = true;
+            i += 1;
+            continue;
+        }
+        if args[i] == "--include" {
+            if i + 1 >= args.len() {
+                eprintln!("--include needs a header");
+                process::exit(2);
+            }
+            includes.push(args[i + 1].clone());
+            i += 2;
+            continue;
+        }
         if infile.is_none() {
             infile = Some(args[i].clone());
         }
         i += 1;
     }
 
-    let src_path = infile.unwrap();
-    let src = fs::read_to_string(&src_path)?;
+    let Some(src_path) = infile else {
+        usage();
+    };
+    let sess = Session::load(&src_path)?;
+    let compiler = Compiler::new(CppOptions {
+        strict,
+        file: None,
+        line_directives,
+        cpp_file: outfile.clone(),
+        structs,
+        includes,
+    });
 
-    let toks = lexer::lex(&src)?;
-    let prog = parser::parse(toks).map_err(|e| format!("Error: {}", e))?;
-    typeck::check(&prog)?;
-
-    let cpp = codegen_cpp::compile_to_cpp(&prog)?;
+    if emit == "bc" {
+        let m = compiler
+            .compile_bytecode(&sess)
+            .unwrap_or_else(|d| fail(&sess, &d));
+        let outp = outfile.unwrap_or_else(|| with_ext(&src_path, "pnac"));
+        fs::write(outp, m.encode())?;
+        return Ok(());
+    }
+    let code = match emit.as_str() {
+        "cpp" => compiler.compile(&sess),
+        "c" => compiler.compile_c(&sess),
+        "js" => compiler.compile_js(&sess),
+        "wat" => compiler.compile_wat(&sess),
+        "ir" => compiler.compile_ir(&sess).map(|m| m.to_string()),
+        _ => {
+            eprintln!(
+                "unknown --emit={} (expected cpp, c, js, wat, bc or ir)",
+                emit
+            );
+            process::exit(2);
+        }
+    }
+    .unwrap_or_else(|d| fail(&sess, &d));
     if let Some(outp) = outfile {
-        fs::write(outp, cpp)?;
+        fs::write(outp, code)?;
     } else {
-        print!("{}", cpp);
+        print!("{}", code);
     }
 
     Ok(())
 }
+
+/// `pna-cc fmt`: rewrites files in place, or with `--check` only lists the
+/// ones that are not formatted. Returns the exit code.
+fn fmt(args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    let check = args.iter().any(|a| a == "--check");
+    let mut code = 0;
+    for path in args.iter().filter(|a| *a != "--check") {
+        let sess = Session::load(path)?;
+        let out = match pna_cc::fmt::format_source(sess.source()) {
+            Ok(out) => out,
+            Err(d) => {
+                eprintln!("{}", sess.render(&d));
+                code = 1;
+                continue;
+            }
+        };
+        if out == sess.source() {
+            continue;
+        }
+        if check {
+            println!("would reformat {}", path);
+            code = 1;
+        } else {
+            fs::write(path, out)?;
+        }
+    }
+    Ok(code)
+}
+
+/// Prints `diags` against `sess` and exits with status 1.
+fn fail(sess: &Session, diags: &[pna_cc::Diagnostic]) -> ! {
+    for d in diags {
+        eprintln!("{}", sess.render(d));
+    }
+    process::exit(1);
+}
+
+/// `pna-cc bundle`: compiles to C++ and cuts the result down to one minimal
+/// file for pasting into a judge (see `pna_cc::bundle`), checking that it
+/// still compiles as C++17 and C++20 with `$CXX` (default `g++`). Prints the
+/// size report on stderr. `--include`d headers are pasted in, since a judge
+/// only gets the one file. Returns the exit code.
+fn bundle(args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    let mut infile = None;
+    let mut outfile = None;
+    let (mut strict, mut shorten, mut verify) = (false, false, true);
+    let mut includes = Vec::new();
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        match a.as_str() {
+            "-o" => outfile = it.next().cloned(),
+            "--include" => includes.extend(it.next().cloned()),
+            "--strict" => strict = true,
+            "--shorten" => shorten = true,
+            "--no-verify" => verify = false,
+            _ => infile = infile.or(Some(a.clone())),
+        }
+    }
+    let Some(src_path) = infile else {
+        eprintln!(
+            "usage: pna-cc bundle <input.pna> [-o out.cpp] [--strict] [--shorten] [--no-verify] [--include h.hpp]..."
+        );
+        return Ok(2);
+    };
+    let sess = Session::load(&src_path)?;
+    let compiler = Compiler::new(CppOptions {
+        strict,
+        includes: includes.clone(),
+        ..CppOptions::default()
+    });
+    let full = compiler.compile(&sess).unwrap_or_else(|d| fail(&sess, &d));
+    let full = inline_headers(&full, &includes)?;
+    let b = pna_cc::bundle::bundle(&full, shorten);
+    if verify {
+        let cxx = env::var("CXX").unwrap_or_else(|_| "g++".into());
+        for std in ["c++17", "c++20"] {
+            if let Err(e) = pna_cc::bundle::check_compiles(&b.code, &cxx, std) {
+                eprintln!(
+                    "{}: bundle does not compile with -std={}:\n{}",
+                    src_path, std, e
+                );
+                return Ok(1);
+            }
+        }
+    }
+    eprintln!(
+        "{}: {} -> {} bytes, {} of {} declarations kept",
+        src_path,
+        full.len(),
+        b.code.len(),
+        b.kept,
+        b.total
+    );
+    match outfile {
+        Some(outp) => fs::write(outp, &b.code)?,
+        None => print!("{}", b.code),
+    }
+    Ok(0)
+}
+
+/// `pna-cc judge`: judges the program on every case in the test directory
+/// (see `pna_cc::judge`) and prints the verdict table and what went wrong in
+/// the failed cases. Returns 0 if all pass.
+fn judge(args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    use pna_cc::judge::{self, Checker, Options};
+    use std::time::Duration;
+
+    let usage = "usage: pna-cc judge <prog.pna> <tests/> [--strict] [--time-limit=SECS] [--memory-limit=MB] [--float[=EPS]] [--include h.hpp]...";
+    let mut paths = Vec::new();
+    let mut opts = Options {
+        cxx: env::var("CXX").unwrap_or_else(|_| "g++".into()),
+        ..Options::default()
+    };
+    let mut it = args.iter();
+    while let Some(a) = it.next() {
+        if a == "--strict" {
+            opts.strict = true;
+        } else if a == "--float" {
+            opts.checker = Checker::Float(1e-6);
+        } else if let Some(v) = a.strip_prefix("--float=") {
+            opts.checker = Checker::Float(v.parse()?);
+        } else if let Some(v) = a.strip_prefix("--time-limit=") {
+            opts.limits.time = Duration::from_secs_f64(v.parse()?);
+        } else if let Some(v) = a.strip_prefix("--memory-limit=") {
+            opts.limits.memory_kb = v.parse::<u64>()? * 1024;
+        } else if a == "--include" {
+            let Some(h) = it.next() else {
+                eprintln!("{}", usage);
+                return Ok(2);
+            };
+            opts.includes
+                .push(fs::canonicalize(h)?.to_string_lossy().into_owned());
+        } else {
+            paths.push(a.as_str());
+        }
+    }
+    let [src_path, dir] = paths[..] else {
+        eprintln!("{}", usage);
+        return Ok(2);
+    };
+
+    let sess = Session::load(src_path)?;
+    match judge::run(&sess, std::path::Path::new(dir), &opts) {
+        Ok(report) => {
+            print!("{}", report);
+            Ok(if report.all_passed() { 0 } else { 1 })
+        }
+        Err(judge::Error::NoCases) => {
+            eprintln!("{}: no N.in / N.out pairs", dir);
+            Ok(2)
+        }
+        Err(judge::Error::Compile(d)) => fail(&sess, &d),
+        Err(judge::Error::Cxx) => {
+            eprintln!("{}: {} failed", src_path, opts.cxx);
+            Ok(1)
+        }
+        Err(judge::Error::Io(e)) => Err(e.into()),
+    }
+}
+
+/// `cpp` with each `#include "h"` line of `headers` replaced by the contents
+/// of `h`, without `#pragma once`.
+fn inline_headers(cpp: &str, headers: &[String]) -> io::Result<String> {
+    let mut out = String::new();
+    for line in cpp.lines() {
+        let included = line
+            .strip_prefix("#include \"")
+            .and_then(|l| l.strip_suffix('"'))
+            .filter(|h| headers.iter().any(|x| x == h));
+        match included {
+            Some(h) => {
+                for l in fs::read_to_string(h)?.lines() {
+                    if l.trim() != "#pragma once" {
+                        out.push_str(l);
+                        out.push('\n');
+                    }
+                }
+            }
+            None => {
+                out.push_str(line);
+                out.push('\n');
+            }
+        }
+    }
+    Ok(out)
+}
+
+/// `path` with its extension replaced by `ext`.
+fn with_ext(path: &str, ext: &str) -> String {
+    std::path::Path::new(path)
+        .with_extension(ext)
+        .to_string_lossy()
+        .into_owned()
+}
+
+/// `pna-cc exec`: runs a `.pnac` file, or compiles and runs a `.pna` source,
+/// on the bytecode VM. Returns the exit code.
+fn exec(args: &[String]) -> Result<i32, Box<dyn std::error::Error>> {
+    let strict = args.iter().any(|a| a == "--strict");
+    let Some(path) = args.iter().find(|a| *a != "--strict") else {
+        eprintln!("usage: pna-cc exec <prog.pnac|prog.pna> [--strict]");
+        return Ok(2);
+    };
+    let bytes = fs::read(path)?;
+    let module = if bytes.starts_with(b"PNAC") {
+        Module::decode(&bytes).map_err(|e| format!("{}: {}", path, e))?
+    } else {
+        let sess = Session::new(path.as_str(), String::from_utf8(bytes)?);
+        Compiler::default()
+            .compile_bytecode(&sess)
+            .unwrap_or_else(|d| fail(&sess, &d))
+    };
+    let out = Box::new(io::BufWriter::new(io::stdout()));
+    let mut vm = Vm::new(&module, out, Box::new(StdinInput::default()), strict);
+    match vm.run() {
+        Ok(()) => Ok(0),
+        Err(e) => {
+            let file = e
+                .file
+                .checked_sub(1)
+                .map_or(path.as_str(), |i| &module.files[i]);
+            eprintln!("{}:{}: runtime error: {}", file, e.line, e.message);
+            Ok(1)
+        }
+    }
+}
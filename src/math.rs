@@ -0,0 +1,146 @@
+//! Math builtins (`sqrt(x)`, `gcd(a, b)`, ...) and constants (`pi`).
+//!
+//! A call whose name is one of these resolves to the builtin unless the
+//! program declares a function (or `extern function`) of that name, and a
+//! constant reads as itself unless the program assigns a variable of that
+//! name. Each backend maps them onto its math library: `<cmath>`
+//! for C++, `<math.h>` for C, `Math` for JavaScript.
+
+use crate::ast::Ty;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Math {
+    Abs,
+    Min,
+    Max,
+    Sqrt,
+    Pow,
+    Floor,
+    Ceil,
+    Round,
+    Gcd,
+    Sin,
+    Cos,
+    Tan,
+    Asin,
+    Acos,
+    Atan,
+    Atan2,
+}
+
+/// Every builtin, in bytecode encoding order (append only).
+pub const ALL: &[Math] = &[
+    Math::Abs,
+    Math::Min,
+    Math::Max,
+    Math::Sqrt,
+    Math::Pow,
+    Math::Floor,
+    Math::Ceil,
+    Math::Round,
+    Math::Gcd,
+    Math::Sin,
+    Math::Cos,
+    Math::Tan,
+    Math::Asin,
+    Math::Acos,
+    Math::Atan,
+    Math::Atan2,
+];
+
+/// Named constants, usable anywhere an expression is.
+const CONSTANTS: &[(&str, f64)] = &[("pi", std::f64::consts::PI)];
+
+/// The value of the constant `name`, if it is one.
+pub fn constant(name: &str) -> Option<f64> {
+    CONSTANTS.iter().find(|(n, _)| *n == name).map(|(_, v)| *v)
+}
+
+impl Math {
+    pub fn parse(name: &str) -> Option<Math> {
+        ALL.iter().copied().find(|m| m.name() == name)
+    }
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Math::Abs => "abs",
+            Math::Min => "min",
+            Math::Max => "max",
+            Math::Sqrt => "sqrt",
+            Math::Pow => "pow",
+            Math::Floor => "floor",
+            Math::Ceil => "ceil",
+            Math::Round => "round",
+            Math::Gcd => "gcd",
+            Math::Sin => "sin",
+            Math::Cos => "cos",
+            Math::Tan => "tan",
+            Math::Asin => "asin",
+            Math::Acos => "acos",
+            Math::Atan => "atan",
+            Math::Atan2 => "atan2",
+        }
+    }
+
+    /// Parameter types, as if declared `fn name(x: double) -> ...`.
+    pub fn params(self) -> &'static [Ty] {
+        match self {
+            Math::Min | Math::Max | Math::Pow | Math::Atan2 => &[Ty::Double, Ty::Double],
+            Math::Gcd => &[Ty::Int, Ty::Int],
+            _ => &[Ty::Double],
+        }
+    }
+
+    pub fn ret(self) -> Ty {
+        match self {
+            Math::Floor | Math::Ceil | Math::Round | Math::Gcd => Ty::Int,
+            _ => Ty::Double,
+        }
+    }
+
+    /// How hovers and completions show it: `pow(x: double, y: double) -> double`.
+    pub fn signature(self) -> String {
+        let names: &[&str] = match self {
+            Math::Pow => &["x", "y"],
+            Math::Atan2 => &["y", "x"],
+            Math::Min | Math::Max | Math::Gcd => &["a", "b"],
+            _ => &["x"],
+        };
+        let params: Vec<String> = names
+            .iter()
+            .zip(self.params())
+            .map(|(n, t)| format!("{}: {}", n, t))
+            .collect();
+        format!("{}({}) -> {}", self.name(), params.join(", "), self.ret())
+    }
+
+    /// The result for arguments already converted to numbers; `int`
+    /// parameters truncate. `round` rounds halves away from zero, like C's
+    /// `round`.
+    pub fn apply(self, x: &[f64]) -> f64 {
+        match self {
+            Math::Abs => x[0].abs(),
+            Math::Min => x[0].min(x[1]),
+            Math::Max => x[0].max(x[1]),
+            Math::Sqrt => x[0].sqrt(),
+            Math::Pow => x[0].powf(x[1]),
+            Math::Floor => x[0].floor(),
+            Math::Ceil => x[0].ceil(),
+            Math::Round => x[0].round(),
+            Math::Gcd => {
+                let (mut a, mut b) = ((x[0] as i64).unsigned_abs(), (x[1] as i64).unsigned_abs());
+                while b != 0 {
+                    (a, b) = (b, a % b);
+                }
+                a as f64
+            }
+            Math::Sin => x[0].sin(),
+            Math::Cos => x[0].cos(),
+            Math::Tan => x[0].tan(),
+            Math::Asin => x[0].asin(),
+            Math::Acos => x[0].acos(),
+            Math::Atan => x[0].atan(),
+            Math::Atan2 => x[0].atan2(x[1]),
+        }
+    }
+}
@@ -0,0 +1,221 @@
+//! Constant folding and dead-code elimination on the IR.
+//!
+//! Operators whose operands are all constants are evaluated with `value`,
+//! the Rust mirror of the runtime, in strict mode: anything that would fail
+//! under `--strict` (`1 / 0`, `"a" - 1`, a bad string index) is left for the
+//! runtime, so both modes keep their behaviour and error lines. A folded
+//! temporary becomes a constant at its single use.
+//!
+//! `if`s on a constant keep only the branch taken, loops whose condition is
+//! constantly false are replaced by their `ended` block, and whatever follows
+//! `return`, `break` or `continue` in a block is dropped.
+
+use crate::ir::*;
+use crate::value::{self, Value};
+use std::collections::HashMap;
+
+/// Folded strings longer than this stay runtime operations, so `"ab" * 1e6`
+/// does not end up as a literal in the generated code.
+const MAX_FOLDED_STR: usize = 4096;
+
+pub fn optimize(m: &mut Module) {
+    for f in m.funcs.iter_mut().chain(std::iter::once(&mut m.main)) {
+        let body = std::mem::take(&mut f.body);
+        f.body = block(body, &mut HashMap::new());
+    }
+}
+
+fn block(b: Vec<Inst>, consts: &mut HashMap<Temp, Const>) -> Vec<Inst> {
+    let mut out = Vec::new();
+    for i in b {
+        inst(i, consts, &mut out);
+        if let Some(Inst::Return(_) | Inst::Break(_) | Inst::Continue(_)) = out.last() {
+            break;
+        }
+    }
+    // A statement that folded away leaves its `Line` behind.
+    let keep: Vec<bool> = out
+        .windows(2)
+        .map(|w| !matches!(w, [Inst::Line(_), Inst::Line(_)]))
+        .chain([true])
+        .collect();
+    let mut keep = keep.into_iter();
+    out.retain(|_| keep.next().unwrap_or(true));
+    out
+}
+
+fn inst(i: Inst, consts: &mut HashMap<Temp, Const>, out: &mut Vec<Inst>) {
+    match i {
+        Inst::Let(t, mut rv) => {
+            rv_operands(&mut rv, |o| subst(o, consts));
+            match fold(&rv) {
+                Some(c) => {
+                    consts.insert(t, c);
+                }
+                None => out.push(Inst::Let(t, rv)),
+            }
+        }
+        Inst::Store(s, mut v) => {
+            subst(&mut v, consts);
+            out.push(Inst::Store(s, v));
+        }
+        Inst::SetField(s, k, mut v) => {
+            subst(&mut v, consts);
+            out.push(Inst::SetField(s, k, v));
+        }
+        Inst::SetIndex(s, mut k, mut v) => {
+            subst(&mut k, consts);
+            subst(&mut v, consts);
+            out.push(Inst::SetIndex(s, k, v));
+        }
+        Inst::Out { mut args, newline } => {
+            args.iter_mut().for_each(|a| subst(a, consts));
+            out.push(Inst::Out { args, newline });
+        }
+        Inst::If {
+            mut cond,
+            then_blk,
+            else_blk,
+        } => {
+            subst(&mut cond, consts);
+            match &cond {
+                Operand::Const(c) => {
+                    let taken = if value::truthy(&to_value(c)) {
+                        then_blk
+                    } else {
+                        else_blk
+                    };
+                    out.extend(block(taken, consts));
+                }
+                Operand::Temp(_) => out.push(Inst::If {
+                    cond,
+                    then_blk: block(then_blk, consts),
+                    else_blk: block(else_blk, consts),
+                }),
+            }
+        }
+        Inst::Loop {
+            id,
+            header,
+            mut cond,
+            body,
+            ended,
+        } => {
+            let header = block(header, consts);
+            subst(&mut cond, consts);
+            match &cond {
+                Operand::Const(c) if !value::truthy(&to_value(c)) => {
+                    out.extend(block(ended, consts));
+                }
+                _ => out.push(Inst::Loop {
+                    id,
+                    header,
+                    cond,
+                    body: block(body, consts),
+                    ended: block(ended, consts),
+                }),
+            }
+        }
+        Inst::Switch {
+            slot,
+            cases,
+            default,
+        } => out.push(Inst::Switch {
+            slot,
+            cases: cases
+                .into_iter()
+                .map(|c| Case {
+                    body: block(c.body, consts),
+                    ..c
+                })
+                .collect(),
+            default: block(default, consts),
+        }),
+        Inst::Return(mut v) => {
+            subst(&mut v, consts);
+            out.push(Inst::Return(v));
+        }
+        i @ (Inst::Line(_) | Inst::Break(_) | Inst::Continue(_) | Inst::Cpp(_)) => out.push(i),
+    }
+}
+
+/// Replaces a folded temporary by its constant.
+fn subst(o: &mut Operand, consts: &mut HashMap<Temp, Const>) {
+    if let Operand::Temp(t) = o {
+        if let Some(c) = consts.remove(t) {
+            *o = Operand::Const(c);
+        }
+    }
+}
+
+fn rv_operands(rv: &mut Rv, mut f: impl FnMut(&mut Operand)) {
+    match rv {
+        Rv::Local(_) | Rv::Name(_) | Rv::NewObj | Rv::Input(_) => {}
+        Rv::Not(a) | Rv::Member(a, _) | Rv::Len(a) | Rv::Keys(a) | Rv::Values(a) => f(a),
+        Rv::Bin(_, a, b)
+        | Rv::Index(a, b)
+        | Rv::SetField(a, _, b)
+        | Rv::Has(a, b)
+        | Rv::Remove(a, b) => {
+            f(a);
+            f(b);
+        }
+        Rv::Concat(xs) | Rv::Format(xs) | Rv::Math(_, xs) | Rv::Call(_, xs) | Rv::Extern(_, xs) => {
+            xs.iter_mut().for_each(f)
+        }
+    }
+}
+
+/// The value of `rv` if it only depends on constants and cannot fail.
+fn fold(rv: &Rv) -> Option<Const> {
+    let v = match rv {
+        Rv::Not(a) => value::unary("!", &operand(a)?),
+        Rv::Bin(op, a, b) => value::binary(op.as_str(), &operand(a)?, &operand(b)?, true).ok()?,
+        Rv::Index(a, b) => value::index(&operand(a)?, &operand(b)?, true).ok()?,
+        Rv::Len(a) => value::len(&operand(a)?),
+        Rv::Concat(xs) => {
+            let mut s = String::new();
+            for x in xs {
+                s.push_str(&value::as_str(&operand(x)?));
+            }
+            Value::Str(s)
+        }
+        Rv::Format(xs) => {
+            let vs = xs.iter().map(operand).collect::<Option<Vec<_>>>()?;
+            value::format(&vs[0], &vs[1..])
+        }
+        Rv::Math(m, xs) => {
+            let vs = xs.iter().map(operand).collect::<Option<Vec<_>>>()?;
+            value::math(*m, &vs, true).ok()?
+        }
+        _ => return None,
+    };
+    from_value(v)
+}
+
+fn operand(o: &Operand) -> Option<Value> {
+    match o {
+        Operand::Const(c) => Some(to_value(c)),
+        Operand::Temp(_) => None,
+    }
+}
+
+fn to_value(c: &Const) -> Value {
+    match c {
+        Const::Num(d) => Value::Num(*d),
+        Const::Str(s) => Value::Str(s.clone()),
+        Const::Bool(b) => Value::Bool(*b),
+        Const::Undef => Value::Undef,
+    }
+}
+
+/// Results the backends can spell as a literal.
+fn from_value(v: Value) -> Option<Const> {
+    match v {
+        Value::Num(d) if d.is_finite() => Some(Const::Num(d)),
+        Value::Str(s) if s.len() <= MAX_FOLDED_STR => Some(Const::Str(s)),
+        Value::Bool(b) => Some(Const::Bool(b)),
+        Value::Undef => Some(Const::Undef),
+        _ => None,
+    }
+}
@@ -1,10 +1,14 @@
 use crate::ast::*;
-use crate::token::Tok;
+use crate::diag::Diagnostic;
+use crate::token::{Tok, TplPart};
 
 #[derive(Debug)]
 pub struct Parser {
     toks: Vec<Tok>,
+    spans: Vec<Span>,
     i: usize,
+    /// Set once the parser has tried to consume past the last token.
+    hit_eof: bool,
 }
 
 fn is_eof(t: &Tok) -> bool {
@@ -13,30 +17,111 @@ fn is_eof(t: &Tok) -> bool {
 fn peek(p: &Parser) -> &Tok {
     p.toks.get(p.i).unwrap_or(&Tok::Eof)
 }
+fn span(p: &Parser) -> Span {
+    p.spans
+        .get(p.i)
+        .or(p.spans.last())
+        .copied()
+        .unwrap_or_default()
+}
 fn bump(p: &mut Parser) -> Tok {
     let t = peek(p).clone();
-    if !is_eof(&t) {
+    if is_eof(&t) {
+        p.hit_eof = true;
+    } else {
         p.i += 1;
     }
     t
 }
-fn expect(p: &mut Parser, want: Tok) -> Tok {
+fn expect(p: &mut Parser, want: Tok) -> Result<Tok, String> {
     let t = bump(p);
     if std::mem::discriminant(&t) != std::mem::discriminant(&want) {
-        panic!("expected {:?}, got {:?}", want, t);
+        return Err(format!("expected {:?}, got {:?}", want, t));
     }
-    t
+    Ok(t)
 }
 
-pub fn parse(toks: Vec<Tok>) -> Result<Program, String> {
-    let mut p = Parser { toks, i: 0 };
-    let mut items = Vec::new();
-    while !is_eof(peek(&p)) {
-        match peek(&p) {
-            Tok::KwFunction => items.push(parse_func(&mut p)?),
-            _ => items.push(Item::Stmt(parse_stmt(&mut p)?)),
+pub fn parse(toks: Vec<(Tok, Span)>) -> Result<Program, Diagnostic> {
+    let mut p = Parser::new(toks);
+    parse_items(&mut p).map_err(|m| error_at(&p, m))
+}
+
+/// How parsing input that may still be partial (e.g. a REPL entry) failed.
+#[derive(Debug)]
+pub enum ParseFail {
+    /// The input ended in the middle of a construct; more lines may fix it.
+    Incomplete,
+    Error(Diagnostic),
+}
+
+/// Like `parse`, but reports running out of tokens as `Incomplete`.
+pub fn parse_incremental(toks: Vec<(Tok, Span)>) -> Result<Program, ParseFail> {
+    let mut p = Parser::new(toks);
+    parse_items(&mut p).map_err(|m| fail(&p, m))
+}
+
+/// Parses the whole token stream as a single expression.
+pub fn parse_expr_only(toks: Vec<(Tok, Span)>) -> Result<Expr, ParseFail> {
+    let mut p = Parser::new(toks);
+    let e = parse_expr(&mut p).map_err(|m| fail(&p, m))?;
+    if !is_eof(peek(&p)) {
+        let m = format!("expected end of expression, got {:?}", bump(&mut p));
+        return Err(ParseFail::Error(error_at(&p, m)));
+    }
+    Ok(e)
+}
+
+impl Parser {
+    fn new(toks: Vec<(Tok, Span)>) -> Self {
+        let (toks, spans) = toks.into_iter().unzip();
+        Parser {
+            toks,
+            spans,
+            i: 0,
+            hit_eof: false,
         }
     }
+}
+
+// errors are raised right after consuming the offending token
+fn error_at(p: &Parser, message: String) -> Diagnostic {
+    let at = p.i.saturating_sub(1);
+    let span = p.spans.get(at).copied().unwrap_or_default();
+    Diagnostic { message, span }
+}
+
+fn fail(p: &Parser, message: String) -> ParseFail {
+    if p.hit_eof {
+        ParseFail::Incomplete
+    } else {
+        ParseFail::Error(error_at(p, message))
+    }
+}
+
+fn parse_items(p: &mut Parser) -> Result<Program, String> {
+    let mut items = Vec::new();
+    while !is_eof(peek(p)) {
+        let item = match peek(p) {
+            Tok::KwFunction => parse_func(p)?,
+            Tok::KwType => parse_type_decl(p)?,
+            Tok::KwEnum => parse_enum_decl(p)?,
+            Tok::KwImport => parse_import(p)?,
+            Tok::Ident(s)
+                if s == "extern" && matches!(p.toks.get(p.i + 1), Some(Tok::KwFunction)) =>
+            {
+                parse_extern(p)?
+            }
+            Tok::Cpp(_) => {
+                let sp = span(p);
+                match parse_stmt(p)?.kind {
+                    StmtKind::Cpp(code) => Item::Cpp { code, span: sp },
+                    _ => unreachable!(),
+                }
+            }
+            _ => Item::Stmt(parse_stmt(p)?),
+        };
+        items.push(item);
+    }
     Ok(Program { items })
 }
 
@@ -46,22 +131,128 @@ fn parse_type(p: &mut Parser) -> Result<Ty, String> {
         Tok::TyInt => Ty::Int,
         Tok::TyString => Ty::String,
         Tok::TyVoid => Ty::Void,
+        Tok::Ident(n) => Ty::Named(n),
         t => return Err(format!("type expected, got {:?}", t)),
     })
 }
 
+fn parse_type_decl(p: &mut Parser) -> Result<Item, String> {
+    let sp = span(p);
+    expect(p, Tok::KwType)?;
+    let name = match bump(p) {
+        Tok::Ident(s) => s,
+        t => return Err(format!("expected type name, got {:?}", t)),
+    };
+    expect(p, Tok::LBrace)?;
+    let mut fields = Vec::new();
+    while !matches!(peek(p), Tok::RBrace | Tok::Eof) {
+        let key = match bump(p) {
+            Tok::Ident(s) => s,
+            t => return Err(format!("expected field name, got {:?}", t)),
+        };
+        expect(p, Tok::Colon)?;
+        fields.push((key, parse_type(p)?));
+        if let Tok::Comma = peek(p) {
+            bump(p);
+        }
+    }
+    expect(p, Tok::RBrace)?;
+    Ok(Item::Type {
+        name,
+        fields,
+        span: sp,
+    })
+}
+
+/// `import "path"` or `import alias from "path"`; `from` is not a keyword.
+fn parse_import(p: &mut Parser) -> Result<Item, String> {
+    let sp = span(p);
+    expect(p, Tok::KwImport)?;
+    let alias = match peek(p) {
+        Tok::Ident(_) => {
+            let Tok::Ident(alias) = bump(p) else {
+                unreachable!()
+            };
+            match bump(p) {
+                Tok::Ident(f) if f == "from" => {}
+                t => {
+                    return Err(format!(
+                        "expected `from` after `import {}`, got {:?}",
+                        alias, t
+                    ))
+                }
+            }
+            Some(alias)
+        }
+        _ => None,
+    };
+    let path = match bump(p) {
+        Tok::String(s) => s,
+        t => {
+            return Err(format!(
+                "expected a file name in quotes after `import`, got {:?}",
+                t
+            ))
+        }
+    };
+    Ok(Item::Import {
+        path,
+        alias,
+        span: sp,
+    })
+}
+
+fn parse_enum_decl(p: &mut Parser) -> Result<Item, String> {
+    let sp = span(p);
+    expect(p, Tok::KwEnum)?;
+    let name = match bump(p) {
+        Tok::Ident(s) => s,
+        t => return Err(format!("expected enum name, got {:?}", t)),
+    };
+    expect(p, Tok::LBrace)?;
+    let mut variants = Vec::new();
+    while !matches!(peek(p), Tok::RBrace | Tok::Eof) {
+        let name = match bump(p) {
+            Tok::Ident(s) => s,
+            t => return Err(format!("expected variant name, got {:?}", t)),
+        };
+        let mut fields = Vec::new();
+        if let Tok::LParen = peek(p) {
+            bump(p);
+            loop {
+                fields.push(parse_type(p)?);
+                if let Tok::Comma = peek(p) {
+                    bump(p);
+                    continue;
+                }
+                expect(p, Tok::RParen)?;
+                break;
+            }
+        }
+        variants.push(Variant { name, fields });
+        if let Tok::Comma = peek(p) {
+            bump(p);
+        }
+    }
+    expect(p, Tok::RBrace)?;
+    Ok(Item::Enum {
+        name,
+        variants,
+        span: sp,
+    })
+}
+
 fn parse_params(p: &mut Parser) -> Result<Vec<Param>, String> {
     let mut ps = Vec::new();
     if let Tok::RParen = peek(p) {
         return Ok(ps);
     }
     loop {
-        let name = if let Tok::Ident(s) = bump(p) {
-            s
-        } else {
-            return Err("param name".into());
+        let name = match bump(p) {
+            Tok::Ident(s) => s,
+            t => return Err(format!("expected parameter name, got {:?}", t)),
         };
-        expect(p, Tok::Colon);
+        expect(p, Tok::Colon)?;
         let ty = parse_type(p)?;
         ps.push(Param { name, ty });
         if let Tok::Comma = peek(p) {
@@ -73,30 +264,56 @@ fn parse_params(p: &mut Parser) -> Result<Vec<Param>, String> {
     Ok(ps)
 }
 
-fn parse_func(p: &mut Parser) -> Result<Item, String> {
-    expect(p, Tok::KwFunction);
-    let name = if let Tok::Ident(s) = bump(p) {
-        s
-    } else {
-        return Err("fn name".into());
+/// The `name(params) -> ret` after `function`.
+fn parse_signature(p: &mut Parser) -> Result<(String, Vec<Param>, Ty), String> {
+    expect(p, Tok::KwFunction)?;
+    let name = match bump(p) {
+        Tok::Ident(s) => s,
+        t => return Err(format!("expected function name, got {:?}", t)),
     };
-    expect(p, Tok::LParen);
+    expect(p, Tok::LParen)?;
     let params = parse_params(p)?;
-    expect(p, Tok::RParen);
-    expect(p, Tok::Arrow);
+    expect(p, Tok::RParen)?;
+    expect(p, Tok::Arrow)?;
     let ret = parse_type(p)?;
-    expect(p, Tok::LBrace);
+    Ok((name, params, ret))
+}
+
+/// `extern function name(params) -> ret`; `extern` is not a keyword.
+fn parse_extern(p: &mut Parser) -> Result<Item, String> {
+    let sp = span(p);
+    bump(p);
+    let (name, params, ret) = parse_signature(p)?;
+    Ok(Item::Extern {
+        name,
+        params,
+        ret,
+        span: sp,
+    })
+}
+
+fn parse_func(p: &mut Parser) -> Result<Item, String> {
+    let sp = span(p);
+    let (name, params, ret) = parse_signature(p)?;
+    expect(p, Tok::LBrace)?;
     let body = parse_block_until(p, Tok::RBrace)?;
-    expect(p, Tok::KwEnd);
+    expect(p, Tok::KwEnd)?;
     Ok(Item::Func {
         name,
         params,
         ret,
         body,
+        span: sp,
     })
 }
 
 fn parse_stmt(p: &mut Parser) -> Result<Stmt, String> {
+    let sp = span(p);
+    let kind = parse_stmt_kind(p)?;
+    Ok(Stmt { kind, span: sp })
+}
+
+fn parse_stmt_kind(p: &mut Parser) -> Result<StmtKind, String> {
     match peek(p) {
         Tok::Ident(_) => {
             let name = if let Tok::Ident(s) = bump(p) {
@@ -107,155 +324,237 @@ fn parse_stmt(p: &mut Parser) -> Result<Stmt, String> {
             match peek(p) {
                 Tok::Colon => {
                     bump(p);
+                    // `name: T {` starts a typed literal; no expression is
+                    // followed by `{`
+                    let ty = match (peek(p), p.toks.get(p.i + 1)) {
+                        (Tok::Ident(t), Some(Tok::LBrace)) => {
+                            let t = t.clone();
+                            bump(p);
+                            Some(t)
+                        }
+                        _ => None,
+                    };
                     if let Tok::LBrace = peek(p) {
                         bump(p);
                         let mut fields = Vec::new();
                         while !matches!(peek(p), Tok::RBrace | Tok::Eof) {
-                            let key = if let Tok::Ident(s) = bump(p) {
-                                s
-                            } else {
-                                return Err("field name".into());
+                            let key = match bump(p) {
+                                Tok::Ident(s) => s,
+                                t => return Err(format!("expected field name, got {:?}", t)),
                             };
-                            expect(p, Tok::Colon);
+                            expect(p, Tok::Colon)?;
                             let e = parse_expr(p)?;
                             if let Tok::Comma = peek(p) {
                                 bump(p);
                             }
                             fields.push((key, e));
                         }
-                        expect(p, Tok::RBrace);
-                        Ok(Stmt::ObjBlock { name, fields })
+                        expect(p, Tok::RBrace)?;
+                        Ok(StmtKind::ObjBlock { name, ty, fields })
                     } else {
                         let e = parse_expr(p)?;
-                        Ok(Stmt::VarAssign { name, expr: e })
+                        Ok(StmtKind::VarAssign { name, expr: e })
                     }
                 }
                 Tok::Dot => {
                     bump(p);
-                    let key = if let Tok::Ident(s) = bump(p) {
-                        s
-                    } else {
-                        return Err("prop".into());
+                    let key = match bump(p) {
+                        Tok::Ident(s) => s,
+                        t => return Err(format!("expected property name, got {:?}", t)),
                     };
-                    expect(p, Tok::Colon);
+                    expect(p, Tok::Colon)?;
                     let e = parse_expr(p)?;
-                    Ok(Stmt::PropAssign {
+                    Ok(StmtKind::PropAssign {
                         base: name,
                         key,
                         expr: e,
                     })
                 }
-                _ => Err("invalid statement".into()),
+                Tok::LBracket => {
+                    bump(p);
+                    let index = parse_expr(p)?;
+                    expect(p, Tok::RBracket)?;
+                    expect(p, Tok::Colon)?;
+                    let e = parse_expr(p)?;
+                    Ok(StmtKind::IndexAssign {
+                        base: name,
+                        index,
+                        expr: e,
+                    })
+                }
+                t => Err(format!(
+                    "expected `:`, `.` or `[` after `{}`, got {:?}",
+                    name, t
+                )),
             }
         }
         Tok::KwLog => {
             bump(p);
-            let e = parse_expr(p)?;
-            Ok(Stmt::Log(e))
+            let es = parse_expr_list(p)?;
+            Ok(StmtKind::Log(es))
+        }
+        Tok::KwPrint => {
+            bump(p);
+            let es = parse_expr_list(p)?;
+            Ok(StmtKind::Print(es))
         }
         Tok::KwCond => parse_cond(p),
         Tok::KwLoop => parse_loop(p),
         Tok::KwWhile => parse_while(p),
         Tok::KwInput => parse_input(p),
+        Tok::KwMatch => parse_match(p),
         Tok::KwBreak => {
             bump(p);
-            Ok(Stmt::Break)
+            Ok(StmtKind::Break)
         }
         Tok::KwContinue => {
             bump(p);
-            Ok(Stmt::Continue)
+            Ok(StmtKind::Continue)
+        }
+        Tok::Cpp(_) => {
+            let Tok::Cpp(code) = bump(p) else {
+                unreachable!()
+            };
+            expect(p, Tok::KwEnd)?;
+            Ok(StmtKind::Cpp(code))
         }
         Tok::KwReturn => {
             bump(p);
             if matches!(peek(p), Tok::RBrace | Tok::KwEnd) {
-                Ok(Stmt::Return(None))
+                Ok(StmtKind::Return(None))
             } else {
                 let e = parse_expr(p)?;
-                Ok(Stmt::Return(Some(e)))
+                Ok(StmtKind::Return(Some(e)))
             }
         }
-        Tok::Eof => Err("eof".into()),
-        _ => Err(format!("unexpected token in stmt: {:?}", peek(p))),
+        Tok::Eof => {
+            bump(p);
+            Err("unexpected end of input, expected a statement".into())
+        }
+        _ => Err(format!("unexpected token in stmt: {:?}", bump(p))),
     }
 }
 
-fn parse_cond(p: &mut Parser) -> Result<Stmt, String> {
-    expect(p, Tok::KwCond);
-    expect(p, Tok::LParen);
+fn parse_cond(p: &mut Parser) -> Result<StmtKind, String> {
+    expect(p, Tok::KwCond)?;
+    expect(p, Tok::LParen)?;
     let cond = parse_expr(p)?;
-    expect(p, Tok::RParen);
-    expect(p, Tok::Arrow);
-    expect(p, Tok::LBrace);
+    expect(p, Tok::RParen)?;
+    expect(p, Tok::Arrow)?;
+    expect(p, Tok::LBrace)?;
     let then_blk = parse_block_until(p, Tok::RBrace)?;
     let mut else_blk = None;
     if let Tok::KwElse = peek(p) {
         bump(p);
-        expect(p, Tok::Arrow);
-        expect(p, Tok::LBrace);
+        expect(p, Tok::Arrow)?;
+        expect(p, Tok::LBrace)?;
         else_blk = Some(parse_block_until(p, Tok::RBrace)?);
     }
-    expect(p, Tok::KwEnd);
-    Ok(Stmt::Cond {
+    expect(p, Tok::KwEnd)?;
+    Ok(StmtKind::Cond {
         cond,
         then_blk,
         else_blk,
     })
 }
 
-fn parse_loop(p: &mut Parser) -> Result<Stmt, String> {
-    expect(p, Tok::KwLoop);
-    expect(p, Tok::LParen);
+fn parse_loop(p: &mut Parser) -> Result<StmtKind, String> {
+    expect(p, Tok::KwLoop)?;
+    expect(p, Tok::LParen)?;
     let cond = parse_expr(p)?;
-    expect(p, Tok::RParen);
-    expect(p, Tok::Arrow);
-    expect(p, Tok::LBrace);
+    expect(p, Tok::RParen)?;
+    expect(p, Tok::Arrow)?;
+    expect(p, Tok::LBrace)?;
     let body = parse_block_until(p, Tok::RBrace)?;
-    expect(p, Tok::KwEnd);
-    Ok(Stmt::Loop { cond, body })
+    expect(p, Tok::KwEnd)?;
+    Ok(StmtKind::Loop { cond, body })
 }
 
-fn parse_while(p: &mut Parser) -> Result<Stmt, String> {
-    expect(p, Tok::KwWhile);
-    expect(p, Tok::LParen);
+fn parse_while(p: &mut Parser) -> Result<StmtKind, String> {
+    expect(p, Tok::KwWhile)?;
+    expect(p, Tok::LParen)?;
     let cond = parse_expr(p)?;
-    expect(p, Tok::RParen);
-    expect(p, Tok::Arrow);
-    expect(p, Tok::LBrace);
+    expect(p, Tok::RParen)?;
+    expect(p, Tok::Arrow)?;
+    expect(p, Tok::LBrace)?;
     let body = parse_block_until(p, Tok::RBrace)?;
     let mut ended = None;
     if let Tok::KwEnded = peek(p) {
         bump(p);
-        expect(p, Tok::LBrace);
+        expect(p, Tok::LBrace)?;
         ended = Some(parse_block_until(p, Tok::RBrace)?);
     }
-    expect(p, Tok::KwEnd);
-    Ok(Stmt::While { cond, body, ended })
+    expect(p, Tok::KwEnd)?;
+    Ok(StmtKind::While { cond, body, ended })
 }
 
-fn parse_input(p: &mut Parser) -> Result<Stmt, String> {
-    expect(p, Tok::KwInput);
-    let prompt = if let Tok::String(s) = bump(p) {
-        s
-    } else {
-        return Err("input needs string".into());
+fn parse_match(p: &mut Parser) -> Result<StmtKind, String> {
+    expect(p, Tok::KwMatch)?;
+    let expr = parse_expr(p)?;
+    expect(p, Tok::Arrow)?;
+    expect(p, Tok::LBrace)?;
+    let mut arms = Vec::new();
+    while !matches!(peek(p), Tok::RBrace | Tok::Eof) {
+        let sp = span(p);
+        let pat = match bump(p) {
+            Tok::Ident(n) if n == "_" => Pat::Wild,
+            Tok::Ident(name) => {
+                let mut binds = Vec::new();
+                if let Tok::LParen = peek(p) {
+                    bump(p);
+                    loop {
+                        match bump(p) {
+                            Tok::Ident(b) => binds.push(b),
+                            t => return Err(format!("expected binding name, got {:?}", t)),
+                        }
+                        if let Tok::Comma = peek(p) {
+                            bump(p);
+                            continue;
+                        }
+                        expect(p, Tok::RParen)?;
+                        break;
+                    }
+                }
+                Pat::Variant { name, binds }
+            }
+            t => return Err(format!("expected a variant or `_`, got {:?}", t)),
+        };
+        expect(p, Tok::Arrow)?;
+        expect(p, Tok::LBrace)?;
+        let body = parse_block_until(p, Tok::RBrace)?;
+        arms.push(Arm {
+            pat,
+            body,
+            span: sp,
+        });
+    }
+    expect(p, Tok::RBrace)?;
+    expect(p, Tok::KwEnd)?;
+    Ok(StmtKind::Match { expr, arms })
+}
+
+fn parse_input(p: &mut Parser) -> Result<StmtKind, String> {
+    expect(p, Tok::KwInput)?;
+    let prompt = match bump(p) {
+        Tok::String(s) => s,
+        t => return Err(format!("input needs a string prompt, got {:?}", t)),
     };
-    expect(p, Tok::Arrow);
+    expect(p, Tok::Arrow)?;
     let target = match bump(p) {
         Tok::Ident(v) => {
             if let Tok::Dot = peek(p) {
                 bump(p);
-                if let Tok::Ident(k) = bump(p) {
-                    Target::Prop { base: v, key: k }
-                } else {
-                    return Err("prop name".into());
+                match bump(p) {
+                    Tok::Ident(k) => Target::Prop { base: v, key: k },
+                    t => return Err(format!("expected property name, got {:?}", t)),
                 }
             } else {
                 Target::Var(v)
             }
         }
-        _ => return Err("input target".into()),
+        t => return Err(format!("expected input target, got {:?}", t)),
     };
-    Ok(Stmt::Input {
+    Ok(StmtKind::Input {
         prompt,
         dst: target,
     })
@@ -266,7 +565,7 @@ fn parse_block_until(p: &mut Parser, until: Tok) -> Result<Vec<Stmt>, String> {
     while std::mem::discriminant(peek(p)) != std::mem::discriminant(&until) {
         v.push(parse_stmt(p)?);
     }
-    expect(p, until);
+    expect(p, until)?;
     Ok(v)
 }
 
@@ -278,52 +577,95 @@ fn bp_infix(op: &Tok) -> Option<(u8, u8)> {
         EqEq | NotEq | Lt | Le | Gt | Ge => (5, 6),
         Plus | Minus => (9, 10),
         Star | Slash | Percent => (11, 12),
+        // right-associative: `a ** b ** c` is `a ** (b ** c)`
+        StarStar => (14, 13),
         _ => return None,
     })
 }
 
+fn parse_expr_list(p: &mut Parser) -> Result<Vec<Expr>, String> {
+    let mut es = vec![parse_expr(p)?];
+    while let Tok::Comma = peek(p) {
+        bump(p);
+        es.push(parse_expr(p)?);
+    }
+    Ok(es)
+}
+
 pub fn parse_expr(p: &mut Parser) -> Result<Expr, String> {
     parse_bp(p, 0)
 }
 
+fn parse_template(parts: Vec<TplPart>) -> Result<Expr, String> {
+    let mut out = Vec::new();
+    for part in parts {
+        match part {
+            TplPart::Lit(s) => out.push(InterpPart::Lit(s)),
+            TplPart::Code(toks) => {
+                let mut sub = Parser::new(toks);
+                if is_eof(peek(&sub)) {
+                    return Err("empty `${}` in string literal".into());
+                }
+                let e = parse_expr(&mut sub)?;
+                if !is_eof(peek(&sub)) {
+                    return Err(format!("unexpected token in `${{...}}`: {:?}", peek(&sub)));
+                }
+                out.push(InterpPart::Expr(e));
+            }
+        }
+    }
+    Ok(Expr::Interp(out))
+}
+
+/// Call arguments after the opening `(`, through the closing `)`.
+fn parse_args(p: &mut Parser) -> Result<Vec<Expr>, String> {
+    let mut args = Vec::new();
+    if let Tok::RParen = peek(p) {
+        bump(p);
+        return Ok(args);
+    }
+    loop {
+        args.push(parse_bp(p, 0)?);
+        if let Tok::Comma = peek(p) {
+            bump(p);
+            continue;
+        }
+        expect(p, Tok::RParen)?;
+        return Ok(args);
+    }
+}
+
 fn parse_bp(p: &mut Parser, min_bp: u8) -> Result<Expr, String> {
     use Tok::*;
     let mut lhs = match bump(p) {
         Number(n) => Expr::Num(n),
         String(s) => Expr::Str(s),
+        Template(parts) => parse_template(parts)?,
         True => Expr::Bool(true),
         False => Expr::Bool(false),
         Ident(id) => {
-            // call or ident/member
-            let mut base = if let LParen = peek(p) {
+            // call, variant or ident
+            if let LParen = peek(p) {
+                bump(p);
+                Expr::Call {
+                    name: id,
+                    args: parse_args(p)?,
+                }
+            } else if let ColonColon = peek(p) {
                 bump(p);
+                let name = match bump(p) {
+                    Ident(n) => n,
+                    t => return Err(format!("expected variant name after `::`, got {:?}", t)),
+                };
                 let mut args = Vec::new();
-                if let RParen = peek(p) {
+                if let LParen = peek(p) {
                     bump(p);
-                } else {
-                    loop {
-                        args.push(parse_bp(p, 0)?);
-                        if let Comma = peek(p) {
-                            bump(p);
-                            continue;
-                        }
-                        expect(p, RParen);
-                        break;
-                    }
+                    args = parse_args(p)?;
                 }
-                Expr::Call { name: id, args }
+                Expr::Variant { ty: id, name, args }
             } else {
                 Expr::Ident(id)
-            };
-            while let Dot = peek(p) {
-                bump(p);
-                if let Ident(k) = bump(p) {
-                    base = Expr::Member(Box::new(base), k);
-                } else {
-                    return Err("member".into());
-                }
             }
-            base
         }
         Bang => {
             let rhs = parse_bp(p, 13)?;
@@ -334,12 +676,31 @@ fn parse_bp(p: &mut Parser, min_bp: u8) -> Result<Expr, String> {
         }
         LParen => {
             let e = parse_bp(p, 0)?;
-            expect(p, RParen);
+            expect(p, RParen)?;
             e
         }
         t => return Err(format!("expr: unexpected token {:?}", t)),
     };
 
+    loop {
+        match peek(p) {
+            Dot => {
+                bump(p);
+                match bump(p) {
+                    Ident(k) => lhs = Expr::Member(Box::new(lhs), k),
+                    t => return Err(format!("expected member name after `.`, got {:?}", t)),
+                }
+            }
+            LBracket => {
+                bump(p);
+                let idx = parse_bp(p, 0)?;
+                expect(p, RBracket)?;
+                lhs = Expr::Index(Box::new(lhs), Box::new(idx));
+            }
+            _ => break,
+        }
+    }
+
     loop {
         let op = peek(p).clone();
         if let Some((l_bp, r_bp)) = bp_infix(&op) {
@@ -360,6 +721,7 @@ fn parse_bp(p: &mut Parser, min_bp: u8) -> Result<Expr, String> {
                 Plus => "+",
                 Minus => "-",
                 Star => "*",
+                StarStar => "**",
                 Slash => "/",
                 Percent => "%",
                 _ => unreachable!(),
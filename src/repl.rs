@@ -0,0 +1,134 @@
+//! `pna-cc repl`: reads entries from stdin and runs them with `interp`.
+//!
+//! An entry is read line by line until it parses; lines that leave a block or
+//! string open continue the entry, and a blank line gives up on it. Entries
+//! that are a single expression print their value instead of running as a
+//! statement.
+
+use crate::ast::{Expr, Item, Program, Stmt, StmtKind};
+use crate::diag::Diagnostic;
+use crate::interp::{Interp, StdinInput};
+use crate::lexer::{self, LexErrorKind};
+use crate::parser::{self, ParseFail};
+use crate::{typeck, value, Session};
+use std::io::{self, Write};
+
+/// File name used when rendering diagnostics.
+const FILE: &str = "<repl>";
+
+enum Entry {
+    Expr(Expr),
+    Program(Program),
+    Incomplete,
+    Error(Diagnostic),
+}
+
+fn read_entry(src: &str) -> Entry {
+    let toks = match lexer::lex(src) {
+        Ok(toks) => toks,
+        Err(e) => {
+            return match e.kind {
+                LexErrorKind::UnterminatedString | LexErrorKind::UnterminatedInterp => {
+                    Entry::Incomplete
+                }
+                k => Entry::Error(Diagnostic::new(k.to_string(), e.span)),
+            }
+        }
+    };
+    if let Ok(e) = parser::parse_expr_only(toks.clone()) {
+        return Entry::Expr(e);
+    }
+    match parser::parse_incremental(toks) {
+        Ok(p) => Entry::Program(p),
+        Err(ParseFail::Incomplete) => Entry::Incomplete,
+        Err(ParseFail::Error(d)) => Entry::Error(d),
+    }
+}
+
+/// Runs the REPL until `:quit` or end of input.
+pub fn run(strict: bool) -> io::Result<()> {
+    let mut it = Interp::new(
+        Box::new(io::stdout()),
+        Box::new(StdinInput::default()),
+        strict,
+    );
+    let mut buf = String::new();
+    // `type` and `enum` declarations from earlier entries, for checking
+    // later ones
+    let mut decls: Vec<Item> = Vec::new();
+    loop {
+        print!("{}", if buf.is_empty() { "pna> " } else { "...> " });
+        io::stdout().flush()?;
+        let mut line = String::new();
+        if io::stdin().read_line(&mut line)? == 0 {
+            println!();
+            return Ok(());
+        }
+        let blank = line.trim().is_empty();
+        if buf.is_empty() {
+            match line.trim() {
+                "" => continue,
+                ":q" | ":quit" => return Ok(()),
+                _ => {}
+            }
+        }
+        buf.push_str(&line);
+
+        let sess = Session::new(FILE, buf.as_str());
+        let prog = match read_entry(&buf) {
+            Entry::Incomplete if !blank => continue,
+            Entry::Incomplete => Err(vec![Diagnostic::new(
+                "unexpected end of input",
+                Default::default(),
+            )]),
+            Entry::Error(d) => Err(vec![d]),
+            Entry::Expr(e) => Ok((Some(e), None)),
+            Entry::Program(p) => Ok((None, Some(p))),
+        };
+        buf.clear();
+        let (expr, prog) = match prog {
+            Ok(x) => x,
+            Err(diags) => {
+                for d in &diags {
+                    eprintln!("{}", sess.render(d));
+                }
+                continue;
+            }
+        };
+
+        // the checker only sees whole programs, so wrap a bare expression
+        let mut checked = prog.clone().unwrap_or_else(|| Program {
+            items: vec![Item::Stmt(Stmt {
+                kind: StmtKind::Log(expr.iter().cloned().collect()),
+                span: Default::default(),
+            })],
+        });
+        let new_decls: Vec<Item> = checked
+            .items
+            .iter()
+            .filter(|it| matches!(it, Item::Type { .. } | Item::Enum { .. }))
+            .cloned()
+            .collect();
+        checked.items.splice(0..0, decls.iter().cloned());
+        if let Err(diags) = typeck::check(&checked) {
+            for d in &diags {
+                eprintln!("{}", sess.render(d));
+            }
+            continue;
+        }
+        decls.extend(new_decls);
+
+        let res = match (expr, prog) {
+            (Some(e), _) => it.eval_expr(&e).map(|v| {
+                if v != value::Value::Undef {
+                    println!("{}", value::as_str(&v));
+                }
+            }),
+            (_, Some(p)) => it.run(&p),
+            _ => Ok(()),
+        };
+        if let Err(e) = res {
+            eprintln!("{}:{}: runtime error: {}", FILE, e.line, e.message);
+        }
+    }
+}
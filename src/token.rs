@@ -1,11 +1,17 @@
+use crate::ast::Span;
+
 #[derive(Debug, Clone)]
 pub enum Tok {
     Ident(String),
     Number(f64),
     String(String),
+    Template(Vec<TplPart>),
+    /// `cpp { ... }`: the C++ text between the braces, as written.
+    Cpp(String),
     True,
     False,
     KwLog,
+    KwPrint,
     KwCond,
     KwElse,
     KwEnd,
@@ -17,21 +23,29 @@ pub enum Tok {
     KwContinue,
     KwFunction,
     KwReturn,
+    KwType,
+    KwEnum,
+    KwMatch,
+    KwImport,
     TyDouble,
     TyInt,
     TyString,
     TyVoid,
     LBrace,
     RBrace,
+    LBracket,
+    RBracket,
     LParen,
     RParen,
     Colon,
+    ColonColon,
     Comma,
     Dot,
     Arrow, // ->
     Plus,
     Minus,
     Star,
+    StarStar, // **
     Slash,
     Percent,
     EqEq,
@@ -45,3 +59,18 @@ pub enum Tok {
     Bang,
     Eof,
 }
+
+/// A piece of an interpolated string literal: `"hi ${name}!"`.
+#[derive(Debug, Clone)]
+pub enum TplPart {
+    Lit(String),
+    Code(Vec<(Tok, Span)>),
+}
+
+/// A `#` or `//` comment, kept as trivia by `lexer::lex_with_comments`.
+#[derive(Debug, Clone)]
+pub struct Comment {
+    /// The comment as written, including the marker, without the newline.
+    pub text: String,
+    pub span: Span,
+}
@@ -1,5 +1,913 @@
-use crate::ast::Program;
+use crate::ast::*;
+use crate::diag::Diagnostic;
+use crate::format_spec;
+use crate::math::{self, Math};
+use std::collections::HashMap;
+use std::fmt;
 
-pub fn check(_p: &Program) -> Result<(), String> {
+/// Functions every program can call without declaring them, besides the
+/// math builtins.
+pub const BUILTINS: &[&str] = &["format", "len", "has", "remove", "keys", "values"];
+
+pub fn check(p: &Program) -> Result<(), Vec<Diagnostic>> {
+    let types = infer(p);
+    let mut diags = Vec::new();
+    check_records(p, &types, &mut diags);
+    check_enums(p, &types, &mut diags);
+    for it in &p.items {
+        match it {
+            Item::Stmt(s) => check_stmt(&Cx::new(&types, None), s, &mut diags),
+            Item::Func {
+                name,
+                params,
+                ret,
+                body,
+                span,
+            } => {
+                check_signature(&types, name, params, ret, *span, &mut diags);
+                check_block(&Cx::new(&types, Some(name)), body, &mut diags);
+            }
+            Item::Extern {
+                name,
+                params,
+                ret,
+                span,
+            } => check_signature(&types, name, params, ret, *span, &mut diags),
+            Item::Cpp { .. } => {}
+            Item::Type { .. } | Item::Enum { .. } => {}
+            // `link::link` resolves imports before checking
+            Item::Import { span, .. } => {
+                let msg = "`import` only works when compiling a file";
+                diags.push(Diagnostic::new(msg, *span));
+            }
+        }
+    }
+    if diags.is_empty() {
+        Ok(())
+    } else {
+        Err(diags)
+    }
+}
+
+/// A function's name and the types of its parameters and return value.
+fn check_signature(
+    types: &Types,
+    name: &str,
+    params: &[Param],
+    ret: &Ty,
+    span: Span,
+    diags: &mut Vec<Diagnostic>,
+) {
+    // imported functions are named `module::name`
+    let own = name.rsplit("::").next().unwrap_or(name);
+    if BUILTINS.contains(&own) {
+        let msg = format!("function `{}` shadows a builtin", name);
+        diags.push(Diagnostic::new(msg, span));
+    }
+    for p in params.iter().filter(|p| p.ty == Ty::Void) {
+        let msg = format!("parameter `{}` cannot have type void", p.name);
+        diags.push(Diagnostic::new(msg, span));
+    }
+    for t in params.iter().map(|p| &p.ty).chain([ret]) {
+        if let Err(msg) = types.known_ty(t) {
+            diags.push(Diagnostic::new(msg, span));
+        }
+    }
+}
+
+/// `type` declarations: unique names and fields, declared field types, and
+/// no record that contains itself (records are values, so it could never be
+/// built).
+fn check_records(p: &Program, types: &Types, diags: &mut Vec<Diagnostic>) {
+    let mut seen = Vec::new();
+    for it in &p.items {
+        let Item::Type { name, fields, span } = it else {
+            continue;
+        };
+        let mut err = |msg: String| diags.push(Diagnostic::new(msg, *span));
+        if seen.contains(&name) {
+            err(format!("type `{}` is declared twice", name));
+        }
+        seen.push(name);
+        for (i, (k, t)) in fields.iter().enumerate() {
+            if fields[..i].iter().any(|(k2, _)| k2 == k) {
+                err(format!("field `{}` of `{}` is declared twice", k, name));
+            }
+            if *t == Ty::Void {
+                err(format!("field `{}` of `{}` cannot have type void", k, name));
+            }
+            if let Err(msg) = types.known_ty(t) {
+                err(msg);
+            }
+        }
+        if types.contains(name, name, &mut Vec::new()) {
+            err(format!("type `{}` contains itself", name));
+        }
+    }
+}
+
+/// `enum` declarations: unique names, variants unique across all enums, and
+/// declared payload types.
+fn check_enums(p: &Program, types: &Types, diags: &mut Vec<Diagnostic>) {
+    let mut seen = Vec::new();
+    let mut seen_variants = Vec::new();
+    for it in &p.items {
+        let Item::Enum {
+            name,
+            variants,
+            span,
+        } = it
+        else {
+            continue;
+        };
+        let mut err = |msg: String| diags.push(Diagnostic::new(msg, *span));
+        if seen.contains(&name) || types.records.contains_key(name) {
+            err(format!("type `{}` is declared twice", name));
+        }
+        seen.push(name);
+        for v in variants {
+            if seen_variants.contains(&&v.name) {
+                err(format!("variant `{}` is declared twice", v.name));
+            }
+            seen_variants.push(&v.name);
+            for t in &v.fields {
+                if *t == Ty::Void {
+                    err(format!("a value of `{}` cannot have type void", v.name));
+                }
+                if let Err(msg) = types.known_ty(t) {
+                    err(msg);
+                }
+            }
+        }
+    }
+}
+
+/// Where a statement is checked: the inferred types and the enclosing
+/// function (`None` at the top level).
+struct Cx<'a> {
+    types: &'a Types,
+    func: Option<&'a str>,
+}
+
+impl<'a> Cx<'a> {
+    fn new(types: &'a Types, func: Option<&'a str>) -> Self {
+        Cx { types, func }
+    }
+
+    fn expr(&self, e: &Expr) -> Type {
+        self.types.expr(self.func, e)
+    }
+
+    /// The declared type of field `key` when `t` is a record.
+    fn field(&self, t: &Type, key: &str) -> Result<Option<Ty>, String> {
+        let Type::Record(name) = t else {
+            return Ok(None);
+        };
+        match self.types.record_field(name, key) {
+            Some(ty) => Ok(Some(ty.clone())),
+            None => Err(format!("type `{}` has no field `{}`", name, key)),
+        }
+    }
+
+    /// `base.key: e`, or `input -> base.key` without `e`.
+    fn set_field(&self, base: &str, key: &str, e: Option<&Expr>) -> Result<(), String> {
+        let t = self.types.var(self.func, base);
+        if let (Some(ty), Some(e)) = (self.field(&t, key)?, e) {
+            self.expect_ty(&ty, &self.expr(e), &format!("field `{}` of `{}`", key, t))?;
+        }
+        Ok(())
+    }
+
+    /// Fails unless a value of static type `t` can be stored where `ty` is
+    /// declared; `Unknown` always fits, and so does anything where `ty` names
+    /// an unknown type, which is reported at the declaration.
+    fn expect_ty(&self, ty: &Ty, t: &Type, what: &str) -> Result<(), String> {
+        let ok = match (ty, t) {
+            (_, Type::Unknown) => true,
+            (Ty::Named(_), _) if self.types.known_ty(ty).is_err() => true,
+            (Ty::Int | Ty::Double, Type::Number | Type::Bool) => true,
+            (Ty::String, Type::String) => true,
+            (Ty::Named(n), Type::Record(m) | Type::Enum(m)) => n == m,
+            // untyped objects are checked at run time, like everything else
+            (Ty::Named(_), Type::Object(_)) => true,
+            _ => false,
+        };
+        if ok {
+            Ok(())
+        } else {
+            Err(format!("{} expects {}, got {}", what, ty, t))
+        }
+    }
+}
+
+/// The subject of a `match` is an enum value, and every arm names a variant
+/// of that enum, at most once, with its payload size; the arms cover every
+/// variant unless the last is `_`. When the subject's type is not known, the
+/// first variant arm picks the enum, so at least one arm must name a variant.
+fn check_match(cx: &Cx, e: &Expr, arms: &[Arm], sp: Span, diags: &mut Vec<Diagnostic>) {
+    let subject = cx.expr(e);
+    let mut en: Option<&str> = match &subject {
+        Type::Enum(n) => Some(n),
+        Type::Unknown | Type::Object(_) => None,
+        t => {
+            diags.push(Diagnostic::new(
+                format!("`match` expects an enum value, got {}", t),
+                sp,
+            ));
+            return;
+        }
+    };
+    let mut matched: Vec<&str> = Vec::new();
+    for (k, arm) in arms.iter().enumerate() {
+        let mut err = |msg: String| diags.push(Diagnostic::new(msg, arm.span));
+        let Pat::Variant { name, binds } = &arm.pat else {
+            if k + 1 != arms.len() {
+                err("`_` must be the last arm".into());
+            }
+            continue;
+        };
+        let Some((owner, v)) = cx.types.variant(name) else {
+            err(format!("unknown variant `{}`", name));
+            continue;
+        };
+        match en {
+            Some(e) if e != owner => {
+                err(format!("`{}` is not a variant of `{}`", name, e));
+                continue;
+            }
+            _ => en = Some(owner),
+        }
+        if matched.contains(&name.as_str()) {
+            err(format!("`{}` is matched twice", name));
+        }
+        matched.push(name);
+        if binds.len() != v.fields.len() {
+            err(format!(
+                "`{}` has {} value(s), but the pattern binds {}",
+                name,
+                v.fields.len(),
+                binds.len()
+            ));
+        }
+    }
+    let Some(en) = en else {
+        let msg = "`match` has no arm naming a variant".to_string();
+        diags.push(Diagnostic::new(msg, sp));
+        return;
+    };
+    let sp = arms.first().map_or(sp, |a| a.span);
+    let what = format!("`match` on `{}`", en);
+    report(
+        cx.expect_ty(&Ty::Named(en.into()), &subject, &what),
+        sp,
+        diags,
+    );
+    if matches!(arms.last().map(|a| &a.pat), Some(Pat::Wild)) {
+        return;
+    }
+    let missing: Vec<String> = cx.types.enums[en]
+        .iter()
+        .filter(|v| !matched.contains(&v.name.as_str()))
+        .map(|v| format!("`{}`", v.name))
+        .collect();
+    if !missing.is_empty() {
+        let msg = format!("`match` on `{}` misses {}", en, missing.join(", "));
+        diags.push(Diagnostic::new(msg, sp));
+    }
+}
+
+/// `Enum::Variant(args)`: a declared variant of that enum, given a value of
+/// the declared type for each field of its payload.
+fn check_variant(cx: &Cx, ty: &str, name: &str, args: &[Expr]) -> Result<(), String> {
+    let vs = cx
+        .types
+        .enums
+        .get(ty)
+        .ok_or_else(|| format!("unknown enum `{}`", ty))?;
+    let v = vs
+        .iter()
+        .find(|v| v.name == name)
+        .ok_or_else(|| format!("enum `{}` has no variant `{}`", ty, name))?;
+    if v.fields.len() != args.len() {
+        return Err(format!(
+            "`{}::{}` takes {} value(s), got {}",
+            ty,
+            name,
+            v.fields.len(),
+            args.len()
+        ));
+    }
+    for (k, (t, a)) in v.fields.iter().zip(args).enumerate() {
+        let what = format!("value {} of `{}::{}`", k + 1, ty, name);
+        cx.expect_ty(t, &cx.expr(a), &what)?;
+    }
+    Ok(())
+}
+
+fn check_block(cx: &Cx, b: &[Stmt], diags: &mut Vec<Diagnostic>) {
+    for s in b {
+        check_stmt(cx, s, diags);
+    }
+}
+
+/// Expressions carry no spans of their own; errors point at the statement.
+fn check_in(cx: &Cx, e: &Expr, sp: Span, diags: &mut Vec<Diagnostic>) {
+    if let Err(msg) = check_expr(cx, e) {
+        diags.push(Diagnostic::new(msg, sp));
+    }
+}
+
+fn report(r: Result<(), String>, sp: Span, diags: &mut Vec<Diagnostic>) {
+    if let Err(msg) = r {
+        diags.push(Diagnostic::new(msg, sp));
+    }
+}
+
+/// A typed literal `name: T { ... }` sets every field of `T` and no other.
+fn check_literal(cx: &Cx, ty: &str, fields: &[(String, Expr)]) -> Result<(), String> {
+    let decl = cx
+        .types
+        .records
+        .get(ty)
+        .ok_or_else(|| format!("unknown type `{}`", ty))?;
+    for (k, e) in fields {
+        let fty = cx
+            .field(&Type::Record(ty.to_string()), k)?
+            .expect("a record");
+        cx.expect_ty(&fty, &cx.expr(e), &format!("field `{}` of `{}`", k, ty))?;
+    }
+    match decl
+        .iter()
+        .find(|(k, _)| !fields.iter().any(|(f, _)| f == k))
+    {
+        Some((k, _)) => Err(format!("missing field `{}` in `{}` literal", k, ty)),
+        None => Ok(()),
+    }
+}
+
+fn check_stmt(cx: &Cx, s: &Stmt, diags: &mut Vec<Diagnostic>) {
+    let sp = s.span;
+    match &s.kind {
+        StmtKind::ObjBlock { ty, fields, .. } => {
+            for (_, e) in fields {
+                check_in(cx, e, sp, diags);
+            }
+            if let Some(ty) = ty {
+                report(check_literal(cx, ty, fields), sp, diags);
+            }
+        }
+        StmtKind::PropAssign { base, key, expr } => {
+            check_in(cx, expr, sp, diags);
+            report(cx.set_field(base, key, Some(expr)), sp, diags);
+        }
+        StmtKind::IndexAssign { base, index, expr } => {
+            check_in(cx, index, sp, diags);
+            check_in(cx, expr, sp, diags);
+            if let Type::Record(t) = cx.types.var(cx.func, base) {
+                let msg = format!("fields of `{}` cannot be set by a computed key", t);
+                diags.push(Diagnostic::new(msg, sp));
+            }
+        }
+        StmtKind::VarAssign { expr, .. } => check_in(cx, expr, sp, diags),
+        StmtKind::Log(es) | StmtKind::Print(es) => {
+            for e in es {
+                check_in(cx, e, sp, diags);
+            }
+        }
+        StmtKind::Cond {
+            cond,
+            then_blk,
+            else_blk,
+        } => {
+            check_in(cx, cond, sp, diags);
+            check_block(cx, then_blk, diags);
+            if let Some(eb) = else_blk {
+                check_block(cx, eb, diags);
+            }
+        }
+        StmtKind::Loop { cond, body } => {
+            check_in(cx, cond, sp, diags);
+            check_block(cx, body, diags);
+        }
+        StmtKind::While { cond, body, ended } => {
+            check_in(cx, cond, sp, diags);
+            check_block(cx, body, diags);
+            if let Some(eb) = ended {
+                check_block(cx, eb, diags);
+            }
+        }
+        StmtKind::Match { expr, arms } => {
+            check_in(cx, expr, sp, diags);
+            check_match(cx, expr, arms, sp, diags);
+            for arm in arms {
+                check_block(cx, &arm.body, diags);
+            }
+        }
+        StmtKind::Return(Some(e)) => check_in(cx, e, sp, diags),
+        StmtKind::Input {
+            dst: Target::Prop { base, key },
+            ..
+        } => report(cx.set_field(base, key, None), sp, diags),
+        StmtKind::Input { .. }
+        | StmtKind::Break
+        | StmtKind::Continue
+        | StmtKind::Return(None)
+        | StmtKind::Cpp(_) => {}
+    }
+}
+
+fn check_expr(cx: &Cx, e: &Expr) -> Result<(), String> {
+    match e {
+        Expr::Num(_) | Expr::Str(_) | Expr::Bool(_) | Expr::Ident(_) => {}
+        Expr::Member(b, k) => {
+            check_expr(cx, b)?;
+            cx.field(&cx.expr(b), k)?;
+        }
+        Expr::Index(b, i) => {
+            check_expr(cx, b)?;
+            check_expr(cx, i)?;
+        }
+        Expr::Unary { rhs, .. } => check_expr(cx, rhs)?,
+        Expr::Binary { lhs, rhs, .. } => {
+            check_expr(cx, lhs)?;
+            check_expr(cx, rhs)?;
+        }
+        Expr::Interp(parts) => {
+            for part in parts {
+                if let InterpPart::Expr(e) = part {
+                    check_expr(cx, e)?;
+                }
+            }
+        }
+        Expr::Variant { ty, name, args } => {
+            for a in args {
+                check_expr(cx, a)?;
+            }
+            check_variant(cx, ty, name, args)?;
+        }
+        Expr::Call { name, args } => {
+            for a in args {
+                check_expr(cx, a)?;
+            }
+            match name.as_str() {
+                "format" => check_format(args)?,
+                "len" | "keys" | "values" if args.len() != 1 => {
+                    return Err(format!("{}: expected 1 argument, got {}", name, args.len()))
+                }
+                "has" | "remove" if args.len() != 2 => {
+                    return Err(format!(
+                        "{}: expected 2 arguments, got {}",
+                        name,
+                        args.len()
+                    ))
+                }
+                // a user or `extern` function hides a math builtin
+                _ if Math::parse(name).is_some() && !cx.types.params.contains_key(name) => {
+                    check_math(cx, name, args)?
+                }
+                _ => {
+                    // only record parameters and record arguments are checked;
+                    // scalars convert at run time
+                    let params = cx.types.params.get(name).map_or(&[][..], |ps| ps);
+                    for (p, a) in params.iter().zip(args) {
+                        let t = cx.expr(a);
+                        if matches!(p.ty, Ty::Named(_))
+                            || matches!(t, Type::Record(_) | Type::Enum(_))
+                        {
+                            let what = format!("parameter `{}` of `{}`", p.name, name);
+                            cx.expect_ty(&p.ty, &t, &what)?;
+                        }
+                    }
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Math builtins take numbers: each argument must fit the `int` or `double`
+/// parameter, as if the builtin were a declared function.
+fn check_math(cx: &Cx, name: &str, args: &[Expr]) -> Result<(), String> {
+    let params = Math::parse(name).expect("a math builtin").params();
+    if params.len() != args.len() {
+        let s = if params.len() == 1 { "" } else { "s" };
+        return Err(format!(
+            "{}: expected {} argument{}, got {}",
+            name,
+            params.len(),
+            s,
+            args.len()
+        ));
+    }
+    for (k, (ty, a)) in params.iter().zip(args).enumerate() {
+        let what = format!("argument {} of `{}`", k + 1, name);
+        cx.expect_ty(ty, &cx.expr(a), &what)?;
+    }
     Ok(())
 }
+
+fn check_format(args: &[Expr]) -> Result<(), String> {
+    let f = match args.first() {
+        Some(f) => f,
+        None => return Err("format: missing format string".into()),
+    };
+    if let Expr::Str(s) = f {
+        let pieces = format_spec::parse(s).map_err(|e| format!("format: {}", e))?;
+        let want = format_spec::holes(&pieces);
+        let got = args.len() - 1;
+        if want != got {
+            return Err(format!(
+                "format: {:?} has {} placeholder(s) but {} argument(s) were given",
+                s, want, got
+            ));
+        }
+    }
+    Ok(())
+}
+
+/// Static type of a value, as far as `infer` can tell without running the
+/// program. PNA is dynamically typed, so this is only used for tooling.
+#[derive(Debug, Clone, PartialEq)]
+pub enum Type {
+    Number,
+    String,
+    Bool,
+    /// Fields in first-assignment order.
+    Object(Vec<(String, Type)>),
+    /// A value of a type declared with `type`.
+    Record(String),
+    /// A value of an `enum`.
+    Enum(String),
+    /// Not known statically, or different types on different paths.
+    Unknown,
+}
+
+impl fmt::Display for Type {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Type::Number => write!(f, "number"),
+            Type::String => write!(f, "string"),
+            Type::Bool => write!(f, "bool"),
+            Type::Object(fields) => {
+                let fs: Vec<String> = fields
+                    .iter()
+                    .map(|(k, t)| format!("{}: {}", k, t))
+                    .collect();
+                write!(f, "{{ {} }}", fs.join(", "))
+            }
+            Type::Record(name) | Type::Enum(name) => f.write_str(name),
+            Type::Unknown => write!(f, "unknown"),
+        }
+    }
+}
+
+impl Type {
+    pub fn of_ty(t: &Ty) -> Type {
+        match t {
+            Ty::Double | Ty::Int => Type::Number,
+            Ty::String => Type::String,
+            Ty::Void => Type::Unknown,
+            Ty::Named(n) => Type::Record(n.clone()),
+        }
+    }
+
+    /// The type of a variable assigned `self` on one path and `other` on
+    /// another.
+    fn join(self, other: Type) -> Type {
+        match (self, other) {
+            (Type::Object(mut a), Type::Object(b)) => {
+                for (k, t) in b {
+                    set_field(&mut a, k, t);
+                }
+                Type::Object(a)
+            }
+            (a, b) if a == b => a,
+            _ => Type::Unknown,
+        }
+    }
+
+    pub fn field(&self, key: &str) -> Option<&Type> {
+        match self {
+            Type::Object(fields) => fields.iter().find(|(k, _)| k == key).map(|(_, t)| t),
+            _ => None,
+        }
+    }
+}
+
+fn set_field(fields: &mut Vec<(String, Type)>, key: String, t: Type) {
+    match fields.iter_mut().find(|(k, _)| *k == key) {
+        Some((_, old)) => *old = std::mem::replace(old, Type::Unknown).join(t),
+        None => fields.push((key, t)),
+    }
+}
+
+/// Inferred variable types per scope: the top level (`None`) and each
+/// function body (`Some(name)`, parameters included).
+#[derive(Debug, Default)]
+pub struct Types {
+    vars: HashMap<(Option<String>, String), Type>,
+    funcs: HashMap<String, Ty>,
+    params: HashMap<String, Vec<Param>>,
+    /// Declared records; the first declaration of a name wins.
+    records: HashMap<String, Vec<(String, Ty)>>,
+    /// Declared enums, likewise.
+    enums: HashMap<String, Vec<Variant>>,
+    /// The enum declaring each variant.
+    variants: HashMap<String, String>,
+}
+
+impl Types {
+    /// Fails for a record or enum type that is not declared.
+    fn known_ty(&self, t: &Ty) -> Result<(), String> {
+        match t {
+            Ty::Named(n) if !self.records.contains_key(n) && !self.enums.contains_key(n) => {
+                Err(format!("unknown type `{}`", n))
+            }
+            _ => Ok(()),
+        }
+    }
+
+    /// The static type of values declared as `t`.
+    pub fn of_ty(&self, t: &Ty) -> Type {
+        match t {
+            Ty::Named(n) if self.enums.contains_key(n) => Type::Enum(n.clone()),
+            t => Type::of_ty(t),
+        }
+    }
+
+    /// The enum declaring variant `name`, and the variant.
+    pub fn variant(&self, name: &str) -> Option<(&str, &Variant)> {
+        let en = self.variants.get(name)?;
+        let v = self.enums[en].iter().find(|v| v.name == name)?;
+        Some((en, v))
+    }
+
+    /// The variants of the enum declared as `name`.
+    pub fn enum_variants(&self, name: &str) -> Option<&[Variant]> {
+        self.enums.get(name).map(|v| &v[..])
+    }
+
+    /// Whether record `outer` has a field of type `inner`, directly or
+    /// through other records; `seen` guards against cycles not through
+    /// `inner`.
+    fn contains(&self, outer: &str, inner: &str, seen: &mut Vec<String>) -> bool {
+        if seen.iter().any(|s| s == outer) {
+            return false;
+        }
+        seen.push(outer.to_string());
+        let fields = self.records.get(outer).map_or(&[][..], |f| f);
+        fields.iter().any(|(_, t)| match t {
+            Ty::Named(n) => n == inner || self.contains(n, inner, seen),
+            _ => false,
+        })
+    }
+
+    fn record_field(&self, record: &str, key: &str) -> Option<&Ty> {
+        let fields = self.records.get(record)?;
+        fields.iter().find(|(k, _)| k == key).map(|(_, t)| t)
+    }
+
+    /// The type of field `key` of a value of type `t`, if known.
+    pub fn field(&self, t: &Type, key: &str) -> Option<Type> {
+        match t {
+            Type::Record(n) => self.record_field(n, key).map(|t| self.of_ty(t)),
+            t => t.field(key).cloned(),
+        }
+    }
+
+    /// Every known field of a value of type `t`.
+    pub fn fields(&self, t: &Type) -> Vec<(String, Type)> {
+        match t {
+            Type::Object(fields) => fields.clone(),
+            Type::Record(n) => self.records.get(n).map_or(Vec::new(), |fs| {
+                fs.iter().map(|(k, t)| (k.clone(), self.of_ty(t))).collect()
+            }),
+            _ => Vec::new(),
+        }
+    }
+
+    /// The fields of the record declared as `name`.
+    pub fn record(&self, name: &str) -> Option<&[(String, Ty)]> {
+        self.records.get(name).map(|f| &f[..])
+    }
+
+    /// Type of `name` as seen from inside `func` (or the top level). Reads
+    /// fall back to the top level, like the runtime's environment chain.
+    pub fn var(&self, func: Option<&str>, name: &str) -> Type {
+        let local = (func.map(str::to_string), name.to_string());
+        let global = (None, name.to_string());
+        self.vars
+            .get(&local)
+            .or_else(|| self.vars.get(&global))
+            .cloned()
+            .unwrap_or(Type::Unknown)
+    }
+
+    /// Every variable visible from `func`, with its type.
+    pub fn visible(&self, func: Option<&str>) -> Vec<(&str, &Type)> {
+        let mut out: Vec<(&str, &Type)> = self
+            .vars
+            .iter()
+            .filter(|((f, _), _)| f.is_none() || f.as_deref() == func)
+            .map(|((_, n), t)| (n.as_str(), t))
+            .collect();
+        out.sort_by_key(|(n, _)| *n);
+        out.dedup_by_key(|(n, _)| *n);
+        out
+    }
+
+    pub fn expr(&self, func: Option<&str>, e: &Expr) -> Type {
+        match e {
+            Expr::Num(_) => Type::Number,
+            Expr::Str(_) | Expr::Interp(_) => Type::String,
+            Expr::Bool(_) => Type::Bool,
+            Expr::Ident(id)
+                if math::constant(id).is_some() && !self.vars.keys().any(|(_, n)| n == id) =>
+            {
+                Type::Number
+            }
+            Expr::Ident(id) => self.var(func, id),
+            Expr::Member(b, k) => self.field(&self.expr(func, b), k).unwrap_or(Type::Unknown),
+            Expr::Index(b, _) => match self.expr(func, b) {
+                Type::String => Type::String,
+                _ => Type::Unknown,
+            },
+            Expr::Unary { .. } => Type::Bool,
+            Expr::Binary { op, lhs, rhs } => {
+                let (l, r) = (self.expr(func, lhs), self.expr(func, rhs));
+                let numeric = |t: &Type| matches!(t, Type::Number | Type::Bool);
+                match op.as_str() {
+                    "+" if l == Type::String || r == Type::String => Type::String,
+                    "*" if (l == Type::String && r == Type::Number)
+                        || (l == Type::Number && r == Type::String) =>
+                    {
+                        Type::String
+                    }
+                    "+" | "*" if numeric(&l) && numeric(&r) => Type::Number,
+                    "+" | "*" => Type::Unknown,
+                    "-" | "/" | "%" | "**" => Type::Number,
+                    _ => Type::Bool,
+                }
+            }
+            Expr::Call { name, .. } => match name.as_str() {
+                "format" => Type::String,
+                "len" => Type::Number,
+                "has" => Type::Bool,
+                "remove" | "keys" | "values" => Type::Object(Vec::new()),
+                _ => match self.funcs.get(name) {
+                    Some(t) => self.of_ty(t),
+                    None if Math::parse(name).is_some() => Type::Number,
+                    None => Type::Unknown,
+                },
+            },
+            Expr::Variant { ty, .. } => Type::Enum(ty.clone()),
+        }
+    }
+
+    fn assign(&mut self, func: Option<&str>, name: &str, t: Type) {
+        let key = (func.map(str::to_string), name.to_string());
+        let t = match self.vars.remove(&key) {
+            Some(old) => old.join(t),
+            None => t,
+        };
+        self.vars.insert(key, t);
+    }
+
+    fn block(&mut self, func: Option<&str>, b: &[Stmt]) {
+        for s in b {
+            self.stmt(func, s);
+        }
+    }
+
+    fn stmt(&mut self, func: Option<&str>, s: &Stmt) {
+        match &s.kind {
+            StmtKind::ObjBlock {
+                name, ty: Some(ty), ..
+            } => self.assign(func, name, Type::Record(ty.clone())),
+            StmtKind::ObjBlock { name, fields, .. } => {
+                let mut fs = Vec::new();
+                for (k, e) in fields {
+                    set_field(&mut fs, k.clone(), self.expr(func, e));
+                }
+                self.assign(func, name, Type::Object(fs));
+            }
+            // setting a field keeps a record a record
+            StmtKind::PropAssign { base, .. }
+            | StmtKind::IndexAssign { base, .. }
+            | StmtKind::Input {
+                dst: Target::Prop { base, .. },
+                ..
+            } if matches!(self.var(func, base), Type::Record(_)) => {
+                let t = self.var(func, base);
+                self.assign(func, base, t);
+            }
+            StmtKind::PropAssign { base, key, expr } => {
+                let t = self.expr(func, expr);
+                self.assign(func, base, Type::Object(vec![(key.clone(), t)]));
+            }
+            StmtKind::IndexAssign { base, .. } => self.assign(func, base, Type::Object(Vec::new())),
+            StmtKind::VarAssign { name, expr } => {
+                let t = self.expr(func, expr);
+                self.assign(func, name, t);
+            }
+            StmtKind::Input { dst, .. } => match dst {
+                Target::Var(v) => self.assign(func, v, Type::Unknown),
+                Target::Prop { base, key } => {
+                    let t = Type::Object(vec![(key.clone(), Type::Unknown)]);
+                    self.assign(func, base, t);
+                }
+            },
+            StmtKind::Cond {
+                then_blk, else_blk, ..
+            } => {
+                self.block(func, then_blk);
+                if let Some(eb) = else_blk {
+                    self.block(func, eb);
+                }
+            }
+            StmtKind::Loop { body, .. } => self.block(func, body),
+            StmtKind::Match { arms, .. } => {
+                for arm in arms {
+                    if let Pat::Variant { name, binds } = &arm.pat {
+                        let fields = self.variant(name).map_or(Vec::new(), |(_, v)| {
+                            v.fields.iter().map(|t| self.of_ty(t)).collect()
+                        });
+                        for (k, b) in binds.iter().enumerate() {
+                            let t = fields.get(k).cloned().unwrap_or(Type::Unknown);
+                            self.assign(func, b, t);
+                        }
+                    }
+                    self.block(func, &arm.body);
+                }
+            }
+            StmtKind::While { body, ended, .. } => {
+                self.block(func, body);
+                if let Some(eb) = ended {
+                    self.block(func, eb);
+                }
+            }
+            StmtKind::Log(_)
+            | StmtKind::Print(_)
+            | StmtKind::Break
+            | StmtKind::Continue
+            | StmtKind::Return(_)
+            | StmtKind::Cpp(_) => {}
+        }
+    }
+}
+
+/// Infers variable types from assignments, in source order. Flow-insensitive:
+/// a variable assigned different types anywhere in its scope is `Unknown`.
+pub fn infer(p: &Program) -> Types {
+    let mut t = Types::default();
+    for it in &p.items {
+        match it {
+            Item::Func {
+                name, params, ret, ..
+            }
+            | Item::Extern {
+                name, params, ret, ..
+            } => {
+                t.funcs.insert(name.clone(), ret.clone());
+                t.params.insert(name.clone(), params.clone());
+            }
+            Item::Type { name, fields, .. } => {
+                t.records
+                    .entry(name.clone())
+                    .or_insert_with(|| fields.clone());
+            }
+            Item::Enum { name, variants, .. } => {
+                if t.enums.contains_key(name) {
+                    continue;
+                }
+                for v in variants {
+                    t.variants
+                        .entry(v.name.clone())
+                        .or_insert_with(|| name.clone());
+                }
+                t.enums.insert(name.clone(), variants.clone());
+            }
+            Item::Stmt(_) | Item::Import { .. } | Item::Cpp { .. } => {}
+        }
+    }
+    for it in &p.items {
+        match it {
+            Item::Stmt(s) => t.stmt(None, s),
+            Item::Func {
+                name, params, body, ..
+            } => {
+                for p in params {
+                    let ty = t.of_ty(&p.ty);
+                    t.assign(Some(name), &p.name, ty);
+                }
+                t.block(Some(name), body);
+            }
+            Item::Type { .. }
+            | Item::Enum { .. }
+            | Item::Import { .. }
+            | Item::Extern { .. }
+            | Item::Cpp { .. } => {}
+        }
+    }
+    t
+}
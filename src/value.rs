@@ -0,0 +1,385 @@
+//! Dynamic values and operators with the same semantics as the embedded C++
+//! runtime (`pna_add`, `as_str`, ...), for evaluating PNA inside the compiler.
+//!
+//! Every operation takes `strict`; failures that the runtime reports with
+//! `pna_fail` under `--strict` come back as `Err(message)`, otherwise the
+//! lenient default is returned.
+
+use crate::math::Math;
+use std::collections::BTreeMap;
+use std::rc::Rc;
+
+#[derive(Debug, Clone, PartialEq, Default)]
+pub enum Value {
+    #[default]
+    Undef,
+    Num(f64),
+    Str(String),
+    Bool(bool),
+    /// Shared until written: `set_prop` copies the fields only when another
+    /// value still refers to them.
+    Obj(Rc<BTreeMap<String, Value>>),
+}
+
+pub type R<T> = Result<T, String>;
+
+pub fn as_str(v: &Value) -> String {
+    match v {
+        Value::Str(s) => s.clone(),
+        Value::Num(d) => num_str(*d),
+        Value::Bool(b) => b.to_string(),
+        Value::Obj(m) => {
+            let fields: Vec<String> = m
+                .iter()
+                .map(|(k, v)| format!("{}:{}", k, as_str(v)))
+                .collect();
+            format!("{{{}}}", fields.join(", "))
+        }
+        Value::Undef => String::new(),
+    }
+}
+
+/// Integral values print without a decimal point, others with up to 12
+/// decimals and trailing zeros trimmed.
+pub fn num_str(d: f64) -> String {
+    if d.is_nan() {
+        return "nan".into();
+    }
+    let rd = d.round();
+    if (d - rd).abs() < 1e-9 {
+        return format!("{:.0}", rd);
+    }
+    let s = format!("{:.12}", d);
+    let s = s.trim_end_matches('0');
+    s.strip_suffix('.').unwrap_or(s).to_string()
+}
+
+/// `strtod` on the whole string; `None` when it is not entirely a number.
+/// Accepts what the C and C++ runtimes do (and `pna_strtod` in JS): leading
+/// white space, then an optional sign and a decimal number with an optional
+/// exponent, a hexadecimal integer after `0x`, or `inf`, `infinity` or `nan`
+/// in any case.
+pub fn parse_num(s: &str) -> Option<f64> {
+    let t = s.trim_start_matches([' ', '\t', '\n', '\x0b', '\x0c', '\r']);
+    let (neg, body) = match t.strip_prefix('-') {
+        Some(rest) => (true, rest),
+        None => (false, t.strip_prefix('+').unwrap_or(t)),
+    };
+    let d = if let Some(hex) = body.strip_prefix("0x").or(body.strip_prefix("0X")) {
+        if hex.is_empty() || !hex.bytes().all(|b| b.is_ascii_hexdigit()) {
+            return None;
+        }
+        hex.chars()
+            .fold(0.0, |n, c| n * 16.0 + c.to_digit(16).unwrap_or(0) as f64)
+    } else if ["inf", "infinity"]
+        .iter()
+        .any(|w| body.eq_ignore_ascii_case(w))
+    {
+        f64::INFINITY
+    } else if body.eq_ignore_ascii_case("nan") {
+        f64::NAN
+    } else if is_decimal(body) {
+        body.parse::<f64>().ok()?
+    } else {
+        return None;
+    };
+    Some(if neg { -d } else { d })
+}
+
+/// Digits with at most one `.` among them, then maybe `e`/`E`, an optional
+/// sign and more digits.
+fn is_decimal(s: &str) -> bool {
+    let (mant, exp) = match s.find(['e', 'E']) {
+        Some(i) => (&s[..i], Some(&s[i + 1..])),
+        None => (s, None),
+    };
+    let digits = mant.bytes().filter(u8::is_ascii_digit).count();
+    let dots = mant.bytes().filter(|&b| b == b'.').count();
+    let exp_ok = exp.is_none_or(|e| {
+        let e = e.strip_prefix(['+', '-']).unwrap_or(e);
+        !e.is_empty() && e.bytes().all(|b| b.is_ascii_digit())
+    });
+    digits > 0 && digits + dots == mant.len() && dots <= 1 && exp_ok
+}
+
+pub fn as_num(v: &Value, strict: bool) -> R<f64> {
+    match v {
+        Value::Num(d) => Ok(*d),
+        Value::Bool(b) => Ok(if *b { 1.0 } else { 0.0 }),
+        Value::Str(s) => match parse_num(s) {
+            Some(d) => Ok(d),
+            None if strict => Err(format!("cannot convert \"{}\" to a number", s)),
+            None => Ok(0.0),
+        },
+        Value::Obj(_) if strict => Err("object used as a number".into()),
+        Value::Undef if strict => Err("undefined value used as a number".into()),
+        _ => Ok(0.0),
+    }
+}
+
+pub fn truthy(v: &Value) -> bool {
+    match v {
+        Value::Bool(b) => *b,
+        Value::Num(d) => *d != 0.0,
+        Value::Str(s) => !s.is_empty(),
+        Value::Obj(m) => !m.is_empty(),
+        Value::Undef => false,
+    }
+}
+
+/// Applies a binary operator as spelled in `Expr::Binary::op`.
+pub fn binary(op: &str, a: &Value, b: &Value, strict: bool) -> R<Value> {
+    let num = |f: fn(f64, f64) -> f64| -> R<Value> {
+        Ok(Value::Num(f(as_num(a, strict)?, as_num(b, strict)?)))
+    };
+    let cmp = |f: fn(f64, f64) -> bool| -> R<Value> {
+        Ok(Value::Bool(f(as_num(a, strict)?, as_num(b, strict)?)))
+    };
+    match op {
+        "+" => {
+            if matches!(a, Value::Str(_)) || matches!(b, Value::Str(_)) {
+                Ok(Value::Str(as_str(a) + &as_str(b)))
+            } else {
+                num(|x, y| x + y)
+            }
+        }
+        "-" => num(|x, y| x - y),
+        "*" => match (a, b) {
+            (Value::Str(s), Value::Num(_)) => Ok(Value::Str(repeat(s, as_num(b, strict)?))),
+            (Value::Num(_), Value::Str(s)) => Ok(Value::Str(repeat(s, as_num(a, strict)?))),
+            _ => num(|x, y| x * y),
+        },
+        "/" => {
+            let r = as_num(b, strict)?;
+            if r == 0.0 && strict {
+                return Err("division by zero".into());
+            }
+            let x = as_num(a, strict)?;
+            Ok(Value::Num(if r == 0.0 { 0.0 } else { x / r }))
+        }
+        "%" => {
+            let x = as_num(a, strict)?;
+            let y = as_num(b, strict)?;
+            if y == 0.0 {
+                if strict {
+                    return Err("modulo by zero".into());
+                }
+                return Ok(Value::Num(0.0));
+            }
+            let q = ((x / y) + 1e-12).floor();
+            let r = x - q * y;
+            Ok(Value::Num(if r.abs() < 1e-12 { 0.0 } else { r }))
+        }
+        "==" | "!=" => {
+            let eq = match (a, b) {
+                (Value::Num(x), Value::Num(y)) => x == y,
+                _ => as_str(a) == as_str(b),
+            };
+            Ok(Value::Bool(eq == (op == "==")))
+        }
+        "<" => cmp(|x, y| x < y),
+        "<=" => cmp(|x, y| x <= y),
+        ">" => cmp(|x, y| x > y),
+        ">=" => cmp(|x, y| x >= y),
+        "**" => math(Math::Pow, &[a.clone(), b.clone()], strict),
+        "&&" => Ok(Value::Bool(truthy(a) && truthy(b))),
+        "||" => Ok(Value::Bool(truthy(a) || truthy(b))),
+        _ => Ok(a.clone()),
+    }
+}
+
+/// Applies a math builtin (`pna_sqrt`, ...). A result that is not a number,
+/// like `sqrt(0 - 1)`, fails under `strict` and is 0 otherwise; `-0` comes
+/// back as `0`.
+pub fn math(f: Math, args: &[Value], strict: bool) -> R<Value> {
+    let x = args
+        .iter()
+        .map(|a| as_num(a, strict))
+        .collect::<R<Vec<f64>>>()?;
+    let r = f.apply(&x);
+    if !r.is_nan() {
+        return Ok(Value::Num(r + 0.0));
+    }
+    if strict {
+        let x: Vec<String> = x.into_iter().map(num_str).collect();
+        return Err(format!("{}({}) is undefined", f.name(), x.join(", ")));
+    }
+    Ok(Value::Num(0.0))
+}
+
+pub fn unary(op: &str, v: &Value) -> Value {
+    match op {
+        "!" => Value::Bool(!truthy(v)),
+        _ => v.clone(),
+    }
+}
+
+/// `string * n` repeats each code point `n` times (negative counts as 0).
+fn repeat(s: &str, n: f64) -> String {
+    let r = n.round().max(0.0) as usize;
+    s.chars().flat_map(|c| std::iter::repeat_n(c, r)).collect()
+}
+
+pub fn len(v: &Value) -> Value {
+    match v {
+        Value::Str(s) => Value::Num(s.chars().count() as f64),
+        Value::Obj(m) => Value::Num(m.len() as f64),
+        _ => Value::Num(0.0),
+    }
+}
+
+/// `base[i]`: a code point of a string, or the field of an object named by
+/// the string form of `i`.
+pub fn index(base: &Value, i: &Value, strict: bool) -> R<Value> {
+    let s = match base {
+        Value::Str(s) => s,
+        Value::Obj(_) => return get_prop(base, &as_str(i), strict),
+        _ => return Ok(Value::Undef),
+    };
+    let d = as_num(i, strict)?;
+    let n = s.chars().count() as f64;
+    if d < 0.0 || d != d.floor() || d >= n {
+        if strict {
+            return Err(format!("string index {} out of range", as_str(i)));
+        }
+        return Ok(Value::Undef);
+    }
+    Ok(s.chars()
+        .nth(d as usize)
+        .map(|c| Value::Str(c.to_string()))
+        .unwrap_or_default())
+}
+
+pub fn get_prop(o: &Value, k: &str, strict: bool) -> R<Value> {
+    match o {
+        Value::Obj(m) => match m.get(k) {
+            Some(v) => Ok(v.clone()),
+            None if strict => Err(format!("undefined property `{}`", k)),
+            None => Ok(Value::Undef),
+        },
+        _ if strict => Err(format!("cannot read property `{}` of a non-object", k)),
+        _ => Ok(Value::Undef),
+    }
+}
+
+/// Sets `k` on `o`, replacing `o` with an empty object first if it is not one.
+pub fn set_prop(o: &mut Value, k: &str, v: Value) {
+    if !matches!(o, Value::Obj(_)) {
+        *o = Value::Obj(Rc::default());
+    }
+    if let Value::Obj(m) = o {
+        Rc::make_mut(m).insert(k.to_string(), v);
+    }
+}
+
+/// Whether `o` is an object with a field named by the string form of `k`.
+pub fn has(o: &Value, k: &Value) -> Value {
+    Value::Bool(matches!(o, Value::Obj(m) if m.contains_key(&as_str(k))))
+}
+
+/// `o` without the field named `k`; anything but an object is returned unchanged.
+pub fn remove(mut o: Value, k: &Value) -> Value {
+    if let Value::Obj(m) = &mut o {
+        let k = as_str(k);
+        if m.contains_key(&k) {
+            Rc::make_mut(m).remove(&k);
+        }
+    }
+    o
+}
+
+/// The field names of `o` as a list: an object with fields `0`, `1`, ...
+pub fn keys(o: &Value) -> Value {
+    match o {
+        Value::Obj(m) => list(m.keys().map(|k| Value::Str(k.clone()))),
+        _ => list(std::iter::empty()),
+    }
+}
+
+/// The field values of `o`, in the same order as `keys`.
+pub fn values(o: &Value) -> Value {
+    match o {
+        Value::Obj(m) => list(m.values().cloned()),
+        _ => list(std::iter::empty()),
+    }
+}
+
+fn list(xs: impl Iterator<Item = Value>) -> Value {
+    Value::Obj(Rc::new(
+        xs.enumerate().map(|(i, x)| (i.to_string(), x)).collect(),
+    ))
+}
+
+/// Converts one whitespace-delimited `input` token like `pna_input`.
+pub fn from_input(tok: &str) -> Value {
+    match parse_num(tok) {
+        Some(d) => Value::Num(d),
+        None => Value::Str(tok.to_string()),
+    }
+}
+
+/// The `format` builtin. Malformed placeholders are copied through verbatim,
+/// exactly like the runtime's `pna_format`.
+pub fn format(f: &Value, args: &[Value]) -> Value {
+    let fs: Vec<char> = as_str(f).chars().collect();
+    let mut out = String::new();
+    let mut ai = 0usize;
+    let mut i = 0usize;
+    while i < fs.len() {
+        let c = fs[i];
+        if (c == '{' || c == '}') && fs.get(i + 1) == Some(&c) {
+            out.push(c);
+            i += 2;
+            continue;
+        }
+        if c != '{' {
+            out.push(c);
+            i += 1;
+            continue;
+        }
+        let close = match fs[i + 1..].iter().position(|&d| d == '}') {
+            Some(k) => i + 1 + k,
+            None => {
+                out.extend(&fs[i..]);
+                break;
+            }
+        };
+        let inner: String = fs[i + 1..close].iter().collect();
+        i = close + 1;
+        match crate::format_spec::parse(&format!("{{{}}}", inner)) {
+            Ok(pieces) => {
+                if let Some(crate::format_spec::Piece::Hole(spec)) = pieces.first() {
+                    let v = args.get(ai).cloned().unwrap_or_default();
+                    ai += 1;
+                    out.push_str(&fmt_one(&v, spec));
+                }
+            }
+            Err(_) => out.push_str(&format!("{{{}}}", inner)),
+        }
+    }
+    Value::Str(out)
+}
+
+fn fmt_one(v: &Value, spec: &crate::format_spec::Spec) -> String {
+    let is_num = matches!(v, Value::Num(_));
+    let mut s = match (spec.precision, v) {
+        (Some(p), Value::Num(d)) => format!("{:.*}", p, d),
+        (Some(p), _) => as_str(v).chars().take(p).collect(),
+        (None, _) => as_str(v),
+    };
+    let pad = spec.width.saturating_sub(s.chars().count());
+    if pad == 0 {
+        return s;
+    }
+    if spec.zero && is_num {
+        let at = usize::from(s.starts_with('-'));
+        s.insert_str(at, &"0".repeat(pad));
+        return s;
+    }
+    let fill = |n: usize| spec.fill.to_string().repeat(n);
+    match spec.align.unwrap_or(if is_num { '>' } else { '<' }) {
+        '<' => s + &fill(pad),
+        '>' => fill(pad) + &s,
+        _ => fill(pad / 2) + &s + &fill(pad - pad / 2),
+    }
+}
@@ -0,0 +1,286 @@
+//! Stack machine that runs a `bytecode::Module`.
+
+use crate::bytecode::{Module, Op};
+use crate::interp::{Input, RuntimeError};
+use crate::value::{self, Value};
+use std::collections::HashMap;
+use std::io::Write;
+
+struct Frame {
+    /// Index into `Vm::fns`.
+    func: usize,
+    ip: usize,
+    /// `None` until the slot is first assigned.
+    locals: Vec<Option<Value>>,
+    /// Stack height when the frame was entered (arguments popped).
+    base: usize,
+}
+
+pub struct Vm<'m> {
+    m: &'m Module,
+    /// `m.funcs` followed by `m.main`.
+    fns: Vec<&'m crate::bytecode::Function>,
+    /// Slot index by name, per function, for lookups by name.
+    slot_maps: Vec<HashMap<&'m str, usize>>,
+    strict: bool,
+    stack: Vec<Value>,
+    frames: Vec<Frame>,
+    out: Box<dyn Write>,
+    input: Box<dyn Input>,
+}
+
+type Res<T> = Result<T, RuntimeError>;
+
+impl<'m> Vm<'m> {
+    pub fn new(m: &'m Module, out: Box<dyn Write>, input: Box<dyn Input>, strict: bool) -> Self {
+        let fns: Vec<_> = m.funcs.iter().chain([&m.main]).collect();
+        let slot_maps = fns
+            .iter()
+            .map(|f| {
+                f.slots
+                    .iter()
+                    .enumerate()
+                    .map(|(i, s)| (s.as_str(), i))
+                    .collect()
+            })
+            .collect();
+        Vm {
+            m,
+            fns,
+            slot_maps,
+            strict,
+            stack: Vec::new(),
+            frames: Vec::new(),
+            out,
+            input,
+        }
+    }
+
+    /// Runs `main` to completion.
+    pub fn run(&mut self) -> Res<()> {
+        let main = self.fns.len() - 1;
+        self.frames.push(Frame {
+            func: main,
+            ip: 0,
+            locals: vec![None; self.fns[main].slots.len()],
+            base: 0,
+        });
+        let r = self.exec();
+        let _ = self.out.flush();
+        r
+    }
+
+    fn fail<T>(&self, message: impl Into<String>) -> Res<T> {
+        let (line, file) = self.frames.last().map_or((0, 0), |f| {
+            let func = self.fns[f.func];
+            let line = func.lines.get(f.ip.saturating_sub(1)).copied();
+            (line.unwrap_or(0), func.file)
+        });
+        Err(RuntimeError {
+            message: message.into(),
+            line: line as usize,
+            file: file as usize,
+        })
+    }
+
+    fn lift<T>(&self, r: value::R<T>) -> Res<T> {
+        r.or_else(|m| self.fail(m))
+    }
+
+    fn pop(&mut self) -> Value {
+        self.stack.pop().unwrap_or_default()
+    }
+
+    /// Pops the top `n` values, in push order.
+    fn pop_n(&mut self, n: u32) -> Vec<Value> {
+        let at = self.stack.len().saturating_sub(n as usize);
+        self.stack.split_off(at)
+    }
+
+    fn konst(&self, k: u32) -> &'m Value {
+        &self.m.consts[k as usize]
+    }
+
+    fn key(&self, k: u32) -> String {
+        value::as_str(self.konst(k))
+    }
+
+    /// Looks `name` up through the frames, innermost first, skipping the
+    /// top `skip` frames. `required` makes a miss an error in strict mode.
+    fn lookup(&self, name: &str, skip: usize, required: bool) -> Res<Value> {
+        for f in self.frames.iter().rev().skip(skip) {
+            if let Some(Some(v)) = self.slot_maps[f.func].get(name).map(|&s| &f.locals[s]) {
+                return Ok(v.clone());
+            }
+        }
+        if self.strict && required {
+            return self.fail(format!("undefined variable `{}`", name));
+        }
+        Ok(Value::Undef)
+    }
+
+    fn exec(&mut self) -> Res<()> {
+        let strict = self.strict;
+        loop {
+            let fr = self.frames.last_mut().expect("a frame is running");
+            let code = &self.fns[fr.func].code;
+            let Some(&op) = code.get(fr.ip) else {
+                return Ok(());
+            };
+            fr.ip += 1;
+            match op {
+                Op::Const(k) => self.stack.push(self.konst(k).clone()),
+                Op::Pop => {
+                    self.pop();
+                }
+                Op::LoadLocal(s) => {
+                    let fr = self.frames.last().unwrap();
+                    let v = match &fr.locals[s as usize] {
+                        Some(v) => v.clone(),
+                        None => {
+                            let name = &self.fns[fr.func].slots[s as usize];
+                            self.lookup(name, 1, true)?
+                        }
+                    };
+                    self.stack.push(v);
+                }
+                Op::StoreLocal(s) => {
+                    let v = self.pop();
+                    self.frames.last_mut().unwrap().locals[s as usize] = Some(v);
+                }
+                Op::SetLocalField(s) | Op::SetLocalIndex(s) => {
+                    let (k, v) = match op {
+                        Op::SetLocalField(_) => {
+                            let k = self.pop();
+                            (k, self.pop())
+                        }
+                        _ => {
+                            let v = self.pop();
+                            (self.pop(), v)
+                        }
+                    };
+                    let k = value::as_str(&k);
+                    let fr = self.frames.last().unwrap();
+                    if fr.locals[s as usize].is_none() {
+                        let name = &self.fns[fr.func].slots[s as usize];
+                        let base = self.lookup(name, 1, false)?;
+                        self.frames.last_mut().unwrap().locals[s as usize] = Some(base);
+                    }
+                    let fr = self.frames.last_mut().unwrap();
+                    let o = fr.locals[s as usize].as_mut().expect("set above");
+                    value::set_prop(o, &k, v);
+                }
+                Op::LoadName(k) => {
+                    let v = self.lookup(&self.key(k), 0, true)?;
+                    self.stack.push(v);
+                }
+                Op::Member(k) => {
+                    let o = self.pop();
+                    let v = self.lift(value::get_prop(&o, &self.key(k), strict))?;
+                    self.stack.push(v);
+                }
+                Op::Index => {
+                    let i = self.pop();
+                    let b = self.pop();
+                    let v = self.lift(value::index(&b, &i, strict))?;
+                    self.stack.push(v);
+                }
+                Op::NewObj => self.stack.push(Value::Obj(Default::default())),
+                Op::SetField(k) => {
+                    let v = self.pop();
+                    let mut o = self.pop();
+                    value::set_prop(&mut o, &self.key(k), v);
+                    self.stack.push(o);
+                }
+                Op::Bin(b) => {
+                    let r = self.pop();
+                    let l = self.pop();
+                    let v = self.lift(value::binary(b.as_str(), &l, &r, strict))?;
+                    self.stack.push(v);
+                }
+                Op::Not => {
+                    let v = self.pop();
+                    self.stack.push(value::unary("!", &v));
+                }
+                Op::Len => {
+                    let v = self.pop();
+                    self.stack.push(value::len(&v));
+                }
+                Op::Has | Op::Remove => {
+                    let k = self.pop();
+                    let o = self.pop();
+                    self.stack.push(match op {
+                        Op::Has => value::has(&o, &k),
+                        _ => value::remove(o, &k),
+                    });
+                }
+                Op::Keys => {
+                    let o = self.pop();
+                    self.stack.push(value::keys(&o));
+                }
+                Op::Values => {
+                    let o = self.pop();
+                    self.stack.push(value::values(&o));
+                }
+                Op::Math(m) => {
+                    let args = self.pop_n(m.params().len() as u32);
+                    let v = self.lift(value::math(m, &args, strict))?;
+                    self.stack.push(v);
+                }
+                Op::Format(n) => {
+                    let args = self.pop_n(n);
+                    let (f, rest) = args.split_first().unwrap_or((&Value::Undef, &[]));
+                    self.stack.push(value::format(f, rest));
+                }
+                Op::Concat(n) => {
+                    let s: String = self.pop_n(n).iter().map(value::as_str).collect();
+                    self.stack.push(Value::Str(s));
+                }
+                Op::Call(f) => {
+                    let func = self.fns[f as usize];
+                    let args = self.pop_n(func.params);
+                    let mut locals: Vec<Option<Value>> = args.into_iter().map(Some).collect();
+                    locals.resize(func.slots.len(), None);
+                    self.frames.push(Frame {
+                        func: f as usize,
+                        ip: 0,
+                        locals,
+                        base: self.stack.len(),
+                    });
+                }
+                Op::Ret => {
+                    let v = self.pop();
+                    let fr = self.frames.pop().unwrap();
+                    if self.frames.is_empty() {
+                        return Ok(());
+                    }
+                    self.stack.truncate(fr.base);
+                    self.stack.push(v);
+                }
+                Op::Log(n) | Op::Print(n) => {
+                    let xs: Vec<String> = self.pop_n(n).iter().map(value::as_str).collect();
+                    let mut line = xs.join(" ");
+                    if let Op::Log(_) = op {
+                        line.push('\n');
+                    }
+                    let _ = self.out.write_all(line.as_bytes());
+                }
+                Op::Input(k) => {
+                    let prompt = self.key(k);
+                    if !prompt.is_empty() {
+                        let _ = self.out.write_all(prompt.as_bytes());
+                        let _ = self.out.flush();
+                    }
+                    let tok = self.input.token().unwrap_or_default();
+                    self.stack.push(value::from_input(&tok));
+                }
+                Op::Jump(t) => self.frames.last_mut().unwrap().ip = t as usize,
+                Op::JumpIfFalse(t) => {
+                    if !value::truthy(&self.pop()) {
+                        self.frames.last_mut().unwrap().ip = t as usize;
+                    }
+                }
+            }
+        }
+    }
+}